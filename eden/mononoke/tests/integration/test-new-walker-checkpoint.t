@@ -22,7 +22,7 @@ bonsai core data, deep, unchunked. This is the base case
   Walking edge types [BookmarkToChangeset, ChangesetToBonsaiParent, ChangesetToFileContent], repo: repo
   Walking node types [Bookmark, Changeset, FileContent], repo: repo
   Seen,Loaded: 7,7, repo: repo
-  * Type:Walked,Checks,Children Bookmark:1,1,2 Changeset:3,* FileContent:3,3,0, repo: repo (glob)
+  * Type:Walked,Checks,Children,Walked% Bookmark:1,1,2,14.3% Changeset:3,* FileContent:3,3,0,42.9%, * (glob)
 
 bonsai core data, chunked, deep, with checkpointing enabled
   $ mononoke_walker -L sizing -L graph scrub -q -p Changeset --chunk-size=2 --checkpoint-name=bonsai_deep --checkpoint-path=test_sqlite -I deep -i bonsai -i FileContent 2>&1 | strip_glog
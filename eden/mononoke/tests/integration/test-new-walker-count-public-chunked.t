@@ -22,17 +22,17 @@ bonsai core data, deep, unchunked. This is the base case
   Walking edge types [BookmarkToChangeset, ChangesetToBonsaiParent, ChangesetToFileContent], repo: repo
   Walking node types [Bookmark, Changeset, FileContent], repo: repo
   Seen,Loaded: 7,7, repo: repo
-  * Type:Walked,Checks,Children Bookmark:1,* Changeset:3,* FileContent:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Bookmark:1,* Changeset:3,* FileContent:3,* (glob)
 
 bonsai core data, chunked, shallow.  Shallow walk with chunked commits should still visit all changesets, but no bookmark
   $ mononoke_walker -L sizing -L chunking scrub -q -p Changeset --chunk-size=2 -I shallow -i bonsai -i FileContent 2>&1 | strip_glog
   Walking edge types [ChangesetToFileContent], repo: repo
   Walking node types [Changeset, FileContent], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children Changeset:2,* FileContent:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% Changeset:2,* FileContent:2,* (glob)
   Deferred: 0, repo: repo
   Seen,Loaded: 2,2, repo: repo
-  * Type:Walked,Checks,Children Changeset:3,* FileContent:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Changeset:3,* FileContent:3,* (glob)
   Deferred: 0, repo: repo
 
 oldest, bonsai core data, chunked, shallow. For a shallow walk we should see no difference counts OldestFirst vs NewestFirst
@@ -47,10 +47,10 @@ bonsai core data, chunked, deep. Should still visit all changesets, but no bookm
   Walking edge types [ChangesetToBonsaiParent, ChangesetToFileContent], repo: repo
   Walking node types [Changeset, FileContent], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children Changeset:2,* FileContent:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% Changeset:2,* FileContent:2,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 3,3, repo: repo
-  * Type:Walked,Checks,Children Changeset:4,* FileContent:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Changeset:4,* FileContent:3,* (glob)
   Deferred: 0, repo: repo
 
 oldest, bonsai core data, chunked, deep. Should still visit all changesets. Expect no deferred edges as OldestFirst (parents always point to edges already walked)
@@ -65,10 +65,10 @@ hg file content, chunked, deep.  Expect deferred as hg changeset parents will po
   Walking edge types [BonsaiHgMappingToHgChangesetViaBonsai, HgChangesetToHgManifest, HgChangesetToHgParent, HgChangesetViaBonsaiToHgChangeset, HgFileEnvelopeToFileContent, HgManifestToChildHgManifest, HgManifestToHgFileEnvelope], repo: repo
   Walking node types [BonsaiHgMapping, FileContent, HgChangeset, HgChangesetViaBonsai, HgFileEnvelope, HgManifest], repo: repo
   Seen,Loaded: 15,14, repo: repo
-  * Type:Walked,Checks,Children BonsaiHgMapping:2,* FileContent:3,* HgChangeset:2,* HgChangesetViaBonsai:3,* HgFileEnvelope:3,* HgManifest:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% BonsaiHgMapping:2,* FileContent:3,* HgChangeset:2,* HgChangesetViaBonsai:3,* HgFileEnvelope:3,* HgManifest:2,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children BonsaiHgMapping:3,* FileContent:3,* HgChangeset:3,* HgChangesetViaBonsai:4,* HgFileEnvelope:3,* HgManifest:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% BonsaiHgMapping:3,* FileContent:3,* HgChangeset:3,* HgChangesetViaBonsai:4,* HgFileEnvelope:3,* HgManifest:3,* (glob)
   Deferred: 0, repo: repo
 
 oldest, hg file content, chunked, deep.  Expect no deferred edges as OldestFirst
@@ -83,10 +83,10 @@ hg file node, chunked, deep.  Expect deferred as hg file node parents will point
   Walking edge types [BonsaiHgMappingToHgChangesetViaBonsai, HgChangesetToHgManifest, HgChangesetToHgManifestFileNode, HgChangesetViaBonsaiToHgChangeset, HgFileNodeToHgCopyfromFileNode, HgFileNodeToHgParentFileNode, HgManifestFileNodeToHgCopyfromFileNode, HgManifestFileNodeToHgParentFileNode, HgManifestToChildHgManifest, HgManifestToHgFileNode], repo: repo
   Walking node types [BonsaiHgMapping, HgChangeset, HgChangesetViaBonsai, HgFileNode, HgManifest, HgManifestFileNode], repo: repo
   Seen,Loaded: 14,12, repo: repo
-  * Type:Walked,Checks,Children BonsaiHgMapping:2,* HgChangeset:2,* HgChangesetViaBonsai:2,* HgFileNode:3,* HgManifest:2,* HgManifestFileNode:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% BonsaiHgMapping:2,* HgChangeset:2,* HgChangesetViaBonsai:2,* HgFileNode:3,* HgManifest:2,* HgManifestFileNode:3,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 6,6, repo: repo
-  * Type:Walked,Checks,Children BonsaiHgMapping:3,* HgChangeset:3,* HgChangesetViaBonsai:3,* HgFileNode:4,* HgManifest:3,* HgManifestFileNode:4,* (glob)
+  * Type:Walked,Checks,Children,Walked% BonsaiHgMapping:3,* HgChangeset:3,* HgChangesetViaBonsai:3,* HgFileNode:4,* HgManifest:3,* HgManifestFileNode:4,* (glob)
   Deferred: 0, repo: repo
 
 oldest, hg file node, chunked, deep.  Expect deferred as hg file node parents will point outside chunk
@@ -97,10 +97,10 @@ derived changeset_info, chunked, deep
   Walking edge types [ChangesetInfoMappingToChangesetInfo, ChangesetInfoToChangesetInfoParent], repo: repo
   Walking node types [ChangesetInfo, ChangesetInfoMapping], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children ChangesetInfo:2,* ChangesetInfoMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% ChangesetInfo:2,* ChangesetInfoMapping:2,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 3,3, repo: repo
-  * Type:Walked,Checks,Children ChangesetInfo:4,* ChangesetInfoMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% ChangesetInfo:4,* ChangesetInfoMapping:3,* (glob)
   Deferred: 0, repo: repo
 
 derived deleted_manifest, chunked, deep.  No deferred as there is no parent lookup in the walk
@@ -119,10 +119,10 @@ derived fsnodes, chunked, deep.  No deferred as there is no parent lookup in the
   Walking edge types [FsnodeMappingToRootFsnode, FsnodeToChildFsnode], repo: repo
   Walking node types [Fsnode, FsnodeMapping], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children Fsnode:2,* FsnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% Fsnode:2,* FsnodeMapping:2,* (glob)
   Deferred: 0, repo: repo
   Seen,Loaded: 2,2, repo: repo
-  * Type:Walked,Checks,Children Fsnode:3,* FsnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Fsnode:3,* FsnodeMapping:3,* (glob)
   Deferred: 0, repo: repo
 
 derived skeleton_manifests, chunked, deep.  No deferred as there is no parent lookup in the walk
@@ -130,10 +130,10 @@ derived skeleton_manifests, chunked, deep.  No deferred as there is no parent lo
   Walking edge types [SkeletonManifestMappingToRootSkeletonManifest, SkeletonManifestToSkeletonManifestChild], repo: repo
   Walking node types [SkeletonManifest, SkeletonManifestMapping], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children SkeletonManifest:2,* SkeletonManifestMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% SkeletonManifest:2,* SkeletonManifestMapping:2,* (glob)
   Deferred: 0, repo: repo
   Seen,Loaded: 2,2, repo: repo
-  * Type:Walked,Checks,Children SkeletonManifest:3,* SkeletonManifestMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% SkeletonManifest:3,* SkeletonManifestMapping:3,* (glob)
   Deferred: 0, repo: repo
 
 derived unodes, chunked, deep. Expect deferred as unode parent will attempt to step outside chunk
@@ -143,11 +143,11 @@ derived unodes, chunked, deep. Expect deferred as unode parent will attempt to s
   Repo bounds: (1, 4), repo: repo
   Starting chunk 1 with bounds (2, 4), repo: repo
   Seen,Loaded: 8,6, repo: repo
-  * Type:Walked,Checks,Children UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
   Deferred: 1, repo: repo
   Starting chunk 2 with bounds (1, 2), repo: repo
   Seen,Loaded: 3,3, repo: repo
-  * Type:Walked,Checks,Children UnodeFile:4,* UnodeManifest:4,* UnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% UnodeFile:4,* UnodeManifest:4,* UnodeMapping:3,* (glob)
   Deferred: 0, repo: repo
   Completed in 2 chunks of size 2, repo: repo
 
@@ -158,7 +158,7 @@ walk with explicit repo bounds, e.g. to reproduce an issue in chunk with bounds
   Repo bounds: (2, 4), repo: repo
   Starting chunk 1 with bounds (2, 4), repo: repo
   Seen,Loaded: 8,6, repo: repo
-  * Type:Walked,Checks,Children UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
   Deferred: 1, repo: repo
   Deferred edge counts by type were: UnodeManifestToUnodeFileChild:1 UnodeManifestToUnodeManifestParent:1, repo: repo
   Completed in 1 chunks of size 2, repo: repo
@@ -170,12 +170,12 @@ derived unodes, chunked, deep with clearing between chunks. Expect more reloaded
   Repo bounds: (1, 4), repo: repo
   Starting chunk 1 with bounds (2, 4), repo: repo
   Seen,Loaded: 8,6, repo: repo
-  * Type:Walked,Checks,Children UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% UnodeFile:3,* UnodeManifest:3,* UnodeMapping:2,* (glob)
   Deferred: 1, repo: repo
   Clearing state after chunk 1, repo: repo
   Starting chunk 2 with bounds (1, 2), repo: repo
   Seen,Loaded: 5,5, repo: repo
-  * Type:Walked,Checks,Children UnodeFile:5,* UnodeManifest:5,* UnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% UnodeFile:5,* UnodeManifest:5,* UnodeMapping:3,* (glob)
   Deferred: 0, repo: repo
   Clearing state after chunk 2, repo: repo
   Completed in 2 chunks of size 2, repo: repo
@@ -185,10 +185,10 @@ derived unodes blame, chunked, deep. Expect deferred as blame entry will attempt
   Walking edge types [UnodeFileToBlame, UnodeManifestToUnodeFileChild, UnodeManifestToUnodeManifestChild, UnodeMappingToRootUnodeManifest], repo: repo
   Walking node types [Blame, UnodeFile, UnodeManifest, UnodeMapping], repo: repo
   Seen,Loaded: 9,8, repo: repo
-  * Type:Walked,Checks,Children Blame:2,* UnodeFile:3,* UnodeManifest:2,* UnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% Blame:2,* UnodeFile:3,* UnodeManifest:2,* UnodeMapping:2,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children Blame:3,* UnodeFile:4,* UnodeManifest:3,* UnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Blame:3,* UnodeFile:4,* UnodeManifest:3,* UnodeMapping:3,* (glob)
   Deferred: 0, repo: repo
 
 derived unodes fastlog, chunked, deep. Expect deferred as fastlog entry will attempt to step outside chunk
@@ -196,8 +196,8 @@ derived unodes fastlog, chunked, deep. Expect deferred as fastlog entry will att
   Walking edge types [FastlogBatchToPreviousBatch, FastlogDirToPreviousBatch, FastlogFileToPreviousBatch, UnodeFileToFastlogFile, UnodeManifestToFastlogDir, UnodeManifestToUnodeFileChild, UnodeManifestToUnodeManifestChild, UnodeMappingToRootUnodeManifest], repo: repo
   Walking node types [FastlogBatch, FastlogDir, FastlogFile, UnodeFile, UnodeManifest, UnodeMapping], repo: repo
   Seen,Loaded: 11,10, repo: repo
-  * Type:Walked,Checks,Children FastlogBatch:0,* FastlogDir:2,* FastlogFile:2,* UnodeFile:3,* UnodeManifest:2,* UnodeMapping:2,* (glob)
+  * Type:Walked,Checks,Children,Walked% FastlogBatch:0,* FastlogDir:2,* FastlogFile:2,* UnodeFile:3,* UnodeManifest:2,* UnodeMapping:2,* (glob)
   Deferred: 1, repo: repo
   Seen,Loaded: 5,5, repo: repo
-  * Type:Walked,Checks,Children FastlogBatch:0,* FastlogDir:3,* FastlogFile:3,* UnodeFile:4,* UnodeManifest:3,* UnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% FastlogBatch:0,* FastlogDir:3,* FastlogFile:3,* UnodeFile:4,* UnodeManifest:3,* UnodeMapping:3,* (glob)
   Deferred: 0, repo: repo
@@ -64,7 +64,7 @@ Output pretty debug to stdout
       ),
   )
   Seen,Loaded: 2,2, repo: repo
-  * Type:Walked,Checks,Children Bookmark:1,1,2 Changeset:1,1,0, repo: repo (glob)
+  * Type:Walked,Checks,Children,Walked% Bookmark:1,1,2,50.0% Changeset:1,1,0,50.0%, * (glob)
 
 Output non-pretty debug to stdout
   $ mononoke_walker -L sizing scrub -q -b master_bookmark -I shallow -i bonsai --include-output-node-type=Changeset --output-format=Debug 2>&1 | strip_glog
@@ -72,4 +72,4 @@ Output non-pretty debug to stdout
   Walking node types [Bookmark, Changeset], repo: repo
   Node Changeset(ChangesetKey { inner: ChangesetId(Blake2(c3384961b16276f2db77df9d7c874bbe981cf0525bd6f84a502f919044f2dabd)), filenode_known_derived: false }): NodeData: Some(Changeset(BonsaiChangeset { inner: BonsaiChangesetMut { parents: [ChangesetId(Blake2(459f16ae564c501cb408c1e5b60fc98a1e8b8e97b9409c7520658bfa1577fb66))], author: "test", author_date: DateTime(1970-01-01T00:00:00+00:00), committer: None, committer_date: None, message: "C", hg_extra: {}, git_extra_headers: None, file_changes: {MPath("C"): Change(TrackedFileChange { inner: BasicFileChange { content_id: ContentId(Blake2(896ad5879a5df0403bfc93fc96507ad9c93b31b11f3d0fa05445da7918241e5d)), file_type: Regular, size: 1 }, copy_from: None })}, is_snapshot: false, git_tree_hash: None, git_annotated_tag: None }, id: ChangesetId(Blake2(c3384961b16276f2db77df9d7c874bbe981cf0525bd6f84a502f919044f2dabd)) }))
   Seen,Loaded: 2,2, repo: repo
-  * Type:Walked,Checks,Children Bookmark:1,1,2 Changeset:1,1,0, repo: repo (glob)
+  * Type:Walked,Checks,Children,Walked% Bookmark:1,1,2,50.0% Changeset:1,1,0,50.0%, * (glob)
@@ -54,35 +54,35 @@ Check we can walk blame on a public commit. In this walk all the Changeset histo
   Walking edge types [BlameToChangeset, ChangesetToUnodeMapping, HgBonsaiMappingToChangeset, UnodeFileToBlame, UnodeFileToUnodeFileParent, UnodeManifestToUnodeFileChild, UnodeManifestToUnodeManifestChild, UnodeManifestToUnodeManifestParent, UnodeMappingToRootUnodeManifest], repo: repo
   Walking node types [Blame, Changeset, HgBonsaiMapping, UnodeFile, UnodeManifest, UnodeMapping], repo: repo
   Seen,Loaded: 16,16, repo: repo
-  * Type:Walked,Checks,Children Blame:3,* Changeset:3,* HgBonsaiMapping:1,* UnodeFile:3,* UnodeManifest:3,* UnodeMapping:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% Blame:3,* Changeset:3,* HgBonsaiMapping:1,* UnodeFile:3,* UnodeManifest:3,* UnodeMapping:3,* (glob)
 
 Check we dont walk blame on a non-public commit.  Because blame is the only path to Changeset history, this results in a shallow walk
   $ mononoke_walker -L sizing scrub -q --walk-root=HgBonsaiMapping:${HGCOMMITCNEW} -I deep -i bonsai -i derived_unodes -i derived_blame -i HgBonsaiMapping -X ChangesetToBonsaiParent -X UnodeFileToLinkedChangeset -X UnodeManifestToLinkedChangeset 2>&1 | strip_glog
   Walking edge types [BlameToChangeset, ChangesetToUnodeMapping, HgBonsaiMappingToChangeset, UnodeFileToBlame, UnodeFileToUnodeFileParent, UnodeManifestToUnodeFileChild, UnodeManifestToUnodeManifestChild, UnodeManifestToUnodeManifestParent, UnodeMappingToRootUnodeManifest], repo: repo
   Walking node types [Blame, Changeset, HgBonsaiMapping, UnodeFile, UnodeManifest, UnodeMapping], repo: repo
   Seen,Loaded: 5,5, repo: repo
-  * Type:Walked,Checks,Children Blame:0,* Changeset:1,* HgBonsaiMapping:1,* UnodeFile:1,* UnodeManifest:1,* UnodeMapping:1,* (glob)
+  * Type:Walked,Checks,Children,Walked% Blame:0,* Changeset:1,* HgBonsaiMapping:1,* UnodeFile:1,* UnodeManifest:1,* UnodeMapping:1,* (glob)
 
 Check we can walk filenodes on a public commit. In this walk all the HgChangeset history steps come from filenodes as we exclude HgChangesetToHgParent etc
   $ mononoke_walker -L sizing scrub -q --walk-root=HgChangesetViaBonsai:${HGCOMMITC} -I deep -x HgBonsaiMapping -i derived_filenodes -i derived_hgchangesets -x HgManifestFileNode -X HgChangesetToHgParent 2>&1 | strip_glog
   Walking edge types [HgChangesetToHgManifest, HgChangesetViaBonsaiToHgChangeset, HgFileNodeToHgCopyfromFileNode, HgFileNodeToHgParentFileNode, HgFileNodeToLinkedHgChangeset, HgManifestToChildHgManifest, HgManifestToHgFileEnvelope, HgManifestToHgFileNode], repo: repo
   Walking node types [HgChangeset, HgChangesetViaBonsai, HgFileEnvelope, HgFileNode, HgManifest], repo: repo
   Seen,Loaded: 20,20, repo: repo
-  * Type:Walked,Checks,Children HgChangeset:4,* HgChangesetViaBonsai:4,* HgFileEnvelope:4,* HgFileNode:4,* HgManifest:4,* (glob)
+  * Type:Walked,Checks,Children,Walked% HgChangeset:4,* HgChangesetViaBonsai:4,* HgFileEnvelope:4,* HgFileNode:4,* HgManifest:4,* (glob)
 
 Check we can walk manifest filenodes on a public commit. In this walk all the HgChangeset history steps come from mf filenodes as we exclude HgChangesetToHgParent etc
   $ mononoke_walker -L sizing scrub -q --walk-root=HgChangesetViaBonsai:${HGCOMMITC} -I deep -x HgBonsaiMapping -i derived_filenodes -i derived_hgchangesets -x HgFileNode -X HgChangesetToHgParent 2>&1 | strip_glog
   Walking edge types [HgChangesetToHgManifest, HgChangesetToHgManifestFileNode, HgChangesetViaBonsaiToHgChangeset, HgManifestFileNodeToHgCopyfromFileNode, HgManifestFileNodeToHgParentFileNode, HgManifestFileNodeToLinkedHgChangeset, HgManifestToChildHgManifest, HgManifestToHgFileEnvelope], repo: repo
   Walking node types [HgChangeset, HgChangesetViaBonsai, HgFileEnvelope, HgManifest, HgManifestFileNode], repo: repo
   Seen,Loaded: 15,15, repo: repo
-  * Type:Walked,Checks,Children HgChangeset:3,* HgChangesetViaBonsai:3,* HgFileEnvelope:3,* HgManifest:3,* HgManifestFileNode:3,* (glob)
+  * Type:Walked,Checks,Children,Walked% HgChangeset:3,* HgChangesetViaBonsai:3,* HgFileEnvelope:3,* HgManifest:3,* HgManifestFileNode:3,* (glob)
 
 Check we dont walk filenodes on a non-public commit.  Because filenodes is the only path to HgChangeset history, this results in a shallow walk
   $ mononoke_walker -L sizing scrub -q --walk-root=HgChangeset:${HGCOMMITCNEW} -I deep -x HgBonsaiMapping -i derived_filenodes -i derived_hgchangesets -X HgChangesetToHgParent 2>&1 | strip_glog
   Walking edge types [HgChangesetToHgManifest, HgChangesetToHgManifestFileNode, HgChangesetViaBonsaiToHgChangeset, HgFileNodeToHgCopyfromFileNode, HgFileNodeToHgParentFileNode, HgFileNodeToLinkedHgChangeset, HgManifestFileNodeToHgCopyfromFileNode, HgManifestFileNodeToHgParentFileNode, HgManifestFileNodeToLinkedHgChangeset, HgManifestToChildHgManifest, HgManifestToHgFileEnvelope, HgManifestToHgFileNode], repo: repo
   Walking node types [HgChangeset, HgChangesetViaBonsai, HgFileEnvelope, HgFileNode, HgManifest, HgManifestFileNode], repo: repo
   Seen,Loaded: 4,4, repo: repo
-  * Type:Walked,Checks,Children HgChangeset:1,* HgFileEnvelope:1,* HgFileNode:1,* HgManifest:1,* (glob)
+  * Type:Walked,Checks,Children,Walked% HgChangeset:1,* HgFileEnvelope:1,* HgFileNode:1,* HgManifest:1,* (glob)
 
 validate, expect failures on phase info, and linknode as we now point to a non-public commit
   $ mononoke_walker --scuba-dataset file://scuba.json -l validate validate -q -I deep -I marker -b master_bookmark 2>&1 | strip_glog  | grep 'Validation failed:' | sed 's/.*"check_type":"\([^"]*\)".*/\1/' | sort
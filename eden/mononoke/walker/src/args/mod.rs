@@ -16,6 +16,9 @@ pub mod validate;
 pub mod walk_params;
 pub mod walk_root;
 
+use std::time::Duration;
+
+use anyhow::Error;
 use clap::Args;
 pub use graph_arg_types::NodeTypeArg;
 pub use hash_validation::HashValidationArgs;
@@ -34,6 +37,11 @@ pub use walk_params::WalkerGraphArgs;
 pub use walk_params::WalkerGraphParams;
 pub use walk_root::WalkRootArgs;
 
+use crate::detail::concurrency::AdaptiveConcurrencyOptions;
+
+// Progress reports in a row that must agree before adaptive concurrency changes
+const ADAPTIVE_CONCURRENCY_CONSECUTIVE: usize = 2;
+
 #[derive(Args, Debug)]
 pub struct WalkerCommonArgs {
     /// Log a lot less
@@ -48,6 +56,28 @@ pub struct WalkerCommonArgs {
     /// Enable derivation of data (e.g. hg, file metadata).
     #[clap(long)]
     pub enable_derive: bool,
+    /// Times to retry a walk step that fails with a transient error, e.g. a
+    /// blobstore timeout, before treating it as failed. Retries are counted
+    /// in progress.
+    #[clap(long, default_value_t = 0)]
+    pub step_retries: u32,
+    /// Always run up to scheduled_max steps at once, rather than fewer while
+    /// steps are erroring or slow.
+    #[clap(long)]
+    pub disable_adaptive_concurrency: bool,
+    /// Fewest steps adaptive concurrency runs at once. Defaults to a tenth of
+    /// scheduled_max.
+    #[clap(long)]
+    pub adaptive_concurrency_min: Option<usize>,
+    /// Percentage of steps erroring between progress reports above which
+    /// adaptive concurrency halves the steps run at once. Under a fifth of
+    /// it, they are increased again.
+    #[clap(long, default_value_t = 5.0)]
+    pub adaptive_concurrency_error_pct: f64,
+    /// 95th percentile step duration in milliseconds above which adaptive
+    /// concurrency halves the steps run at once. Not checked if unset.
+    #[clap(long)]
+    pub adaptive_concurrency_max_latency_ms: Option<u64>,
     /// Limit the amount of data fetched from stores, by not streaming
     /// large files to the end. Only used by `scrub` subcommand.
     #[clap(long)]
@@ -76,6 +106,34 @@ pub struct WalkerCommonArgs {
     pub tailing: TailArgs,
 }
 
+impl WalkerCommonArgs {
+    /// Adaptive concurrency for a walk of up to scheduled_max steps at once, None if disabled
+    pub fn adaptive_concurrency(
+        &self,
+        scheduled_max: usize,
+    ) -> Result<Option<AdaptiveConcurrencyOptions>, Error> {
+        if self.disable_adaptive_concurrency {
+            return Ok(None);
+        }
+        let error_rate_high = self.adaptive_concurrency_error_pct / 100.0;
+        let options = AdaptiveConcurrencyOptions {
+            min: self
+                .adaptive_concurrency_min
+                .unwrap_or(scheduled_max / 10)
+                .clamp(1, scheduled_max.max(1)),
+            max: scheduled_max.max(1),
+            error_rate_high,
+            error_rate_low: error_rate_high / 5.0,
+            latency_high: self
+                .adaptive_concurrency_max_latency_ms
+                .map(Duration::from_millis),
+            consecutive: ADAPTIVE_CONCURRENCY_CONSECUTIVE,
+        };
+        options.validate()?;
+        Ok(Some(options))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, AsRefStr, EnumVariantNames, EnumString)]
 pub enum OutputFormat {
     Debug,
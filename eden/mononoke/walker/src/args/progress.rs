@@ -5,28 +5,371 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
+use anyhow::format_err;
+use anyhow::Context;
+use anyhow::Error;
 use clap::Args;
+use clap::ValueEnum;
 
+use crate::args::graph_arg_types::NodeTypeArg;
+use crate::detail::graph::NodeType;
+use crate::detail::jsonl::FsyncPolicy;
+use crate::detail::jsonl::JsonlOptions;
+use crate::detail::progress::auto_walk_instance;
+use crate::detail::progress::NumberFormat;
+use crate::detail::progress::PayloadSizeOptions;
+use crate::detail::progress::ProgressDisplay;
 use crate::detail::progress::ProgressOptions;
+use crate::detail::progress::ProgressOptionsBuilder;
+use crate::detail::progress::QuietMode;
+use crate::detail::progress::SlowdownDetection;
+use crate::detail::progress::TypeGrouping;
+use crate::detail::progress::UnchangedProgress;
+use crate::detail::report::BaselineThresholds;
+use crate::detail::report::FinalReportOptions;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressDisplayArg {
+    /// Progress bar if stderr is a terminal, otherwise log lines.
+    Auto,
+    Bar,
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressQuietArg {
+    /// Periodic progress, heartbeats and the final summary.
+    Full,
+    /// Only the final summary.
+    FinalOnly,
+    /// Every Nth periodic report, see --progress-every-nth, and the final summary.
+    EveryNth,
+    /// No progress output at all.
+    Silent,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressTypeGroupingArg {
+    /// Detail and stats per node type.
+    Types,
+    /// Detail and stats per node type group, e.g. hg or derived data.
+    Groups,
+    /// Both per node type and per group.
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressUnchangedArg {
+    /// The full progress line.
+    Log,
+    /// A short line saying how long there has been no progress.
+    Compact,
+    /// No line. Stats are still emitted.
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressNumbersArg {
+    /// Human readable if stderr is a terminal, otherwise raw.
+    Auto,
+    /// Counts like 18.2M and durations like 1d 2h.
+    Human,
+    /// Plain integers and seconds.
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressJsonlFsyncArg {
+    /// Leave it to the OS.
+    Never,
+    /// After every report, so none are lost on a crash.
+    EveryReport,
+    /// Only before rotating a full file.
+    OnRotate,
+}
+
+fn parse_error_budget(arg: &str) -> Result<(NodeType, u64), Error> {
+    let (node_type, budget) = arg
+        .split_once('=')
+        .ok_or_else(|| format_err!("Expected TYPE=COUNT for error budget, got {}", arg))?;
+    let node_type = NodeType::from_str(node_type)
+        .with_context(|| format_err!("Unknown NodeType {}", node_type))?;
+    let budget = budget
+        .parse()
+        .with_context(|| format_err!("Bad error budget count in {}", arg))?;
+    Ok((node_type, budget))
+}
 
 #[derive(Args, Debug)]
 pub struct ProgressArgs {
     /// Minimum interval between progress reports in seconds.
     #[clap(long, default_value_t = 5)]
     pub progress_interval: u64,
+    /// Adapt the interval between progress reports to log at most this many
+    /// lines an hour, never reporting more often than --progress-interval.
+    #[clap(long)]
+    pub progress_max_lines_per_hour: Option<u64>,
+    /// Flag node types that have not been walked for this many seconds in
+    /// progress reports, e.g. to spot a starved part of the walk.
+    #[clap(long)]
+    pub progress_idle_type_secs: Option<u64>,
+    /// Call out the slowest walk worker in progress reports when it has been inactive
+    /// this many seconds longer than the median worker.
+    #[clap(long)]
+    pub progress_worker_skew_secs: Option<u64>,
+    /// Make progress reports on wall clock multiples of --progress-interval, e.g. on
+    /// the minute for an interval of 60, to line up with other per-minute dashboards.
+    #[clap(long)]
+    pub progress_align_to_wall_clock: bool,
+    /// Warn when the walk rate is this many percent below the median of recent
+    /// progress reports for several reports in a row.
+    #[clap(long)]
+    pub progress_slowdown_pct: Option<f64>,
+    /// Number of recent progress reports to take the median rate of.
+    #[clap(long, default_value_t = 10)]
+    pub progress_slowdown_window: usize,
+    /// Consecutive slow progress reports before warning of a slowdown.
+    #[clap(long, default_value_t = 3)]
+    pub progress_slowdown_reports: usize,
+    /// Seconds from the start of the walk not to check for slowdowns, while it
+    /// ramps up.
+    #[clap(long, default_value_t = 300)]
+    pub progress_slowdown_warm_up_secs: u64,
+    /// Upper bounds in bytes of the payload size histogram buckets, e.g. of file
+    /// content sizes. Defaults to powers of ten from 1000 to 10^9.
+    #[clap(long, value_delimiter = ',')]
+    pub progress_payload_buckets: Vec<u64>,
+    /// Node types to also keep a payload size histogram of their own for.
+    #[clap(long)]
+    pub progress_payload_node_type: Vec<NodeTypeArg>,
+    /// Node types to also report on their own, every
+    /// --progress-interest-interval-secs, in a compact line alongside the main
+    /// progress reports. Can be repeated.
+    #[clap(long)]
+    pub progress_interest_node_type: Vec<NodeTypeArg>,
+    /// Seconds between reports of --progress-interest-node-type.
+    #[clap(long, default_value_t = 5)]
+    pub progress_interest_interval_secs: u64,
+    /// Label for this walk's progress log lines, Scuba rows and stats keys, to
+    /// tell apart several walks run concurrently in one process. Use "auto" for a
+    /// random label.
+    #[clap(long)]
+    pub progress_walk_instance: Option<String>,
+    /// Append every progress report to this file as a line of JSON, rotating it
+    /// by size. Write failures are warned about and don't stop the walk.
+    #[clap(long)]
+    pub progress_jsonl_file: Option<PathBuf>,
+    /// Rotate the progress JSON lines file once it would go over this many MiB.
+    #[clap(long, default_value_t = 100, requires = "progress_jsonl_file")]
+    pub progress_jsonl_max_mb: u64,
+    /// Rotated progress JSON lines files to keep, as FILE.1 up to FILE.N.
+    #[clap(long, default_value_t = 5, requires = "progress_jsonl_file")]
+    pub progress_jsonl_keep: usize,
+    /// When to fsync the progress JSON lines file.
+    #[clap(long, value_enum, default_value_t = ProgressJsonlFsyncArg::OnRotate, requires = "progress_jsonl_file")]
+    pub progress_jsonl_fsync: ProgressJsonlFsyncArg,
     /// Sample the walk output stream for progress roughly 1 in N steps.
     /// Only log if progress-interval has passed.
     #[clap(long, default_value_t = 100)]
     pub progress_sample_rate: u64,
+    /// How to display progress. The final summary is always logged.
+    #[clap(long, value_enum, default_value_t = ProgressDisplayArg::Auto)]
+    pub progress_display: ProgressDisplayArg,
+    /// Which progress reports to output. --quiet implies final-only
+    /// unless set otherwise.
+    #[clap(long, value_enum, default_value_t = ProgressQuietArg::Full)]
+    pub progress_quiet: ProgressQuietArg,
+    /// Output one in N periodic progress reports with --progress-quiet=every-nth.
+    #[clap(long, default_value_t = 10)]
+    pub progress_every_nth: u64,
+    /// Leave per node type rates out of progress reports, for shorter lines.
+    #[clap(long)]
+    pub progress_no_type_rates: bool,
+    /// Emit per node type stats and Scuba rows for types that have not changed
+    /// only every Nth progress report. Changed types are always emitted.
+    #[clap(long, default_value_t = 10)]
+    pub progress_type_emit_every_nth: u64,
+    /// Whether progress detail and stats are per node type or per group of node
+    /// types. Group stats are always emitted.
+    #[clap(long, value_enum, default_value_t = ProgressTypeGroupingArg::Types)]
+    pub progress_type_grouping: ProgressTypeGroupingArg,
+    /// How to log periodic progress reports where nothing changed since the
+    /// last one.
+    #[clap(long, value_enum, default_value_t = ProgressUnchangedArg::Compact)]
+    pub progress_unchanged: ProgressUnchangedArg,
+    /// Show the longest running step in progress reports once it has been
+    /// running for this many seconds.
+    #[clap(long, default_value_t = 60)]
+    pub progress_stuck_step_secs: u64,
+    /// Errors allowed for a node type before the run is marked failed, as
+    /// TYPE=COUNT. The walk still completes. Can be repeated.
+    #[clap(long, value_parser = parse_error_budget)]
+    pub progress_error_budget: Vec<(NodeType, u64)>,
+    /// Log every Nth node walked of each type at debug level, to see concrete
+    /// nodes going through the walk. 0 for none.
+    #[clap(long, default_value_t = 0)]
+    pub progress_sample_node_every_nth: u64,
+    /// How to write counts and durations in progress lines. Structured log
+    /// fields, stats and summary files always have raw numbers.
+    #[clap(long, value_enum, default_value_t = ProgressNumbersArg::Auto)]
+    pub progress_numbers: ProgressNumbersArg,
+    /// Included node types that are not warned about if the walk never reaches
+    /// them, e.g. derived data that may not have been derived.
+    #[clap(long)]
+    pub progress_allow_unwalked_node_type: Vec<NodeTypeArg>,
+    /// Node types to walk without counting in progress, e.g. chatty types only
+    /// walked to reach others. Their steps are reported as a single total.
+    #[clap(long)]
+    pub progress_exclude_node_type: Vec<NodeTypeArg>,
+    /// Save a JSON summary of the walk per node type when it ends, replacing the
+    /// file atomically. If the walk fails it is still saved, with completed false.
+    #[clap(long)]
+    pub progress_summary_file: Option<PathBuf>,
+    /// Compare the walk against a summary file saved by a previous run.
+    /// A missing or unreadable baseline is only warned about.
+    #[clap(long)]
+    pub progress_baseline_file: Option<PathBuf>,
+    /// Fail the run if any node type's walked count drops by more than this
+    /// percentage compared to the baseline.
+    #[clap(long, requires = "progress_baseline_file")]
+    pub baseline_max_walked_drop_pct: Option<f64>,
+    /// Fail the run if any node type's walk rate drops by more than this
+    /// percentage compared to the baseline.
+    #[clap(long, requires = "progress_baseline_file")]
+    pub baseline_max_rate_drop_pct: Option<f64>,
+    /// Fail the run if any node type's errors increase by more than this
+    /// compared to the baseline.
+    #[clap(long, requires = "progress_baseline_file")]
+    pub baseline_max_errors_increase: Option<u64>,
+    /// Unix socket to serve the walk's progress on as JSON, for querying a
+    /// running walk. Any existing file at the path is replaced. Not used when sharded.
+    #[clap(long)]
+    pub progress_admin_socket: Option<PathBuf>,
 }
 
 impl ProgressArgs {
-    pub fn parse_args(&self) -> ProgressOptions {
-        ProgressOptions {
-            sample_rate: self.progress_sample_rate,
-            interval: Duration::from_secs(self.progress_interval),
+    pub fn parse_args(&self, quiet: bool) -> Result<ProgressOptions, Error> {
+        let builder = ProgressOptionsBuilder::default()
+            .with_sample_rate(self.progress_sample_rate)
+            .with_interval(Duration::from_secs(self.progress_interval))
+            .with_display(match self.progress_display {
+                ProgressDisplayArg::Auto if std::io::stderr().is_terminal() => ProgressDisplay::Bar,
+                ProgressDisplayArg::Auto | ProgressDisplayArg::Log => ProgressDisplay::Log,
+                ProgressDisplayArg::Bar => ProgressDisplay::Bar,
+            })
+            .with_quiet(match self.progress_quiet {
+                ProgressQuietArg::Full if quiet => QuietMode::FinalOnly,
+                ProgressQuietArg::Full => QuietMode::Full,
+                ProgressQuietArg::FinalOnly => QuietMode::FinalOnly,
+                ProgressQuietArg::EveryNth => QuietMode::EveryNth(self.progress_every_nth),
+                ProgressQuietArg::Silent => QuietMode::Silent,
+            })
+            .with_type_rates(!self.progress_no_type_rates)
+            .with_type_emit_every_nth(self.progress_type_emit_every_nth)
+            .with_type_grouping(match self.progress_type_grouping {
+                ProgressTypeGroupingArg::Types => TypeGrouping::Types,
+                ProgressTypeGroupingArg::Groups => TypeGrouping::Groups,
+                ProgressTypeGroupingArg::Both => TypeGrouping::Both,
+            })
+            .with_stuck_step_threshold(Duration::from_secs(self.progress_stuck_step_secs))
+            .with_error_budgets(self.progress_error_budget.iter().cloned().collect())
+            .with_sample_node_every_nth(self.progress_sample_node_every_nth)
+            .with_wall_clock_aligned(self.progress_align_to_wall_clock)
+            .with_payload_sizes(self.parse_payload_sizes())
+            .with_types_of_interest(
+                NodeTypeArg::parse_args(&self.progress_interest_node_type),
+                Duration::from_secs(self.progress_interest_interval_secs),
+            )
+            .with_number_format(match self.progress_numbers {
+                ProgressNumbersArg::Auto if std::io::stderr().is_terminal() => NumberFormat::Human,
+                ProgressNumbersArg::Auto | ProgressNumbersArg::Raw => NumberFormat::Raw,
+                ProgressNumbersArg::Human => NumberFormat::Human,
+            })
+            .with_unchanged(match self.progress_unchanged {
+                ProgressUnchangedArg::Log => UnchangedProgress::Log,
+                ProgressUnchangedArg::Compact => UnchangedProgress::Compact,
+                ProgressUnchangedArg::Skip => UnchangedProgress::Skip,
+            });
+        let builder = match self.progress_max_lines_per_hour {
+            Some(max_lines) => builder.with_max_lines_per_hour(max_lines),
+            None => builder,
+        };
+        let builder = match self.progress_slowdown_pct {
+            Some(drop_pct) => builder.with_slowdown(SlowdownDetection {
+                window: self.progress_slowdown_window,
+                drop_pct,
+                consecutive: self.progress_slowdown_reports,
+                warm_up: Duration::from_secs(self.progress_slowdown_warm_up_secs),
+            }),
+            None => builder,
+        };
+        let builder = match &self.progress_jsonl_file {
+            Some(path) => builder.with_jsonl(JsonlOptions {
+                path: path.clone(),
+                max_bytes: self.progress_jsonl_max_mb * 1024 * 1024,
+                keep: self.progress_jsonl_keep,
+                fsync: match self.progress_jsonl_fsync {
+                    ProgressJsonlFsyncArg::Never => FsyncPolicy::Never,
+                    ProgressJsonlFsyncArg::EveryReport => FsyncPolicy::EveryReport,
+                    ProgressJsonlFsyncArg::OnRotate => FsyncPolicy::OnRotate,
+                },
+            }),
+            None => builder,
+        };
+        let builder = match self.progress_idle_type_secs {
+            Some(secs) => builder.with_idle_type_threshold(Duration::from_secs(secs)),
+            None => builder,
+        };
+        match self.progress_worker_skew_secs {
+            Some(secs) => builder.with_worker_skew_threshold(Duration::from_secs(secs)),
+            None => builder,
+        }
+        .build()
+    }
+
+    fn parse_payload_sizes(&self) -> PayloadSizeOptions {
+        let mut payload_sizes = PayloadSizeOptions {
+            types: NodeTypeArg::parse_args(&self.progress_payload_node_type),
+            ..Default::default()
+        };
+        if !self.progress_payload_buckets.is_empty() {
+            payload_sizes.bounds = self.progress_payload_buckets.clone();
+        }
+        payload_sizes
+    }
+
+    /// Empty if there is no walk instance label
+    pub fn parse_walk_instance(&self) -> String {
+        match self.progress_walk_instance.as_deref() {
+            Some("auto") => auto_walk_instance(),
+            Some(walk_instance) => walk_instance.to_string(),
+            None => String::new(),
+        }
+    }
+
+    pub fn parse_unwalked_ok_types(&self) -> HashSet<NodeType> {
+        NodeTypeArg::parse_args(&self.progress_allow_unwalked_node_type)
+    }
+
+    pub fn parse_excluded_types(&self) -> HashSet<NodeType> {
+        NodeTypeArg::parse_args(&self.progress_exclude_node_type)
+    }
+
+    pub fn parse_report_args(&self) -> FinalReportOptions {
+        FinalReportOptions {
+            summary_file: self.progress_summary_file.clone(),
+            baseline_file: self.progress_baseline_file.clone(),
+            thresholds: BaselineThresholds {
+                max_walked_drop_pct: self.baseline_max_walked_drop_pct,
+                max_rate_drop_pct: self.baseline_max_rate_drop_pct,
+                max_errors_increase: self.baseline_max_errors_increase,
+            },
         }
     }
 }
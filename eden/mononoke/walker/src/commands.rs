@@ -6,7 +6,10 @@
  */
 
 use std::collections::HashSet;
+use std::sync::Arc;
 
+use crate::detail::blobstore::BlobstoreReadCounts;
+use crate::detail::blobstore::ScrubRepairCounts;
 use crate::detail::graph::EdgeType;
 use crate::detail::graph::NodeType;
 use crate::detail::progress::ProgressStateCountByType;
@@ -37,6 +40,10 @@ pub struct JobWalkParams {
     pub error_as_data_node_types: HashSet<NodeType>,
     pub error_as_data_edge_types: HashSet<EdgeType>,
     pub repo_count: usize,
+    // Times to retry a step that failed with a transient error
+    pub step_retries: u32,
+    pub scrub_repair_counts: Arc<ScrubRepairCounts>,
+    pub blobstore_read_counts: Arc<BlobstoreReadCounts>,
 }
 
 #[derive(Clone)]
@@ -45,9 +52,22 @@ pub struct JobParams {
     pub per_repo: Vec<(RepoSubcommandParams, RepoWalkParams)>,
 }
 
+impl JobParams {
+    /// Handles on each repo's walk progress, for reporting once the walk is done
+    pub fn progress_states(
+        &self,
+    ) -> Vec<ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>>> {
+        self.per_repo
+            .iter()
+            .map(|(sub_params, _)| sub_params.progress_state.clone())
+            .collect()
+    }
+}
+
 mononoke_app::subcommands! {
     mod compression_benefit;
     mod corpus;
+    mod progress_diff;
     mod scrub;
     mod validate;
 }
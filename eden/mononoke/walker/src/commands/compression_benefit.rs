@@ -28,7 +28,10 @@ use crate::args::SamplingArgs;
 use crate::args::WalkerCommonArgs;
 use crate::commands::JobParams;
 use crate::commands::COMPRESSION_BENEFIT;
+use crate::detail::admin::AdminListener;
+use crate::detail::admin::DumpOnSignal;
 use crate::detail::graph::Node;
+use crate::detail::report::finish_walk;
 use crate::detail::sampling::WalkSampleMapping;
 use crate::detail::sizing::compression_benefit;
 use crate::detail::sizing::SizingCommand;
@@ -183,7 +186,7 @@ async fn setup_sizing(
 
     let command = SizingCommand {
         compression_level: *compression_level,
-        progress_options: common_args.progress.parse_args(),
+        progress_options: common_args.progress.parse_args(common_args.quiet)?,
         sampling_options: sampling.parse_args(100 /* default_sample_rate */)?,
         sampler,
     };
@@ -229,13 +232,28 @@ pub async fn run_unsharded(
     args: CommandArgs,
 ) -> Result<(), Error> {
     let (job_params, command) = setup_sizing(repos, &app, &args).await?;
+    let progress_states = job_params.progress_states();
+    let _admin_listener = args
+        .common_args
+        .progress
+        .progress_admin_socket
+        .as_ref()
+        .map(|path| AdminListener::spawn(app.logger().clone(), path, progress_states.clone()))
+        .transpose()?;
+    let _dump_on_signal = DumpOnSignal::spawn(app.logger().clone(), progress_states.clone())?;
     // When running in unsharded setting, walker sizing doesn't need to
     // be cancelled midway.
-    compression_benefit(
+    let walked = compression_benefit(
         app.fb,
         job_params,
         command,
         Arc::new(AtomicBool::new(false)),
     )
-    .await
+    .await;
+    finish_walk(
+        app.logger(),
+        &args.common_args.progress.parse_report_args(),
+        &progress_states,
+        walked,
+    )
 }
@@ -28,10 +28,13 @@ use crate::args::SamplingArgs;
 use crate::args::WalkerCommonArgs;
 use crate::commands::JobParams;
 use crate::commands::CORPUS;
+use crate::detail::admin::AdminListener;
+use crate::detail::admin::DumpOnSignal;
 use crate::detail::corpus::corpus;
 use crate::detail::corpus::CorpusCommand;
 use crate::detail::corpus::CorpusSample;
 use crate::detail::corpus::CorpusSamplingHandler;
+use crate::detail::report::finish_walk;
 use crate::setup::setup_common;
 use crate::WalkerArgs;
 
@@ -190,7 +193,7 @@ async fn setup_corpus(
 
     let command = CorpusCommand {
         output_dir: output_dir.clone(),
-        progress_options: common_args.progress.parse_args(),
+        progress_options: common_args.progress.parse_args(common_args.quiet)?,
         sampling_options: sampling.parse_args(100 /* default_sample_rate */)?,
         sampling_path_regex: sampling.sample_path_regex.clone(),
         sampler,
@@ -238,13 +241,28 @@ pub async fn run_unsharded(
     args: CommandArgs,
 ) -> Result<(), Error> {
     let (job_params, command) = setup_corpus(repos, &app, &args).await?;
+    let progress_states = job_params.progress_states();
+    let _admin_listener = args
+        .common_args
+        .progress
+        .progress_admin_socket
+        .as_ref()
+        .map(|path| AdminListener::spawn(app.logger().clone(), path, progress_states.clone()))
+        .transpose()?;
+    let _dump_on_signal = DumpOnSignal::spawn(app.logger().clone(), progress_states.clone())?;
     // When running in unsharded setting, walker corpus doesn't need to
     // be cancelled midway.
-    corpus(
+    let walked = corpus(
         app.fb,
         job_params,
         command,
         Arc::new(AtomicBool::new(false)),
     )
-    .await
+    .await;
+    finish_walk(
+        app.logger(),
+        &args.common_args.progress.parse_report_args(),
+        &progress_states,
+        walked,
+    )
 }
@@ -0,0 +1,47 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+use clap::Parser;
+use clap::ValueEnum;
+use mononoke_app::MononokeApp;
+
+use crate::detail::report::diff_reports;
+use crate::detail::report::format_diffs;
+use crate::detail::report::load_reports;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DiffFormat {
+    /// A table per repo.
+    Text,
+    /// The per repo and per type differences as JSON.
+    Json,
+}
+
+/// Compare two final report files saved by --progress-summary-file.
+#[derive(Parser)]
+pub struct CommandArgs {
+    /// Report from the earlier run.
+    pub before: PathBuf,
+
+    /// Report from the later run.
+    pub after: PathBuf,
+
+    #[clap(long, value_enum, default_value_t = DiffFormat::Text)]
+    pub format: DiffFormat,
+}
+
+pub async fn run(_app: MononokeApp, args: CommandArgs) -> Result<(), Error> {
+    let diffs = diff_reports(&load_reports(&args.before)?, &load_reports(&args.after)?);
+    match args.format {
+        DiffFormat::Text => print!("{}", format_diffs(&diffs)),
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diffs)?),
+    }
+    Ok(())
+}
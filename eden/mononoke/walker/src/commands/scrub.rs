@@ -31,7 +31,10 @@ use crate::args::ScrubPackLogArgs;
 use crate::args::WalkerCommonArgs;
 use crate::commands::JobParams;
 use crate::commands::SCRUB;
+use crate::detail::admin::AdminListener;
+use crate::detail::admin::DumpOnSignal;
 use crate::detail::graph::Node;
+use crate::detail::report::finish_walk;
 use crate::detail::sampling::WalkSampleMapping;
 use crate::detail::scrub::scrub_objects;
 use crate::detail::scrub::ScrubCommand;
@@ -193,7 +196,7 @@ async fn setup_scrub(
         limit_data_fetch: common_args.limit_data_fetch,
         output_format: output_format.clone(),
         output_node_types: output_nodes.parse_args(),
-        progress_options: common_args.progress.parse_args(),
+        progress_options: common_args.progress.parse_args(common_args.quiet)?,
         sampling_options: sampling.parse_args(1)?,
         pack_info_log_options: pack_log_info.parse_args(app.fb)?,
         sampler: component_sampler,
@@ -240,13 +243,28 @@ pub async fn run_unsharded(
     args: CommandArgs,
 ) -> Result<(), Error> {
     let (job_params, command) = setup_scrub(repos, &app, &args).await?;
+    let progress_states = job_params.progress_states();
+    let _admin_listener = args
+        .common_args
+        .progress
+        .progress_admin_socket
+        .as_ref()
+        .map(|path| AdminListener::spawn(app.logger().clone(), path, progress_states.clone()))
+        .transpose()?;
+    let _dump_on_signal = DumpOnSignal::spawn(app.logger().clone(), progress_states.clone())?;
     // When running in unsharded setting, walker scrub doesn't have a need to
     // be cancelled midway.
-    scrub_objects(
+    let walked = scrub_objects(
         app.fb,
         job_params,
         command,
         Arc::new(AtomicBool::new(false)),
     )
-    .await
+    .await;
+    finish_walk(
+        app.logger(),
+        &args.common_args.progress.parse_report_args(),
+        &progress_states,
+        walked,
+    )
 }
@@ -28,6 +28,9 @@ use crate::args::ValidateCheckTypeArgs;
 use crate::args::WalkerCommonArgs;
 use crate::commands::JobParams;
 use crate::commands::VALIDATE;
+use crate::detail::admin::AdminListener;
+use crate::detail::admin::DumpOnSignal;
+use crate::detail::report::finish_walk;
 use crate::detail::validate::validate;
 use crate::detail::validate::ValidateCommand;
 use crate::setup::setup_common;
@@ -173,7 +176,7 @@ async fn setup_validate(
 
     let command = ValidateCommand {
         include_check_types: check_types.parse_args(),
-        progress_options: common_args.progress.parse_args(),
+        progress_options: common_args.progress.parse_args(common_args.quiet)?,
     };
     Ok((job_params, command))
 }
@@ -217,13 +220,28 @@ pub async fn run_unsharded(
     args: CommandArgs,
 ) -> Result<(), Error> {
     let (job_params, command) = setup_validate(repos, &app, &args).await?;
+    let progress_states = job_params.progress_states();
+    let _admin_listener = args
+        .common_args
+        .progress
+        .progress_admin_socket
+        .as_ref()
+        .map(|path| AdminListener::spawn(app.logger().clone(), path, progress_states.clone()))
+        .transpose()?;
+    let _dump_on_signal = DumpOnSignal::spawn(app.logger().clone(), progress_states.clone())?;
     // When running in unsharded setting, walker validate doesn't need to
     // be cancelled midway.
-    validate(
+    let walked = validate(
         app.fb,
         job_params,
         command,
         Arc::new(AtomicBool::new(false)),
     )
-    .await
+    .await;
+    finish_walk(
+        app.logger(),
+        &args.common_args.progress.parse_report_args(),
+        &progress_states,
+        walked,
+    )
 }
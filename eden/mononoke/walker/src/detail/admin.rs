@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Error;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::task::JoinHandle;
+
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::report::FinalReport;
+use crate::detail::state::StepStats;
+
+type ProgressStates = Vec<ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>>>;
+
+// How long a client has to send its request, after which it gets the summary. Lets
+// clients that only read keep working.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+// Longest request line read
+const MAX_REQUEST_LEN: u64 = 256;
+
+/// What a client asks for in the first line it sends
+#[derive(Debug, PartialEq)]
+enum Request {
+    /// Every repo's progress as JSON, asked for by an empty line or "summary"
+    Summary,
+    /// Every repo's full progress table as text, asked for by "dump"
+    Dump,
+}
+
+impl Request {
+    fn parse(line: &str) -> Result<Self, Error> {
+        match line.trim() {
+            "" | "summary" => Ok(Request::Summary),
+            "dump" => Ok(Request::Dump),
+            other => bail!(
+                "Unknown admin request {:?}, expected summary or dump",
+                other
+            ),
+        }
+    }
+}
+
+/// Answers each connection to the admin socket with the current progress of every repo's
+/// walk, by default as JSON in the same form as the saved summary file. Stops and removes
+/// the socket when dropped.
+pub struct AdminListener {
+    path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl AdminListener {
+    pub fn spawn(
+        logger: Logger,
+        path: &Path,
+        progress_states: ProgressStates,
+    ) -> Result<Self, Error> {
+        // Left behind if a previous run was killed
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("While removing old admin socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("While binding admin socket {}", path.display()))?;
+        info!(logger, "Serving walk progress on {}", path.display());
+        let handle = tokio::spawn(serve(logger, listener, progress_states));
+        Ok(Self {
+            path: path.to_path_buf(),
+            handle,
+        })
+    }
+}
+
+impl Drop for AdminListener {
+    fn drop(&mut self) {
+        self.handle.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+async fn serve(logger: Logger, listener: UnixListener, progress_states: ProgressStates) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                let logger = logger.clone();
+                let progress_states = progress_states.clone();
+                // Separate task so a slow reader can't hold up other queries
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &progress_states).await {
+                        warn!(logger, "Admin socket query failed: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => warn!(logger, "Admin socket accept failed: {:?}", e),
+        }
+    }
+}
+
+async fn read_request(stream: &mut UnixStream) -> Result<Request, Error> {
+    let mut line = String::new();
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_LEN));
+    match tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut line)).await {
+        Ok(read) => {
+            read?;
+            Request::parse(&line)
+        }
+        Err(_elapsed) => Ok(Request::Summary),
+    }
+}
+
+async fn respond(mut stream: UnixStream, progress_states: &ProgressStates) -> Result<(), Error> {
+    let request = read_request(&mut stream).await?;
+    // Each lock is only held to copy the numbers out, never across an await
+    let response = match request {
+        Request::Summary => {
+            let reports: Vec<FinalReport> =
+                progress_states.iter().map(|s| s.final_report()).collect();
+            serde_json::to_vec(&reports)?
+        }
+        Request::Dump => progress_states
+            .iter()
+            .map(|s| s.dump())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_bytes(),
+    };
+    stream.write_all(&response).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Logs every repo's full progress table each time the process gets SIGUSR1, for looking
+/// into a walk without the admin socket. Stops handling the signal when dropped.
+pub struct DumpOnSignal(JoinHandle<()>);
+
+impl DumpOnSignal {
+    pub fn spawn(logger: Logger, progress_states: ProgressStates) -> Result<Self, Error> {
+        let mut signals =
+            signal(SignalKind::user_defined1()).context("While installing the SIGUSR1 handler")?;
+        let handle = tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                for state in &progress_states {
+                    info!(logger, "{}", state.dump());
+                }
+            }
+        });
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for DumpOnSignal {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use fbinit::FacebookInit;
+    use futures::future::try_join_all;
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::graph::Node;
+    use crate::detail::graph::NodeType;
+    use crate::detail::progress::ProgressOptionsBuilder;
+    use crate::detail::progress::ProgressRecorder;
+    use crate::detail::progress::ProgressReporter;
+    use crate::detail::progress::ProgressStateBuilder;
+
+    async fn request(path: &Path, request: Option<&str>) -> Result<Vec<u8>, Error> {
+        let mut stream = UnixStream::connect(path).await?;
+        // Without a request the server waits for one until it times out
+        if let Some(request) = request {
+            stream.write_all(request.as_bytes()).await?;
+            stream.shutdown().await?;
+        }
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn query(path: &Path) -> Result<Vec<FinalReport>, Error> {
+        Ok(serde_json::from_slice(&request(path, Some("")).await?)?)
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturingDrain(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for CapturingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record<'_>,
+            _values: &slog::OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    fn test_state(
+        fb: FacebookInit,
+        logger: &Logger,
+    ) -> Result<ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>>, Error>
+    {
+        let state = ProgressStateMutex::new(
+            ProgressStateBuilder::new(fb, logger.clone(), "scrub", "repo".to_string())
+                .with_included_types(hashset! {NodeType::PhaseMapping})
+                .build()?,
+        );
+        state.record_step(
+            &Node::PhaseMapping(ChangesetId::from_byte_array([1; 32])),
+            Some(&StepStats::default()),
+        );
+        Ok(state)
+    }
+
+    #[fbinit::test]
+    async fn test_admin_query(fb: FacebookInit) -> Result<(), Error> {
+        let logger = Logger::root(slog::Discard, o!());
+        let state = ProgressStateMutex::new(
+            ProgressStateBuilder::new(fb, logger.clone(), "scrub", "repo".to_string())
+                .with_included_types(hashset! {NodeType::PhaseMapping})
+                .with_options(
+                    ProgressOptionsBuilder::default()
+                        .with_time_only()
+                        .with_interval(Duration::from_millis(1))
+                        .build()?,
+                )
+                .build()?,
+        );
+        let dir = std::env::temp_dir().join(format!("walker_admin_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("admin.sock");
+        // A stale socket from an earlier run is replaced
+        fs::write(&path, "")?;
+        let listener = AdminListener::spawn(logger, &path, vec![state.clone()])?;
+
+        let reports = query(&path).await?;
+        assert_eq!(1, reports.len());
+        assert_eq!("repo", reports[0].repo);
+        assert!(reports[0].types.is_empty());
+
+        for i in 0..5 {
+            state.record_step(
+                &Node::PhaseMapping(ChangesetId::from_byte_array([i; 32])),
+                Some(&StepStats::default()),
+            );
+        }
+
+        // Concurrent queries, while the walk is also reporting
+        let reporter = {
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || {
+                for _ in 0..100 {
+                    state.report_throttled();
+                }
+            })
+        };
+        let all = try_join_all((0..10).map(|_| query(&path))).await?;
+        reporter.await?;
+        for reports in all {
+            assert_eq!(5, reports[0].types["PhaseMapping"].walked);
+        }
+
+        drop(listener);
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_admin_requests(fb: FacebookInit) -> Result<(), Error> {
+        let logger = Logger::root(slog::Discard, o!());
+        let state = test_state(fb, &logger)?;
+        let dir = std::env::temp_dir().join(format!("walker_admin_dump_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("admin.sock");
+        let listener = AdminListener::spawn(logger, &path, vec![state.clone()])?;
+
+        let dump = String::from_utf8(request(&path, Some("dump\n")).await?)?;
+        assert!(dump.starts_with("Progress of scrub in repo"));
+        assert!(dump.contains("\nPhaseMapping       1"));
+
+        let summary: Vec<FinalReport> =
+            serde_json::from_slice(&request(&path, Some("summary\n")).await?)?;
+        assert_eq!(1, summary[0].types["PhaseMapping"].walked);
+
+        // A client that sends nothing still gets the summary, once the server stops waiting
+        let summary: Vec<FinalReport> = serde_json::from_slice(&request(&path, None).await?)?;
+        assert_eq!(1, summary[0].types["PhaseMapping"].walked);
+
+        // Nothing for an unknown request
+        assert!(request(&path, Some("everything\n")).await?.is_empty());
+        assert_eq!(
+            "Unknown admin request \"everything\", expected summary or dump",
+            Request::parse("everything\n").unwrap_err().to_string()
+        );
+
+        drop(listener);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_dump_on_signal(fb: FacebookInit) -> Result<(), Error> {
+        let drain = CapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+        let state = test_state(fb, &logger)?;
+        let _dump = DumpOnSignal::spawn(logger, vec![state.clone()])?;
+
+        let status = std::process::Command::new("kill")
+            .arg("-USR1")
+            .arg(std::process::id().to_string())
+            .status()?;
+        assert!(status.success());
+        for _ in 0..100 {
+            if !drain.0.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let logged = drain.0.lock().unwrap().clone();
+        assert_eq!(1, logged.len());
+        assert!(logged[0].starts_with("Progress of scrub in repo"));
+        assert!(logged[0].contains("\nPhaseMapping       1"));
+        Ok(())
+    }
+}
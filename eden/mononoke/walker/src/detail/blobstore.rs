@@ -8,23 +8,36 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 use anyhow::anyhow;
 use anyhow::Error;
+use blobstore::BlobstoreBytes;
+use blobstore::BlobstoreGetData;
+use blobstore::BlobstoreIsPresent;
 use blobstore::BlobstoreMetadata;
 use context::CoreContext;
+use futures::stream::Stream;
+use futures::stream::TryStreamExt;
 use metaconfig_types::BlobConfig;
 use metaconfig_types::BlobstoreId;
 use mononoke_types::repo::REPO_PREFIX_REGEX;
 use mononoke_types::RepositoryId;
 use multiplexedblob::LoggingScrubHandler;
 use multiplexedblob::ScrubHandler;
+use samplingblob::ComponentSamplingHandler;
 use scuba::value::NullScubaValue;
 use scuba::value::ScubaValue;
 use scuba_ext::MononokeScubaSampleBuilder;
 use stats::prelude::*;
 
 use crate::detail::pack::CTIME;
+use crate::detail::state::BlobstoreReads;
+use crate::detail::state::StepStats;
 use crate::detail::validate::CHECK_FAIL;
 use crate::detail::validate::CHECK_TYPE;
 use crate::detail::validate::ERROR_MSG;
@@ -39,10 +52,154 @@ define_stats! {
 
 pub const BLOBSTORE_ID: &str = "blobstore_id";
 
+/// Repairs seen by the scrub handler that are not yet attributed to a walk step.
+/// The handler is only given the blobstore key, so the walk takes these as
+/// steps complete to get them into the progress reporting.
+#[derive(Debug, Default)]
+pub struct ScrubRepairCounts {
+    repaired: AtomicUsize,
+    unrepairable: AtomicUsize,
+    // Written by the repairs, where the store reports the size
+    bytes_written: AtomicU64,
+}
+
+impl ScrubRepairCounts {
+    pub fn record(&self, is_repaired: bool, bytes_written: u64) {
+        if is_repaired {
+            self.repaired.fetch_add(1, Ordering::Relaxed);
+            self.bytes_written
+                .fetch_add(bytes_written, Ordering::Relaxed);
+        } else {
+            self.unrepairable.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take the (repaired, unrepairable, bytes written) counts since the last call
+    pub fn take(&self) -> (usize, usize, u64) {
+        (
+            self.repaired.swap(0, Ordering::Relaxed),
+            self.unrepairable.swap(0, Ordering::Relaxed),
+            self.bytes_written.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Reads from each component blobstore that are not yet attributed to a walk
+/// step. Like the repair counts these are taken as steps complete.
+#[derive(Debug, Default)]
+pub struct BlobstoreReadCounts {
+    reads: Mutex<BlobstoreReads>,
+}
+
+impl BlobstoreReadCounts {
+    pub fn record(&self, blobstore_id: BlobstoreId) {
+        self.reads
+            .lock()
+            .expect("lock poisoned")
+            .record(u64::from(blobstore_id), 1);
+    }
+
+    /// Take the reads since the last call
+    pub fn take(&self) -> BlobstoreReads {
+        std::mem::take(&mut *self.reads.lock().expect("lock poisoned"))
+    }
+}
+
+/// Attribute the blobstore reads made so far to the step stats as they pass
+pub fn read_counting_stream<InStream, K, Payload>(
+    read_counts: Arc<BlobstoreReadCounts>,
+    s: InStream,
+) -> impl Stream<Item = Result<(K, Payload, Option<StepStats>), Error>>
+where
+    InStream: Stream<Item = Result<(K, Payload, Option<StepStats>), Error>> + 'static + Send,
+{
+    s.map_ok(move |(key, payload, stats_opt)| {
+        let stats_opt = stats_opt.map(|mut stats| {
+            stats.blobstore_reads = stats.blobstore_reads + read_counts.take();
+            stats
+        });
+        (key, payload, stats_opt)
+    })
+}
+
+/// Counts reads by component blobstore, passing all samples on to the inner
+/// handler if there is one.
+#[derive(Debug)]
+pub struct ReadCountingSampler {
+    counts: Arc<BlobstoreReadCounts>,
+    inner: Option<Arc<dyn ComponentSamplingHandler>>,
+}
+
+impl ReadCountingSampler {
+    pub fn new(
+        counts: Arc<BlobstoreReadCounts>,
+        inner: Option<Arc<dyn ComponentSamplingHandler>>,
+    ) -> Self {
+        Self { counts, inner }
+    }
+}
+
+impl ComponentSamplingHandler for ReadCountingSampler {
+    fn sample_get(
+        &self,
+        ctx: &CoreContext,
+        key: &str,
+        value: Option<&BlobstoreGetData>,
+        inner_id: Option<BlobstoreId>,
+    ) -> Result<(), Error> {
+        if let Some(inner_id) = inner_id {
+            self.counts.record(inner_id);
+        }
+        match &self.inner {
+            Some(inner) => inner.sample_get(ctx, key, value, inner_id),
+            None => Ok(()),
+        }
+    }
+
+    fn sample_put(
+        &self,
+        ctx: &CoreContext,
+        key: &str,
+        value: &BlobstoreBytes,
+        inner_id: Option<BlobstoreId>,
+    ) -> Result<(), Error> {
+        match &self.inner {
+            Some(inner) => inner.sample_put(ctx, key, value, inner_id),
+            None => Ok(()),
+        }
+    }
+
+    fn sample_is_present(
+        &self,
+        ctx: &CoreContext,
+        key: &str,
+        value: &BlobstoreIsPresent,
+        inner_id: Option<BlobstoreId>,
+    ) -> Result<(), Error> {
+        match &self.inner {
+            Some(inner) => inner.sample_is_present(ctx, key, value, inner_id),
+            None => Ok(()),
+        }
+    }
+
+    fn sample_unlink(
+        &self,
+        ctx: &CoreContext,
+        key: &str,
+        inner_id: Option<BlobstoreId>,
+    ) -> Result<(), Error> {
+        match &self.inner {
+            Some(inner) => inner.sample_unlink(ctx, key, inner_id),
+            None => Ok(()),
+        }
+    }
+}
+
 pub struct StatsScrubHandler {
     scuba: MononokeScubaSampleBuilder,
     subcommand_stats_key: &'static str,
     repo_id_to_name: HashMap<RepositoryId, String>,
+    repair_counts: Arc<ScrubRepairCounts>,
     inner: LoggingScrubHandler,
 }
 
@@ -52,12 +209,14 @@ impl StatsScrubHandler {
         scuba: MononokeScubaSampleBuilder,
         subcommand_stats_key: &'static str,
         repo_id_to_name: HashMap<RepositoryId, String>,
+        repair_counts: Arc<ScrubRepairCounts>,
     ) -> Self {
         Self {
             scuba,
             subcommand_stats_key,
             inner: LoggingScrubHandler::new(quiet),
             repo_id_to_name,
+            repair_counts,
         }
     }
 }
@@ -89,6 +248,10 @@ impl ScrubHandler for StatsScrubHandler {
     ) {
         self.inner
             .on_repair(ctx, blobstore_id, key, is_repaired, meta);
+        self.repair_counts.record(
+            is_repaired,
+            meta.sizes().map_or(0, |sizes| sizes.unique_compressed_size),
+        );
 
         let ctime = match meta.ctime() {
             Some(ctime) => ScubaValue::from(ctime),
@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Error;
+use slog::info;
+use slog::Logger;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+use crate::detail::progress::FeedbackSample;
+use crate::detail::progress::ProgressFeedback;
+
+// Step latency below this fraction of latency_high counts as healthy, so latencies
+// just under the threshold change nothing
+const HEALTHY_LATENCY_FRACTION: f64 = 0.5;
+// Each increase adds this fraction of max, so recovering from min takes a few reports
+const INCREASE_FRACTION: usize = 10;
+
+/// Bounds and thresholds for AdaptiveConcurrency
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveConcurrencyOptions {
+    pub min: usize,
+    pub max: usize,
+    /// Errors per step walked above which concurrency is reduced
+    pub error_rate_high: f64,
+    /// Errors per step walked below which concurrency can be increased. Rates between
+    /// the two thresholds leave it as it is.
+    pub error_rate_low: f64,
+    /// Step latency above which concurrency is reduced, if set
+    pub latency_high: Option<Duration>,
+    /// Consecutive samples on the same side of the thresholds before changing
+    pub consecutive: usize,
+}
+
+impl AdaptiveConcurrencyOptions {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.min == 0 || self.min > self.max {
+            bail!(
+                "Adaptive concurrency min must be at least 1 and at most max {}, got {}",
+                self.max,
+                self.min
+            );
+        }
+        if !(0.0..=self.error_rate_high).contains(&self.error_rate_low) {
+            bail!(
+                "Adaptive concurrency low error rate {} must be between 0 and the high rate {}",
+                self.error_rate_low,
+                self.error_rate_high
+            );
+        }
+        if self.consecutive == 0 {
+            bail!("Adaptive concurrency consecutive samples must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Health {
+    Healthy,
+    Neutral,
+    Unhealthy,
+}
+
+/// Picks how many steps the walk runs at once from its progress feedback. Halves the
+/// limit when errors or latency are high and adds a tenth of max when they are low,
+/// each only after enough consecutive samples agree, and always within min and max.
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    options: AdaptiveConcurrencyOptions,
+    limit: usize,
+    // The latest report observed, so each sample is only counted once
+    last_report: Option<u64>,
+    // Consecutive samples of the same health, reset by each change
+    streak: (Health, usize),
+}
+
+impl AdaptiveConcurrency {
+    /// Starts at max, as the walk would without adapting
+    pub fn new(options: AdaptiveConcurrencyOptions) -> Result<Self, Error> {
+        options.validate()?;
+        Ok(Self {
+            limit: options.max,
+            options,
+            last_report: None,
+            streak: (Health::Neutral, 0),
+        })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    fn health(&self, sample: &FeedbackSample) -> Health {
+        let latency_pct = self
+            .options
+            .latency_high
+            .zip(sample.latency)
+            .map(|(high, latency)| latency.as_secs_f64() / high.as_secs_f64());
+        if sample.error_rate > self.options.error_rate_high
+            || latency_pct.is_some_and(|pct| pct > 1.0)
+        {
+            Health::Unhealthy
+        } else if sample.error_rate < self.options.error_rate_low
+            && latency_pct.is_none_or(|pct| pct < HEALTHY_LATENCY_FRACTION)
+        {
+            Health::Healthy
+        } else {
+            Health::Neutral
+        }
+    }
+
+    /// Counts the sample towards a change, and returns the new limit if it made one.
+    /// Samples already observed are ignored.
+    pub fn observe(&mut self, sample: &FeedbackSample) -> Option<usize> {
+        if self.last_report == Some(sample.report) {
+            return None;
+        }
+        self.last_report = Some(sample.report);
+        let health = self.health(sample);
+        self.streak = match self.streak {
+            (streak_health, count) if streak_health == health => (health, count + 1),
+            _ => (health, 1),
+        };
+        if health == Health::Neutral || self.streak.1 < self.options.consecutive {
+            return None;
+        }
+        self.streak = (Health::Neutral, 0);
+        let limit = match health {
+            Health::Unhealthy => self.limit / 2,
+            _ => self.limit + (self.options.max / INCREASE_FRACTION).max(1),
+        }
+        .clamp(self.options.min, self.options.max);
+        if limit == self.limit {
+            return None;
+        }
+        self.limit = limit;
+        Some(limit)
+    }
+}
+
+/// Limits the steps running at once to less than the walk's configured concurrency,
+/// which its stream keeps buffering up to. Lowering the limit holds back free permits
+/// straight away, and the rest as running steps finish.
+#[derive(Debug)]
+pub struct ConcurrencyGate {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    limit: AtomicUsize,
+    // Permits kept from steps, max - limit of them once running steps catch up
+    held: Mutex<Vec<OwnedSemaphorePermit>>,
+}
+
+/// Lets one step through the gate until dropped
+#[must_use]
+pub struct GatePass {
+    gate: Arc<ConcurrencyGate>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConcurrencyGate {
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+            limit: AtomicUsize::new(max),
+            held: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let limit = limit.clamp(1, self.max);
+        self.limit.store(limit, Ordering::Relaxed);
+        let to_hold = self.max - limit;
+        let mut held = self.held.lock().unwrap();
+        held.truncate(to_hold);
+        while held.len() < to_hold {
+            match self.semaphore.clone().try_acquire_owned() {
+                Ok(permit) => held.push(permit),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Waits until the step can run under the current limit
+    pub async fn enter(self: &Arc<Self>) -> GatePass {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Concurrency gate semaphore is never closed");
+        GatePass {
+            gate: self.clone(),
+            permit: Some(permit),
+        }
+    }
+
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut held = self
+            .held
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if held.len() < self.max - self.limit() {
+            held.push(permit);
+        }
+    }
+}
+
+impl Drop for GatePass {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.gate.release(permit);
+        }
+    }
+}
+
+/// What the walk driver consults on each step, to adjust its concurrency to the latest
+/// progress feedback
+pub struct AdaptiveGate {
+    logger: Logger,
+    feedback: Arc<ProgressFeedback>,
+    controller: Mutex<AdaptiveConcurrency>,
+    gate: Arc<ConcurrencyGate>,
+    // The latest report acted on
+    seen_reports: AtomicU64,
+}
+
+impl AdaptiveGate {
+    pub fn new(
+        logger: Logger,
+        feedback: Arc<ProgressFeedback>,
+        options: AdaptiveConcurrencyOptions,
+    ) -> Result<Self, Error> {
+        let gate = Arc::new(ConcurrencyGate::new(options.max));
+        Ok(Self {
+            logger,
+            feedback,
+            controller: Mutex::new(AdaptiveConcurrency::new(options)?),
+            gate,
+            seen_reports: AtomicU64::new(0),
+        })
+    }
+
+    /// Applies any new feedback, then waits until the step can run
+    pub async fn enter(&self) -> GatePass {
+        let reports = self.feedback.reports();
+        if self.seen_reports.swap(reports, Ordering::Relaxed) != reports {
+            self.adjust();
+        }
+        self.gate.enter().await
+    }
+
+    fn adjust(&self) {
+        let sample = match self.feedback.latest() {
+            Some(sample) => sample,
+            None => return,
+        };
+        let mut controller = self.controller.lock().unwrap();
+        let previous = controller.limit();
+        if let Some(limit) = controller.observe(&sample) {
+            self.gate.set_limit(limit);
+            info!(
+                self.logger,
+                "Adjusted walk concurrency from {} to {}, error rate {:.3}, step latency {:?}",
+                previous,
+                limit,
+                sample.error_rate,
+                sample.latency,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> AdaptiveConcurrencyOptions {
+        AdaptiveConcurrencyOptions {
+            min: 10,
+            max: 100,
+            error_rate_high: 0.1,
+            error_rate_low: 0.01,
+            latency_high: Some(Duration::from_millis(100)),
+            consecutive: 2,
+        }
+    }
+
+    fn run(controller: &mut AdaptiveConcurrency, samples: &[(f64, Option<u64>)]) -> Vec<usize> {
+        samples
+            .iter()
+            .map(|(error_rate, latency_ms)| {
+                let sample = FeedbackSample {
+                    report: controller.last_report.unwrap_or(0) + 1,
+                    error_rate: *error_rate,
+                    latency: latency_ms.map(Duration::from_millis),
+                };
+                controller.observe(&sample);
+                controller.limit()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_adaptive_concurrency() -> Result<(), Error> {
+        let mut controller = AdaptiveConcurrency::new(options())?;
+        assert_eq!(100, controller.limit());
+
+        // Halves after two bad samples in a row, down to min
+        assert_eq!(
+            vec![100, 50, 50, 25, 25, 12, 12, 10],
+            run(&mut controller, &[(0.2, None); 8])
+        );
+
+        // A neutral sample breaks the streak, so alternating changes nothing
+        assert_eq!(
+            vec![10, 10, 10, 10],
+            run(
+                &mut controller,
+                &[(0.0, None), (0.05, None), (0.0, None), (0.05, None)]
+            )
+        );
+
+        // Recovers by a tenth of max at a time, up to max
+        assert_eq!(
+            vec![10, 20, 20, 30],
+            run(&mut controller, &[(0.0, Some(10)); 4])
+        );
+        run(&mut controller, &[(0.0, None); 20]);
+        assert_eq!(100, controller.limit());
+
+        // Latency over the threshold counts as unhealthy, and between half of it and the
+        // threshold as neutral
+        assert_eq!(
+            vec![100, 100, 100, 50],
+            run(
+                &mut controller,
+                &[
+                    (0.0, Some(80)),
+                    (0.0, Some(80)),
+                    (0.0, Some(200)),
+                    (0.0, Some(200))
+                ]
+            )
+        );
+
+        // The same sample twice is only counted once
+        let sample = FeedbackSample {
+            report: 1000,
+            error_rate: 0.5,
+            latency: None,
+        };
+        assert_eq!(None, controller.observe(&sample));
+        assert_eq!(None, controller.observe(&sample));
+        assert_eq!(50, controller.limit());
+
+        assert!(AdaptiveConcurrency::new(AdaptiveConcurrencyOptions {
+            min: 0,
+            ..options()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_gate() {
+        let gate = Arc::new(ConcurrencyGate::new(3));
+        let blocked = |gate: &Arc<ConcurrencyGate>| {
+            let gate = gate.clone();
+            async move {
+                tokio::time::timeout(Duration::from_millis(10), gate.enter())
+                    .await
+                    .is_err()
+            }
+        };
+
+        let first = gate.enter().await;
+        let second = gate.enter().await;
+        // Only one permit is free to hold back, the other is taken when a step finishes
+        gate.set_limit(1);
+        assert!(blocked(&gate).await);
+        drop(first);
+        assert!(blocked(&gate).await);
+        drop(second);
+        let only = gate.enter().await;
+        assert!(blocked(&gate).await);
+
+        // Raising the limit frees the held permits straight away
+        gate.set_limit(3);
+        let _more = gate.enter().await;
+        let _most = gate.enter().await;
+        assert!(blocked(&gate).await);
+        drop(only);
+        assert_eq!(3, gate.limit());
+    }
+}
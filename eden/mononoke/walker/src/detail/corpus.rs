@@ -7,17 +7,23 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
 use blobstore::BlobstoreGetData;
 use cloned::cloned;
 use context::CoreContext;
 use context::SamplingKey;
+use derive_more::Add;
+use derive_more::Div;
+use derive_more::Mul;
+use derive_more::Sub;
 use fbinit::FacebookInit;
 use filetime::FileTime;
 use futures::future;
@@ -34,12 +40,17 @@ use percent_encoding::CONTROLS;
 use regex::Regex;
 use repo_identity::RepoIdentityRef;
 use samplingblob::SamplingHandler;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use stats::prelude::*;
 use tokio::fs::{self as tkfs};
 
 use crate::commands::JobParams;
 use crate::commands::JobWalkParams;
 use crate::commands::RepoSubcommandParams;
 use crate::commands::CORPUS;
+use crate::detail::blobstore::read_counting_stream;
 use crate::detail::graph::FileContentData;
 use crate::detail::graph::Node;
 use crate::detail::graph::NodeData;
@@ -48,9 +59,14 @@ use crate::detail::graph::WrappedPath;
 use crate::detail::progress::progress_stream;
 use crate::detail::progress::report_state;
 use crate::detail::progress::ProgressOptions;
+use crate::detail::progress::ProgressRecorder;
 use crate::detail::progress::ProgressReporter;
+use crate::detail::progress::ProgressReporterUnprotected;
+use crate::detail::progress::ProgressStateBuilder;
 use crate::detail::progress::ProgressStateCountByType;
 use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::QuietMode;
+use crate::detail::progress::StepProgress;
 use crate::detail::sampling::PathTrackingRoute;
 use crate::detail::sampling::SampleTrigger;
 use crate::detail::sampling::SamplingOptions;
@@ -58,7 +74,6 @@ use crate::detail::sampling::SamplingWalkVisitor;
 use crate::detail::sampling::WalkKeyOptPath;
 use crate::detail::sampling::WalkPayloadMtime;
 use crate::detail::sampling::WalkSampleMapping;
-use crate::detail::scrub::ScrubStats;
 use crate::detail::tail::walk_exact_tail;
 use crate::detail::walk::RepoWalkParams;
 use crate::detail::walk::RepoWalkTypeParams;
@@ -76,14 +91,141 @@ const DUMP_DIR: &str = ".mononoke,";
 // A subdir used for temp files before they are moved to final location
 const INFLIGHT_DIR: &str = "Inflight";
 
+define_stats! {
+    prefix = "mononoke.walker";
+    walk_progress_files_written: dynamic_timeseries("{}.progress.{}.files_written", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_bytes_written: dynamic_timeseries("{}.progress.{}.bytes_written", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_write_errors: dynamic_timeseries("{}.progress.{}.write_errors", (subcommand: &'static str, repo: String); Rate, Sum),
+}
+
+const BYTES_PER_MB: f64 = 1_000_000.0;
+
+/// What was dumped to disk, per step and as summed for reporting
+#[derive(Add, Div, Mul, Sub, Clone, Copy, Default, Debug, PartialEq)]
+pub struct CorpusProgressSummary {
+    pub files_written: u64,
+    pub bytes_written: u64,
+    /// Steps whose files could not be moved into place
+    pub errors: u64,
+}
+
+// Write errors are counted here as the walk continues past them
+impl StepProgress for CorpusProgressSummary {}
+
+impl fmt::Display for CorpusProgressSummary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{},{},{}",
+            self.files_written, self.bytes_written, self.errors
+        )
+    }
+}
+
+fn per_second(summary: CorpusProgressSummary, elapsed: Duration) -> CorpusProgressSummary {
+    if elapsed.as_millis() > 0 {
+        summary * 1000 / (elapsed.as_millis() as u64)
+    } else {
+        CorpusProgressSummary::default()
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / BYTES_PER_MB
+}
+
+impl ProgressStateCountByType<CorpusProgressSummary, CorpusProgressSummary> {
+    pub fn report_progress_log(&mut self, delta_time: Option<Duration>) {
+        let times = self.report_times(delta_time);
+        let total_time = times.total;
+        let summary_by_type: HashMap<NodeType, CorpusProgressSummary> = self
+            .work_stats
+            .stats_by_type
+            .iter()
+            .map(|(k, (_i, v))| (*k, *v))
+            .collect();
+        let new_summary = summary_by_type
+            .values()
+            .fold(CorpusProgressSummary::default(), |acc, v| acc + *v);
+        let delta_summary = new_summary - self.reporting_stats.last_summary;
+
+        let def = CorpusProgressSummary::default();
+        let detail = &self
+            .params
+            .types_sorted_by_name
+            .iter()
+            .map(|t| {
+                let s = summary_by_type.get(t).unwrap_or(&def);
+                format!("{}:{}", t, s)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let (delta_s, delta_summary_per_s) = times
+            .delta
+            .map_or((0, CorpusProgressSummary::default()), |delta_time| {
+                (delta_time.as_secs(), per_second(delta_summary, delta_time))
+            });
+
+        let total_summary_per_s = per_second(new_summary, times.active);
+
+        info!(
+            self.params.logger,
+            "MB/s,Files/s,Files,Bytes,Errors; Delta {:.2}/s,{:06}/s,{},{}s; Run {:.2}/s,{:06}/s,{},{}s; Type:Files,Bytes,Errors {}",
+            mb(delta_summary_per_s.bytes_written),
+            delta_summary_per_s.files_written,
+            delta_summary,
+            delta_s,
+            mb(total_summary_per_s.bytes_written),
+            total_summary_per_s.files_written,
+            new_summary,
+            total_time.as_secs(),
+            detail,
+        );
+
+        let key = (
+            self.params.subcommand_stats_key,
+            self.params.stats_key.clone(),
+        );
+        STATS::walk_progress_files_written
+            .add_value(delta_summary.files_written as i64, key.clone());
+        STATS::walk_progress_bytes_written
+            .add_value(delta_summary.bytes_written as i64, key.clone());
+        STATS::walk_progress_write_errors.add_value(delta_summary.errors as i64, key);
+
+        self.reporting_stats.last_summary_by_type = summary_by_type;
+        self.reporting_stats.last_summary = new_summary;
+        self.finish_report(&times);
+    }
+}
+
+impl ProgressReporterUnprotected
+    for ProgressStateCountByType<CorpusProgressSummary, CorpusProgressSummary>
+{
+    fn report_progress(&mut self) {
+        self.report_progress_log(None);
+    }
+
+    fn report_throttled(&mut self) {
+        if let Some(delta_time) = self.should_log_throttled() {
+            self.report_progress_log(Some(delta_time));
+        }
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.params.quiet_mode()
+    }
+}
+
 // Force load of leaf data like file contents that graph traversal did not need
 // Output is the samples
 fn corpus_stream<InStream, SS>(
+    logger: Logger,
     scheduled_max: usize,
     output_dir: Option<String>,
     s: InStream,
     sampler: Arc<CorpusSamplingHandler<CorpusSample>>,
-) -> impl Stream<Item = Result<(Node, Option<()>, Option<ScrubStats>), Error>>
+) -> impl Stream<Item = Result<(Node, Option<()>, Option<CorpusProgressSummary>), Error>>
 where
     InStream: Stream<Item = Result<(WalkKeyOptPath<WrappedPath>, WalkPayloadMtime, Option<SS>), Error>>
         + 'static
@@ -101,33 +243,48 @@ where
                     // includes thrift wrapper overhead so more closely matches store
                     .map_ok(move |_num_bytes| {
                         let sample = sampler.complete_step(&walk_key);
-                        let size = ScrubStats::from(sample.as_ref());
-                        (walk_key, sample, mtime, Some(size))
+                        (walk_key, sample, mtime)
                     })
                     .left_future()
             }
             _ => {
                 let sample = sampler.complete_step(&walk_key);
-                let size = ScrubStats::from(sample.as_ref());
-                future::ready(Ok((walk_key, sample, mtime, Some(size)))).right_future()
+                future::ready(Ok((walk_key, sample, mtime))).right_future()
             }
         }
     })
     .try_buffer_unordered(scheduled_max)
     // Dump the data to disk
-    .map_ok(move |(walk_key, sample, mtime, stats)| {
+    .map_ok(move |(walk_key, sample, mtime)| {
         let node = walk_key.node;
+        let written = CorpusProgressSummary::from(sample.as_ref());
         match sample {
-            Some(sample) => move_node_files(
-                output_dir.clone(),
-                node.clone(),
-                walk_key.path,
-                mtime,
-                sample,
-            )
-            .map_ok(move |()| (node, Some(()), stats))
-            .left_future(),
-            None => future::ok((node, Some(()), stats)).right_future(),
+            Some(sample) => {
+                cloned!(logger);
+                move_node_files(
+                    output_dir.clone(),
+                    node.clone(),
+                    walk_key.path,
+                    mtime,
+                    sample,
+                )
+                .map(move |r| {
+                    let stats = match r {
+                        Ok(()) => written,
+                        // Count it and carry on, one bad file shouldn't lose the rest of the dump
+                        Err(e) => {
+                            warn!(logger, "Could not dump {:?}: {:?}", node, e);
+                            CorpusProgressSummary {
+                                errors: 1,
+                                ..Default::default()
+                            }
+                        }
+                    };
+                    Ok((node, Some(()), Some(stats)))
+                })
+                .left_future()
+            }
+            None => future::ok((node, Some(()), Some(written))).right_future(),
         }
     })
     .try_buffer_unordered(scheduled_max)
@@ -312,12 +469,15 @@ impl Default for CorpusSample {
     }
 }
 
-impl From<Option<&CorpusSample>> for ScrubStats {
+// Files are only written when there is an output dir, otherwise the sample is empty
+impl From<Option<&CorpusSample>> for CorpusProgressSummary {
     fn from(sample: Option<&CorpusSample>) -> Self {
         sample
-            .map(|sample| ScrubStats {
-                blobstore_keys: sample.data.values().len() as u64,
-                blobstore_bytes: sample.data.values().by_ref().sum(),
+            .filter(|sample| sample.inflight_dir.is_some())
+            .map(|sample| CorpusProgressSummary {
+                files_written: sample.data.len() as u64,
+                bytes_written: sample.data.values().sum(),
+                errors: 0,
             })
             .unwrap_or_default()
     }
@@ -416,32 +576,56 @@ async fn run_one(
     command: CorpusCommand,
     cancellation_requested: Arc<AtomicBool>,
 ) -> Result<(), Error> {
-    let sizing_progress_state =
-        ProgressStateMutex::new(ProgressStateCountByType::<ScrubStats, ScrubStats>::new(
+    let sizing_progress_state = ProgressStateMutex::new(
+        ProgressStateBuilder::new(
             fb,
             repo_params.logger.clone(),
             CORPUS,
             repo_params.repo.repo_identity().name().to_string(),
-            command.sampling_options.node_types.clone(),
-            command.progress_options,
-        ));
+        )
+        .with_included_types(command.sampling_options.node_types.clone())
+        .with_options(command.progress_options.clone())
+        .with_walk_instance(repo_params.walk_instance.clone())
+        .build::<CorpusProgressSummary, CorpusProgressSummary>()?,
+    );
 
     let make_sink = {
-        cloned!(command, job_params.quiet, sub_params.progress_state,);
+        cloned!(
+            command,
+            job_params.blobstore_read_counts,
+            sub_params.progress_state,
+        );
         move |ctx: &CoreContext, repo_params: &RepoWalkParams| {
-            cloned!(ctx, repo_params.scheduled_max);
-            async move |walk_output, _run_start, _chunk_num, _checkpoint_name| {
-                cloned!(ctx, sizing_progress_state);
-                let walk_progress = progress_stream(quiet, &progress_state, walk_output);
+            cloned!(ctx, repo_params.logger, repo_params.scheduled_max);
+            async move |walk_output,
+                        _run_start,
+                        chunk_num,
+                        _checkpoint_name,
+                        chunk_bounds,
+                        iteration| {
+                cloned!(ctx, blobstore_read_counts, sizing_progress_state);
+                if let Some(iteration) = iteration {
+                    progress_state.start_iteration(iteration);
+                }
+                if let Some(chunk_bounds) = chunk_bounds {
+                    progress_state.set_position(chunk_bounds.clone());
+                    progress_state.start_chunk(chunk_num, chunk_bounds);
+                }
+                let walk_output = read_counting_stream(blobstore_read_counts, walk_output);
+                let walk_progress = progress_stream(&progress_state, walk_output);
 
                 let corpus = corpus_stream(
+                    logger,
                     scheduled_max,
                     command.output_dir,
                     walk_progress,
                     command.sampler,
                 );
-                let report_sizing = progress_stream(quiet, &sizing_progress_state, corpus);
-                report_state(ctx, report_sizing).await?;
+                let report_sizing = progress_stream(&sizing_progress_state, corpus);
+                // Send the rows of the reports so far even if the walk fails
+                report_state(ctx, progress_state.quiet_mode(), report_sizing)
+                    .await
+                    .inspect_err(|_| progress_state.flush())?;
                 sizing_progress_state.report_progress();
                 progress_state.report_progress();
                 Ok(())
@@ -481,3 +665,130 @@ async fn run_one(
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use mononoke_types::ContentId;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::NumberFormat;
+    use crate::detail::progress::PayloadSizeOptions;
+    use crate::detail::progress::ProgressDisplay;
+    use crate::detail::progress::ProgressRecorderUnprotected;
+    use crate::detail::progress::TypeGrouping;
+    use crate::detail::progress::UnchangedProgress;
+
+    fn written(files_written: u64, bytes_written: u64) -> CorpusProgressSummary {
+        CorpusProgressSummary {
+            files_written,
+            bytes_written,
+            errors: 0,
+        }
+    }
+
+    #[test]
+    fn test_written_from_sample() {
+        let sample = CorpusSample {
+            inflight_dir: Some(PathBuf::from("/tmp/inflight")),
+            data: hashmap! {
+                "key1".to_string() => 1000,
+                "key2".to_string() => 24,
+            },
+        };
+        assert_eq!(written(2, 1024), CorpusProgressSummary::from(Some(&sample)));
+
+        // Nothing is written without an output dir
+        let sample = CorpusSample::default();
+        assert_eq!(
+            CorpusProgressSummary::default(),
+            CorpusProgressSummary::from(Some(&sample))
+        );
+        assert_eq!(
+            CorpusProgressSummary::default(),
+            CorpusProgressSummary::from(None)
+        );
+    }
+
+    #[test]
+    fn test_write_rates() {
+        let rate = per_second(written(10, 5_000_000), Duration::from_secs(2));
+        assert_eq!(written(5, 2_500_000), rate);
+        assert_eq!(2.5, mb(rate.bytes_written));
+
+        // Sub second intervals still give per second rates
+        let rate = per_second(written(1, 500_000), Duration::from_millis(500));
+        assert_eq!(written(2, 1_000_000), rate);
+
+        assert_eq!(
+            CorpusProgressSummary::default(),
+            per_second(written(1, 1), Duration::ZERO)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_corpus_progress_by_type(fb: FacebookInit) {
+        let mut state = ProgressStateBuilder::new(
+            fb,
+            Logger::root(slog::Discard, o!()),
+            CORPUS,
+            "repo".to_string(),
+        )
+        .with_included_types(hashset! {NodeType::FileContent, NodeType::PhaseMapping})
+        .with_options(ProgressOptions {
+            sample_rate: 1,
+            interval: Duration::from_secs(1),
+            display: ProgressDisplay::Log,
+            quiet: QuietMode::Full,
+            type_rates: false,
+            type_emit_every_nth: 1,
+            type_grouping: TypeGrouping::Types,
+            stuck_step_threshold: Duration::from_secs(60),
+            unchanged: UnchangedProgress::Log,
+            error_budgets: HashMap::new(),
+            sample_node_every_nth: 0,
+            number_format: NumberFormat::Raw,
+            max_lines_per_hour: None,
+            idle_type_threshold: None,
+            worker_skew_threshold: None,
+            wall_clock_aligned: false,
+            slowdown: None,
+            payload_sizes: PayloadSizeOptions::default(),
+            jsonl: None,
+            types_of_interest: HashSet::new(),
+            interest_interval: Duration::from_secs(5),
+        })
+        .build::<CorpusProgressSummary, CorpusProgressSummary>()
+        .unwrap();
+        let content = |i| Node::FileContent(ContentId::from_byte_array([i; 32]));
+        let phase = |i| Node::PhaseMapping(ChangesetId::from_byte_array([i; 32]));
+
+        state.record_step(&content(1), Some(&written(1, 3000)));
+        state.record_step(&content(2), Some(&written(1, 2000)));
+        state.record_step(&phase(1), Some(&written(1, 10)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(written(3, 5010), state.reporting_stats.last_summary);
+
+        let failed = CorpusProgressSummary {
+            errors: 1,
+            ..Default::default()
+        };
+        state.record_step(&content(3), Some(&failed));
+        state.report_progress();
+
+        let by_type = &state.reporting_stats.last_summary_by_type;
+        assert_eq!(
+            Some(&CorpusProgressSummary {
+                files_written: 2,
+                bytes_written: 5000,
+                errors: 1,
+            }),
+            by_type.get(&NodeType::FileContent)
+        );
+        assert_eq!(Some(&written(1, 10)), by_type.get(&NodeType::PhaseMapping));
+        assert_eq!(1, state.reporting_stats.last_summary.errors);
+    }
+}
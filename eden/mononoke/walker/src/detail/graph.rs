@@ -8,6 +8,7 @@
 use std::fmt;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::io;
 use std::str::FromStr;
 
 use ahash::RandomState;
@@ -53,6 +54,7 @@ use mercurial_types::HgParents;
 use mononoke_types::basename_suffix_skeleton_manifest::BasenameSuffixSkeletonManifest;
 use mononoke_types::blame_v2::BlameV2;
 use mononoke_types::deleted_manifest_v2::DeletedManifestV2;
+use mononoke_types::errors::MononokeTypeError;
 use mononoke_types::fastlog_batch::FastlogBatch;
 use mononoke_types::fsnode::Fsnode;
 use mononoke_types::skeleton_manifest::SkeletonManifest;
@@ -525,6 +527,22 @@ impl fmt::Display for NodeType {
     }
 }
 
+define_type_enum! {
+    enum NodeTypeGroup {
+        Bonsai,
+        Hg,
+        Content,
+        Derived,
+        Other,
+    }
+}
+
+impl fmt::Display for NodeTypeGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 impl NodeType {
     /// Derived data types are keyed by their statically defined NAME
     pub fn derived_data_name(&self) -> Option<&'static str> {
@@ -617,6 +635,49 @@ impl NodeType {
             NodeType::UnodeMapping => false,
         }
     }
+
+    /// Coarse grouping for progress reporting. No wildcard, so new types must pick one.
+    pub fn group(&self) -> NodeTypeGroup {
+        match self {
+            NodeType::Root => NodeTypeGroup::Other,
+            // Bonsai
+            NodeType::Bookmark => NodeTypeGroup::Bonsai,
+            NodeType::Changeset => NodeTypeGroup::Bonsai,
+            NodeType::BonsaiHgMapping => NodeTypeGroup::Bonsai,
+            NodeType::PhaseMapping => NodeTypeGroup::Bonsai,
+            NodeType::PublishedBookmarks => NodeTypeGroup::Bonsai,
+            // Hg
+            NodeType::HgBonsaiMapping => NodeTypeGroup::Hg,
+            NodeType::HgChangeset => NodeTypeGroup::Hg,
+            NodeType::HgChangesetViaBonsai => NodeTypeGroup::Hg,
+            NodeType::HgManifest => NodeTypeGroup::Hg,
+            NodeType::HgFileEnvelope => NodeTypeGroup::Hg,
+            NodeType::HgFileNode => NodeTypeGroup::Hg,
+            NodeType::HgManifestFileNode => NodeTypeGroup::Hg,
+            // Content
+            NodeType::FileContent => NodeTypeGroup::Content,
+            NodeType::FileContentMetadataV2 => NodeTypeGroup::Content,
+            NodeType::AliasContentMapping => NodeTypeGroup::Content,
+            // Derived Data
+            NodeType::Blame => NodeTypeGroup::Derived,
+            NodeType::ChangesetInfo => NodeTypeGroup::Derived,
+            NodeType::ChangesetInfoMapping => NodeTypeGroup::Derived,
+            NodeType::DeletedManifestV2 => NodeTypeGroup::Derived,
+            NodeType::DeletedManifestV2Mapping => NodeTypeGroup::Derived,
+            NodeType::FastlogBatch => NodeTypeGroup::Derived,
+            NodeType::FastlogDir => NodeTypeGroup::Derived,
+            NodeType::FastlogFile => NodeTypeGroup::Derived,
+            NodeType::Fsnode => NodeTypeGroup::Derived,
+            NodeType::FsnodeMapping => NodeTypeGroup::Derived,
+            NodeType::SkeletonManifest => NodeTypeGroup::Derived,
+            NodeType::SkeletonManifestMapping => NodeTypeGroup::Derived,
+            NodeType::BasenameSuffixSkeletonManifest => NodeTypeGroup::Derived,
+            NodeType::BasenameSuffixSkeletonManifestMapping => NodeTypeGroup::Derived,
+            NodeType::UnodeFile => NodeTypeGroup::Derived,
+            NodeType::UnodeManifest => NodeTypeGroup::Derived,
+            NodeType::UnodeMapping => NodeTypeGroup::Derived,
+        }
+    }
 }
 
 const ROOT_FINGERPRINT: u64 = 0;
@@ -827,11 +888,46 @@ impl fmt::Debug for FileContentData {
     }
 }
 
+/// Broad kind of a step error, as each kind needs a different response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The data is there but is not valid, e.g. fails to deserialize
+    Corrupt,
+    /// Likely to succeed on retry, e.g. io errors and timeouts
+    Transient,
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn classify(err: &Error) -> Self {
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<MononokeTypeError>() {
+                match e {
+                    MononokeTypeError::BlobDeserializeError(_)
+                    | MononokeTypeError::InvalidThrift(..) => return ErrorCategory::Corrupt,
+                    _ => {}
+                }
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                return if e.kind() == io::ErrorKind::InvalidData {
+                    ErrorCategory::Corrupt
+                } else {
+                    ErrorCategory::Transient
+                };
+            }
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return ErrorCategory::Transient;
+            }
+        }
+        ErrorCategory::Other
+    }
+}
+
 /// The data from the walk - this is the "full" form but not necessarily fully loaded.
 /// e.g. file content streams are passed to you to read, they aren't pre-loaded to bytes.
 #[derive(Debug)]
 pub enum NodeData {
-    ErrorAsData(Node),
+    ErrorAsData(Node, ErrorCategory),
     // Weren't able to find node
     MissingAsData(Node),
     // Node has an invalid hash
@@ -1135,6 +1231,17 @@ impl Node {
     }
 }
 
+// Type, key and path if any, only built when actually formatted
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.get_type(), self.stats_key())?;
+        if let Some(path) = self.stats_path() {
+            write!(f, " {}", path)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
@@ -1145,6 +1252,23 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_error_category() {
+        let corrupt = Error::from(MononokeTypeError::BlobDeserializeError("key".to_string()))
+            .context("loading");
+        assert_eq!(ErrorCategory::Corrupt, ErrorCategory::classify(&corrupt));
+
+        let invalid = Error::from(io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        assert_eq!(ErrorCategory::Corrupt, ErrorCategory::classify(&invalid));
+
+        let io_error = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+            .context("fetching");
+        assert_eq!(ErrorCategory::Transient, ErrorCategory::classify(&io_error));
+
+        let other = format_err!("something else");
+        assert_eq!(ErrorCategory::Other, ErrorCategory::classify(&other));
+    }
+
     #[test]
     fn test_node_size() {
         // Node size is important as we have lots of them, add a test to check for accidental changes
@@ -1159,6 +1283,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_node_type_groups() {
+        // Every group is used, so none is left empty by a type moving
+        let groups: HashSet<NodeTypeGroup> = NodeType::iter().map(|t| t.group()).collect();
+        assert_eq!(NodeTypeGroup::COUNT, groups.len());
+        for t in NodeType::iter() {
+            let name: &'static str = t.into();
+            if name.starts_with("Hg") {
+                assert_eq!(NodeTypeGroup::Hg, t.group(), "{}", t);
+            }
+            // Other than hg and filenodes, derived types are all in the derived group
+            if t.group() != NodeTypeGroup::Hg && t != NodeType::BonsaiHgMapping {
+                assert_eq!(
+                    t.derived_data_name().is_some(),
+                    t.group() == NodeTypeGroup::Derived,
+                    "{}",
+                    t
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_small_graphs() -> Result<(), Error> {
         create_graph!(
@@ -0,0 +1,263 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Error;
+use serde::Serialize;
+use slog::warn;
+use slog::Logger;
+
+// At most one warning about failed writes per interval, as a broken disk would otherwise
+// warn on every report
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When to fsync the report file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Leave it to the OS
+    Never,
+    /// After every line, so no report is lost on a crash
+    EveryReport,
+    /// Only before a full file is rotated out
+    OnRotate,
+}
+
+/// Where and how to keep a local history of progress reports, one JSON object a line
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonlOptions {
+    pub path: PathBuf,
+    /// Rotate the file before a line would take it past this size
+    pub max_bytes: u64,
+    /// Rotated files to keep, as path.1 for the newest up to path.N
+    pub keep: usize,
+    pub fsync: FsyncPolicy,
+}
+
+/// Appends records to a size rotated JSON lines file. Write failures are warned about,
+/// rate limited, and never returned, so a full disk can't stop a walk.
+pub struct JsonlSink {
+    options: JsonlOptions,
+    // Opened on first use, and again after a failure
+    file: Option<File>,
+    len: u64,
+    last_warned: Option<Instant>,
+    failed_since_warned: u64,
+}
+
+impl JsonlSink {
+    pub fn new(options: JsonlOptions) -> Self {
+        Self {
+            options,
+            file: None,
+            len: 0,
+            last_warned: None,
+            failed_since_warned: 0,
+        }
+    }
+
+    pub fn options(&self) -> &JsonlOptions {
+        &self.options
+    }
+
+    /// Append the record as a line, warning at most once a WARN_INTERVAL if it fails
+    pub fn append<T: Serialize>(&mut self, logger: &Logger, now: Instant, record: &T) {
+        if let Err(e) = self.try_append(record) {
+            // Reopen on the next append, in case the file was moved or deleted
+            self.file = None;
+            self.failed_since_warned += 1;
+            let warn_due = self
+                .last_warned
+                .is_none_or(|last| now.saturating_duration_since(last) >= WARN_INTERVAL);
+            if warn_due {
+                warn!(
+                    logger,
+                    "Failed to write progress to {}, {} failed writes since the last warning: {:?}",
+                    self.options.path.display(),
+                    self.failed_since_warned,
+                    e,
+                );
+                self.last_warned = Some(now);
+                self.failed_since_warned = 0;
+            }
+        }
+    }
+
+    fn try_append<T: Serialize>(&mut self, record: &T) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if self.file.is_none() {
+            self.open()?;
+        }
+        // Always write at least one line to a file, even one over the limit
+        if self.len > 0 && self.len + line.len() as u64 > self.options.max_bytes {
+            self.rotate()?;
+        }
+        let file = self.file.as_mut().context("Progress file not open")?;
+        file.write_all(&line)?;
+        self.len += line.len() as u64;
+        if self.options.fsync == FsyncPolicy::EveryReport {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.options.path)
+            .with_context(|| format!("Opening {}", self.options.path.display()))?;
+        self.len = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.options.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        if let Some(file) = self.file.take() {
+            if self.options.fsync == FsyncPolicy::OnRotate {
+                file.sync_data()?;
+            }
+        }
+        if self.options.keep == 0 {
+            fs::remove_file(&self.options.path)?;
+        } else {
+            // Oldest first, so each rename moves into a free slot
+            for n in (1..self.options.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.options.path, self.rotated_path(1))?;
+        }
+        self.open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use serde_json::json;
+    use slog::o;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("walker_jsonl_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn lines(path: &PathBuf) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = test_dir("rotation");
+        let path = dir.join("progress.jsonl");
+        let mut sink = JsonlSink::new(JsonlOptions {
+            path: path.clone(),
+            max_bytes: 100,
+            keep: 2,
+            fsync: FsyncPolicy::OnRotate,
+        });
+        let logger = Logger::root(slog::Discard, o!());
+        let now = Instant::now();
+        // Each line is 27 bytes, so 3 to a file
+        for i in 0..30 {
+            sink.append(&logger, now, &json!({"report": 1000 + i, "x": "abcd"}));
+        }
+
+        assert!(!sink.rotated_path(3).exists());
+        let mut reports = vec![];
+        for path in [sink.rotated_path(2), sink.rotated_path(1), path] {
+            assert!(fs::metadata(&path).unwrap().len() <= 100);
+            reports.extend(
+                lines(&path)
+                    .iter()
+                    .map(|line| line["report"].as_u64().unwrap()),
+            );
+        }
+        // The newest lines, in order, with nothing lost in the kept files
+        assert_eq!((1021..1030).collect::<Vec<_>>(), reports);
+
+        // Appends to an existing file after a restart, rotating by its size
+        let mut sink = JsonlSink::new(sink.options().clone());
+        sink.append(&logger, now, &json!({"report": 1030, "x": "abcd"}));
+        assert_eq!(1027, lines(&sink.rotated_path(1))[0]["report"]);
+        assert_eq!(1030, lines(&sink.options().path)[0]["report"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_failures_warn() {
+        let dir = test_dir("failures");
+        let warnings = Arc::new(Mutex::new(vec![]));
+        let logger = Logger::root(CountingDrain(warnings.clone()), o!());
+        let mut sink = JsonlSink::new(JsonlOptions {
+            path: dir.join("missing_dir").join("progress.jsonl"),
+            max_bytes: 100,
+            keep: 1,
+            fsync: FsyncPolicy::EveryReport,
+        });
+        let start = Instant::now();
+        for secs in [0, 1, 2, 61, 62] {
+            sink.append(&logger, start + Duration::from_secs(secs), &json!({}));
+        }
+        assert_eq!(2, warnings.lock().unwrap().len());
+        assert!(warnings.lock().unwrap()[1].contains(", 3 failed writes since"));
+
+        // Recovers once the file can be written
+        fs::create_dir_all(dir.join("missing_dir")).unwrap();
+        sink.append(
+            &logger,
+            start + Duration::from_secs(63),
+            &json!({"ok": true}),
+        );
+        assert_eq!(1, lines(&sink.options().path).len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    struct CountingDrain(Arc<Mutex<Vec<String>>>);
+
+    impl slog::Drain for CountingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record<'_>,
+            _values: &slog::OwnedKVList,
+        ) -> Result<Self::Ok, Self::Err> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+}
@@ -5,15 +5,21 @@
  * GNU General Public License version 2.
  */
 
+pub mod admin;
 pub mod blobstore;
 pub mod checkpoint;
+pub mod concurrency;
 #[macro_use]
 pub mod graph;
 pub mod corpus;
+pub mod jsonl;
 pub mod log;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod pack;
 pub mod parse_node;
 pub mod progress;
+pub mod report;
 pub mod sampling;
 pub mod scrub;
 pub mod sizing;
@@ -0,0 +1,515 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Export of the walk progress stats as OpenTelemetry metrics over OTLP, for
+//! deployments that collect metrics that way rather than from the process stats.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use once_cell::sync::OnceCell;
+use opentelemetry::metrics::Counter;
+use opentelemetry::metrics::Meter;
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::metrics::ObservableGauge;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::runtime;
+use slog::warn;
+use slog::Logger;
+
+use crate::detail::graph::NodeType;
+use crate::detail::graph::NodeTypeGroup;
+use crate::detail::progress::ProgressGauge;
+use crate::detail::progress::ProgressStat;
+use crate::detail::progress::ProgressStatsSink;
+use crate::detail::progress::ProgressTypeStat;
+
+const METER_NAME: &str = "mononoke.walker";
+
+// Either set turns the export on, the rest of the exporter config is read by the exporter
+const OTLP_ENDPOINT_VARS: &[&str] = &[
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT",
+];
+
+// One exporter for the process, shared by the per repo sinks
+static PROVIDER: OnceCell<SdkMeterProvider> = OnceCell::new();
+
+// Latest value of each gauge by (gauge, subcommand, repo), read by the gauge callbacks
+type GaugeValues = Arc<Mutex<HashMap<(ProgressGauge, &'static str, String), i64>>>;
+
+/// Passes the progress stats on to another sink, and also records the main counters
+/// and gauges as OpenTelemetry metrics, exported on each report.
+pub struct OtlpProgressStatsSink {
+    inner: Arc<dyn ProgressStatsSink>,
+    logger: Logger,
+    provider: SdkMeterProvider,
+    counters: HashMap<ProgressStat, Counter<u64>>,
+    type_counters: HashMap<ProgressTypeStat, Counter<u64>>,
+    payload_bytes: Counter<u64>,
+    gauge_values: GaugeValues,
+    // Kept so the callbacks stay registered
+    _gauges: Vec<ObservableGauge<i64>>,
+}
+
+/// An OTLP sink wrapping the given one if an OTLP endpoint is set in the environment,
+/// otherwise None
+pub fn otlp_stats_sink_from_env(
+    logger: &Logger,
+    inner: Arc<dyn ProgressStatsSink>,
+) -> Result<Option<Arc<dyn ProgressStatsSink>>, Error> {
+    if !OTLP_ENDPOINT_VARS
+        .iter()
+        .any(|var| std::env::var_os(var).is_some())
+    {
+        return Ok(None);
+    }
+    let provider = PROVIDER.get_or_try_init(|| {
+        opentelemetry_otlp::new_pipeline()
+            .metrics(runtime::Tokio)
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+            .build()
+    })?;
+    Ok(Some(Arc::new(OtlpProgressStatsSink::new(
+        logger.clone(),
+        inner,
+        provider.clone(),
+    ))))
+}
+
+/// Provider exporting to the given OTLP gRPC endpoint, rather than one from the environment
+pub fn otlp_provider(endpoint: String) -> Result<SdkMeterProvider, Error> {
+    Ok(opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .build()?)
+}
+
+fn stat_name(stat: ProgressStat) -> Option<&'static str> {
+    match stat {
+        ProgressStat::Walked => Some("walked"),
+        ProgressStat::Queued => Some("queued"),
+        ProgressStat::Errors => Some("errors"),
+        ProgressStat::Missing => Some("missing"),
+        ProgressStat::Repaired => Some("repaired"),
+        ProgressStat::Unrepairable => Some("unrepairable"),
+        ProgressStat::BlobsWritten => Some("blobs_written"),
+        ProgressStat::BytesWritten => Some("bytes_written"),
+        ProgressStat::Retries => Some("retries"),
+        // The breakdowns and bookkeeping stay in the process stats
+        ProgressStat::HashValidationFailure
+        | ProgressStat::CorruptErrors
+        | ProgressStat::TransientErrors
+        | ProgressStat::OtherErrors
+        | ProgressStat::Inconsistent
+        | ProgressStat::Heartbeat
+        | ProgressStat::Slowdown
+        | ProgressStat::OverheadMicros => None,
+    }
+}
+
+fn gauge_name(gauge: ProgressGauge) -> &'static str {
+    match gauge {
+        ProgressGauge::WalkedPerSecond => "walked_per_s",
+        ProgressGauge::QueuedPerSecond => "queued_per_s",
+        ProgressGauge::InFlight => "in_flight",
+        ProgressGauge::Outstanding => "outstanding",
+        ProgressGauge::MaxOutstanding => "max_outstanding",
+        ProgressGauge::InFlightAvg => "in_flight_avg",
+        ProgressGauge::InFlightPeak => "in_flight_peak",
+    }
+}
+
+fn type_stat_name(stat: ProgressTypeStat) -> &'static str {
+    match stat {
+        ProgressTypeStat::Walked => "walked",
+        ProgressTypeStat::Queued => "queued",
+        ProgressTypeStat::Errors => "errors",
+    }
+}
+
+fn metric_name(name: &str) -> String {
+    format!("{}.progress.{}", METER_NAME, name)
+}
+
+fn attributes(subcommand: &'static str, repo: &str) -> Vec<KeyValue> {
+    vec![
+        KeyValue::new("subcommand", subcommand),
+        KeyValue::new("repo", repo.to_string()),
+    ]
+}
+
+impl OtlpProgressStatsSink {
+    pub fn new(
+        logger: Logger,
+        inner: Arc<dyn ProgressStatsSink>,
+        provider: SdkMeterProvider,
+    ) -> Self {
+        let meter = provider.meter(METER_NAME);
+        let counters = [
+            ProgressStat::Walked,
+            ProgressStat::Queued,
+            ProgressStat::Errors,
+            ProgressStat::Missing,
+            ProgressStat::Repaired,
+            ProgressStat::Unrepairable,
+            ProgressStat::BlobsWritten,
+            ProgressStat::BytesWritten,
+            ProgressStat::Retries,
+        ]
+        .into_iter()
+        .filter_map(|stat| {
+            let name = stat_name(stat)?;
+            Some((stat, meter.u64_counter(metric_name(name)).init()))
+        })
+        .collect();
+        let type_counters = [
+            ProgressTypeStat::Walked,
+            ProgressTypeStat::Queued,
+            ProgressTypeStat::Errors,
+        ]
+        .into_iter()
+        .map(|stat| {
+            let name = format!("by_type.{}", type_stat_name(stat));
+            (stat, meter.u64_counter(metric_name(&name)).init())
+        })
+        .collect();
+        let payload_bytes = meter.u64_counter(metric_name("payload_bytes")).init();
+        let gauge_values = GaugeValues::default();
+        let gauges = [
+            ProgressGauge::WalkedPerSecond,
+            ProgressGauge::QueuedPerSecond,
+            ProgressGauge::InFlight,
+            ProgressGauge::Outstanding,
+            ProgressGauge::MaxOutstanding,
+            ProgressGauge::InFlightAvg,
+            ProgressGauge::InFlightPeak,
+        ]
+        .into_iter()
+        .map(|gauge| observable_gauge(&meter, gauge, gauge_values.clone()))
+        .collect();
+        Self {
+            inner,
+            logger,
+            provider,
+            counters,
+            type_counters,
+            payload_bytes,
+            gauge_values,
+            _gauges: gauges,
+        }
+    }
+}
+
+fn observable_gauge(
+    meter: &Meter,
+    gauge: ProgressGauge,
+    values: GaugeValues,
+) -> ObservableGauge<i64> {
+    meter
+        .i64_observable_gauge(metric_name(gauge_name(gauge)))
+        .with_callback(move |observer| {
+            for ((g, subcommand, repo), value) in values.lock().expect("lock poisoned").iter() {
+                if *g == gauge {
+                    observer.observe(*value, &attributes(subcommand, repo));
+                }
+            }
+        })
+        .init()
+}
+
+// Stats are deltas so never negative, but don't let a bad value wrap
+fn counter_value(value: i64) -> u64 {
+    value.max(0) as u64
+}
+
+impl ProgressStatsSink for OtlpProgressStatsSink {
+    fn add_value(&self, stat: ProgressStat, subcommand: &'static str, repo: &str, value: i64) {
+        self.inner.add_value(stat, subcommand, repo, value);
+        if let Some(counter) = self.counters.get(&stat) {
+            counter.add(counter_value(value), &attributes(subcommand, repo));
+        }
+    }
+
+    fn set_gauge(&self, gauge: ProgressGauge, subcommand: &'static str, repo: &str, value: i64) {
+        self.inner.set_gauge(gauge, subcommand, repo, value);
+        self.gauge_values
+            .lock()
+            .expect("lock poisoned")
+            .insert((gauge, subcommand, repo.to_string()), value);
+    }
+
+    fn add_type_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        node_type: NodeType,
+        value: i64,
+    ) {
+        self.inner
+            .add_type_value(stat, subcommand, repo, node_type, value);
+        if let Some(counter) = self.type_counters.get(&stat) {
+            let mut attributes = attributes(subcommand, repo);
+            attributes.push(KeyValue::new("node_type", node_type.to_string()));
+            counter.add(counter_value(value), &attributes);
+        }
+    }
+
+    fn add_group_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        group: NodeTypeGroup,
+        value: i64,
+    ) {
+        self.inner
+            .add_group_value(stat, subcommand, repo, group, value);
+    }
+
+    fn add_derived_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        derived: &'static str,
+        value: i64,
+    ) {
+        self.inner
+            .add_derived_value(stat, subcommand, repo, derived, value);
+    }
+
+    fn add_blobstore_reads(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        blobstore: &str,
+        value: i64,
+    ) {
+        self.inner
+            .add_blobstore_reads(subcommand, repo, blobstore, value);
+    }
+
+    fn add_payload_bytes(&self, subcommand: &'static str, repo: &str, bytes: i64) {
+        self.inner.add_payload_bytes(subcommand, repo, bytes);
+        self.payload_bytes
+            .add(counter_value(bytes), &attributes(subcommand, repo));
+    }
+
+    fn add_phase_value(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        phase: &'static str,
+        value: i64,
+    ) {
+        self.inner.add_phase_value(subcommand, repo, phase, value);
+    }
+
+    // Every report, so the final report is exported before the process exits
+    fn flush(&self) {
+        self.inner.flush();
+        if let Err(e) = self.provider.force_flush() {
+            warn!(self.logger, "Failed to export progress metrics: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::MetricsService;
+    use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::MetricsServiceServer;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+    use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceResponse;
+    use opentelemetry_proto::tonic::common::v1::any_value;
+    use opentelemetry_proto::tonic::metrics::v1::metric;
+    use opentelemetry_proto::tonic::metrics::v1::number_data_point;
+    use opentelemetry_proto::tonic::metrics::v1::NumberDataPoint;
+    use slog::o;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+
+    // Just enough of an OTLP collector to see what was exported
+    #[derive(Clone, Default)]
+    struct TestCollector(Arc<Mutex<Vec<ExportMetricsServiceRequest>>>);
+
+    #[tonic::async_trait]
+    impl MetricsService for TestCollector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportMetricsServiceRequest>,
+        ) -> Result<tonic::Response<ExportMetricsServiceResponse>, tonic::Status> {
+            self.0.lock().unwrap().push(request.into_inner());
+            Ok(tonic::Response::new(ExportMetricsServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    impl TestCollector {
+        // (attributes, value) of each data point of the named metric, in the latest export
+        fn points(&self, name: &str) -> Vec<(Vec<(String, String)>, i64)> {
+            let requests = self.0.lock().unwrap();
+            let metrics = requests
+                .last()
+                .into_iter()
+                .flat_map(|r| &r.resource_metrics)
+                .flat_map(|r| &r.scope_metrics)
+                .flat_map(|s| &s.metrics)
+                .filter(|m| m.name == name);
+            let mut points = vec![];
+            for m in metrics {
+                let data_points: &[NumberDataPoint] = match &m.data {
+                    Some(metric::Data::Sum(sum)) => &sum.data_points,
+                    Some(metric::Data::Gauge(gauge)) => &gauge.data_points,
+                    _ => &[],
+                };
+                for p in data_points {
+                    let mut attributes: Vec<_> = p
+                        .attributes
+                        .iter()
+                        .filter_map(|kv| match kv.value.as_ref()?.value.as_ref()? {
+                            any_value::Value::StringValue(s) => Some((kv.key.clone(), s.clone())),
+                            _ => None,
+                        })
+                        .collect();
+                    attributes.sort();
+                    let value = match p.value {
+                        Some(number_data_point::Value::AsInt(v)) => v,
+                        Some(number_data_point::Value::AsDouble(v)) => v as i64,
+                        None => 0,
+                    };
+                    points.push((attributes, value));
+                }
+            }
+            points
+        }
+    }
+
+    #[derive(Default)]
+    struct NoopSink;
+
+    impl ProgressStatsSink for NoopSink {
+        fn add_value(&self, _: ProgressStat, _: &'static str, _: &str, _: i64) {}
+        fn set_gauge(&self, _: ProgressGauge, _: &'static str, _: &str, _: i64) {}
+        fn add_type_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: NodeType,
+            _: i64,
+        ) {
+        }
+        fn add_group_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: NodeTypeGroup,
+            _: i64,
+        ) {
+        }
+        fn add_derived_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: &'static str,
+            _: i64,
+        ) {
+        }
+        fn add_blobstore_reads(&self, _: &'static str, _: &str, _: &str, _: i64) {}
+        fn add_payload_bytes(&self, _: &'static str, _: &str, _: i64) {}
+        fn add_phase_value(&self, _: &'static str, _: &str, _: &'static str, _: i64) {}
+    }
+
+    fn attrs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // Multi threaded, as flushing blocks on the exporter task
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_export_to_collector() -> Result<(), Error> {
+        let collector = TestCollector::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(MetricsServiceServer::new(collector.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let sink = OtlpProgressStatsSink::new(
+            Logger::root(slog::Discard, o!()),
+            Arc::new(NoopSink),
+            otlp_provider(format!("http://{}", addr))?,
+        );
+        sink.add_value(ProgressStat::Walked, "scrub", "repo", 10);
+        sink.add_value(ProgressStat::Walked, "scrub", "repo", 5);
+        sink.add_value(ProgressStat::Errors, "scrub", "repo", 1);
+        // Not exported
+        sink.add_value(ProgressStat::Heartbeat, "scrub", "repo", 1);
+        sink.add_type_value(
+            ProgressTypeStat::Walked,
+            "scrub",
+            "repo",
+            NodeType::FileContent,
+            7,
+        );
+        sink.add_payload_bytes("scrub", "repo", 4096);
+        sink.set_gauge(ProgressGauge::Outstanding, "scrub", "repo", 3);
+        sink.set_gauge(ProgressGauge::Outstanding, "scrub", "repo", 2);
+        tokio::task::spawn_blocking(move || sink.flush()).await?;
+
+        let repo = attrs(&[("repo", "repo"), ("subcommand", "scrub")]);
+        assert_eq!(
+            vec![(repo.clone(), 15)],
+            collector.points("mononoke.walker.progress.walked")
+        );
+        assert_eq!(
+            vec![(repo.clone(), 1)],
+            collector.points("mononoke.walker.progress.errors")
+        );
+        assert_eq!(
+            vec![(repo.clone(), 4096)],
+            collector.points("mononoke.walker.progress.payload_bytes")
+        );
+        assert_eq!(
+            vec![(repo.clone(), 2)],
+            collector.points("mononoke.walker.progress.outstanding")
+        );
+        assert_eq!(
+            vec![(
+                attrs(&[
+                    ("node_type", "FileContent"),
+                    ("repo", "repo"),
+                    ("subcommand", "scrub")
+                ]),
+                7
+            )],
+            collector.points("mononoke.walker.progress.by_type.walked")
+        );
+        assert!(collector
+            .points("mononoke.walker.progress.heartbeat")
+            .is_empty());
+        Ok(())
+    }
+}
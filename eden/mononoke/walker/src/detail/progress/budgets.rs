@@ -0,0 +1,428 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use slog::warn;
+
+use crate::detail::graph::NodeType;
+use crate::detail::progress::ProgressStat;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::report::BudgetViolation;
+use crate::detail::state::StepStats;
+
+/// When to warn that the walk has slowed down, e.g. from blobstore throttling
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowdownDetection {
+    /// Number of trailing throttled report rates to compare against the median of
+    pub window: usize,
+    /// How far below the trailing median, in percent, a rate must be to count as slow
+    pub drop_pct: f64,
+    /// Consecutive slow reports before warning
+    pub consecutive: usize,
+    /// Time from the start of the walk during which rates are ignored, as they climb
+    pub warm_up: Duration,
+}
+
+// Trailing walk rates of throttled reports, for SlowdownDetection
+#[derive(Debug, Default)]
+pub struct SlowdownState {
+    rates: VecDeque<f64>,
+    // Consecutive slow reports, warned about when it reaches the configured count
+    slow_reports: usize,
+}
+
+impl SlowdownState {
+    // The trailing median if the rate is slow and the window is full, then adds the rate
+    // to the window. Slow rates are added too, so a walk that settles at a lower rate
+    // stops counting as slow once the window has caught up.
+    fn slow_against(&mut self, detection: &SlowdownDetection, rate: f64) -> Option<f64> {
+        let median = (self.rates.len() >= detection.window).then(|| {
+            let mut sorted: Vec<f64> = self.rates.iter().copied().collect();
+            sorted.sort_by(f64::total_cmp);
+            sorted[sorted.len() / 2]
+        });
+        self.rates.push_back(rate);
+        while self.rates.len() > detection.window {
+            self.rates.pop_front();
+        }
+        median.filter(|median| rate < median * (1.0 - detection.drop_pct / 100.0))
+    }
+}
+
+// Bookkeeping bugs have shown up as counts that cannot happen, so describe any we see
+fn inconsistencies(current: &ProgressSummary, last: &ProgressSummary) -> Vec<String> {
+    let mut found = Vec::new();
+    if current.checked > current.walked {
+        found.push(format!(
+            "checked {} > walked {}",
+            current.checked, current.walked
+        ));
+    }
+    if current.errors > current.walked {
+        found.push(format!(
+            "errors {} > walked {}",
+            current.errors, current.walked
+        ));
+    }
+    // Sub saturates, so a counter going backwards would otherwise show as a zero delta
+    for (name, now, before) in [
+        ("walked", current.walked, last.walked),
+        ("checked", current.checked, last.checked),
+        ("children", current.queued, last.queued),
+        ("errors", current.errors, last.errors),
+        ("missing", current.missing, last.missing),
+    ] {
+        if now < before {
+            found.push(format!("{} went backwards {} -> {}", name, before, now));
+        }
+    }
+    found
+}
+
+// Problems already warned about, so each is warned about once per run
+#[derive(Default)]
+pub(super) struct BudgetWarnings {
+    // Types already warned about by check_invariants
+    pub(super) warned_inconsistent: HashSet<NodeType>,
+    // Types already warned about by check_error_budgets
+    pub(super) warned_budget: HashSet<NodeType>,
+    // Only used with slowdown detection
+    pub(super) slowdown: SlowdownState,
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    // Only reports problems, never changes the numbers that get reported
+    pub(super) fn check_invariants(
+        &mut self,
+        summary_by_type: &HashMap<NodeType, ProgressSummary>,
+    ) {
+        for t in &self.params.types_sorted_by_name {
+            let current = match summary_by_type.get(t) {
+                Some(current) => current,
+                None => continue,
+            };
+            let last = self
+                .reporting_stats
+                .last_summary_by_type
+                .get(t)
+                .cloned()
+                .unwrap_or_default();
+            let found = inconsistencies(current, &last);
+            if found.is_empty() {
+                continue;
+            }
+            self.params.stats_sink.add_value(
+                ProgressStat::Inconsistent,
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                found.len() as i64,
+            );
+            if self.reporting_stats.budgets.warned_inconsistent.insert(*t) {
+                warn!(
+                    self.params.logger,
+                    "Inconsistent progress for {}: {}",
+                    t,
+                    found.join(", ")
+                );
+            }
+        }
+    }
+
+    // Types over budget with their errors and budget, in name order
+    fn over_budget(&self) -> Vec<(NodeType, u64, u64)> {
+        let summary_by_type = self.summary_by_type();
+        self.params
+            .types_sorted_by_name
+            .iter()
+            .filter_map(|t| {
+                let budget = *self.params.options.error_budgets.get(t)?;
+                let errors = summary_by_type.get(t)?.errors;
+                (errors > budget).then_some((*t, errors, budget))
+            })
+            .collect()
+    }
+
+    /// Types whose errors so far are over their budget, in name order
+    pub fn budget_violations(&self) -> Vec<BudgetViolation> {
+        self.over_budget()
+            .into_iter()
+            .map(|(t, errors, budget)| BudgetViolation {
+                node_type: t.to_string(),
+                errors,
+                budget,
+            })
+            .collect()
+    }
+
+    // Warns once per type, the run is only marked failed once the walk completes
+    pub(super) fn check_error_budgets(&mut self) {
+        for (t, errors, budget) in self.over_budget() {
+            if self.reporting_stats.budgets.warned_budget.insert(t) {
+                warn!(
+                    self.params.logger,
+                    "Error budget exceeded for {} in {}: {} errors, budget {}",
+                    t,
+                    self.params.repo_stats_key,
+                    errors,
+                    budget,
+                );
+            }
+        }
+    }
+
+    // Warns once per slowdown, when enough consecutive throttled reports have walked well
+    // below the trailing median rate. Reports during warm up are ignored entirely.
+    pub(super) fn check_slowdown(&mut self, run_time: Duration, rate: f64) {
+        let detection = match &self.params.options.slowdown {
+            Some(detection) if run_time >= detection.warm_up => detection,
+            _ => return,
+        };
+        let slowdown = &mut self.reporting_stats.budgets.slowdown;
+        match slowdown.slow_against(detection, rate) {
+            Some(median) => {
+                slowdown.slow_reports += 1;
+                if slowdown.slow_reports == detection.consecutive {
+                    self.params.stats_sink.add_value(
+                        ProgressStat::Slowdown,
+                        self.params.subcommand_stats_key,
+                        &self.params.stats_key,
+                        1,
+                    );
+                    warn!(
+                        self.params.logger,
+                        "Walk slowed down in {}: {:.1}/s, from a median of {:.1}/s over the last {} reports",
+                        self.params.repo_stats_key,
+                        rate,
+                        median,
+                        detection.window,
+                    );
+                }
+            }
+            None => slowdown.slow_reports = 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[test]
+    fn test_inconsistencies() {
+        let last = ProgressSummary {
+            walked: 10,
+            checked: 10,
+            ..Default::default()
+        };
+        let consistent = ProgressSummary {
+            walked: 12,
+            checked: 11,
+            errors: 2,
+            ..Default::default()
+        };
+        assert!(inconsistencies(&consistent, &last).is_empty());
+
+        let current = ProgressSummary {
+            walked: 8,
+            checked: 9,
+            errors: 9,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "checked 9 > walked 8".to_string(),
+                "errors 9 > walked 8".to_string(),
+                "walked went backwards 10 -> 8".to_string(),
+                "checked went backwards 10 -> 9".to_string(),
+            ],
+            inconsistencies(&current, &last)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_inconsistent_counters_warn(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let inconsistent = StepStats {
+            visited_of_type: 5,
+            ..Default::default()
+        };
+        let inconsistent_warnings = || {
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Inconsistent"))
+                .collect::<Vec<_>>()
+        };
+
+        state.record_step(&phase_node(0), Some(&inconsistent));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let warnings = inconsistent_warnings();
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].contains("PhaseMapping"));
+        assert!(warnings[0].contains("checked 5 > walked 1"));
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Inconsistent, "repo".to_string(), 1)));
+
+        // Numbers are reported as is
+        assert_eq!(5, state.summary().checked());
+
+        // Still counted, but only warned about once per type
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(inconsistent_warnings().is_empty());
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Inconsistent, "repo".to_string(), 1)));
+
+        // Consistent types are not flagged
+        state.record_step(&changeset_node(0), Some(&children(1)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(inconsistent_warnings().is_empty());
+        assert_eq!(
+            1,
+            stats
+                .take()
+                .iter()
+                .filter(|(stat, _, _)| *stat == ProgressStat::Inconsistent)
+                .count()
+        );
+    }
+
+    #[fbinit::test]
+    fn test_error_budgets(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.error_budgets = hashmap! {
+            NodeType::Changeset => 1,
+            NodeType::PhaseMapping => 2,
+        };
+        let state = ProgressStateMutex::new(state);
+        let error = StepStats {
+            error_count: 1,
+            ..Default::default()
+        };
+
+        // Changeset is at its budget, PhaseMapping goes over it
+        state.record_step(&changeset_node(0), Some(&error));
+        for i in 0..2 {
+            state.record_step(&phase_node(i), Some(&error));
+        }
+        assert!(state.budget_violations().is_empty());
+        state.record_step(&phase_node(2), Some(&error));
+        let expected = vec![BudgetViolation {
+            node_type: "PhaseMapping".to_string(),
+            errors: 3,
+            budget: 2,
+        }];
+        assert_eq!(expected, state.budget_violations());
+
+        // Warned about once, and the walk carries on
+        state.report_progress();
+        state.record_step(&phase_node(3), Some(&error));
+        state.report_progress();
+        let warned: Vec<_> = drain
+            .take()
+            .into_iter()
+            .filter(|msg| msg.starts_with("Error budget exceeded"))
+            .collect();
+        assert_eq!(
+            vec!["Error budget exceeded for PhaseMapping in repo: 3 errors, budget 2".to_string()],
+            warned
+        );
+        assert_eq!(4, state.summary_by_type()[&NodeType::PhaseMapping].walked);
+        assert_eq!(
+            vec!["PhaseMapping".to_string()],
+            state
+                .final_report()
+                .budget_violations
+                .iter()
+                .map(|v| v.node_type.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[fbinit::test]
+    fn test_slowdown(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb)
+            .with_clock(clock.clone())
+            .with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.slowdown = Some(SlowdownDetection {
+            window: 5,
+            drop_pct: 20.0,
+            consecutive: 3,
+            warm_up: Duration::from_secs(10),
+        });
+        let mut i: u64 = 0;
+        // One throttled report a second walking the given number of steps
+        let mut report_at = |state: &mut ProgressStateCountByType<StepStats, ProgressSummary>,
+                             rate: u64| {
+            for _ in 0..rate {
+                i += 1;
+                state.record_step(&phase_node(i as u8), Some(&children(0)));
+            }
+            clock.advance(Duration::from_secs(1));
+            state.report_progress_log(Some(Duration::from_secs(1)));
+        };
+        let slowdowns = |drain: &CapturingDrain| {
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Walk slowed down"))
+                .collect::<Vec<_>>()
+        };
+
+        // Ramping up from nothing during warm up is not a slowdown, nor is the drop after
+        for rate in [1000, 10, 20, 50, 100, 200, 400, 800, 1000] {
+            report_at(&mut state, rate);
+        }
+        for _ in 0..10 {
+            report_at(&mut state, 1000);
+        }
+        assert!(slowdowns(&drain).is_empty());
+
+        // A single slow report, or a dip under the threshold, is not enough
+        for rate in [500, 1000, 1000, 850, 850, 850, 1000] {
+            report_at(&mut state, rate);
+        }
+        assert!(slowdowns(&drain).is_empty());
+
+        // A steady decline warns once
+        for rate in [900, 700, 600, 500, 450, 400, 350, 300, 250, 200, 180, 160] {
+            report_at(&mut state, rate);
+        }
+        let warnings = slowdowns(&drain);
+        assert_eq!(1, warnings.len(), "{:?}", warnings);
+        assert_eq!(
+            "Walk slowed down in repo: 450.0/s, from a median of 700.0/s over the last 5 reports",
+            warnings[0]
+        );
+        let count = stats
+            .take()
+            .into_iter()
+            .filter(|(stat, _, _)| *stat == ProgressStat::Slowdown)
+            .count();
+        assert_eq!(1, count);
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::Instant;
+
+use slog::info;
+
+use crate::detail::log;
+use crate::detail::progress::ProgressRates;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::state::StepStats;
+
+// Snapshot taken as a chunk starts, so we can report deltas within the chunk
+pub struct ChunkProgress<T> {
+    pub chunk_index: u64,
+    pub bounds_description: String,
+    pub start_time: Instant,
+    pub start_summary: T,
+}
+
+// Snapshot taken as a tail iteration starts, so we can report per-iteration numbers
+pub struct IterationProgress<T> {
+    pub iteration: u64,
+    pub start_time: Instant,
+    pub start_summary: T,
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    /// Log the summary of the chunk in progress, if any, and forget it
+    pub(super) fn report_chunk_summary(&mut self) {
+        if let Some(chunk) = self.reporting_stats.chunk.take() {
+            let chunk_summary = self.summary() - chunk.start_summary;
+            let chunk_time = self
+                .params
+                .clock
+                .now()
+                .saturating_duration_since(chunk.start_time);
+            let chunk_summary_per_s = ProgressRates::new(&chunk_summary, chunk_time);
+            info!(
+                self.params.logger,
+                #log::CHUNKING,
+                "Completed chunk {} with bounds {}: Walked/s,Children/s,Walked,Errors,Missing,Children,Time {:.1}/s,{:.1}/s,{},{},{},{},{}s",
+                chunk.chunk_index,
+                chunk.bounds_description,
+                chunk_summary_per_s.walked,
+                chunk_summary_per_s.queued,
+                chunk_summary.walked,
+                chunk_summary.errors,
+                chunk_summary.missing,
+                chunk_summary.queued,
+                chunk_time.as_secs(),
+            );
+        }
+    }
+
+    /// Log the summary of the iteration in progress, if any, and forget it
+    pub(super) fn report_iteration_summary(&mut self) {
+        if let Some(iteration) = self.reporting_stats.iteration.take() {
+            let iteration_summary = self.summary() - iteration.start_summary;
+            // Use the last report as the end, so any sleep between iterations is not counted
+            let iteration_time = self
+                .reporting_stats
+                .last_update
+                .saturating_duration_since(iteration.start_time);
+            let iteration_summary_per_s = ProgressRates::new(&iteration_summary, iteration_time);
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Completed iteration {}: Walked/s,Children/s,Walked,Errors,Missing,Children,Time {:.1}/s,{:.1}/s,{},{},{},{},{}s",
+                iteration.iteration,
+                iteration_summary_per_s.walked,
+                iteration_summary_per_s.queued,
+                iteration_summary.walked,
+                iteration_summary.errors,
+                iteration_summary.missing,
+                iteration_summary.queued,
+                iteration_time.as_secs(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[fbinit::test]
+    fn test_chunk_progress(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+
+        state.start_chunk(1, "(0, 10)".to_string());
+        for i in 0..3 {
+            state.record_step(&phase_node(i), Some(&children(2)));
+        }
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let chunk = state.reporting_stats.chunk.as_ref().unwrap();
+        assert_eq!(1, chunk.chunk_index);
+        assert_eq!(0, chunk.start_summary.walked);
+
+        state.start_chunk(2, "(10, 20)".to_string());
+        for i in 3..5 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        let chunk = state.reporting_stats.chunk.as_ref().unwrap();
+        assert_eq!(2, chunk.chunk_index);
+        assert_eq!("(10, 20)", chunk.bounds_description);
+
+        // Within chunk numbers only cover the second chunk
+        let chunk_summary = state.summary() - chunk.start_summary;
+        assert_eq!(2, chunk_summary.walked);
+        assert_eq!(2, chunk_summary.queued);
+
+        // Run numbers cover both chunks
+        let run_summary = state.summary();
+        assert_eq!(5, run_summary.walked);
+        assert_eq!(8, run_summary.queued);
+        assert_eq!(5, state.work_stats.total_progress);
+
+        // Final report closes the chunk but keeps the run totals
+        state.report_progress();
+        assert!(state.reporting_stats.chunk.is_none());
+        assert_eq!(5, state.reporting_stats.last_summary.walked);
+        assert_eq!(8, state.reporting_stats.last_summary.queued);
+    }
+
+    #[fbinit::test]
+    fn test_iteration_progress(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+
+        state.start_iteration(1);
+        for i in 0..3 {
+            state.record_step(&phase_node(i), Some(&children(2)));
+        }
+        state.report_progress();
+        let iteration = state.reporting_stats.iteration.as_ref().unwrap();
+        assert_eq!(1, iteration.iteration);
+        assert_eq!(0, iteration.start_summary.walked);
+
+        // Repeat calls within an iteration keep the existing snapshot
+        state.start_iteration(1);
+        let iteration = state.reporting_stats.iteration.as_ref().unwrap();
+        assert_eq!(0, iteration.start_summary.walked);
+
+        state.start_iteration(2);
+        for i in 3..5 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        state.report_progress();
+        let iteration = state.reporting_stats.iteration.as_ref().unwrap();
+        assert_eq!(2, iteration.iteration);
+
+        // Iteration numbers only cover the second iteration
+        let iteration_summary = state.summary() - iteration.start_summary;
+        assert_eq!(2, iteration_summary.walked);
+        assert_eq!(2, iteration_summary.queued);
+
+        // Run numbers are cumulative over both iterations
+        assert_eq!(5, state.reporting_stats.last_summary.walked);
+        assert_eq!(8, state.reporting_stats.last_summary.queued);
+        assert_eq!(5, state.work_stats.total_progress);
+    }
+}
@@ -0,0 +1,1038 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use derive_more::Add;
+use slog::info;
+use strum::EnumCount;
+use strum::IntoEnumIterator;
+
+use crate::detail::graph::Node;
+use crate::detail::graph::NodeType;
+use crate::detail::log;
+use crate::detail::progress::histograms::LatencyCounts;
+use crate::detail::progress::histograms::StepLatencies;
+use crate::detail::progress::sort_by_string;
+use crate::detail::progress::Clock;
+use crate::detail::progress::ProgressStat;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::report::VisitHitReport;
+use crate::detail::state::StepStats;
+
+// Shards and per shard cap of the in flight registry. Steps beyond the cap are not tracked.
+const IN_FLIGHT_SHARDS: usize = 16;
+const IN_FLIGHT_SHARD_CAP: usize = 1024;
+// Percentile of step durations published in each FeedbackSample
+pub(super) const FEEDBACK_LATENCY_PERCENTILE: f64 = 0.95;
+
+/// Steps started by the walk driver and not yet finished, so one that is stuck can be
+/// reported. Each step is removed when its InFlightStep is dropped, which also happens
+/// when a step panics or its task is cancelled.
+#[derive(Debug)]
+pub struct InFlightSteps {
+    shards: Vec<Mutex<HashMap<u64, (Node, Instant)>>>,
+    next_id: AtomicU64,
+    // Of the tracked steps, recorded as they finish
+    pub(super) latencies: StepLatencies,
+}
+
+impl Default for InFlightSteps {
+    fn default() -> Self {
+        Self {
+            shards: (0..IN_FLIGHT_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            next_id: AtomicU64::new(0),
+            latencies: StepLatencies::default(),
+        }
+    }
+}
+
+impl InFlightSteps {
+    fn shard(&self, id: u64) -> &Mutex<HashMap<u64, (Node, Instant)>> {
+        &self.shards[id as usize % IN_FLIGHT_SHARDS]
+    }
+
+    pub(super) fn start(self: &Arc<Self>, n: &Node) -> InFlightStep {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut shard = self.shard(id).lock().unwrap();
+        if shard.len() >= IN_FLIGHT_SHARD_CAP {
+            return InFlightStep::untracked();
+        }
+        shard.insert(id, (n.clone(), Instant::now()));
+        InFlightStep {
+            step: Some((self.clone(), id)),
+            worker: None,
+        }
+    }
+
+    /// The step that has been running longest and when it started
+    pub fn oldest(&self) -> Option<(Node, Instant)> {
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                shard
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .min_by_key(|(_, start)| *start)
+                    .cloned()
+            })
+            .min_by_key(|(_, start)| *start)
+    }
+}
+
+/// Registration of one in flight step, removed on drop
+#[must_use]
+pub struct InFlightStep {
+    step: Option<(Arc<InFlightSteps>, u64)>,
+    // The worker slot running the step, if workers are tracked
+    pub(super) worker: Option<(Arc<WorkerSlots>, usize)>,
+}
+
+impl InFlightStep {
+    /// A registration that tracks nothing
+    pub fn untracked() -> Self {
+        Self {
+            step: None,
+            worker: None,
+        }
+    }
+}
+
+impl Drop for InFlightStep {
+    fn drop(&mut self) {
+        if let Some((steps, id)) = self.step.take() {
+            // Poisoned by a panic elsewhere is fine, the map is still consistent
+            let mut shard = steps
+                .shard(id)
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some((_, start)) = shard.remove(&id) {
+                steps.latencies.record(start.elapsed());
+            }
+        }
+        if let Some((workers, id)) = self.worker.take() {
+            workers.finish(id);
+        }
+    }
+}
+
+/// The walk's recent health, published by each progress report for the walk driver to
+/// adapt to. See AdaptiveConcurrency.
+#[derive(Debug, Default)]
+pub struct ProgressFeedback {
+    latest: Mutex<Option<FeedbackSample>>,
+    // The latest sample's report, readable without the lock
+    reports: AtomicU64,
+}
+
+/// What a report saw since the previous one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeedbackSample {
+    /// Counts reports from the start of the walk, so a sample already seen can be told apart
+    pub report: u64,
+    /// Errors per step walked. Errors with nothing walked count as 1.
+    pub error_rate: f64,
+    /// The FEEDBACK_LATENCY_PERCENTILE of the steps that finished, None if none did or
+    /// the walk driver does not register steps in flight
+    pub latency: Option<Duration>,
+}
+
+impl ProgressFeedback {
+    pub(super) fn publish(&self, error_rate: f64, latency: Option<Duration>) {
+        let mut latest = self.latest.lock().unwrap();
+        let report = latest.map_or(1, |sample| sample.report + 1);
+        *latest = Some(FeedbackSample {
+            report,
+            error_rate,
+            latency,
+        });
+        self.reports.store(report, Ordering::Relaxed);
+    }
+
+    /// Reports published so far. Lock free, so a driver can check for a new sample on
+    /// every step.
+    pub fn reports(&self) -> u64 {
+        self.reports.load(Ordering::Relaxed)
+    }
+
+    /// The sample from the most recent report, None before the first
+    pub fn latest(&self) -> Option<FeedbackSample> {
+        *self.latest.lock().unwrap()
+    }
+}
+
+// What the walk driver passed on before the latest report. Each is left at its default
+// if the driver does not track it.
+#[derive(Default)]
+pub(super) struct DriverGauges {
+    // Where the walk is, as described by the walk driver. Empty if not known.
+    pub(super) position: Arc<String>,
+    // Steps queued but not started, and the most there have been
+    pub(super) outstanding: u64,
+    pub(super) max_outstanding: u64,
+    // Steps not taken due to type filters, by the type they lead to
+    pub(super) filtered: HashMap<NodeType, u64>,
+    // Visited check outcomes by type, and their total as of the last report for the
+    // timeseries
+    pub(super) visit_hits: HashMap<NodeType, VisitHits>,
+    pub(super) last_visit_hits: VisitHits,
+    // The longest running step and when it started, if the walk driver registers steps
+    pub(super) oldest_in_flight: Option<(Node, Instant)>,
+    pub(super) in_flight_occupancy: Option<InFlightWindow>,
+    // Step durations as of the latest and the last report, for the feedback latency
+    pub(super) step_latencies: LatencyCounts,
+    pub(super) last_step_latencies: LatencyCounts,
+    pub(super) feedback: Arc<ProgressFeedback>,
+    // Indexed by worker id
+    pub(super) workers: Vec<WorkerActivity>,
+}
+
+pub(super) fn error_rate(summary: &ProgressSummary) -> f64 {
+    match (summary.walked, summary.errors) {
+        (_, 0) => 0.0,
+        (0, _) => 1.0,
+        (walked, errors) => errors as f64 / walked as f64,
+    }
+}
+
+/// What one of the walk's workers has done, as of the last report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkerActivity {
+    pub steps: u64,
+    // Start of the current step if busy, otherwise when the last step finished
+    pub last_active: Instant,
+    pub busy: bool,
+}
+
+struct WorkerSlotsInner {
+    workers: Vec<WorkerActivity>,
+    free: BinaryHeap<cmp::Reverse<usize>>,
+}
+
+/// The walk's concurrent workers, each a slot running one step at a time. A step takes
+/// the lowest free slot when registered in flight and frees it when the registration is
+/// dropped, so there are never more workers than the walk's concurrency.
+pub struct WorkerSlots {
+    clock: Arc<dyn Clock>,
+    max_workers: usize,
+    inner: Mutex<WorkerSlotsInner>,
+}
+
+impl fmt::Debug for WorkerSlots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerSlots")
+            .field("max_workers", &self.max_workers)
+            .finish()
+    }
+}
+
+impl WorkerSlots {
+    pub fn new(max_workers: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            max_workers,
+            inner: Mutex::new(WorkerSlotsInner {
+                workers: Vec::new(),
+                free: BinaryHeap::new(),
+            }),
+        }
+    }
+
+    // None if every slot is busy, which only happens if the walk runs more steps at
+    // once than it was configured to
+    pub(super) fn start(&self) -> Option<usize> {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        let id = match inner.free.pop() {
+            Some(cmp::Reverse(id)) => id,
+            None if inner.workers.len() < self.max_workers => {
+                inner.workers.push(WorkerActivity {
+                    steps: 0,
+                    last_active: now,
+                    busy: false,
+                });
+                inner.workers.len() - 1
+            }
+            None => return None,
+        };
+        let worker = &mut inner.workers[id];
+        worker.busy = true;
+        worker.last_active = now;
+        Some(id)
+    }
+
+    pub(super) fn finish(&self, id: usize) {
+        let now = self.clock.now();
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let worker = &mut inner.workers[id];
+        worker.steps += 1;
+        worker.busy = false;
+        worker.last_active = now;
+        inner.free.push(cmp::Reverse(id));
+    }
+
+    /// Each worker's activity, indexed by worker id
+    pub fn snapshot(&self) -> Vec<WorkerActivity> {
+        self.inner.lock().unwrap().workers.clone()
+    }
+}
+
+/// Steps queued by the walk driver but not yet started. Updated on every step, so kept in
+/// atomics rather than under the progress lock. A dequeue can be seen before the enqueue
+/// it pairs with, so the raw count may briefly dip below zero, but it is never reported
+/// as negative and evens out once both are seen.
+#[derive(Debug, Default)]
+pub struct OutstandingWork {
+    current: AtomicI64,
+    max: AtomicI64,
+}
+
+impl OutstandingWork {
+    pub(super) fn enqueued(&self, count: u64) {
+        let count = count as i64;
+        let current = self.current.fetch_add(count, Ordering::Relaxed) + count;
+        self.max.fetch_max(current, Ordering::Relaxed);
+    }
+
+    pub(super) fn dequeued(&self, count: u64) {
+        self.current.fetch_sub(count as i64, Ordering::Relaxed);
+    }
+
+    /// Current and high water mark
+    pub fn get(&self) -> (u64, u64) {
+        let current = self.current.load(Ordering::Relaxed).max(0);
+        // The max is updated just after the current count, so may not have caught up yet
+        let max = self.max.load(Ordering::Relaxed).max(current);
+        (current as u64, max as u64)
+    }
+}
+
+/// Steps the walk would have taken but for its node and edge type filters, by the type
+/// of node they lead to. Counted before the node is made, so lock free and cheap enough
+/// for every edge.
+#[derive(Debug)]
+pub struct FilteredSteps {
+    by_type: [AtomicU64; NodeType::COUNT],
+}
+
+impl Default for FilteredSteps {
+    fn default() -> Self {
+        Self {
+            by_type: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl FilteredSteps {
+    pub(super) fn record(&self, t: NodeType) {
+        self.by_type[t as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Types with any steps filtered out
+    pub fn get(&self) -> HashMap<NodeType, u64> {
+        NodeType::iter()
+            .filter_map(|t| {
+                let count = self.by_type[t as usize].load(Ordering::Relaxed);
+                (count > 0).then_some((t, count))
+            })
+            .collect()
+    }
+}
+
+/// Steps offered to the walk's visited check, and how many of them it turned away as
+/// their node was already visited
+#[derive(Add, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VisitHits {
+    pub offered: u64,
+    pub already_visited: u64,
+}
+
+impl VisitHits {
+    /// Share of offered steps already visited, as a percentage. None if none were offered.
+    pub fn hit_pct(&self) -> Option<f64> {
+        (self.offered > 0).then(|| 100.0 * self.already_visited as f64 / self.offered as f64)
+    }
+
+    pub(super) fn report(&self) -> VisitHitReport {
+        VisitHitReport {
+            offered: self.offered,
+            already_visited: self.already_visited,
+            hit_pct: self.hit_pct().unwrap_or_default(),
+        }
+    }
+}
+
+/// Outcomes of the walk's visited check by the type of node checked, to show how much
+/// its deduplication saves. Lock free, as the check runs for every edge.
+#[derive(Debug)]
+pub struct VisitChecks {
+    offered: [AtomicU64; NodeType::COUNT],
+    already_visited: [AtomicU64; NodeType::COUNT],
+}
+
+impl Default for VisitChecks {
+    fn default() -> Self {
+        Self {
+            offered: std::array::from_fn(|_| AtomicU64::new(0)),
+            already_visited: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl VisitChecks {
+    pub(super) fn record(&self, t: NodeType, already_visited: bool) {
+        self.offered[t as usize].fetch_add(1, Ordering::Relaxed);
+        if already_visited {
+            self.already_visited[t as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Types with any steps offered
+    pub fn get(&self) -> HashMap<NodeType, VisitHits> {
+        NodeType::iter()
+            .filter_map(|t| {
+                // Loaded first as it is updated last, so it is never ahead of offered
+                let already_visited = self.already_visited[t as usize].load(Ordering::Relaxed);
+                let offered = self.offered[t as usize].load(Ordering::Relaxed);
+                (offered > 0).then_some((
+                    t,
+                    VisitHits {
+                        offered: offered.max(already_visited),
+                        already_visited,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Steps running at once in the walk driver's buffered stream, as set by the driver each
+/// time it changes. Lock free. The fields are updated separately so a window taken
+/// while they change can be off by an update, which is fine for tuning concurrency.
+#[derive(Debug, Default)]
+pub struct InFlightOccupancy {
+    // Not reported until the driver first sets it
+    used: AtomicBool,
+    current: AtomicU64,
+    // Since the window was last taken
+    sum: AtomicU64,
+    updates: AtomicU64,
+    peak: AtomicU64,
+}
+
+/// Occupancy over the time between two reports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InFlightWindow {
+    /// Averaged over the updates in the window
+    pub avg: f64,
+    pub peak: u64,
+    /// The most the driver runs at once, if known
+    pub limit: Option<u64>,
+}
+
+impl InFlightOccupancy {
+    pub(super) fn set(&self, in_flight: u64) {
+        self.used.store(true, Ordering::Relaxed);
+        self.current.store(in_flight, Ordering::Relaxed);
+        self.sum.fetch_add(in_flight, Ordering::Relaxed);
+        self.updates.fetch_add(1, Ordering::Relaxed);
+        self.peak.fetch_max(in_flight, Ordering::Relaxed);
+    }
+
+    // The window since this was last called, starting the next one from the current
+    // occupancy. With no updates in between, the occupancy held steady all along.
+    pub(super) fn take(&self, limit: Option<u64>) -> Option<InFlightWindow> {
+        if !self.used.load(Ordering::Relaxed) {
+            return None;
+        }
+        let current = self.current.load(Ordering::Relaxed);
+        let updates = self.updates.swap(0, Ordering::Relaxed);
+        let sum = self.sum.swap(0, Ordering::Relaxed);
+        let peak = self.peak.swap(current, Ordering::Relaxed);
+        Some(InFlightWindow {
+            avg: if updates == 0 {
+                current as f64
+            } else {
+                sum as f64 / updates as f64
+            },
+            peak: peak.max(current),
+            limit,
+        })
+    }
+}
+
+// Each type's share of steps already visited, in type name order
+pub(super) fn visit_hits_detail(visit_hits: &HashMap<NodeType, VisitHits>) -> String {
+    sort_by_string(visit_hits.keys())
+        .iter()
+        .filter_map(|t| Some(format!("{}:{:.1}", t, visit_hits[t].hit_pct()?)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    /// The busy worker inactive longest, how long for, and the median inactivity over all
+    /// workers. None with fewer than two workers or none busy.
+    pub fn slowest_worker(&self, now: Instant) -> Option<(usize, Duration, Duration)> {
+        let workers = &self.reporting_stats.gauges.workers;
+        if workers.len() < 2 {
+            return None;
+        }
+        let age = |w: &WorkerActivity| now.saturating_duration_since(w.last_active);
+        let (id, slowest) = workers
+            .iter()
+            .enumerate()
+            .filter(|(_id, w)| w.busy)
+            // Lowest id on ties
+            .max_by_key(|(id, w)| (age(w), cmp::Reverse(*id)))?;
+        let mut ages: Vec<Duration> = workers.iter().map(age).collect();
+        ages.sort();
+        Some((id, age(slowest), ages[ages.len() / 2]))
+    }
+
+    // How much the visited check saved, as the share of steps it found already visited
+    pub(super) fn report_visit_hits(&self) {
+        let total = self.total_visit_hits();
+        if let Some(pct) = total.hit_pct() {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Steps already visited, % of offered: {:.1} of {} {}",
+                pct,
+                total.offered,
+                visit_hits_detail(&self.reporting_stats.gauges.visit_hits);
+                "visit_offered" => total.offered,
+                "already_visited" => total.already_visited,
+            );
+        }
+    }
+
+    // Type filters leave steps out before they are walked, so say how many, as otherwise
+    // they look the same as parts of the graph that were never reached
+    pub(super) fn report_filtered(&self) {
+        let filtered = &self.reporting_stats.gauges.filtered;
+        if filtered.is_empty() {
+            return;
+        }
+        let total: u64 = filtered.values().sum();
+        let detail = sort_by_string(filtered.keys())
+            .iter()
+            .map(|t| format!("{}:{}", t, filtered[t]))
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "Steps filtered out by node or edge type: {} {}",
+            total,
+            detail;
+            "filtered" => total,
+        );
+    }
+
+    pub(super) fn total_visit_hits(&self) -> VisitHits {
+        self.reporting_stats
+            .gauges
+            .visit_hits
+            .values()
+            .fold(VisitHits::default(), |acc, hits| acc + *hits)
+    }
+
+    // Deltas since the last report, so the hit ratio can be graphed from the two
+    pub(super) fn report_visit_stats(&mut self) {
+        let hits = self.total_visit_hits();
+        let last = self.reporting_stats.gauges.last_visit_hits;
+        if hits == last {
+            return;
+        }
+        for (stat, value, last) in [
+            (ProgressStat::VisitOffered, hits.offered, last.offered),
+            (
+                ProgressStat::AlreadyVisited,
+                hits.already_visited,
+                last.already_visited,
+            ),
+        ] {
+            self.params.stats_sink.add_value(
+                stat,
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                value.saturating_sub(last) as i64,
+            );
+        }
+        self.reporting_stats.gauges.last_visit_hits = hits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::btreemap;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[fbinit::test]
+    fn test_outstanding_work(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let state = ProgressStateMutex::new(state);
+
+        // A dequeue seen before its enqueue is not shown as negative, and does not leave
+        // the count off once the enqueue arrives
+        state.record_dequeued(1);
+        assert_eq!((0, 0), state.outstanding());
+        state.record_enqueued(1);
+        assert_eq!((0, 0), state.outstanding());
+
+        // Each thread queues children then starts them, as the walk does
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                let state = state.clone();
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        state.record_enqueued(2);
+                        state.record_dequeued(1);
+                        let (outstanding, max_outstanding) = state.outstanding();
+                        assert!(outstanding <= max_outstanding);
+                        state.record_dequeued(1);
+                    }
+                });
+            }
+            // Reporting while the counts change
+            s.spawn(|| {
+                for _ in 0..100 {
+                    state.report_throttled();
+                }
+            });
+        });
+        let (outstanding, max_outstanding) = state.outstanding();
+        assert_eq!(0, outstanding);
+        assert!((2..=16).contains(&max_outstanding), "{}", max_outstanding);
+
+        state.record_enqueued(3);
+        let max_outstanding = max_outstanding.max(3);
+        state.record_step(&phase_node(0), Some(&children(0)));
+        drain.take();
+        state.report_progress();
+        let logged = drain.take();
+        let expected = format!("Outstanding,Max 3,{}; ", max_outstanding);
+        assert!(
+            logged
+                .iter()
+                .any(|msg| msg.starts_with("Walked/s") && msg.contains(&expected)),
+            "{:?}",
+            logged
+        );
+        assert_eq!(Some(3), stats.gauge(ProgressGauge::Outstanding, "repo"));
+        assert_eq!(
+            Some(max_outstanding as i64),
+            stats.gauge(ProgressGauge::MaxOutstanding, "repo")
+        );
+    }
+
+    #[fbinit::test]
+    fn test_oldest_in_flight(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let state = ProgressStateMutex::new(state);
+        let stuck_progress = |state: &ProgressStateMutex<_>| {
+            drain.take();
+            state.report_progress();
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Walked/s"))
+                .find_map(|msg| {
+                    msg.split("; ")
+                        .find(|part| part.starts_with("Oldest in flight"))
+                        .map(str::to_string)
+                })
+        };
+
+        // Long lived step, with quick ones coming and going alongside it
+        let stuck = state.record_in_flight(&phase_node(7));
+        for i in 0..100 {
+            let _quick = state.record_in_flight(&phase_node(i));
+        }
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(None, stuck_progress(&state));
+
+        clock.advance(Duration::from_secs(91));
+        let expected = format!("Oldest in flight 120s {:?}", phase_node(7));
+        assert_eq!(Some(expected), stuck_progress(&state));
+
+        drop(stuck);
+        assert_eq!(None, state.in_flight.oldest());
+        assert_eq!(None, stuck_progress(&state));
+
+        // A step that panics is still removed
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _step = state.record_in_flight(&phase_node(8));
+            panic!("step failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(None, state.in_flight.oldest());
+    }
+
+    #[fbinit::test]
+    fn test_stalled_worker(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.worker_skew_threshold = Some(Duration::from_secs(10));
+        let state = ProgressStateMutex::new(state).with_worker_slots(3);
+        let slowest = |state: &ProgressStateMutex<_>| {
+            state.report_throttled();
+            drain.take().into_iter().find_map(|msg| {
+                msg.split("; ")
+                    .find(|part| part.starts_with("Slowest worker"))
+                    .map(str::to_string)
+            })
+        };
+
+        // Worker 0 wedges on its first step, while 1 and 2 keep going
+        let wedged = state.record_in_flight(&phase_node(0));
+        for i in 1..=20 {
+            let a = state.record_in_flight(&phase_node(i));
+            let b = state.record_in_flight(&phase_node(i + 100));
+            // More steps than workers are not attributed
+            let c = state.record_in_flight(&phase_node(i + 200));
+            assert!(c.worker.is_none());
+            clock.advance(Duration::from_secs(1));
+            state.record_step(&phase_node(i), Some(&children(0)));
+            drop((a, b, c));
+            if i == 5 {
+                assert_eq!(None, slowest(&state));
+            }
+        }
+        assert_eq!(
+            Some("Slowest worker 0 20s, median 0s".to_string()),
+            slowest(&state)
+        );
+
+        let report = state.final_report();
+        assert_eq!(
+            vec![(0, 0, 20.0, true), (1, 20, 0.0, false), (2, 20, 0.0, false)],
+            report
+                .workers
+                .iter()
+                .map(|w| (w.id, w.steps, w.idle_secs, w.busy))
+                .collect::<Vec<_>>()
+        );
+
+        // Once it finishes its slot is reused first, and nothing is called out
+        drop(wedged);
+        let next = state.record_in_flight(&phase_node(50));
+        assert_eq!(Some(0), next.worker.as_ref().map(|(_, id)| *id));
+        drop(next);
+        clock.advance(Duration::from_secs(1));
+        state.record_step(&phase_node(50), Some(&children(0)));
+        assert_eq!(None, slowest(&state));
+        assert_eq!(2, state.final_report().workers[0].steps);
+
+        // Untracked without worker slots
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        let step = state.record_in_flight(&phase_node(0));
+        assert!(step.worker.is_none());
+        assert!(state.final_report().workers.is_empty());
+    }
+
+    #[fbinit::test]
+    fn test_in_flight_occupancy(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let state = ProgressStateMutex::new(state).with_in_flight_limit(8);
+        let report = || {
+            drain.take();
+            state
+                .lock_for_report()
+                .report_progress_log(Some(Duration::from_secs(1)));
+            drain.take().remove(0)
+        };
+
+        // Not shown until the driver sets it
+        state.record_step(&phase_node(0), Some(&children(0)));
+        assert!(!report().contains("InFlight"));
+
+        for in_flight in [2, 4, 6] {
+            state.set_in_flight(in_flight);
+        }
+        let line = report();
+        assert!(
+            line.contains("InFlight Avg,Peak,Limit 4.0,6,8; "),
+            "{}",
+            line
+        );
+        assert_eq!(Some(4), stats.gauge(ProgressGauge::InFlightAvg, "repo"));
+        assert_eq!(Some(6), stats.gauge(ProgressGauge::InFlightPeak, "repo"));
+
+        // Nothing changed, so it stayed at the last value throughout
+        let line = report();
+        assert!(
+            line.contains("InFlight Avg,Peak,Limit 6.0,6,8; "),
+            "{}",
+            line
+        );
+
+        // Each window starts from where the last ended, so its peak counts the 6 held
+        // at the start
+        state.set_in_flight(3);
+        state.set_in_flight(1);
+        let line = report();
+        assert!(
+            line.contains("InFlight Avg,Peak,Limit 2.0,6,8; "),
+            "{}",
+            line
+        );
+        let line = report();
+        assert!(
+            line.contains("InFlight Avg,Peak,Limit 1.0,1,8; "),
+            "{}",
+            line
+        );
+    }
+
+    #[fbinit::test]
+    fn test_progress_feedback(fb: FacebookInit) {
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        let feedback = state.feedback().unwrap();
+        let report = || {
+            state
+                .lock_for_report()
+                .report_progress_log(Some(Duration::from_secs(1)));
+            feedback.latest().unwrap()
+        };
+        assert_eq!(None, feedback.latest());
+        assert_eq!(0, feedback.reports());
+
+        for i in 0..3 {
+            let _in_flight = state.record_in_flight(&phase_node(i));
+            state.record_step(&phase_node(i), Some(&children(0)));
+        }
+        state.record_step(
+            &changeset_node(0),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        let sample = report();
+        assert_eq!(1, sample.report);
+        assert_eq!(1, feedback.reports());
+        assert_eq!(0.25, sample.error_rate);
+        // Three quick steps, all well under a second
+        assert!(sample
+            .latency
+            .is_some_and(|latency| latency < Duration::from_secs(1)));
+
+        // Only what happened since the last report
+        let sample = report();
+        assert_eq!(
+            (2, 0.0, None),
+            (sample.report, sample.error_rate, sample.latency)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_filtered_steps(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let state = ProgressStateMutex::new(state);
+
+        // A walk offered steps to four types, only two of which pass its filters
+        let offered = [
+            NodeType::Changeset,
+            NodeType::PhaseMapping,
+            NodeType::FileContent,
+            NodeType::BonsaiHgMapping,
+        ];
+        std::thread::scope(|s| {
+            for thread in 0..4u8 {
+                let state = state.clone();
+                s.spawn(move || {
+                    for i in 0..25u8 {
+                        let t = offered[(i % 4) as usize];
+                        let i = thread * 25 + i;
+                        match t {
+                            NodeType::Changeset => {
+                                state.record_step(&changeset_node(i), Some(&children(0)))
+                            }
+                            NodeType::PhaseMapping => {
+                                state.record_step(&phase_node(i), Some(&children(0)))
+                            }
+                            _ => state.record_filtered(t),
+                        }
+                    }
+                });
+            }
+        });
+
+        let report = state.final_report();
+        let offered_of = |t: NodeType| {
+            let t = t.to_string();
+            report.types.get(&t).map_or(0, |r| r.walked) + report.filtered.get(&t).unwrap_or(&0)
+        };
+        assert_eq!(28, offered_of(NodeType::Changeset));
+        assert_eq!(24, offered_of(NodeType::PhaseMapping));
+        assert_eq!(24, offered_of(NodeType::FileContent));
+        assert_eq!(24, offered_of(NodeType::BonsaiHgMapping));
+        assert_eq!(
+            btreemap! {
+                "BonsaiHgMapping".to_string() => 24,
+                "FileContent".to_string() => 24,
+            },
+            report.filtered
+        );
+
+        state.report_progress();
+        assert!(drain.take().contains(
+            &"Steps filtered out by node or edge type: 48 BonsaiHgMapping:24 FileContent:24"
+                .to_string()
+        ));
+    }
+
+    #[fbinit::test]
+    fn test_visit_hits(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let state = ProgressStateMutex::new(state);
+
+        // Each changeset is offered twice and the phase four times, as a visited check
+        // backed by a set would see them
+        let mut visited = HashSet::new();
+        let offered = (0..4)
+            .chain(0..4)
+            .map(changeset_node)
+            .chain(std::iter::repeat_n(phase_node(0), 4));
+        for node in offered {
+            state.record_visit_check(node.get_type(), !visited.insert(node));
+        }
+
+        let report = state.final_report();
+        assert_eq!(
+            Some(VisitHitReport {
+                offered: 12,
+                already_visited: 7,
+                hit_pct: 700.0 / 12.0,
+            }),
+            report.visit_hits
+        );
+        assert_eq!(50.0, report.visit_hits_by_type["Changeset"].hit_pct);
+        assert_eq!(75.0, report.visit_hits_by_type["PhaseMapping"].hit_pct);
+
+        state.report_progress();
+        let logged = drain.take();
+        assert!(
+            logged[0].contains("; Visited% 58.3 Changeset:50.0 PhaseMapping:75.0; "),
+            "{:?}",
+            logged
+        );
+        assert!(
+            logged.contains(
+                &"Steps already visited, % of offered: 58.3 of 12 Changeset:50.0 PhaseMapping:75.0"
+                    .to_string()
+            ),
+            "{:?}",
+            logged
+        );
+
+        // The timeseries get what changed since the last report
+        for node in [changeset_node(0), changeset_node(4)] {
+            state.record_visit_check(node.get_type(), !visited.insert(node));
+        }
+        state.report_progress();
+        let visit_stats = stats
+            .values
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(stat, _, _)| {
+                matches!(
+                    stat,
+                    ProgressStat::VisitOffered | ProgressStat::AlreadyVisited
+                )
+            })
+            .map(|(stat, _, value)| (*stat, *value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (ProgressStat::VisitOffered, 12),
+                (ProgressStat::AlreadyVisited, 7),
+                (ProgressStat::VisitOffered, 2),
+                (ProgressStat::AlreadyVisited, 1),
+            ],
+            visit_stats
+        );
+    }
+    #[fbinit::test]
+    fn test_position(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.interval = Duration::ZERO;
+        let state = ProgressStateMutex::new(state);
+        let reports = || {
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Walked/s"))
+                .collect::<Vec<_>>()
+        };
+
+        // Nothing shown until a position is set
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.report_throttled();
+        let logged = reports();
+        assert_eq!(1, logged.len());
+        assert!(!logged[0].contains("Position"));
+
+        state.set_position("(0, 10)".to_string());
+        state.set_position("(10, 20)".to_string());
+        state.record_step(&phase_node(1), Some(&children(0)));
+        state.report_throttled();
+        let logged = reports();
+        assert_eq!(1, logged.len());
+        assert!(logged[0].contains("Position (10, 20); "));
+
+        // Final report has the latest position too
+        state.set_position("(20, 30)".to_string());
+        state.report_progress();
+        let logged = reports();
+        assert_eq!(1, logged.len());
+        assert!(logged[0].contains("Position (20, 30); "));
+    }
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use slog::info;
+
+use crate::detail::graph::NodeType;
+use crate::detail::log;
+use crate::detail::progress::options::NumberFormat;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::report::PayloadBucketReport;
+use crate::detail::report::PayloadSizeReport;
+use crate::detail::state::StepStats;
+
+// Buckets of step durations, by powers of two microseconds. The last bucket takes
+// everything from about 9 days.
+const LATENCY_BUCKETS: usize = 40;
+
+/// Durations of finished steps, counted in buckets by powers of two microseconds. Lock
+/// free, as every step updates it.
+#[derive(Debug)]
+pub struct StepLatencies {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl Default for StepLatencies {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl StepLatencies {
+    pub(super) fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        // Bucket b holds [2^(b-1), 2^b) microseconds, and bucket 0 holds 0
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts so far, from the start of the walk
+    pub fn snapshot(&self) -> LatencyCounts {
+        LatencyCounts(
+            self.buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+        )
+    }
+}
+
+/// Counts of step durations per StepLatencies bucket
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyCounts(Vec<u64>);
+
+impl LatencyCounts {
+    /// The steps counted here but not in an earlier snapshot
+    pub fn since(&self, earlier: &LatencyCounts) -> LatencyCounts {
+        LatencyCounts(
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(i, count)| count.saturating_sub(earlier.0.get(i).copied().unwrap_or(0)))
+                .collect(),
+        )
+    }
+
+    /// Upper bound of the bucket holding the given fraction of steps, None if there are none
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let total: u64 = self.0.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let target = ((total as f64 * fraction).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        self.0.iter().enumerate().find_map(|(bucket, count)| {
+            seen += count;
+            (seen >= target).then(|| Duration::from_micros(1 << bucket))
+        })
+    }
+}
+
+/// Histogram buckets for step payload sizes, as a mean hides the few huge blobs that can
+/// dominate a walk. Memory is fixed by the number of buckets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PayloadSizeOptions {
+    /// Increasing upper bounds in bytes, each bucket excluding its bound. A last bucket
+    /// takes everything from the highest bound up.
+    pub bounds: Vec<u64>,
+    /// Types that also get a histogram of their own
+    pub types: HashSet<NodeType>,
+}
+
+impl Default for PayloadSizeOptions {
+    fn default() -> Self {
+        Self {
+            // Powers of ten from 1k to 1G
+            bounds: (3..=9).map(|exp| 10u64.pow(exp)).collect(),
+            types: HashSet::new(),
+        }
+    }
+}
+
+// Max number of payload size buckets, so histograms stay small
+pub(super) const MAX_PAYLOAD_BUCKETS: usize = 64;
+
+/// Counts of payload sizes by bucket, for bounds from PayloadSizeOptions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PayloadHistogram {
+    counts: Vec<u64>,
+    total_bytes: u64,
+    max_bytes: u64,
+}
+
+impl PayloadHistogram {
+    pub(super) fn add(&mut self, bounds: &[u64], bytes: u64) {
+        self.counts.resize(bounds.len() + 1, 0);
+        self.counts[bounds.partition_point(|bound| *bound <= bytes)] += 1;
+        self.total_bytes += bytes;
+        self.max_bytes = cmp::max(self.max_bytes, bytes);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn report(&self, bounds: &[u64]) -> PayloadSizeReport {
+        PayloadSizeReport {
+            buckets: self
+                .counts
+                .iter()
+                .enumerate()
+                .map(|(i, count)| PayloadBucketReport {
+                    below: bounds.get(i).copied(),
+                    count: *count,
+                })
+                .collect(),
+            count: self.count(),
+            total_bytes: self.total_bytes,
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    // e.g. <1000:5 <10000:2 >=10000:1 max 12345
+    pub(super) fn detail(&self, bounds: &[u64], numbers: NumberFormat) -> String {
+        let mut detail: Vec<String> = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| match bounds.get(i) {
+                Some(bound) => format!("<{}:{}", numbers.count(*bound), count),
+                None => format!(
+                    ">={}:{}",
+                    numbers.count(*bounds.last().unwrap_or(&0)),
+                    count
+                ),
+            })
+            .collect();
+        detail.push(format!("max {}", numbers.count(self.max_bytes)));
+        detail.join(" ")
+    }
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    // Final payload size distributions, only logged if any step had a payload size
+    pub(super) fn report_payload_sizes(&self) {
+        let bounds = &self.params.options.payload_sizes.bounds;
+        let numbers = self.params.options.number_format;
+        if self.work_stats.payload_sizes.count() == 0 {
+            return;
+        }
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "Payload bytes {}",
+            self.work_stats.payload_sizes.detail(bounds, numbers),
+        );
+        for t in &self.params.types_sorted_by_name {
+            if let Some(histogram) = self.work_stats.payload_sizes_by_type.get(t) {
+                info!(
+                    self.params.logger,
+                    #log::GRAPH,
+                    "Payload bytes {} {}",
+                    t,
+                    histogram.detail(bounds, numbers),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashset;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[fbinit::test]
+    fn test_payload_sizes(fb: FacebookInit) {
+        let sink = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(sink.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.payload_sizes = PayloadSizeOptions {
+            bounds: vec![10, 100, 1000],
+            types: hashset! {NodeType::PhaseMapping},
+        };
+        let payload = |bytes| StepStats {
+            payload_bytes: Some(bytes),
+            ..Default::default()
+        };
+
+        for (i, bytes) in [0, 5, 10, 50, 99, 100, 5000].into_iter().enumerate() {
+            state.record_step(&phase_node(i as u8), Some(&payload(bytes)));
+        }
+        state.record_steps(&[
+            (changeset_node(0), Some(payload(20000))),
+            (changeset_node(1), Some(StepStats::default())),
+            (changeset_node(2), None),
+        ]);
+
+        let bucket_counts = |report: &PayloadSizeReport| {
+            report
+                .buckets
+                .iter()
+                .map(|b| (b.below, b.count))
+                .collect::<Vec<_>>()
+        };
+        let report = state.final_report();
+        let overall = report.payload_sizes.unwrap();
+        assert_eq!(
+            vec![(Some(10), 2), (Some(100), 3), (Some(1000), 1), (None, 2)],
+            bucket_counts(&overall)
+        );
+        assert_eq!(8, overall.count);
+        assert_eq!(25264, overall.total_bytes);
+        assert_eq!(20000, overall.max_bytes);
+        let phases = &report.payload_sizes_by_type["PhaseMapping"];
+        assert_eq!(
+            vec![(Some(10), 2), (Some(100), 3), (Some(1000), 1), (None, 1)],
+            bucket_counts(phases)
+        );
+        assert_eq!(5000, phases.max_bytes);
+        assert_eq!(1, report.payload_sizes_by_type.len());
+
+        assert_eq!(
+            vec![0, 5, 10, 50, 99, 100, 5000, 20000],
+            sink.take_payload_bytes()
+        );
+        drain.take();
+        state.report_payload_sizes();
+        assert_eq!(
+            vec![
+                "Payload bytes <10:2 <100:3 <1000:1 >=1000:2 max 20000",
+                "Payload bytes PhaseMapping <10:2 <100:3 <1000:1 >=1000:1 max 5000",
+            ],
+            drain.take()
+        );
+    }
+
+    #[test]
+    fn test_latency_percentile() {
+        let latencies = StepLatencies::default();
+        assert_eq!(None, latencies.snapshot().percentile(0.95));
+        for micros in [0, 1, 3, 100, 100, 100, 100, 100, 100, 5000] {
+            latencies.record(Duration::from_micros(micros));
+        }
+        let counts = latencies.snapshot();
+        assert_eq!(Some(Duration::from_micros(1)), counts.percentile(0.1));
+        assert_eq!(Some(Duration::from_micros(128)), counts.percentile(0.5));
+        assert_eq!(Some(Duration::from_micros(8192)), counts.percentile(0.95));
+
+        latencies.record(Duration::from_secs(1));
+        assert_eq!(
+            Some(Duration::from_micros(1 << 20)),
+            latencies.snapshot().since(&counts).percentile(0.95)
+        );
+    }
+}
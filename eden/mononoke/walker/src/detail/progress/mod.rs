@@ -0,0 +1,3598 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Add;
+use std::ops::Sub;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::bail;
+use anyhow::Error;
+use arc_swap::ArcSwap;
+use context::CoreContext;
+use derive_more::Add;
+use fbinit::FacebookInit;
+use futures::stream;
+use futures::stream::Stream;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
+use scuba_ext::MononokeScubaSampleBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::debug;
+use slog::info;
+use slog::o;
+use slog::warn;
+use slog::Logger;
+use strum::IntoEnumIterator;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::detail::graph::ErrorCategory;
+use crate::detail::graph::Node;
+use crate::detail::graph::NodeType;
+use crate::detail::graph::NodeTypeGroup;
+use crate::detail::log;
+use crate::detail::report::BudgetViolation;
+use crate::detail::report::FinalReport;
+use crate::detail::state::BlobstoreReads;
+use crate::detail::state::StepPhase;
+use crate::detail::state::StepStats;
+
+mod budgets;
+mod chunk;
+mod gauges;
+mod histograms;
+mod options;
+mod reports;
+mod sinks;
+mod throttle;
+
+use budgets::BudgetWarnings;
+pub use budgets::SlowdownDetection;
+pub use budgets::SlowdownState;
+pub use chunk::ChunkProgress;
+pub use chunk::IterationProgress;
+use gauges::DriverGauges;
+pub use gauges::FeedbackSample;
+pub use gauges::FilteredSteps;
+pub use gauges::InFlightOccupancy;
+pub use gauges::InFlightStep;
+pub use gauges::InFlightSteps;
+pub use gauges::InFlightWindow;
+pub use gauges::OutstandingWork;
+pub use gauges::ProgressFeedback;
+pub use gauges::VisitChecks;
+pub use gauges::VisitHits;
+pub use gauges::WorkerActivity;
+pub use gauges::WorkerSlots;
+pub use histograms::LatencyCounts;
+pub use histograms::PayloadHistogram;
+pub use histograms::PayloadSizeOptions;
+pub use histograms::StepLatencies;
+pub use options::human_count;
+pub use options::human_duration;
+pub use options::NumberFormat;
+pub use options::ProgressDisplay;
+pub use options::ProgressOptions;
+pub use options::ProgressOptionsBuilder;
+pub use options::QuietMode;
+pub use options::TypeGrouping;
+pub use options::UnchangedProgress;
+use reports::summarize_by_type;
+use reports::InterestBaseline;
+pub use sinks::iso_timestamp;
+pub use sinks::DefaultProgressStatsSink;
+use sinks::EmittedStats;
+pub use sinks::ProgressBar;
+pub use sinks::ProgressStatsSink;
+pub use sinks::ReportChannel;
+pub use sinks::ReportLine;
+use sinks::ReportSinks;
+pub use sinks::ScubaRows;
+pub use throttle::PausedTime;
+pub use throttle::ReportTimes;
+use throttle::ThrottleState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProgressStat {
+    Walked,
+    Queued,
+    Errors,
+    Missing,
+    HashValidationFailure,
+    CorruptErrors,
+    TransientErrors,
+    OtherErrors,
+    Repaired,
+    Unrepairable,
+    BlobsWritten,
+    BytesWritten,
+    Retries,
+    // Progress bookkeeping that breaks an invariant, see check_invariants
+    Inconsistent,
+    Heartbeat,
+    // Sustained drops in walk rate, see SlowdownDetection
+    Slowdown,
+    // Estimated time spent in progress bookkeeping, see ProgressOverhead
+    OverheadMicros,
+    // Steps offered to the walk's visited check, and those turned away, see VisitChecks
+    VisitOffered,
+    AlreadyVisited,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProgressGauge {
+    WalkedPerSecond,
+    QueuedPerSecond,
+    // Children queued but not yet walked
+    InFlight,
+    // As reported by the walk driver, see OutstandingWork
+    Outstanding,
+    MaxOutstanding,
+    // Steps running in the walk driver's buffered stream, see InFlightOccupancy
+    InFlightAvg,
+    InFlightPeak,
+}
+
+// The subset of stats also kept per NodeType and NodeTypeGroup
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProgressTypeStat {
+    Walked,
+    Queued,
+    Errors,
+}
+
+// Max number of already available steps progress_stream records under one lock
+const PROGRESS_BATCH_SIZE: usize = 1000;
+// Max number of distinct erroring nodes remembered per type
+const DISTINCT_ERRORS_CAP: usize = 10000;
+// Max number of distinct repo keys remembered once folded into OTHER_REPO_KEY
+const FOLDED_KEYS_CAP: usize = 10000;
+// Max number of recent erroring nodes kept for the report
+const RECENT_ERRORS_CAP: usize = 100;
+/// Key of the per repo stats past the max_repo_keys cap
+pub const OTHER_REPO_KEY: &str = "(other)";
+
+// Scuba columns for the per type progress rows
+const NODE_TYPE: &str = "node_type";
+const WALKED: &str = "walked";
+const QUEUED: &str = "queued";
+const ERRORS: &str = "errors";
+const DELTA_WALKED: &str = "delta_walked";
+const DELTA_QUEUED: &str = "delta_queued";
+const DELTA_ERRORS: &str = "delta_errors";
+// NODE_TYPE of the row for all types together
+const TOTAL: &str = "total";
+// Set on every row and progress record, so rows from one run can be told apart from
+// concurrent ones
+const RUN_ID: &str = "run_id";
+// UTC ISO-8601 time, set on every row and progress record so they can be lined up with
+// other sources whatever the log drain's own time format
+const TIMESTAMP: &str = "timestamp";
+// Characters of the run id shown in the progress line, enough to tell runs apart
+const SHORT_RUN_ID_LEN: usize = 8;
+// Set on every row when the walk has an instance label, see with_walk_instance
+const WALK_INSTANCE: &str = "walk_instance";
+// Columns only on the rows with the final counts, logged once per run
+const FINAL: &str = "final";
+const CHECKED: &str = "checked";
+const ELAPSED_SECS: &str = "elapsed_secs";
+// How long flush waits for the batched rows to be sent at the end of a run
+const SCUBA_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the progress recorder can learn about each step beyond its type
+pub trait StepProgress {
+    fn error_count(&self) -> u64 {
+        0
+    }
+
+    fn children(&self) -> u64 {
+        0
+    }
+
+    fn blobstore_reads(&self) -> BlobstoreReads {
+        BlobstoreReads::default()
+    }
+
+    /// Only looked at for changeset steps
+    fn phase(&self) -> Option<StepPhase> {
+        None
+    }
+
+    /// Size of the data loaded by the step, e.g. file content size
+    fn payload_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    /// Hash validation failures, which mean the stored data is corrupt
+    fn hash_mismatches(&self) -> u64 {
+        0
+    }
+}
+
+impl StepProgress for StepStats {
+    fn error_count(&self) -> u64 {
+        self.error_count as u64
+    }
+
+    fn children(&self) -> u64 {
+        self.num_expanded_new as u64
+    }
+
+    fn blobstore_reads(&self) -> BlobstoreReads {
+        self.blobstore_reads
+    }
+
+    fn phase(&self) -> Option<StepPhase> {
+        self.phase
+    }
+
+    fn payload_bytes(&self) -> Option<u64> {
+        self.payload_bytes
+    }
+
+    fn hash_mismatches(&self) -> u64 {
+        self.hash_validation_failure_count as u64
+    }
+}
+
+pub trait ProgressRecorderUnprotected<SS> {
+    fn record_step(&mut self, n: &Node, ss: Option<&SS>);
+
+    fn record_steps(&mut self, batch: &[(Node, Option<SS>)]) {
+        for (n, ss) in batch {
+            self.record_step(n, ss.as_ref());
+        }
+    }
+
+    fn set_sample_builder(&mut self, s: MononokeScubaSampleBuilder);
+
+    /// Stats of failed attempts at a step that is being retried, added to its type
+    /// without counting another step walked
+    fn record_retries(&mut self, _n: &Node, _ss: &SS) {}
+
+    /// Start of a known wait, e.g. for a lock or a back off, to leave out of run rates.
+    /// Calls may nest, with the time counted until the outermost is resumed.
+    fn pause(&mut self) {}
+
+    /// End of a wait started by pause. Extra calls are ignored.
+    fn resume(&mut self) {}
+
+    /// Where each report publishes the walk's recent health, if it does
+    fn feedback(&self) -> Option<Arc<ProgressFeedback>> {
+        None
+    }
+}
+
+pub trait ProgressReporterUnprotected {
+    /// Report progress unconditional. e.g. called at end of run to report final progress
+    fn report_progress(&mut self);
+
+    /// Apply your own throttling criteria, once called might chose to do nothing,
+    /// in which case return None, otherwise return how long since last report.
+    fn report_throttled(&mut self);
+
+    /// Called by chunked walks as each chunk begins, so reports can show within-chunk progress.
+    fn start_chunk(&mut self, _chunk_index: u64, _bounds_description: String) {}
+
+    /// How often report_heartbeat should be called from the background, if at all
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called periodically regardless of whether steps are being recorded, so that
+    /// a stalled walk still reports.
+    fn report_heartbeat(&mut self) {}
+
+    /// Called by tailing walks as each iteration begins, so reports can show per-iteration
+    /// progress alongside the run totals. Repeat calls for the same iteration do nothing.
+    fn start_iteration(&mut self, _iteration: u64) {}
+
+    /// Which reports the caller should request
+    fn quiet_mode(&self) -> QuietMode {
+        QuietMode::Full
+    }
+
+    /// Latest position set on the recorder, passed on before each report. Empty if unset.
+    fn update_position(&mut self, _position: Arc<String>) {}
+
+    /// Latest outstanding work and its high water mark, passed on before each report
+    fn update_outstanding(&mut self, _outstanding: u64, _max_outstanding: u64) {}
+
+    /// Steps filtered out by type so far, passed on before each report
+    fn update_filtered(&mut self, _filtered: HashMap<NodeType, u64>) {}
+
+    /// Outcomes of the visited check by type so far, passed on before each report
+    fn update_visit_hits(&mut self, _hits: HashMap<NodeType, VisitHits>) {}
+
+    /// The longest running step and when it started, passed on before each report
+    fn update_oldest_in_flight(&mut self, _oldest: Option<(Node, Instant)>) {}
+
+    /// Occupancy of the walk driver's buffered stream since the last report, passed on
+    /// before each report. None if the driver does not set it.
+    fn update_in_flight_occupancy(&mut self, _window: Option<InFlightWindow>) {}
+
+    /// Activity of each of the walk's workers, passed on before each report. Empty if
+    /// workers are not tracked.
+    fn update_workers(&mut self, _workers: Vec<WorkerActivity>) {}
+
+    /// Durations of the steps registered in flight so far, passed on before each report
+    fn update_step_latencies(&mut self, _latencies: LatencyCounts) {}
+
+    /// Send anything still batched. Called at the end of the run and when it aborts, so
+    /// nothing from the reports made so far is lost.
+    fn flush(&mut self) {}
+}
+
+/// Maps a node to the repo it was walked in, for walks covering several repos
+pub type RepoKeyFn = Arc<dyn Fn(&Node) -> &str + Send + Sync>;
+
+/// Source of time for progress reporting, so tests can control it
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, which unlike now can jump, e.g. when corrected by NTP
+    fn wall_now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+pub struct ProgressStateByTypeParams {
+    pub fb: FacebookInit,
+    pub logger: Logger,
+    pub subcommand_stats_key: &'static str,
+    pub repo_stats_key: String,
+    pub types_sorted_by_name: Vec<NodeType>,
+    // If set, stats are also kept per repo
+    pub repo_key_fn: Option<RepoKeyFn>,
+    // If set, repos past this many keep their stats together under OTHER_REPO_KEY
+    pub max_repo_keys: Option<usize>,
+    pub clock: Arc<dyn Clock>,
+    pub stats_sink: Arc<dyn ProgressStatsSink>,
+    // Unique to this progress state, added to every scuba row
+    pub run_id: String,
+    // Tells this walk's output apart from other walks in the process. Empty for none.
+    pub walk_instance: String,
+    // repo_stats_key namespaced by walk_instance, for stats keys
+    pub stats_key: String,
+    // Discards until set_sample_builder is called
+    pub scuba_builder: MononokeScubaSampleBuilder,
+    // Included types that may legitimately never be walked, e.g. only reachable
+    // from data that has not been derived, so are not warned about
+    pub unwalked_ok_types: HashSet<NodeType>,
+    // Walked for traversal only, so only counted in WorkByType::uncounted
+    pub excluded_from_progress: HashSet<NodeType>,
+    options: ProgressOptions,
+}
+
+impl ProgressStateByTypeParams {
+    /// Stats key for the repo, namespaced by the walk instance if there is one
+    pub fn instance_key(&self, repo: &str) -> String {
+        if self.walk_instance.is_empty() {
+            repo.to_string()
+        } else {
+            format!("{}.{}", repo, self.walk_instance)
+        }
+    }
+
+    pub fn quiet_mode(&self) -> QuietMode {
+        self.options.quiet
+    }
+
+    /// Groups of the included types, in NodeTypeGroup order
+    pub fn groups(&self) -> Vec<NodeTypeGroup> {
+        let included: HashSet<NodeTypeGroup> = self
+            .types_sorted_by_name
+            .iter()
+            .map(|t| t.group())
+            .collect();
+        NodeTypeGroup::iter()
+            .filter(|g| included.contains(g))
+            .collect()
+    }
+}
+
+pub struct ProgressStateWorkByType<SS>
+where
+    SS: Add<SS, Output = SS> + Default,
+{
+    pub stats_by_type: HashMap<NodeType, (u64, SS)>,
+    // Only populated when a repo_key_fn is set
+    pub stats_by_repo: HashMap<String, HashMap<NodeType, (u64, SS)>>,
+    // Repo keys counted under OTHER_REPO_KEY as they were over the max_repo_keys cap
+    pub folded_repo_keys: FoldedKeys,
+    pub distinct_errors_by_type: HashMap<NodeType, DistinctErrors>,
+    pub children_by_type: HashMap<NodeType, ChildrenStats>,
+    total_progress: u64,
+    // Steps of types excluded from progress, walked but in none of the above
+    uncounted: u64,
+    // Over all steps, including uncounted ones as the reads still happened
+    blobstore_reads: BlobstoreReads,
+    pub changeset_phases: PhaseCounts,
+    // When each type was first and most recently walked
+    pub seen_by_type: HashMap<NodeType, (Instant, Instant)>,
+    pub payload_sizes: PayloadHistogram,
+    // Only for the types in PayloadSizeOptions::types
+    pub payload_sizes_by_type: HashMap<NodeType, PayloadHistogram>,
+    pub recent_errors: RecentErrors,
+}
+
+// Distribution of new children per step, as a few huge nodes behave very
+// differently to many small ones
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChildrenStats {
+    pub steps: u64,
+    pub total: u64,
+    pub max: u64,
+}
+
+/// Changesets walked by phase. Unknown where the walk had not looked the phase up.
+#[derive(
+    Add,
+    Clone,
+    Copy,
+    Default,
+    Debug,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize
+)]
+pub struct PhaseCounts {
+    pub public: u64,
+    pub draft: u64,
+    pub unknown: u64,
+}
+
+impl PhaseCounts {
+    fn record(&mut self, phase: Option<StepPhase>) {
+        match phase {
+            Some(StepPhase::Public) => self.public += 1,
+            Some(StepPhase::Draft) => self.draft += 1,
+            None => self.unknown += 1,
+        }
+    }
+
+    pub fn by_name(&self) -> [(&'static str, u64); 3] {
+        [
+            ("public", self.public),
+            ("draft", self.draft),
+            ("unknown", self.unknown),
+        ]
+    }
+}
+
+impl ChildrenStats {
+    fn add(&mut self, children: u64) {
+        self.steps += 1;
+        self.total += children;
+        self.max = cmp::max(self.max, children);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.steps += other.steps;
+        self.total += other.total;
+        self.max = cmp::max(self.max, other.max);
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.steps > 0 {
+            self.total as f64 / self.steps as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+// Only one in this many record_step calls is timed, so the timing stays cheap
+const OVERHEAD_SAMPLE_RATE: u64 = 64;
+
+// Time spent in the progress bookkeeping itself. Steps are timed 1 in
+// OVERHEAD_SAMPLE_RATE and scaled up, reports are infrequent so are always timed.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ProgressOverhead {
+    pub record_calls: u64,
+    pub record_timed: u64,
+    pub record_timed_duration: Duration,
+    pub report_calls: u64,
+    pub report_duration: Duration,
+    // Estimate in microseconds as of the last overhead stat emitted
+    last_emitted_us: u64,
+}
+
+impl ProgressOverhead {
+    // Returns the start time if this batch of calls is one to time
+    fn start_record(&mut self, calls: u64) -> Option<Instant> {
+        let sampled = self.record_calls / OVERHEAD_SAMPLE_RATE
+            != (self.record_calls + calls) / OVERHEAD_SAMPLE_RATE;
+        self.record_calls += calls;
+        sampled.then(|| {
+            self.record_timed += calls;
+            Instant::now()
+        })
+    }
+
+    fn finish_record(&mut self, started: Option<Instant>) {
+        if let Some(started) = started {
+            self.record_timed_duration += started.elapsed();
+        }
+    }
+
+    fn add_report(&mut self, duration: Duration) {
+        self.report_calls += 1;
+        self.report_duration += duration;
+    }
+
+    /// Estimated total time spent recording steps
+    pub fn record_duration(&self) -> Duration {
+        if self.record_timed == 0 {
+            return Duration::ZERO;
+        }
+        self.record_timed_duration
+            .mul_f64(self.record_calls as f64 / self.record_timed as f64)
+    }
+
+    /// Estimated total time spent in progress bookkeeping
+    pub fn total(&self) -> Duration {
+        self.record_duration() + self.report_duration
+    }
+
+    /// Estimated overhead as a percentage of the elapsed time
+    pub fn pct_of(&self, elapsed: Duration) -> f64 {
+        if elapsed.is_zero() {
+            0.0
+        } else {
+            (self.total().as_secs_f64() * 100.0 / elapsed.as_secs_f64()).min(100.0)
+        }
+    }
+
+    // Estimate added in microseconds since this was last called. Whole microseconds
+    // are tracked so the emitted deltas sum to the total.
+    fn take_delta_us(&mut self) -> u64 {
+        let total_us = self.total().as_micros() as u64;
+        let delta = total_us.saturating_sub(self.last_emitted_us);
+        self.last_emitted_us = total_us;
+        delta
+    }
+}
+
+// Nodes that errored, as retries and revisits can report the same node many times.
+// Bounded, so only exact up to DISTINCT_ERRORS_CAP.
+#[derive(Default)]
+pub struct DistinctErrors {
+    nodes: HashSet<Node>,
+    truncated: bool,
+}
+
+impl DistinctErrors {
+    fn insert(&mut self, n: &Node) {
+        if self.nodes.contains(n) {
+            return;
+        }
+        if self.nodes.len() < DISTINCT_ERRORS_CAP {
+            self.nodes.insert(n.clone());
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.nodes.len() as u64
+    }
+
+    /// Whether there were more distinct nodes than we could track
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+// Keys folded into a shared bucket. Bounded like DistinctErrors, so only exact up to
+// FOLDED_KEYS_CAP.
+#[derive(Default)]
+pub struct FoldedKeys {
+    keys: HashSet<String>,
+    truncated: bool,
+}
+
+impl FoldedKeys {
+    fn insert(&mut self, key: &str) {
+        if self.keys.contains(key) {
+            return;
+        }
+        if self.keys.len() < FOLDED_KEYS_CAP {
+            self.keys.insert(key.to_string());
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.keys.len() as u64
+    }
+
+    /// Whether there were more distinct keys than we could track
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl fmt::Display for FoldedKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.count())?;
+        if self.truncated {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+/// The most recent erroring nodes by key, oldest first, with the kind of error each had.
+/// Only the last RECENT_ERRORS_CAP are kept.
+#[derive(Default)]
+pub struct RecentErrors {
+    errors: VecDeque<(ErrorCategory, String)>,
+}
+
+impl RecentErrors {
+    fn push(&mut self, category: ErrorCategory, key: String) {
+        if self.errors.len() == RECENT_ERRORS_CAP {
+            self.errors.pop_front();
+        }
+        self.errors.push_back((category, key));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(ErrorCategory, String)> {
+        self.errors.iter()
+    }
+}
+
+impl fmt::Display for DistinctErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.count())?;
+        if self.truncated {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
+fn add_step<SS>(stats_by_type: &mut HashMap<NodeType, (u64, SS)>, k: NodeType, opt: Option<&SS>)
+where
+    SS: Add<SS, Output = SS> + Copy + Default,
+{
+    let entry = stats_by_type.entry(k).or_insert((0, SS::default()));
+    entry.0 += 1;
+    if let Some(ss) = opt {
+        entry.1 = entry.1 + *ss;
+    }
+}
+
+impl<SS> ProgressStateWorkByType<SS>
+where
+    SS: Add<SS, Output = SS> + Copy + Default + StepProgress,
+{
+    /// Number of steps recorded so far
+    pub fn total_progress(&self) -> u64 {
+        self.total_progress
+    }
+
+    /// Number of steps of types excluded from progress
+    pub fn uncounted(&self) -> u64 {
+        self.uncounted
+    }
+
+    /// Reads so far by blobstore id
+    pub fn blobstore_reads(&self) -> &BlobstoreReads {
+        &self.blobstore_reads
+    }
+
+    // The step's payload size, if it had one
+    fn record_payload(
+        &mut self,
+        n: &Node,
+        opt: Option<&SS>,
+        options: &PayloadSizeOptions,
+    ) -> Option<u64> {
+        let bytes = opt.and_then(|ss| ss.payload_bytes())?;
+        self.payload_sizes.add(&options.bounds, bytes);
+        if options.types.contains(&n.get_type()) {
+            self.payload_sizes_by_type
+                .entry(n.get_type())
+                .or_default()
+                .add(&options.bounds, bytes);
+        }
+        Some(bytes)
+    }
+
+    fn record_seen(&mut self, t: NodeType, now: Instant) {
+        self.seen_by_type
+            .entry(t)
+            .and_modify(|(_first, last)| *last = now)
+            .or_insert((now, now));
+    }
+
+    fn record_blobstore_reads(&mut self, opt: Option<&SS>) {
+        if let Some(reads) = opt.map(|ss| ss.blobstore_reads()) {
+            if !reads.is_empty() {
+                self.blobstore_reads = self.blobstore_reads + reads;
+            }
+        }
+    }
+
+    /// Number of nodes of the given type walked so far
+    pub fn walked_of_type(&self, t: NodeType) -> u64 {
+        self.stats_by_type.get(&t).map_or(0, |(walked, _)| *walked)
+    }
+
+    fn record_step(&mut self, n: &Node, opt: Option<&SS>) {
+        // Global stats
+        self.total_progress += 1;
+        // Bonsai only, so each changeset is counted once
+        if n.get_type() == NodeType::Changeset {
+            self.changeset_phases.record(opt.and_then(|ss| ss.phase()));
+        }
+        // By type
+        add_step(&mut self.stats_by_type, n.get_type(), opt);
+        if let Some(ss) = opt {
+            self.children_by_type
+                .entry(n.get_type())
+                .or_default()
+                .add(ss.children());
+        }
+        if opt.is_some_and(|ss| ss.error_count() > 0) {
+            self.distinct_errors_by_type
+                .entry(n.get_type())
+                .or_default()
+                .insert(n);
+        }
+    }
+
+    // Only the stats, as the step itself is recorded once it completes
+    fn record_retries(&mut self, t: NodeType, ss: &SS) {
+        let entry = self.stats_by_type.entry(t).or_insert((0, SS::default()));
+        entry.1 = entry.1 + *ss;
+    }
+
+    /// Distinct erroring nodes over all types, and whether that is a lower bound
+    pub fn distinct_errors(&self) -> (u64, bool) {
+        self.distinct_errors_by_type
+            .values()
+            .fold((0, false), |(count, truncated), v| {
+                (count + v.count(), truncated || v.truncated())
+            })
+    }
+
+    fn record_repo_step(
+        &mut self,
+        repo: &str,
+        max_repo_keys: Option<usize>,
+        n: &Node,
+        opt: Option<&SS>,
+    ) {
+        let repo = match max_repo_keys {
+            Some(max) if !self.stats_by_repo.contains_key(repo) => {
+                let tracked = self.stats_by_repo.len()
+                    - usize::from(self.stats_by_repo.contains_key(OTHER_REPO_KEY));
+                if tracked >= max {
+                    self.folded_repo_keys.insert(repo);
+                    OTHER_REPO_KEY
+                } else {
+                    repo
+                }
+            }
+            _ => repo,
+        };
+        // Avoid allocating the key for repos we have already seen
+        let stats_by_type = match self.stats_by_repo.get_mut(repo) {
+            Some(stats_by_type) => stats_by_type,
+            None => self.stats_by_repo.entry(repo.to_string()).or_default(),
+        };
+        add_step(stats_by_type, n.get_type(), opt);
+    }
+}
+
+#[derive(Add, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ProgressSummary {
+    walked: u64,
+    checked: u64,
+    queued: u64,
+    errors: u64,
+    missing: u64,
+    hash_validation_failure: u64,
+    // Breakdown of errors
+    corrupt: u64,
+    transient: u64,
+    other_errors: u64,
+    // Scrub repairs, zero for other subcommands
+    repaired: u64,
+    unrepairable: u64,
+    // Blobs put back to the blobstore, zero for walks that only read
+    blobs_written: u64,
+    bytes_written: u64,
+    // Failed attempts at steps that were retried
+    retries: u64,
+}
+
+impl ProgressSummary {
+    pub fn walked(&self) -> u64 {
+        self.walked
+    }
+
+    pub fn checked(&self) -> u64 {
+        self.checked
+    }
+
+    pub fn queued(&self) -> u64 {
+        self.queued
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    pub fn missing(&self) -> u64 {
+        self.missing
+    }
+
+    pub fn hash_validation_failure(&self) -> u64 {
+        self.hash_validation_failure
+    }
+
+    pub fn corrupt(&self) -> u64 {
+        self.corrupt
+    }
+
+    pub fn transient(&self) -> u64 {
+        self.transient
+    }
+
+    pub fn other_errors(&self) -> u64 {
+        self.other_errors
+    }
+
+    pub fn repaired(&self) -> u64 {
+        self.repaired
+    }
+
+    pub fn unrepairable(&self) -> u64 {
+        self.unrepairable
+    }
+
+    pub fn blobs_written(&self) -> u64 {
+        self.blobs_written
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+
+    /// Failed attempts per step walked, zero before anything is walked
+    pub fn retries_per_walked(&self) -> f64 {
+        if self.walked > 0 {
+            self.retries as f64 / self.walked as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+// Saturating so that counters that were reset (e.g. state cleared) give a zero delta
+// rather than wrapping around.
+impl Sub for ProgressSummary {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            walked: self.walked.saturating_sub(other.walked),
+            checked: self.checked.saturating_sub(other.checked),
+            queued: self.queued.saturating_sub(other.queued),
+            errors: self.errors.saturating_sub(other.errors),
+            missing: self.missing.saturating_sub(other.missing),
+            hash_validation_failure: self
+                .hash_validation_failure
+                .saturating_sub(other.hash_validation_failure),
+            corrupt: self.corrupt.saturating_sub(other.corrupt),
+            transient: self.transient.saturating_sub(other.transient),
+            other_errors: self.other_errors.saturating_sub(other.other_errors),
+            repaired: self.repaired.saturating_sub(other.repaired),
+            unrepairable: self.unrepairable.saturating_sub(other.unrepairable),
+            blobs_written: self.blobs_written.saturating_sub(other.blobs_written),
+            bytes_written: self.bytes_written.saturating_sub(other.bytes_written),
+            retries: self.retries.saturating_sub(other.retries),
+        }
+    }
+}
+
+// Rates are only for display, so kept as f64 to show fractional rates on short intervals
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct ProgressRates {
+    walked: f64,
+    queued: f64,
+    blobs_written: f64,
+    bytes_written: f64,
+}
+
+impl ProgressRates {
+    fn new(summary: &ProgressSummary, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            Self {
+                walked: summary.walked as f64 / secs,
+                queued: summary.queued as f64 / secs,
+                blobs_written: summary.blobs_written as f64 / secs,
+                bytes_written: summary.bytes_written as f64 / secs,
+            }
+        } else {
+            Self::default()
+        }
+    }
+}
+
+// Takes a summary type as a parameter. e.g. ProgressSummary
+pub struct ProgressStateReporting<T> {
+    pub start_time: Instant,
+    pub last_summary_by_type: HashMap<NodeType, T>,
+    pub last_summary: T,
+    pub last_update: Instant,
+    // Only populated when a repo_key_fn is set
+    pub last_summary_by_repo: HashMap<String, T>,
+    // Only set while a chunked walk is in a chunk
+    pub chunk: Option<ChunkProgress<T>>,
+    // Only set when tailing
+    pub iteration: Option<IterationProgress<T>>,
+    // Left out of run rates
+    pub paused: PausedTime,
+    throttle: ThrottleState,
+    // As passed on by the walk driver before each report
+    gauges: DriverGauges,
+    budgets: BudgetWarnings,
+    sinks: ReportSinks,
+    emitted: EmittedStats<T>,
+    interest: InterestBaseline<T>,
+}
+
+// Can retain between runs to have cumulative progress reported
+pub struct ProgressStateCountByType<SS, T>
+where
+    SS: Add<SS, Output = SS> + Default,
+{
+    pub params: ProgressStateByTypeParams,
+    pub work_stats: ProgressStateWorkByType<SS>,
+    pub reporting_stats: ProgressStateReporting<T>,
+    pub overhead: ProgressOverhead,
+}
+
+/// Cumulative counts from a progress state, to retain between runs or to fan in the
+/// progress of a walk split into shards. Merging is associative and commutative, so
+/// snapshots can be combined in any order. Distinct errors and per repo stats are
+/// not carried over.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProgressSnapshot {
+    // Wall clock, as an Instant can't be saved. None in an empty snapshot.
+    pub start_time: Option<SystemTime>,
+    pub total_progress: u64,
+    // By type name, like the final report
+    pub types: BTreeMap<String, TypeSnapshot>,
+    // Steps of types excluded from progress
+    #[serde(default)]
+    pub uncounted: u64,
+    #[serde(default)]
+    pub changeset_phases: PhaseCounts,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TypeSnapshot {
+    pub walked: u64,
+    pub stats: StepStats,
+    pub children: ChildrenStats,
+}
+
+impl ProgressSnapshot {
+    /// Add in another snapshot's counts, keeping the earliest start time
+    pub fn merge(&mut self, other: ProgressSnapshot) {
+        self.start_time = match (self.start_time, other.start_time) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+        self.total_progress += other.total_progress;
+        self.uncounted += other.uncounted;
+        self.changeset_phases = self.changeset_phases + other.changeset_phases;
+        for (name, t) in other.types {
+            let entry = self.types.entry(name).or_default();
+            entry.walked += t.walked;
+            entry.stats = entry.stats + t.stats;
+            entry.children.merge(&t.children);
+        }
+    }
+}
+
+pub fn sort_by_string<C, T>(c: C) -> Vec<T>
+where
+    C: IntoIterator<Item = T>,
+    T: ToString,
+{
+    let mut v: Vec<_> = c.into_iter().collect();
+    v.sort_by_key(|k| k.to_string());
+    v
+}
+
+/// Builds a ProgressStateCountByType, checking its options and types make sense
+pub struct ProgressStateBuilder {
+    fb: FacebookInit,
+    logger: Logger,
+    subcommand_stats_key: &'static str,
+    repo_stats_key: String,
+    included_types: HashSet<NodeType>,
+    unwalked_ok_types: HashSet<NodeType>,
+    excluded_from_progress: HashSet<NodeType>,
+    options: ProgressOptionsBuilder,
+    repo_key_fn: Option<RepoKeyFn>,
+    max_repo_keys: Option<usize>,
+    walk_instance: String,
+    clock: Option<Arc<dyn Clock>>,
+    stats_sink: Option<Arc<dyn ProgressStatsSink>>,
+    report_sender: Option<mpsc::Sender<ReportLine>>,
+}
+
+impl ProgressStateBuilder {
+    pub fn new(
+        fb: FacebookInit,
+        logger: Logger,
+        subcommand_stats_key: &'static str,
+        repo_stats_key: String,
+    ) -> Self {
+        Self {
+            fb,
+            logger,
+            subcommand_stats_key,
+            repo_stats_key,
+            included_types: HashSet::new(),
+            unwalked_ok_types: HashSet::new(),
+            excluded_from_progress: HashSet::new(),
+            options: ProgressOptionsBuilder::default(),
+            repo_key_fn: None,
+            max_repo_keys: None,
+            walk_instance: String::new(),
+            clock: None,
+            stats_sink: None,
+            report_sender: None,
+        }
+    }
+
+    pub fn with_included_types(mut self, included_types: HashSet<NodeType>) -> Self {
+        self.included_types = included_types;
+        self
+    }
+
+    /// Included types not to warn about if the walk never reaches them
+    pub fn with_unwalked_ok_types(mut self, unwalked_ok_types: HashSet<NodeType>) -> Self {
+        self.unwalked_ok_types = unwalked_ok_types;
+        self
+    }
+
+    /// Types to walk without counting them in progress, as their counts would swamp the
+    /// rest. Their steps are only counted as a total.
+    pub fn with_excluded_from_progress(mut self, excluded: HashSet<NodeType>) -> Self {
+        self.excluded_from_progress = excluded;
+        self
+    }
+
+    /// Replaces the default options, they are still validated by build
+    pub fn with_options(mut self, options: ProgressOptions) -> Self {
+        self.options.options = options;
+        self
+    }
+
+    pub fn with_repo_key_fn(mut self, repo_key_fn: RepoKeyFn) -> Self {
+        self.repo_key_fn = Some(repo_key_fn);
+        self
+    }
+
+    pub fn with_max_repo_keys(mut self, max_repo_keys: usize) -> Self {
+        self.max_repo_keys = Some(max_repo_keys);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn with_stats_sink(mut self, stats_sink: Arc<dyn ProgressStatsSink>) -> Self {
+        self.stats_sink = Some(stats_sink);
+        self
+    }
+
+    pub fn with_walk_instance(mut self, walk_instance: String) -> Self {
+        self.walk_instance = walk_instance;
+        self
+    }
+
+    pub fn with_report_channel(mut self, report_sender: mpsc::Sender<ReportLine>) -> Self {
+        self.report_sender = Some(report_sender);
+        self
+    }
+
+    pub fn build<SS, T>(self) -> Result<ProgressStateCountByType<SS, T>, Error>
+    where
+        SS: Add<SS, Output = SS> + Default,
+        T: Default,
+    {
+        if self.repo_stats_key.is_empty() {
+            bail!(
+                "Progress for {} needs a repo stats key",
+                self.subcommand_stats_key
+            );
+        }
+        let included_types: HashSet<NodeType> = self
+            .included_types
+            .difference(&self.excluded_from_progress)
+            .copied()
+            .collect();
+        if included_types.is_empty() {
+            bail!(
+                "No node types to report {} progress for in {}",
+                self.subcommand_stats_key,
+                self.repo_stats_key
+            );
+        }
+        let options = self.options.build()?;
+        let mut state = ProgressStateCountByType::unvalidated(
+            self.fb,
+            self.logger,
+            self.subcommand_stats_key,
+            self.repo_stats_key,
+            included_types,
+            options,
+        );
+        state.params.unwalked_ok_types = self.unwalked_ok_types;
+        state.params.excluded_from_progress = self.excluded_from_progress;
+        if let Some(repo_key_fn) = self.repo_key_fn {
+            state = state.with_repo_key_fn(repo_key_fn);
+        }
+        if let Some(max_repo_keys) = self.max_repo_keys {
+            state = state.with_max_repo_keys(max_repo_keys);
+        }
+        if let Some(clock) = self.clock {
+            state = state.with_clock(clock);
+        }
+        if let Some(stats_sink) = self.stats_sink {
+            state = state.with_stats_sink(stats_sink);
+        }
+        if let Some(report_sender) = self.report_sender {
+            state = state.with_report_channel(report_sender);
+        }
+        Ok(state.with_walk_instance(self.walk_instance))
+    }
+}
+
+/// A short random walk instance label, for when the caller has no name for the walk
+pub fn auto_walk_instance() -> String {
+    Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+impl<SS, T> ProgressStateCountByType<SS, T>
+where
+    SS: Add<SS, Output = SS> + Default,
+    T: Default,
+{
+    #[deprecated(note = "use ProgressStateBuilder, which validates the options and types")]
+    pub fn new(
+        fb: FacebookInit,
+        logger: Logger,
+        subcommand_stats_key: &'static str,
+        repo_stats_key: String,
+        included_types: HashSet<NodeType>,
+        options: ProgressOptions,
+    ) -> Self {
+        Self::unvalidated(
+            fb,
+            logger,
+            subcommand_stats_key,
+            repo_stats_key,
+            included_types,
+            options,
+        )
+    }
+
+    fn unvalidated(
+        fb: FacebookInit,
+        logger: Logger,
+        subcommand_stats_key: &'static str,
+        repo_stats_key: String,
+        included_types: HashSet<NodeType>,
+        options: ProgressOptions,
+    ) -> Self {
+        let types_by_name = sort_by_string(included_types);
+
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let now = clock.now();
+        let run_id = Uuid::new_v4().to_string();
+        let mut scuba_builder = MononokeScubaSampleBuilder::with_discard();
+        scuba_builder.add(RUN_ID, run_id.clone());
+        // The time each record is logged, rather than of the report, which can differ
+        // for a paused or fake clock
+        let logger = logger.new(o!(
+            RUN_ID => run_id.clone(),
+            TIMESTAMP => slog::FnValue(|_| iso_timestamp(SystemTime::now())),
+        ));
+        Self {
+            params: ProgressStateByTypeParams {
+                fb,
+                logger,
+                subcommand_stats_key,
+                stats_key: repo_stats_key.clone(),
+                repo_stats_key,
+                types_sorted_by_name: types_by_name,
+                repo_key_fn: None,
+                max_repo_keys: None,
+                clock,
+                stats_sink: Arc::new(DefaultProgressStatsSink::new(fb)),
+                run_id,
+                walk_instance: String::new(),
+                scuba_builder,
+                unwalked_ok_types: HashSet::new(),
+                excluded_from_progress: HashSet::new(),
+                options,
+            },
+            // Updated by record_step
+            work_stats: ProgressStateWorkByType::<SS> {
+                stats_by_type: HashMap::new(),
+                stats_by_repo: HashMap::new(),
+                folded_repo_keys: FoldedKeys::default(),
+                distinct_errors_by_type: HashMap::new(),
+                children_by_type: HashMap::new(),
+                total_progress: 0,
+                uncounted: 0,
+                blobstore_reads: BlobstoreReads::default(),
+                changeset_phases: PhaseCounts::default(),
+                seen_by_type: HashMap::new(),
+                payload_sizes: PayloadHistogram::default(),
+                payload_sizes_by_type: HashMap::new(),
+                recent_errors: RecentErrors::default(),
+            },
+            // Updated by report_*
+            reporting_stats: ProgressStateReporting::<T> {
+                start_time: now,
+                last_summary_by_type: HashMap::new(),
+                last_summary: T::default(),
+                last_update: now,
+                last_summary_by_repo: HashMap::new(),
+                chunk: None,
+                iteration: None,
+                paused: PausedTime::default(),
+                throttle: ThrottleState::new(now),
+                gauges: DriverGauges::default(),
+                budgets: BudgetWarnings::default(),
+                sinks: ReportSinks::default(),
+                emitted: EmittedStats::default(),
+                interest: InterestBaseline::new(now),
+            },
+            // Updated by record_step and report_progress_log
+            overhead: ProgressOverhead::default(),
+        }
+    }
+
+    /// Keep stats per repo as well as overall, using the repo key each node maps to
+    pub fn with_repo_key_fn(mut self, repo_key_fn: RepoKeyFn) -> Self {
+        self.params.repo_key_fn = Some(repo_key_fn);
+        self
+    }
+
+    /// Keep stats for at most this many repo keys, counting any others together under
+    /// OTHER_REPO_KEY so the per repo stats stay bounded
+    pub fn with_max_repo_keys(mut self, max_repo_keys: usize) -> Self {
+        self.params.max_repo_keys = Some(max_repo_keys);
+        self
+    }
+
+    /// Use a different time source, restarting the run timings from its current time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        self.params.clock = clock;
+        self.reporting_stats.start_time = now;
+        self.reporting_stats.last_update = now;
+        self.reporting_stats.interest.last_update = now;
+        self
+    }
+
+    /// Send the progress stats somewhere other than the process stats
+    pub fn with_stats_sink(mut self, stats_sink: Arc<dyn ProgressStatsSink>) -> Self {
+        self.params.stats_sink = stats_sink;
+        self
+    }
+
+    /// Also send each report to the given channel, for embedders that want progress in
+    /// their own code rather than the logs. Reports are dropped, and counted, when the
+    /// channel is full, so a slow receiver never holds up the walk.
+    pub fn with_report_channel(mut self, report_sender: mpsc::Sender<ReportLine>) -> Self {
+        self.reporting_stats.sinks.report_channel = Some(ReportChannel::new(report_sender));
+        self
+    }
+
+    /// Reports not sent to the report channel as it was full
+    pub fn dropped_reports(&self) -> u64 {
+        self.reporting_stats
+            .sinks
+            .report_channel
+            .as_ref()
+            .map_or(0, |channel| channel.dropped())
+    }
+
+    /// Label log lines, Scuba rows and stats keys with the walk instance, to tell them
+    /// apart from other walks in the same process. Empty for none, which keeps the
+    /// single walk stats keys.
+    pub fn with_walk_instance(mut self, walk_instance: String) -> Self {
+        if !walk_instance.is_empty() {
+            self.params.logger = self
+                .params
+                .logger
+                .new(o!("walk_instance" => walk_instance.clone()));
+            self.params
+                .scuba_builder
+                .add(WALK_INSTANCE, walk_instance.clone());
+        }
+        self.params.walk_instance = walk_instance;
+        self.params.stats_key = self.params.instance_key(&self.params.repo_stats_key);
+        self
+    }
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    /// Summary per type of everything walked so far
+    pub fn summary_by_type(&self) -> HashMap<NodeType, ProgressSummary> {
+        summarize_by_type(&self.work_stats.stats_by_type)
+    }
+
+    /// Cumulative counts so far, to merge into another state
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let work = &self.work_stats;
+        let elapsed = self
+            .params
+            .clock
+            .now()
+            .saturating_duration_since(self.reporting_stats.start_time);
+        let types = work
+            .stats_by_type
+            .iter()
+            .map(|(t, (walked, stats))| {
+                let snapshot = TypeSnapshot {
+                    walked: *walked,
+                    stats: *stats,
+                    children: work.children_by_type.get(t).copied().unwrap_or_default(),
+                };
+                (t.to_string(), snapshot)
+            })
+            .collect();
+        ProgressSnapshot {
+            start_time: SystemTime::now().checked_sub(elapsed),
+            total_progress: work.total_progress,
+            types,
+            uncounted: work.uncounted,
+            changeset_phases: work.changeset_phases,
+        }
+    }
+
+    /// Add in the counts from another run or shard. The merged counts are reported as
+    /// totals, but not as progress in this run's deltas and rates. The run start moves
+    /// back if the snapshot's is earlier.
+    pub fn merge(&mut self, snapshot: ProgressSnapshot) -> Result<(), Error> {
+        // Check the types first, so an error leaves this state as it was
+        let types = snapshot
+            .types
+            .into_iter()
+            .map(|(name, t)| match name.parse::<NodeType>() {
+                Ok(node_type) => Ok((node_type, t)),
+                Err(_) => bail!("Unknown node type {} in progress snapshot", name),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut merged = HashMap::new();
+        for (node_type, t) in types {
+            let entry = self
+                .work_stats
+                .stats_by_type
+                .entry(node_type)
+                .or_insert((0, StepStats::default()));
+            entry.0 += t.walked;
+            entry.1 = entry.1 + t.stats;
+            self.work_stats
+                .children_by_type
+                .entry(node_type)
+                .or_default()
+                .merge(&t.children);
+            merged.insert(node_type, (t.walked, t.stats));
+        }
+        self.work_stats.total_progress += snapshot.total_progress;
+        self.work_stats.uncounted += snapshot.uncounted;
+        self.work_stats.changeset_phases =
+            self.work_stats.changeset_phases + snapshot.changeset_phases;
+
+        let reporting = &mut self.reporting_stats;
+        reporting.emitted.last_changeset_phases =
+            reporting.emitted.last_changeset_phases + snapshot.changeset_phases;
+        for (_walked, stats) in merged.values() {
+            self.work_stats.blobstore_reads =
+                self.work_stats.blobstore_reads + stats.blobstore_reads;
+            reporting.emitted.last_blobstore_reads =
+                reporting.emitted.last_blobstore_reads + stats.blobstore_reads;
+        }
+        for (node_type, summary) in summarize_by_type(&merged) {
+            for last in [
+                &mut reporting.last_summary_by_type,
+                &mut reporting.emitted.last_emitted_by_type,
+            ] {
+                let entry = last.entry(node_type).or_default();
+                *entry = *entry + summary;
+            }
+            reporting.last_summary = reporting.last_summary + summary;
+        }
+
+        if let Some(started) = snapshot
+            .start_time
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .and_then(|ago| self.params.clock.now().checked_sub(ago))
+        {
+            reporting.start_time = cmp::min(reporting.start_time, started);
+        }
+        Ok(())
+    }
+
+    /// Summary of everything walked so far, over all types
+    pub fn summary(&self) -> ProgressSummary {
+        self.summary_by_type()
+            .values()
+            .fold(ProgressSummary::default(), |acc, v| acc + *v)
+    }
+
+    /// Included types with nothing walked, split into those not expected and those in
+    /// unwalked_ok_types, each in name order
+    pub fn unwalked_types(&self) -> (Vec<NodeType>, Vec<NodeType>) {
+        self.params
+            .types_sorted_by_name
+            .iter()
+            .filter(|t| self.work_stats.walked_of_type(**t) == 0)
+            .partition(|t| !self.params.unwalked_ok_types.contains(t))
+    }
+}
+
+// A hash mismatch means the stored data is corrupt, so the node is logged as soon as it
+// is found rather than only counted
+fn record_hash_mismatch<SS>(logger: &Logger, work_stats: &mut ProgressStateWorkByType<SS>, n: &Node)
+where
+    SS: Add<SS, Output = SS> + Default,
+{
+    let key = n.to_string();
+    warn!(logger, "Hash mismatch, data is corrupt for {}", key);
+    work_stats.recent_errors.push(ErrorCategory::Corrupt, key);
+}
+
+impl<SS, T> ProgressStateCountByType<SS, T>
+where
+    SS: Add<SS, Output = SS> + Copy + Default + StepProgress,
+{
+    // Called after the node is recorded, so its type's count includes it
+    fn sample_node(&self, n: &Node) {
+        let every_nth = self.params.options.sample_node_every_nth;
+        if every_nth == 0 {
+            return;
+        }
+        let t = n.get_type();
+        // The first of each type is always logged
+        if (self.work_stats.walked_of_type(t) - 1) % every_nth == 0 {
+            debug!(self.params.logger, #log::GRAPH, "Sample node {}", n);
+        }
+    }
+}
+
+impl<SS, T> ProgressRecorderUnprotected<SS> for ProgressStateCountByType<SS, T>
+where
+    SS: Add<SS, Output = SS> + Copy + Default + StepProgress,
+{
+    fn record_step(&mut self, n: &Node, opt: Option<&SS>) {
+        let started = self.overhead.start_record(1);
+        self.work_stats.record_blobstore_reads(opt);
+        if opt.is_some_and(|ss| ss.hash_mismatches() > 0) {
+            record_hash_mismatch(&self.params.logger, &mut self.work_stats, n);
+        }
+        if self.params.excluded_from_progress.contains(&n.get_type()) {
+            self.work_stats.uncounted += 1;
+        } else {
+            self.work_stats.record_step(n, opt);
+            self.work_stats
+                .record_seen(n.get_type(), self.params.clock.now());
+            if let Some(bytes) =
+                self.work_stats
+                    .record_payload(n, opt, &self.params.options.payload_sizes)
+            {
+                self.params.stats_sink.add_payload_bytes(
+                    self.params.subcommand_stats_key,
+                    &self.params.stats_key,
+                    bytes as i64,
+                );
+            }
+            self.sample_node(n);
+            if let Some(repo_key_fn) = self.params.repo_key_fn.as_ref() {
+                self.work_stats
+                    .record_repo_step(repo_key_fn(n), self.params.max_repo_keys, n, opt);
+            }
+        }
+        self.overhead.finish_record(started);
+    }
+
+    fn record_steps(&mut self, batch: &[(Node, Option<SS>)]) {
+        let started = self.overhead.start_record(batch.len() as u64);
+        let excluded = &self.params.excluded_from_progress;
+        let counted = |(n, _): &&(Node, Option<SS>)| !excluded.contains(&n.get_type());
+        // One clock read for the batch, as it was all available at once
+        let now = self.params.clock.now();
+        for step in batch {
+            self.work_stats.record_blobstore_reads(step.1.as_ref());
+            if step.1.is_some_and(|ss| ss.hash_mismatches() > 0) {
+                record_hash_mismatch(&self.params.logger, &mut self.work_stats, &step.0);
+            }
+            if counted(&step) {
+                let (n, ss) = step;
+                self.work_stats.record_step(n, ss.as_ref());
+                self.work_stats.record_seen(n.get_type(), now);
+                if let Some(bytes) = self.work_stats.record_payload(
+                    n,
+                    ss.as_ref(),
+                    &self.params.options.payload_sizes,
+                ) {
+                    self.params.stats_sink.add_payload_bytes(
+                        self.params.subcommand_stats_key,
+                        &self.params.stats_key,
+                        bytes as i64,
+                    );
+                }
+                self.sample_node(n);
+            } else {
+                self.work_stats.uncounted += 1;
+            }
+        }
+        if let Some(repo_key_fn) = self.params.repo_key_fn.as_ref() {
+            for (n, ss) in batch.iter().filter(counted) {
+                self.work_stats.record_repo_step(
+                    repo_key_fn(n),
+                    self.params.max_repo_keys,
+                    n,
+                    ss.as_ref(),
+                );
+            }
+        }
+        self.overhead.finish_record(started);
+    }
+
+    fn set_sample_builder(&mut self, mut s: MononokeScubaSampleBuilder) {
+        s.add(RUN_ID, self.params.run_id.clone());
+        if !self.params.walk_instance.is_empty() {
+            s.add(WALK_INSTANCE, self.params.walk_instance.clone());
+        }
+        self.params.scuba_builder = s;
+    }
+
+    fn record_retries(&mut self, n: &Node, ss: &SS) {
+        if !self.params.excluded_from_progress.contains(&n.get_type()) {
+            self.work_stats.record_retries(n.get_type(), ss);
+        }
+    }
+
+    fn pause(&mut self) {
+        self.reporting_stats.paused.pause(self.params.clock.now());
+    }
+
+    fn resume(&mut self) {
+        if !self.reporting_stats.paused.resume(self.params.clock.now())
+            && self.reporting_stats.paused.unbalanced == 1
+        {
+            warn!(
+                self.params.logger,
+                "Progress resumed without being paused in {}, ignoring", self.params.repo_stats_key
+            );
+        }
+    }
+
+    fn feedback(&self) -> Option<Arc<ProgressFeedback>> {
+        Some(self.reporting_stats.gauges.feedback.clone())
+    }
+}
+
+impl ProgressReporterUnprotected for ProgressStateCountByType<StepStats, ProgressSummary> {
+    fn report_progress(&mut self) {
+        self.report_progress_log(None);
+        self.log_final_rows();
+        self.report_chunk_summary();
+        self.report_errors_by_type();
+        self.report_children_stats();
+        self.report_payload_sizes();
+        self.report_unwalked_types();
+        self.report_uncounted();
+        self.report_filtered();
+        self.report_visit_hits();
+        self.report_idle_types();
+        self.report_derived_breakdown();
+        self.report_overhead();
+        self.flush();
+    }
+
+    fn report_throttled(&mut self) {
+        if let Some(delta_time) = self.should_log_throttled() {
+            self.report_progress_log(Some(delta_time));
+        } else if let Some(delta_time) = self.should_log_interest() {
+            self.report_interest(delta_time);
+        }
+    }
+
+    fn start_chunk(&mut self, chunk_index: u64, bounds_description: String) {
+        // Previous chunk may not have had a final report
+        self.report_chunk_summary();
+        self.reporting_stats.chunk = Some(ChunkProgress {
+            chunk_index,
+            bounds_description,
+            start_time: self.params.clock.now(),
+            start_summary: self.summary(),
+        });
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        Some(self.params.options.interval)
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.params.quiet_mode()
+    }
+
+    fn update_position(&mut self, position: Arc<String>) {
+        self.reporting_stats.gauges.position = position;
+    }
+
+    fn update_outstanding(&mut self, outstanding: u64, max_outstanding: u64) {
+        self.reporting_stats.gauges.outstanding = outstanding;
+        self.reporting_stats.gauges.max_outstanding = max_outstanding;
+    }
+
+    fn update_filtered(&mut self, filtered: HashMap<NodeType, u64>) {
+        self.reporting_stats.gauges.filtered = filtered;
+    }
+
+    fn update_visit_hits(&mut self, hits: HashMap<NodeType, VisitHits>) {
+        self.reporting_stats.gauges.visit_hits = hits;
+    }
+
+    fn update_oldest_in_flight(&mut self, oldest: Option<(Node, Instant)>) {
+        self.reporting_stats.gauges.oldest_in_flight = oldest;
+    }
+
+    fn update_in_flight_occupancy(&mut self, window: Option<InFlightWindow>) {
+        self.reporting_stats.gauges.in_flight_occupancy = window;
+    }
+
+    fn update_workers(&mut self, workers: Vec<WorkerActivity>) {
+        self.reporting_stats.gauges.workers = workers;
+    }
+
+    fn update_step_latencies(&mut self, latencies: LatencyCounts) {
+        self.reporting_stats.gauges.step_latencies = latencies;
+    }
+
+    fn flush(&mut self) {
+        self.reporting_stats
+            .sinks
+            .scuba_rows
+            .log_all(&self.params.logger);
+        self.params.scuba_builder.flush(SCUBA_FLUSH_TIMEOUT);
+    }
+
+    fn report_heartbeat(&mut self) {
+        self.params.stats_sink.add_value(
+            ProgressStat::Heartbeat,
+            self.params.subcommand_stats_key,
+            &self.params.stats_key,
+            1,
+        );
+        let now = self.params.clock.now();
+        let delta_time = now.saturating_duration_since(self.reporting_stats.last_update);
+        if delta_time < self.report_interval() {
+            // Steps are driving reports
+            return;
+        }
+        self.reporting_stats.last_update = now;
+        self.adapt_interval(delta_time);
+        if self.summary().walked > self.reporting_stats.last_summary.walked {
+            // Walking, but slower than the sample rate
+            self.report_progress_log(Some(delta_time));
+        } else {
+            // Stalled. Emit explicit zeros so this is distinguishable from not running.
+            self.report_stats(&self.params.stats_key, &ProgressSummary::default());
+            self.report_gauges(&self.params.stats_key, &ProgressRates::default());
+            if self.params.options.display == ProgressDisplay::Log {
+                self.log_unchanged();
+            }
+        }
+    }
+
+    fn start_iteration(&mut self, iteration: u64) {
+        if self
+            .reporting_stats
+            .iteration
+            .as_ref()
+            .is_some_and(|current| current.iteration == iteration)
+        {
+            return;
+        }
+        self.report_iteration_summary();
+        self.reporting_stats.iteration = Some(IterationProgress {
+            iteration,
+            start_time: self.params.clock.now(),
+            start_summary: self.summary(),
+        });
+    }
+}
+
+pub trait ProgressRecorder<SS> {
+    fn record_step(&self, n: &Node, ss: Option<&SS>);
+    fn record_steps(&self, batch: &[(Node, Option<SS>)]) {
+        for (n, ss) in batch {
+            self.record_step(n, ss.as_ref());
+        }
+    }
+    fn set_sample_builder(&self, s: MononokeScubaSampleBuilder);
+    /// Describe where the walk currently is, e.g. chunk bounds. Cheap enough for the hot path.
+    fn set_position(&self, position: String);
+    /// Steps queued by the walk driver. Lock free, so safe to call from every step.
+    fn record_enqueued(&self, count: u64);
+    /// A step to a node of type t not taken as its node or edge type is filtered out.
+    /// Lock free, so safe to call for every edge.
+    fn record_filtered(&self, _t: NodeType) {}
+    /// The walk's visited check of a step to a node of type t, and whether it was turned
+    /// away as already visited. Lock free, so safe to call for every edge.
+    fn record_visit_check(&self, _t: NodeType, _already_visited: bool) {}
+    /// Steps the walk driver has taken off its queue to start
+    fn record_dequeued(&self, count: u64);
+    /// Track a step as in flight until the returned registration is dropped
+    fn record_in_flight(&self, n: &Node) -> InFlightStep;
+    /// Steps the walk driver is running at once, set whenever that changes. Lock free.
+    fn set_in_flight(&self, _in_flight: u64) {}
+    /// See ProgressRecorderUnprotected::record_retries
+    fn record_retries(&self, _n: &Node, _ss: &SS) {}
+    /// Start of a known wait, see ProgressRecorderUnprotected::pause
+    fn pause(&self) {}
+    /// End of a wait started by pause
+    fn resume(&self) {}
+    /// See ProgressRecorderUnprotected::feedback
+    fn feedback(&self) -> Option<Arc<ProgressFeedback>> {
+        None
+    }
+}
+
+pub trait ProgressReporter {
+    fn report_progress(&self);
+    fn report_throttled(&self);
+    /// As report_throttled, but skips the report if the state is locked, as the next
+    /// batch of steps will try again. For the walk's own reports, so workers recording
+    /// steps never queue up behind reporting as well.
+    fn report_throttled_nonblocking(&self);
+    fn start_chunk(&self, chunk_index: u64, bounds_description: String);
+    fn start_iteration(&self, iteration: u64);
+    fn heartbeat_interval(&self) -> Option<Duration>;
+    fn report_heartbeat(&self);
+    fn quiet_mode(&self) -> QuietMode;
+    fn flush(&self);
+}
+
+#[derive(Debug)]
+pub struct ProgressStateMutex<Inner> {
+    inner: Arc<Mutex<Inner>>,
+    // Kept outside the lock so setting it never waits on reporting
+    position: Arc<ArcSwap<String>>,
+    outstanding: Arc<OutstandingWork>,
+    filtered: Arc<FilteredSteps>,
+    visit_checks: Arc<VisitChecks>,
+    in_flight: Arc<InFlightSteps>,
+    // Only set if the walk driver's workers are tracked
+    workers: Option<Arc<WorkerSlots>>,
+    occupancy: Arc<InFlightOccupancy>,
+    in_flight_limit: Option<u64>,
+}
+
+impl<Inner> ProgressStateMutex<Inner> {
+    pub fn new(inner: Inner) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            position: Arc::new(ArcSwap::from_pointee(String::new())),
+            outstanding: Arc::new(OutstandingWork::default()),
+            filtered: Arc::new(FilteredSteps::default()),
+            visit_checks: Arc::new(VisitChecks::default()),
+            in_flight: Arc::new(InFlightSteps::default()),
+            workers: None,
+            occupancy: Arc::new(InFlightOccupancy::default()),
+            in_flight_limit: None,
+        }
+    }
+
+    pub fn outstanding(&self) -> (u64, u64) {
+        self.outstanding.get()
+    }
+
+    /// The most steps the walk driver runs at once, to report its occupancy against
+    pub fn with_in_flight_limit(mut self, limit: usize) -> Self {
+        self.in_flight_limit = Some(limit as u64);
+        self
+    }
+}
+
+impl<Inner> ProgressStateMutex<Inner>
+where
+    Inner: ProgressReporterUnprotected,
+{
+    // Lock for reporting, with the latest position and outstanding work passed on
+    fn lock_for_report(&self) -> MutexGuard<'_, Inner> {
+        // Scanned before locking so steps finishing are not held up by the report
+        let oldest_in_flight = self.in_flight.oldest();
+        let workers = self.workers_snapshot();
+        let inner = self.inner.lock().unwrap();
+        self.prepare_report(inner, oldest_in_flight, workers)
+    }
+
+    // As lock_for_report, but None if the lock is already held. Also None if it is
+    // poisoned, the walk should not panic over a skippable report.
+    fn try_lock_for_report(&self) -> Option<MutexGuard<'_, Inner>> {
+        let oldest_in_flight = self.in_flight.oldest();
+        let workers = self.workers_snapshot();
+        let inner = self.inner.try_lock().ok()?;
+        Some(self.prepare_report(inner, oldest_in_flight, workers))
+    }
+
+    fn workers_snapshot(&self) -> Vec<WorkerActivity> {
+        self.workers
+            .as_ref()
+            .map_or_else(Vec::new, |workers| workers.snapshot())
+    }
+
+    fn prepare_report<'a>(
+        &self,
+        mut inner: MutexGuard<'a, Inner>,
+        oldest_in_flight: Option<(Node, Instant)>,
+        workers: Vec<WorkerActivity>,
+    ) -> MutexGuard<'a, Inner> {
+        inner.update_oldest_in_flight(oldest_in_flight);
+        inner.update_workers(workers);
+        inner.update_position(self.position.load_full());
+        let (outstanding, max_outstanding) = self.outstanding.get();
+        inner.update_outstanding(outstanding, max_outstanding);
+        inner.update_filtered(self.filtered.get());
+        inner.update_visit_hits(self.visit_checks.get());
+        inner.update_in_flight_occupancy(self.occupancy.take(self.in_flight_limit));
+        inner.update_step_latencies(self.in_flight.latencies.snapshot());
+        inner
+    }
+}
+
+impl<Inner, SS> ProgressRecorder<SS> for ProgressStateMutex<Inner>
+where
+    Inner: ProgressRecorderUnprotected<SS>,
+{
+    fn record_step(&self, n: &Node, ss: Option<&SS>) {
+        self.inner.lock().unwrap().record_step(n, ss)
+    }
+
+    fn record_steps(&self, batch: &[(Node, Option<SS>)]) {
+        self.inner.lock().unwrap().record_steps(batch)
+    }
+
+    fn set_sample_builder(&self, s: MononokeScubaSampleBuilder) {
+        self.inner.lock().unwrap().set_sample_builder(s)
+    }
+
+    fn set_position(&self, position: String) {
+        self.position.store(Arc::new(position))
+    }
+
+    fn record_enqueued(&self, count: u64) {
+        self.outstanding.enqueued(count)
+    }
+
+    fn record_dequeued(&self, count: u64) {
+        self.outstanding.dequeued(count)
+    }
+
+    fn record_filtered(&self, t: NodeType) {
+        self.filtered.record(t)
+    }
+
+    fn record_visit_check(&self, t: NodeType, already_visited: bool) {
+        self.visit_checks.record(t, already_visited)
+    }
+
+    fn set_in_flight(&self, in_flight: u64) {
+        self.occupancy.set(in_flight)
+    }
+
+    fn record_retries(&self, n: &Node, ss: &SS) {
+        self.inner.lock().unwrap().record_retries(n, ss)
+    }
+
+    fn pause(&self) {
+        self.inner.lock().unwrap().pause()
+    }
+
+    fn resume(&self) {
+        self.inner.lock().unwrap().resume()
+    }
+
+    fn feedback(&self) -> Option<Arc<ProgressFeedback>> {
+        self.inner.lock().unwrap().feedback()
+    }
+
+    fn record_in_flight(&self, n: &Node) -> InFlightStep {
+        let mut step = self.in_flight.start(n);
+        step.worker = self
+            .workers
+            .as_ref()
+            .and_then(|workers| Some((workers.clone(), workers.start()?)));
+        step
+    }
+}
+
+impl<Inner> ProgressReporter for ProgressStateMutex<Inner>
+where
+    Inner: ProgressReporterUnprotected,
+{
+    fn report_progress(&self) {
+        let mut inner = self.lock_for_report();
+        if inner.quiet_mode().reports_final() {
+            inner.report_progress()
+        } else {
+            inner.flush()
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.lock().unwrap().flush()
+    }
+
+    fn report_throttled(&self) {
+        self.lock_for_report().report_throttled()
+    }
+
+    fn report_throttled_nonblocking(&self) {
+        // Throttling state is only updated by a report that goes ahead
+        if let Some(mut inner) = self.try_lock_for_report() {
+            inner.report_throttled()
+        }
+    }
+
+    fn start_chunk(&self, chunk_index: u64, bounds_description: String) {
+        self.inner
+            .lock()
+            .unwrap()
+            .start_chunk(chunk_index, bounds_description)
+    }
+
+    fn start_iteration(&self, iteration: u64) {
+        self.inner.lock().unwrap().start_iteration(iteration)
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().heartbeat_interval()
+    }
+
+    fn report_heartbeat(&self) {
+        self.lock_for_report().report_heartbeat()
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.inner.lock().unwrap().quiet_mode()
+    }
+}
+
+impl<SS, T> ProgressStateMutex<ProgressStateCountByType<SS, T>>
+where
+    SS: Add<SS, Output = SS> + Copy + Default + StepProgress,
+{
+    pub fn total_progress(&self) -> u64 {
+        self.inner.lock().unwrap().work_stats.total_progress()
+    }
+
+    pub fn walked_of_type(&self, t: NodeType) -> u64 {
+        self.inner.lock().unwrap().work_stats.walked_of_type(t)
+    }
+}
+
+impl ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>> {
+    /// Attribute steps registered in flight to at most max_workers workers, normally the
+    /// walk's concurrency, timed by the inner state's clock
+    pub fn with_worker_slots(mut self, max_workers: usize) -> Self {
+        let clock = self.inner.lock().unwrap().params.clock.clone();
+        self.workers = Some(Arc::new(WorkerSlots::new(max_workers, clock)));
+        self
+    }
+
+    pub fn summary(&self) -> ProgressSummary {
+        self.inner.lock().unwrap().summary()
+    }
+
+    pub fn summary_by_type(&self) -> HashMap<NodeType, ProgressSummary> {
+        self.inner.lock().unwrap().summary_by_type()
+    }
+
+    pub fn final_report(&self) -> FinalReport {
+        self.lock_for_report().final_report()
+    }
+
+    pub fn dump(&self) -> String {
+        self.lock_for_report().dump()
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.inner.lock().unwrap().snapshot()
+    }
+
+    pub fn merge(&self, snapshot: ProgressSnapshot) -> Result<(), Error> {
+        self.inner.lock().unwrap().merge(snapshot)
+    }
+
+    pub fn budget_violations(&self) -> Vec<BudgetViolation> {
+        self.inner.lock().unwrap().budget_violations()
+    }
+}
+
+impl<Inner> Clone for ProgressStateMutex<Inner> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            position: self.position.clone(),
+            outstanding: self.outstanding.clone(),
+            filtered: self.filtered.clone(),
+            visit_checks: self.visit_checks.clone(),
+            in_flight: self.in_flight.clone(),
+            workers: self.workers.clone(),
+            occupancy: self.occupancy.clone(),
+            in_flight_limit: self.in_flight_limit,
+        }
+    }
+}
+
+// Stops the heartbeat task when the stream it is attached to is dropped
+struct HeartbeatGuard(JoinHandle<()>);
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Call report_heartbeat every interval until dropped
+fn spawn_heartbeat<PS>(progress_state: &PS) -> Option<HeartbeatGuard>
+where
+    PS: 'static + Send + Clone + ProgressReporter,
+{
+    // tokio intervals cannot be zero, and a zero interval has nothing to catch up on anyway
+    let interval = progress_state
+        .heartbeat_interval()
+        .filter(|interval| !interval.is_zero())?;
+    let progress_state = progress_state.clone();
+    Some(HeartbeatGuard(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // First tick is immediate
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            progress_state.report_heartbeat();
+        }
+    })))
+}
+
+// Log some status update, passing on all data unchanged.
+// Steps that are already available are recorded as a batch to save on locking.
+// In Full mode also reports from the background while the stream is alive, so stalls are visible.
+pub fn progress_stream<InStream, PS, Payload, SS, K, E>(
+    progress_state: &PS,
+    s: InStream,
+) -> impl Stream<Item = Result<(K, Payload, Option<SS>), E>>
+where
+    InStream: Stream<Item = Result<(K, Payload, Option<SS>), E>> + 'static + Send,
+    PS: 'static + Send + Clone + ProgressRecorder<SS> + ProgressReporter,
+    SS: Clone,
+    K: 'static,
+    // Make sure we can convert from K reference to Node reference
+    for<'b> &'b Node: From<&'b K>,
+{
+    progress_stream_by(progress_state, s, |key: &K| {
+        let n: &Node = key.into();
+        n.clone()
+    })
+}
+
+/// As progress_stream, for keys without a conversion to &Node, using node_of to get the
+/// Node each key is for
+pub fn progress_stream_by<InStream, PS, Payload, SS, K, E, F>(
+    progress_state: &PS,
+    s: InStream,
+    node_of: F,
+) -> impl Stream<Item = Result<(K, Payload, Option<SS>), E>>
+where
+    InStream: Stream<Item = Result<(K, Payload, Option<SS>), E>> + 'static + Send,
+    PS: 'static + Send + Clone + ProgressRecorder<SS> + ProgressReporter,
+    SS: Clone,
+    K: 'static,
+    F: Fn(&K) -> Node + 'static + Send,
+{
+    let quiet = progress_state.quiet_mode();
+    let heartbeat = if quiet == QuietMode::Full {
+        spawn_heartbeat(progress_state)
+    } else {
+        None
+    };
+    s.ready_chunks(PROGRESS_BATCH_SIZE)
+        .map({
+            let progress_state = progress_state.clone();
+            move |rs| {
+                let _heartbeat = &heartbeat;
+                let batch: Vec<_> = rs
+                    .iter()
+                    .filter_map(|r| r.as_ref().ok())
+                    .map(|(key, _payload, stats_opt)| (node_of(key), stats_opt.clone()))
+                    .collect();
+                if !batch.is_empty() {
+                    progress_state.record_steps(&batch);
+                    if quiet.reports_throttled() {
+                        progress_state.report_throttled_nonblocking();
+                    }
+                }
+                stream::iter(rs)
+            }
+        })
+        .flatten()
+}
+
+// Final status summary, plus count of seen nodes
+pub async fn report_state<InStream, ND, SS, E>(
+    ctx: CoreContext,
+    quiet: QuietMode,
+    s: InStream,
+) -> Result<(), E>
+where
+    InStream: Stream<Item = Result<(Node, Option<ND>, Option<SS>), E>> + 'static + Send,
+{
+    let (seen, loaded) = s
+        .try_fold((0_usize, 0_usize), {
+            async move |(mut seen, mut loaded), (_n, nd, _ss)| {
+                seen += 1;
+                if nd.is_some() {
+                    loaded += 1;
+                }
+                Ok((seen, loaded))
+            }
+        })
+        .await?;
+
+    if quiet.reports_final() {
+        info!(ctx.logger(), #log::LOADED, "Seen,Loaded: {},{}", seen, loaded);
+    }
+    Ok(())
+}
+
+/// Stand ins for the progress state, for tests of walk logic
+#[cfg(test)]
+pub mod testing;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+
+    use chrono::DateTime;
+    use maplit::hashmap;
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use mononoke_types::ContentId;
+    use slog::o;
+
+    use super::testing::*;
+    use super::*;
+    use crate::detail::graph::ChangesetKey;
+    use crate::detail::report::TypeReport;
+
+    #[fbinit::test]
+    fn test_record_steps_matches_record_step(fb: FacebookInit) {
+        let batch: Vec<_> = (0..10)
+            .map(|i| (phase_node(i), Some(children(i as usize))))
+            .collect();
+
+        let mut unbatched = test_progress_state(fb);
+        for (n, ss) in &batch {
+            unbatched.record_step(n, ss.as_ref());
+        }
+        let mut batched = test_progress_state(fb);
+        batched.record_steps(&batch);
+
+        assert_eq!(
+            unbatched.work_stats.total_progress,
+            batched.work_stats.total_progress
+        );
+        let unbatched_summary = unbatched.summary();
+        let batched_summary = batched.summary();
+        assert_eq!(unbatched_summary.walked, batched_summary.walked);
+        assert_eq!(unbatched_summary.queued, batched_summary.queued);
+        assert_eq!(45, batched_summary.queued);
+    }
+
+    #[fbinit::test]
+    fn test_record_steps_throttle(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+        state.params.options.sample_rate = 4;
+        state.params.options.interval = Duration::from_secs(0);
+
+        // A batch that steps over the multiple of the sample rate still gets checked
+        let batch: Vec<_> = (0..5).map(|i| (phase_node(i), None)).collect();
+        state.record_steps(&batch);
+        assert!(state.should_log_throttled().is_some());
+        // But only once per sample
+        assert!(state.should_log_throttled().is_none());
+    }
+
+    #[fbinit::test]
+    fn test_record_steps_concurrent(fb: FacebookInit) {
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        let threads: Vec<_> = (0..8u8)
+            .map(|t| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let batch: Vec<_> = (0..100u8)
+                        .map(|i| (phase_node(i), Some(children(1))))
+                        .collect();
+                    for _ in 0..10 {
+                        if t % 2 == 0 {
+                            state.record_steps(&batch);
+                        } else {
+                            for (n, ss) in &batch {
+                                state.record_step(n, ss.as_ref());
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let inner = state.inner.lock().unwrap();
+        assert_eq!(8000, inner.work_stats.total_progress);
+        let summary = inner.summary();
+        assert_eq!(8000, summary.walked);
+        assert_eq!(8000, summary.queued);
+    }
+
+    fn bench_batch() -> Vec<(Node, Option<StepStats>)> {
+        (0..=255u8)
+            .map(|i| (phase_node(i), Some(children(1))))
+            .collect()
+    }
+
+    // Per step recording through the mutex, to compare with bench_record_steps
+    #[bench]
+    fn bench_record_step(b: &mut test::Bencher) {
+        let fb = unsafe { fbinit::perform_init() };
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        let batch = bench_batch();
+        b.iter(|| {
+            for (n, ss) in &batch {
+                state.record_step(n, ss.as_ref());
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_record_steps(b: &mut test::Bencher) {
+        let fb = unsafe { fbinit::perform_init() };
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        let batch = bench_batch();
+        b.iter(|| state.record_steps(&batch));
+    }
+
+    #[test]
+    fn test_rates_sub_second() {
+        let summary = ProgressSummary {
+            walked: 5,
+            queued: 5,
+            ..Default::default()
+        };
+        let rates = ProgressRates::new(&summary, Duration::from_millis(100));
+        assert_eq!(50.0, rates.walked);
+        assert_eq!("50.0/s", format!("{:.1}/s", rates.walked));
+
+        let rates = ProgressRates::new(&summary, Duration::from_millis(3000));
+        assert_eq!("1.7/s", format!("{:.1}/s", rates.walked));
+
+        // No divide by zero
+        let rates = ProgressRates::new(&summary, Duration::ZERO);
+        assert_eq!(ProgressRates::default(), rates);
+    }
+
+    #[test]
+    fn test_rates_long_run() {
+        let summary = ProgressSummary {
+            walked: 1_000_000,
+            queued: 10_000_000_000,
+            ..Default::default()
+        };
+        let rates = ProgressRates::new(&summary, Duration::from_secs(3 * 60 * 60));
+        assert_eq!("92.6/s", format!("{:.1}/s", rates.walked));
+        assert_eq!("925925.9/s", format!("{:.1}/s", rates.queued));
+    }
+
+    #[test]
+    fn test_summary_sub_saturates() {
+        let reset = ProgressSummary {
+            walked: 1,
+            queued: 0,
+            errors: u64::MAX,
+            ..Default::default()
+        };
+        let before = ProgressSummary {
+            walked: 10,
+            queued: u64::MAX,
+            errors: 1,
+            ..Default::default()
+        };
+        let delta = reset - before;
+        assert_eq!(0, delta.walked);
+        assert_eq!(0, delta.queued);
+        assert_eq!(u64::MAX - 1, delta.errors);
+    }
+
+    #[test]
+    fn test_rates_at_overflow_boundary() {
+        // Would have overflowed when scaled by 1000 as u64
+        let summary = ProgressSummary {
+            walked: u64::MAX / 100,
+            queued: u64::MAX,
+            ..Default::default()
+        };
+        let rates = ProgressRates::new(&summary, Duration::from_millis(1));
+        // No wrap around, the rates are above anything a u64 could hold
+        assert!(rates.walked > u64::MAX as f64);
+        assert!(rates.queued > rates.walked);
+        assert!(rates.queued.is_finite());
+
+        let rates = ProgressRates::new(&summary, Duration::MAX);
+        assert!(rates.queued.is_finite());
+        assert!(rates.walked < 1.0);
+    }
+
+    #[fbinit::test]
+    fn test_accessors(fb: FacebookInit) {
+        let state = ProgressStateMutex::new(test_progress_state(fb));
+        assert_eq!(0, state.total_progress());
+        assert_eq!(0, state.walked_of_type(NodeType::PhaseMapping));
+
+        let mut batch: Vec<_> = (0..3).map(|i| (phase_node(i), Some(children(2)))).collect();
+        batch.push((
+            Node::Changeset(ChangesetKey {
+                inner: ChangesetId::from_byte_array([9; 32]),
+                filenode_known_derived: false,
+            }),
+            None,
+        ));
+        state.record_steps(&batch);
+
+        assert_eq!(4, state.total_progress());
+        assert_eq!(3, state.walked_of_type(NodeType::PhaseMapping));
+        assert_eq!(1, state.walked_of_type(NodeType::Changeset));
+        assert_eq!(0, state.walked_of_type(NodeType::Bookmark));
+
+        let summary = state.summary();
+        assert_eq!(4, summary.walked());
+        assert_eq!(6, summary.queued());
+        assert_eq!(0, summary.errors());
+
+        let snapshot = state.summary_by_type();
+        assert_eq!(2, snapshot.len());
+        assert_eq!(3, snapshot[&NodeType::PhaseMapping].walked());
+        assert_eq!(0, snapshot[&NodeType::Changeset].queued());
+    }
+
+    #[fbinit::test]
+    fn test_distinct_errors(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+        let error = StepStats {
+            error_count: 1,
+            ..Default::default()
+        };
+        for _ in 0..5 {
+            state.record_step(&phase_node(1), Some(&error));
+        }
+        state.record_step(&phase_node(2), Some(&children(1)));
+
+        assert_eq!(5, state.summary().errors());
+        assert_eq!((1, false), state.work_stats.distinct_errors());
+        let by_type = &state.work_stats.distinct_errors_by_type[&NodeType::PhaseMapping];
+        assert_eq!("1", by_type.to_string());
+    }
+
+    #[test]
+    fn test_distinct_errors_bounded() {
+        let mut errors = DistinctErrors::default();
+        for i in 0..DISTINCT_ERRORS_CAP + 10 {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            errors.insert(&Node::PhaseMapping(ChangesetId::from_byte_array(id)));
+        }
+        assert_eq!(DISTINCT_ERRORS_CAP as u64, errors.count());
+        assert!(errors.truncated());
+        assert_eq!(format!("{}+", DISTINCT_ERRORS_CAP), errors.to_string());
+    }
+
+    #[fbinit::test]
+    fn test_heartbeat_zero_emission(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb)
+            .with_clock(clock.clone())
+            .with_stats_sink(stats.clone());
+
+        state.record_step(&phase_node(0), Some(&children(2)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Walked, "repo".to_string(), 1)));
+
+        // Interval not yet elapsed, only the heartbeat itself
+        clock.advance(Duration::from_millis(500));
+        state.report_heartbeat();
+        assert_eq!(
+            vec![(ProgressStat::Heartbeat, "repo".to_string(), 1)],
+            stats.take()
+        );
+
+        // Nothing walked over the interval, explicit zeros
+        clock.advance(Duration::from_secs(1));
+        state.report_heartbeat();
+        let captured = stats.take();
+        assert_eq!(14, captured.len());
+        assert_eq!(Some(0), stats.gauge(ProgressGauge::WalkedPerSecond, "repo"));
+        assert!(captured.contains(&(ProgressStat::Heartbeat, "repo".to_string(), 1)));
+        assert!(captured.contains(&(ProgressStat::Walked, "repo".to_string(), 0)));
+        assert!(captured.contains(&(ProgressStat::Queued, "repo".to_string(), 0)));
+
+        // Walked, but not enough to trigger a throttled report
+        state.record_step(&phase_node(1), Some(&children(3)));
+        clock.advance(Duration::from_secs(1));
+        state.report_heartbeat();
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Walked, "repo".to_string(), 1)));
+        assert!(captured.contains(&(ProgressStat::Queued, "repo".to_string(), 3)));
+        assert_eq!(2, state.reporting_stats.last_summary.walked);
+    }
+
+    #[fbinit::test]
+    async fn test_heartbeat_background(fb: FacebookInit) {
+        tokio::time::pause();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let state = ProgressStateMutex::new(test_progress_state(fb).with_stats_sink(stats.clone()));
+
+        let guard = spawn_heartbeat(&state).unwrap();
+        tokio::time::sleep(Duration::from_millis(3500)).await;
+        let heartbeats = stats
+            .take()
+            .into_iter()
+            .filter(|(stat, _, _)| *stat == ProgressStat::Heartbeat)
+            .count();
+        assert_eq!(3, heartbeats);
+
+        // Dropping the guard stops the heartbeat
+        drop(guard);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(stats.take().is_empty());
+    }
+
+    #[fbinit::test]
+    fn test_children_stats(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+        // One huge node among many small ones
+        state.record_step(&phase_node(0), Some(&children(2_000_000)));
+        for i in 1..100 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        // Steps without stats are not part of the distribution
+        state.record_step(&phase_node(100), None);
+
+        let stats = state.work_stats.children_by_type[&NodeType::PhaseMapping];
+        assert_eq!(100, stats.steps);
+        assert_eq!(2_000_099, stats.total);
+        assert_eq!(2_000_000, stats.max);
+        assert_eq!(20000.99, stats.mean());
+        assert!(!state
+            .work_stats
+            .children_by_type
+            .contains_key(&NodeType::Changeset));
+        assert_eq!(0.0, ChildrenStats::default().mean());
+    }
+
+    #[fbinit::test]
+    fn test_error_categories(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        let error = |corrupt, transient, other| StepStats {
+            error_count: 1,
+            corrupt_error_count: corrupt,
+            transient_error_count: transient,
+            other_error_count: other,
+            ..Default::default()
+        };
+        state.record_step(&phase_node(0), Some(&error(1, 0, 0)));
+        state.record_step(&phase_node(1), Some(&error(0, 1, 0)));
+        state.record_step(&phase_node(2), Some(&error(0, 1, 0)));
+        state.record_step(&phase_node(3), Some(&error(0, 0, 1)));
+        state.record_step(
+            &phase_node(4),
+            Some(&StepStats {
+                missing_count: 1,
+                ..Default::default()
+            }),
+        );
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let summary = state.summary();
+        assert_eq!(4, summary.errors());
+        assert_eq!(1, summary.corrupt());
+        assert_eq!(2, summary.transient());
+        assert_eq!(1, summary.other_errors());
+        assert_eq!(1, summary.missing());
+
+        let captured = stats.take();
+        for (stat, value) in [
+            (ProgressStat::Errors, 4),
+            (ProgressStat::CorruptErrors, 1),
+            (ProgressStat::TransientErrors, 2),
+            (ProgressStat::OtherErrors, 1),
+            (ProgressStat::Missing, 1),
+        ] {
+            assert!(captured.contains(&(stat, "repo".to_string(), value)));
+        }
+
+        // Deltas only cover the new errors
+        state.record_step(&phase_node(5), Some(&error(1, 0, 0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::CorruptErrors, "repo".to_string(), 1)));
+        assert!(captured.contains(&(ProgressStat::TransientErrors, "repo".to_string(), 0)));
+    }
+
+    #[fbinit::test]
+    fn test_missing_not_errors(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let missing = StepStats {
+            missing_count: 1,
+            ..Default::default()
+        };
+        let error = StepStats {
+            error_count: 1,
+            other_error_count: 1,
+            ..Default::default()
+        };
+        state.record_steps(&[
+            (phase_node(0), Some(missing)),
+            (phase_node(1), Some(missing)),
+            (phase_node(2), Some(error)),
+            (changeset_node(0), Some(missing)),
+            (changeset_node(1), Some(StepStats::default())),
+        ]);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let line = drain.take().remove(0);
+        assert!(line.contains("Delta 5.0/s,0.0/s,5,1,3,0,1s;"), "{}", line);
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Missing, "repo".to_string(), 3)));
+        assert!(captured.contains(&(ProgressStat::Errors, "repo".to_string(), 1)));
+
+        // Only the new missing steps are in the next delta
+        state.record_step(&phase_node(3), Some(&missing));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(drain.take()[0].contains("Delta 1.0/s,0.0/s,1,0,1,0,1s;"));
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Missing, "repo".to_string(), 1)));
+        assert!(captured.contains(&(ProgressStat::Errors, "repo".to_string(), 0)));
+
+        let report = state.final_report();
+        assert_eq!(3, report.types["PhaseMapping"].missing);
+        assert_eq!(1, report.types["PhaseMapping"].errors);
+        assert_eq!(1, report.types["Changeset"].missing);
+        assert_eq!(0, report.types["Changeset"].errors);
+
+        // Reports saved before the counter existed still load
+        let json = r#"{"walked": 1, "errors": 0, "rate": 1.0}"#;
+        let old: TypeReport = serde_json::from_str(json).unwrap();
+        assert_eq!(0, old.missing);
+    }
+
+    // Returns how many reports were emitted, counted by their walked stat, and the total walked
+    async fn count_reports(fb: FacebookInit, quiet: QuietMode) -> (usize, u64) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.options.quiet = quiet;
+        state.params.options.interval = Duration::ZERO;
+        let state = ProgressStateMutex::new(state);
+
+        // Several batches, each of which can trigger a throttled report
+        let steps = (0..4 * PROGRESS_BATCH_SIZE)
+            .map(|i| Ok::<_, Error>((phase_node(i as u8), (), Some(children(0)))));
+        progress_stream(&state, stream::iter(steps))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        state.report_progress();
+
+        let reports = stats
+            .take()
+            .iter()
+            .filter(|(stat, _, _)| *stat == ProgressStat::Walked)
+            .count();
+        (reports, state.summary().walked())
+    }
+
+    #[fbinit::test]
+    async fn test_quiet_modes(fb: FacebookInit) {
+        let walked = 4 * PROGRESS_BATCH_SIZE as u64;
+        assert_eq!((5, walked), count_reports(fb, QuietMode::Full).await);
+        assert_eq!((3, walked), count_reports(fb, QuietMode::EveryNth(2)).await);
+        assert_eq!((1, walked), count_reports(fb, QuietMode::FinalOnly).await);
+        assert_eq!((0, walked), count_reports(fb, QuietMode::Silent).await);
+    }
+
+    #[fbinit::test]
+    fn test_progress_state_builder(fb: FacebookInit) -> Result<(), Error> {
+        let builder = || {
+            ProgressStateBuilder::new(
+                fb,
+                Logger::root(slog::Discard, o!()),
+                "test",
+                "repo".to_string(),
+            )
+        };
+        let err =
+            |builder: ProgressStateBuilder| match builder.build::<StepStats, ProgressSummary>() {
+                Ok(_) => panic!("expected build to fail"),
+                Err(e) => e.to_string(),
+            };
+        assert_eq!(
+            "No node types to report test progress for in repo",
+            err(builder())
+        );
+        assert_eq!(
+            "Progress for test needs a repo stats key",
+            err(ProgressStateBuilder::new(
+                fb,
+                Logger::root(slog::Discard, o!()),
+                "test",
+                String::new(),
+            )
+            .with_included_types(hashset! {NodeType::PhaseMapping}))
+        );
+        // Options are checked even when not from ProgressOptionsBuilder
+        let mut options = ProgressOptionsBuilder::default().build()?;
+        options.sample_rate = 0;
+        assert_eq!(
+            "Progress sample rate must be at least 1, use time only mode to sample every step",
+            err(builder()
+                .with_included_types(hashset! {NodeType::PhaseMapping})
+                .with_options(options))
+        );
+
+        let clock = FakeClock::new();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = builder()
+            .with_included_types(hashset! {NodeType::Changeset, NodeType::PhaseMapping})
+            .with_options(
+                ProgressOptionsBuilder::default()
+                    .with_sample_rate(1)
+                    .with_interval(Duration::from_secs(1))
+                    .with_display(ProgressDisplay::Log)
+                    .with_quiet(QuietMode::EveryNth(2))
+                    .with_type_rates(false)
+                    .with_type_emit_every_nth(1)
+                    .build()?,
+            )
+            .with_repo_key_fn(Arc::new(|_: &Node| "repo"))
+            .with_clock(clock.clone())
+            .with_stats_sink(stats.clone())
+            .build::<StepStats, ProgressSummary>()?;
+        assert_eq!(
+            vec![NodeType::Changeset, NodeType::PhaseMapping],
+            state.params.types_sorted_by_name
+        );
+        assert_eq!(QuietMode::EveryNth(2), state.quiet_mode());
+        assert!(!state.params.options.type_rates);
+
+        state.record_step(&phase_node(0), Some(&children(1)));
+        assert_eq!(1, state.work_stats.stats_by_repo["repo"].len());
+        clock.advance(Duration::from_secs(1));
+        state.report_progress_log(None);
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Walked, "repo".to_string(), 1)));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_progress_overhead(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+
+        // Only the calls that cross a multiple of the sample rate are timed
+        for i in 0..200 {
+            state.record_step(&phase_node(i as u8), None);
+        }
+        assert_eq!(200, state.overhead.record_calls);
+        assert_eq!(3, state.overhead.record_timed);
+        let batch: Vec<_> = (0..100).map(|i| (phase_node(i), None)).collect();
+        state.record_steps(&batch);
+        assert_eq!(300, state.overhead.record_calls);
+        assert_eq!(103, state.overhead.record_timed);
+        assert!(state.overhead.record_timed_duration > Duration::ZERO);
+
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(2, state.overhead.report_calls);
+        assert!(state.overhead.report_duration > Duration::ZERO);
+        let emitted: i64 = stats
+            .take()
+            .iter()
+            .filter(|(stat, _, _)| *stat == ProgressStat::OverheadMicros)
+            .map(|(_, _, value)| value)
+            .sum();
+        assert_eq!(state.overhead.total().as_micros() as i64, emitted);
+
+        let report = state.final_report();
+        assert!(report.overhead_ms > 0.0);
+        assert!((0.0..=100.0).contains(&report.overhead_pct));
+
+        // Sampled step time is scaled up to all the calls
+        let overhead = ProgressOverhead {
+            record_calls: 128,
+            record_timed: 2,
+            record_timed_duration: Duration::from_millis(10),
+            report_calls: 1,
+            report_duration: Duration::from_millis(360),
+            ..Default::default()
+        };
+        assert_eq!(Duration::from_millis(640), overhead.record_duration());
+        assert_eq!(Duration::from_secs(1), overhead.total());
+        assert_eq!(10.0, overhead.pct_of(Duration::from_secs(10)));
+        assert_eq!(0.0, overhead.pct_of(Duration::ZERO));
+        assert_eq!(100.0, overhead.pct_of(Duration::from_millis(500)));
+        assert_eq!(Duration::ZERO, ProgressOverhead::default().total());
+    }
+
+    // Slow to emit a report, to show up workers waiting on reporting
+    struct SlowStatsSink {
+        delay: Duration,
+        reports: AtomicU64,
+    }
+
+    impl ProgressStatsSink for SlowStatsSink {
+        fn add_value(&self, _: ProgressStat, _: &'static str, _: &str, _: i64) {}
+
+        fn set_gauge(&self, gauge: ProgressGauge, _: &'static str, _: &str, _: i64) {
+            // Set once per report
+            if gauge == ProgressGauge::InFlight {
+                self.reports.fetch_add(1, Ordering::Relaxed);
+                std::thread::sleep(self.delay);
+            }
+        }
+
+        fn add_type_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: NodeType,
+            _: i64,
+        ) {
+        }
+
+        fn add_group_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: NodeTypeGroup,
+            _: i64,
+        ) {
+        }
+
+        fn add_derived_value(
+            &self,
+            _: ProgressTypeStat,
+            _: &'static str,
+            _: &str,
+            _: &'static str,
+            _: i64,
+        ) {
+        }
+
+        fn add_blobstore_reads(&self, _: &'static str, _: &str, _: &str, _: i64) {}
+
+        fn add_payload_bytes(&self, _: &'static str, _: &str, _: i64) {}
+
+        fn add_phase_value(&self, _: &'static str, _: &str, _: &'static str, _: i64) {}
+    }
+
+    #[fbinit::test]
+    fn test_report_throttled_nonblocking(fb: FacebookInit) {
+        let sink = Arc::new(SlowStatsSink {
+            delay: Duration::from_millis(200),
+            reports: AtomicU64::new(0),
+        });
+        let mut state = test_progress_state(fb).with_stats_sink(sink.clone());
+        state.params.options.interval = Duration::from_millis(1);
+        let state = ProgressStateMutex::new(state);
+
+        // Skipped while the lock is held, without touching the throttling state
+        state.record_step(&phase_node(0), None);
+        std::thread::sleep(Duration::from_millis(2));
+        {
+            let held = state.inner.lock().unwrap();
+            let last_update = held.reporting_stats.last_update;
+            state.report_throttled_nonblocking();
+            assert_eq!(last_update, held.reporting_stats.last_update);
+            assert_eq!(0, held.reporting_stats.throttle.last_sample);
+        }
+        assert_eq!(0, sink.reports.load(Ordering::Relaxed));
+        // So the next attempt still reports
+        state.report_throttled_nonblocking();
+        assert_eq!(1, sink.reports.load(Ordering::Relaxed));
+
+        // While a slow report runs, workers reporting as they go, as progress_stream
+        // does, carry on rather than queueing up behind it one after another
+        std::thread::scope(|s| {
+            let reporter = {
+                let state = state.clone();
+                s.spawn(move || state.report_progress())
+            };
+            while sink.reports.load(Ordering::Relaxed) < 2 {
+                std::thread::yield_now();
+            }
+            let started = Instant::now();
+            let workers: Vec<_> = (0..8)
+                .map(|_| {
+                    let state = state.clone();
+                    s.spawn(move || {
+                        for _ in 0..100 {
+                            state.report_throttled_nonblocking();
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            assert!(started.elapsed() < sink.delay, "{:?}", started.elapsed());
+            reporter.join().unwrap();
+        });
+        assert_eq!(2, sink.reports.load(Ordering::Relaxed));
+
+        // The final report always goes ahead
+        let before = sink.reports.load(Ordering::Relaxed);
+        state.report_progress();
+        assert_eq!(before + 1, sink.reports.load(Ordering::Relaxed));
+    }
+
+    #[fbinit::test]
+    fn test_report_throttled_nonblocking_poisoned(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+        state.params.options.interval = Duration::from_millis(1);
+        let state = ProgressStateMutex::new(state);
+        state.record_step(&phase_node(0), None);
+
+        // A panic elsewhere while reporting leaves the walk's reports skipped
+        let poisoner = state.clone();
+        std::thread::spawn(move || {
+            let _held = poisoner.inner.lock().unwrap();
+            panic!("poison the progress state");
+        })
+        .join()
+        .unwrap_err();
+        assert!(state.inner.is_poisoned());
+        std::thread::sleep(Duration::from_millis(2));
+        state.report_throttled_nonblocking();
+    }
+
+    #[fbinit::test]
+    fn test_sample_node(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let sampled = || -> Vec<String> {
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Sample node"))
+                .collect()
+        };
+
+        // Off by default
+        state.record_step(&phase_node(100), None);
+        assert!(sampled().is_empty());
+
+        // Counted per type, so the one changeset among many phases is still seen. The
+        // phase recorded while off still counts.
+        state.params.options.sample_node_every_nth = 3;
+        let mut batch: Vec<_> = (0..7).map(|i| (phase_node(i), None)).collect();
+        batch.insert(3, (changeset_node(0), None));
+        state.record_steps(&batch);
+        state.record_step(&changeset_node(1), None);
+        let expected: Vec<_> = [phase_node(2), changeset_node(0), phase_node(5)]
+            .iter()
+            .map(|n| format!("Sample node {}", n))
+            .collect();
+        assert_eq!(expected, sampled());
+        assert!(expected[1].starts_with("Sample node Changeset changeset.blake2."));
+    }
+
+    // Small deterministic generator, so the property tests are repeatable
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn below(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    fn random_snapshot(rng: &mut XorShift) -> ProgressSnapshot {
+        let mut snapshot = ProgressSnapshot {
+            start_time: (rng.below(4) != 0)
+                .then(|| SystemTime::UNIX_EPOCH + Duration::from_secs(rng.below(1000))),
+            total_progress: rng.below(1000),
+            types: BTreeMap::new(),
+            uncounted: rng.below(1000),
+            changeset_phases: PhaseCounts {
+                public: rng.below(100),
+                draft: rng.below(100),
+                unknown: rng.below(100),
+            },
+        };
+        for t in [
+            NodeType::Changeset,
+            NodeType::PhaseMapping,
+            NodeType::FileContent,
+        ] {
+            if rng.below(2) == 0 {
+                continue;
+            }
+            let stats = StepStats {
+                error_count: rng.below(10) as usize,
+                missing_count: rng.below(10) as usize,
+                num_expanded_new: rng.below(100) as usize,
+                visited_of_type: rng.below(100) as usize,
+                ..Default::default()
+            };
+            let children = ChildrenStats {
+                steps: rng.below(100),
+                total: rng.below(100),
+                max: rng.below(100),
+            };
+            let walked = rng.below(100);
+            snapshot.types.insert(
+                t.to_string(),
+                TypeSnapshot {
+                    walked,
+                    stats,
+                    children,
+                },
+            );
+        }
+        snapshot
+    }
+
+    fn merged(mut a: ProgressSnapshot, b: ProgressSnapshot) -> ProgressSnapshot {
+        a.merge(b);
+        a
+    }
+
+    #[test]
+    fn test_snapshot_merge_properties() {
+        let mut rng = XorShift(0x2545f4914f6cdd1d);
+        for _ in 0..500 {
+            let a = random_snapshot(&mut rng);
+            let b = random_snapshot(&mut rng);
+            let c = random_snapshot(&mut rng);
+            assert_eq!(
+                merged(merged(a.clone(), b.clone()), c.clone()),
+                merged(a.clone(), merged(b.clone(), c.clone())),
+                "not associative for {:?} {:?} {:?}",
+                a,
+                b,
+                c
+            );
+            assert_eq!(merged(a.clone(), b.clone()), merged(b.clone(), a.clone()));
+            assert_eq!(a, merged(a.clone(), ProgressSnapshot::default()));
+        }
+    }
+
+    #[fbinit::test]
+    fn test_snapshot_merge_shards(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut shard_a = test_progress_state(fb).with_clock(clock.clone());
+        let mut shard_b = test_progress_state(fb).with_clock(clock.clone());
+        // Shard a started first
+        clock.advance(Duration::from_secs(100));
+        for i in 0..3 {
+            shard_a.record_step(&changeset_node(i), Some(&children(2)));
+        }
+        shard_a.record_step(
+            &phase_node(0),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        for i in 0..4 {
+            shard_b.record_step(&phase_node(i), Some(&children(1)));
+        }
+
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut fan_in = test_progress_state(fb)
+            .with_clock(clock.clone())
+            .with_stats_sink(stats.clone());
+        // Round trips through a saved file
+        let json = serde_json::to_string(&shard_a.snapshot()).unwrap();
+        fan_in.merge(serde_json::from_str(&json).unwrap()).unwrap();
+        fan_in.merge(shard_b.snapshot()).unwrap();
+
+        let summary = fan_in.summary();
+        assert_eq!(8, summary.walked());
+        assert_eq!(1, summary.errors());
+        assert_eq!(10, summary.queued());
+        assert_eq!(8, fan_in.work_stats.total_progress());
+        assert_eq!(
+            5,
+            fan_in.work_stats.children_by_type[&NodeType::PhaseMapping].steps
+        );
+        // Earliest start wins, allowing for the wall clock moving on
+        let elapsed = clock
+            .now()
+            .saturating_duration_since(fan_in.reporting_stats.start_time);
+        assert!(
+            elapsed >= Duration::from_secs(99) && elapsed <= Duration::from_secs(101),
+            "{:?}",
+            elapsed
+        );
+
+        // Order doesn't matter
+        let mut other_order = test_progress_state(fb);
+        other_order.merge(shard_b.snapshot()).unwrap();
+        other_order.merge(shard_a.snapshot()).unwrap();
+        let without_start = |mut s: ProgressSnapshot| {
+            s.start_time = None;
+            s
+        };
+        assert_eq!(
+            without_start(fan_in.snapshot()),
+            without_start(other_order.snapshot())
+        );
+
+        // The merged work is not progress made by this state
+        fan_in.record_step(&phase_node(9), None);
+        fan_in.report_progress_log(Some(Duration::from_secs(1)));
+        let walked: HashMap<_, _> = stats
+            .take_type_values()
+            .into_iter()
+            .filter(|(stat, _, _)| *stat == ProgressTypeStat::Walked)
+            .map(|(_, t, v)| (t, v))
+            .collect();
+        assert_eq!(
+            hashmap! {NodeType::Changeset => 0, NodeType::PhaseMapping => 1},
+            walked
+        );
+        assert_eq!(6, fan_in.final_report().types["PhaseMapping"].walked);
+
+        let mut bad = shard_b.snapshot();
+        bad.types
+            .insert("NotAType".to_string(), TypeSnapshot::default());
+        assert_eq!(
+            "Unknown node type NotAType in progress snapshot",
+            fan_in.merge(bad).unwrap_err().to_string()
+        );
+        assert_eq!(9, fan_in.summary().walked());
+    }
+
+    #[fbinit::test]
+    fn test_excluded_from_progress(fb: FacebookInit) -> Result<(), Error> {
+        let drain = CapturingDrain::default();
+        let options = ProgressOptionsBuilder::default()
+            .with_sample_rate(1)
+            .with_unchanged(UnchangedProgress::Log)
+            .build()?;
+        let builder = || {
+            ProgressStateBuilder::new(
+                fb,
+                Logger::root(drain.clone(), o!()),
+                "test",
+                "repo".to_string(),
+            )
+            .with_excluded_from_progress(hashset! {NodeType::FileContent})
+            .with_options(options.clone())
+        };
+        let mut state = builder()
+            .with_included_types(
+                hashset! {NodeType::Changeset, NodeType::FileContent, NodeType::PhaseMapping},
+            )
+            .build::<StepStats, ProgressSummary>()?;
+        assert_eq!(
+            vec![NodeType::Changeset, NodeType::PhaseMapping],
+            state.params.types_sorted_by_name
+        );
+        let content_node = |i| Node::FileContent(ContentId::from_byte_array([i; 32]));
+
+        state.record_step(&changeset_node(0), Some(&children(4)));
+        state.record_step(&content_node(0), Some(&children(0)));
+        state.record_steps(&[
+            (content_node(1), None),
+            (phase_node(0), Some(children(0))),
+            (content_node(2), None),
+        ]);
+        state.report_progress();
+
+        let logged = drain.take();
+        assert!(
+            !logged.iter().any(|l| l.contains("FileContent:")),
+            "{:?}",
+            logged
+        );
+        assert!(logged.contains(
+            &"Steps not counted in progress, of excluded types [FileContent]: 3".to_string()
+        ));
+        assert_eq!(2, state.summary().walked());
+        assert_eq!(2, state.work_stats.total_progress());
+        assert_eq!(3, state.work_stats.uncounted());
+        assert_eq!(0, state.work_stats.walked_of_type(NodeType::FileContent));
+        let report = state.final_report();
+        assert_eq!(3, report.uncounted_steps);
+        assert!(!report.types.contains_key("FileContent"));
+        // Not an included type that was never walked
+        assert!(report.unwalked_types.is_empty());
+
+        // Nothing left to report on
+        assert!(builder()
+            .with_included_types(hashset! {NodeType::FileContent})
+            .build::<StepStats, ProgressSummary>()
+            .is_err());
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum StepFailure {
+        Missing(u8),
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct TaggedKey {
+        node: Node,
+        tag: u8,
+    }
+
+    impl<'a> From<&'a TaggedKey> for &'a Node {
+        fn from(key: &'a TaggedKey) -> &'a Node {
+            &key.node
+        }
+    }
+
+    // Every third step fails
+    fn step(i: u8) -> Result<(TaggedKey, (), Option<StepStats>), StepFailure> {
+        if i % 3 == 0 {
+            Err(StepFailure::Missing(i))
+        } else {
+            Ok((
+                TaggedKey {
+                    node: phase_node(i),
+                    tag: i,
+                },
+                (),
+                Some(children(1)),
+            ))
+        }
+    }
+
+    #[fbinit::test]
+    async fn test_progress_stream_typed_errors(fb: FacebookInit) {
+        // Errors pass through as they are, and are not recorded
+        let progress = MockProgress::default();
+        let out: Vec<_> = progress_stream(&progress, stream::iter((1..7).map(step)))
+            .collect()
+            .await;
+        assert_eq!((1..7).map(step).collect::<Vec<_>>(), out);
+        progress.assert_walked(&[(NodeType::PhaseMapping, 4)]);
+        // All available at once, so one batch and one report
+        assert_eq!(vec![4], progress.calls());
+        assert_eq!(vec![MockReport::ThrottledNonblocking], progress.reports());
+
+        // Or with the node given by a projection rather than a conversion
+        let progress = MockProgress::default().with_quiet(QuietMode::FinalOnly);
+        let by_tag = |key: &TaggedKey| changeset_node(key.tag);
+        let out: Vec<_> = progress_stream_by(&progress, stream::iter((1..7).map(step)), by_tag)
+            .collect()
+            .await;
+        assert_eq!(6, out.len());
+        progress.assert_walked(&[(NodeType::Changeset, 4)]);
+        assert_eq!(
+            4,
+            progress.stats_of_type(NodeType::Changeset).num_expanded_new
+        );
+        assert!(progress.reports().is_empty());
+
+        let ctx = CoreContext::test_mock(fb);
+        let nodes = |n: u8| {
+            stream::iter((1..n).map(|i| step(i).map(|(key, _, ss)| (key.node, Some(()), ss))))
+        };
+        assert_eq!(
+            Ok(()),
+            report_state(ctx.clone(), QuietMode::Full, nodes(3)).await
+        );
+        assert_eq!(
+            Err(StepFailure::Missing(3)),
+            report_state(ctx, QuietMode::Full, nodes(7)).await
+        );
+    }
+
+    #[fbinit::test]
+    fn test_max_repo_keys(fb: FacebookInit) {
+        const REPOS: [&str; 5] = ["repo_a", "repo_b", "repo_c", "repo_d", "repo_e"];
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb)
+            .with_repo_key_fn(Arc::new(|n: &Node| match n {
+                // Low byte of phase_node's id
+                Node::PhaseMapping(id) => {
+                    REPOS[(id.sampling_fingerprint() % 256) as usize % REPOS.len()]
+                }
+                _ => "repo_a",
+            }))
+            .with_max_repo_keys(2);
+        state.params.logger = Logger::root(drain.clone(), o!());
+
+        for i in 0..10 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        state.record_steps(&[(phase_node(4), Some(children(2)))]);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let by_repo = &state.reporting_stats.last_summary_by_repo;
+        let mut keys: Vec<_> = by_repo.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(vec![OTHER_REPO_KEY, "repo_a", "repo_b"], keys);
+        assert_eq!(2, by_repo["repo_a"].walked);
+        assert_eq!(7, by_repo[OTHER_REPO_KEY].walked);
+        assert_eq!(3, state.work_stats.folded_repo_keys.count());
+        assert!(!state.work_stats.folded_repo_keys.truncated());
+
+        // Folding keeps the per repo stats adding up to the overall numbers
+        let total = by_repo
+            .values()
+            .fold(ProgressSummary::default(), |acc, v| acc + *v);
+        assert_eq!(state.reporting_stats.last_summary, total);
+        assert_eq!(11, total.walked);
+        assert_eq!(12, total.queued);
+
+        let logged = drain.take();
+        assert!(
+            logged
+                .iter()
+                .any(|l| l == "Repo (other) has 3 repos over the cap of 2"),
+            "{:?}",
+            logged
+        );
+        assert_eq!(3, state.final_report().folded_repo_keys);
+    }
+
+    #[fbinit::test]
+    fn test_walk_instances(fb: FacebookInit) -> Result<(), Error> {
+        let sink = Arc::new(CapturingStatsSink::default());
+        let drain = KvDrain::default();
+        let log_file = |instance: &str| {
+            std::env::temp_dir().join(format!(
+                "walker_instance_{}_{}",
+                instance,
+                std::process::id()
+            ))
+        };
+        let make_state = |instance: &str| -> Result<_, Error> {
+            let mut state = test_progress_state(fb);
+            state.params.logger = Logger::root(drain.clone(), o!());
+            let mut state = state
+                .with_stats_sink(sink.clone())
+                .with_walk_instance(instance.to_string());
+            let _ = std::fs::remove_file(log_file(instance));
+            state.set_sample_builder(
+                MononokeScubaSampleBuilder::with_discard().with_log_file(log_file(instance))?,
+            );
+            Ok(state)
+        };
+        let states = vec![make_state("a")?, make_state("b")?];
+
+        // The same walk in both, at the same time
+        std::thread::scope(|s| {
+            for mut state in states {
+                s.spawn(move || {
+                    for i in 0..10 {
+                        state.record_step(&phase_node(i), Some(&children(1)));
+                    }
+                    state.report_progress_log(Some(Duration::from_secs(1)));
+                });
+            }
+        });
+
+        let mut stats_keys: Vec<String> =
+            sink.take().into_iter().map(|(_, repo, _)| repo).collect();
+        stats_keys.sort();
+        stats_keys.dedup();
+        assert_eq!(vec!["repo.a", "repo.b"], stats_keys);
+
+        let logged = drain.take();
+        assert!(!logged.is_empty());
+        for instance in ["a", "b"] {
+            assert!(logged
+                .iter()
+                .any(|(_, kv)| kv.get("walk_instance").map(String::as_str) == Some(instance)));
+        }
+        assert!(logged
+            .iter()
+            .all(|(_, kv)| kv.contains_key("walk_instance")));
+
+        for instance in ["a", "b"] {
+            let rows = std::fs::read_to_string(log_file(instance))?
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<serde_json::Value>, _>>()?;
+            std::fs::remove_file(log_file(instance))?;
+            assert!(!rows.is_empty());
+            assert!(rows
+                .iter()
+                .all(|row| row[WALK_INSTANCE].as_str() == Some(instance)));
+        }
+
+        // No instance keeps the single walk keys
+        let mut state = test_progress_state(fb)
+            .with_stats_sink(sink.clone())
+            .with_walk_instance(String::new());
+        state.record_step(&phase_node(0), Some(&children(1)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(sink.take().iter().all(|(_, repo, _)| repo == "repo"));
+        assert_eq!(8, auto_walk_instance().len());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_blobs_written(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let writes = |blobs_written, bytes_written| StepStats {
+            repaired_count: blobs_written,
+            blobs_written,
+            bytes_written,
+            ..Default::default()
+        };
+
+        // Reads only, so no write columns
+        state.record_step(&phase_node(0), Some(&writes(0, 0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let line = drain.take().remove(0);
+        assert!(!line.contains("Written"), "{}", line);
+
+        // A repair storm
+        for i in 1..=10 {
+            state.record_step(&phase_node(i), Some(&writes(20, 2000)));
+        }
+        state.report_progress_log(Some(Duration::from_secs(2)));
+        let summary = state.summary();
+        assert_eq!(200, summary.blobs_written());
+        assert_eq!(20_000, summary.bytes_written());
+        let line = drain.take().remove(0);
+        assert!(
+            line.contains("Written Blobs/s,Bytes/s,Blobs,Bytes 100.0/s,10000.0/s,200,20000; "),
+            "{}",
+            line
+        );
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::BlobsWritten, "repo".to_string(), 200)));
+        assert!(captured.contains(&(ProgressStat::BytesWritten, "repo".to_string(), 20_000)));
+
+        // Deltas only cover the new writes, with the run totals kept
+        state.record_step(&phase_node(11), Some(&writes(1, 50)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let line = drain.take().remove(0);
+        assert!(line.contains("Bytes 1.0/s,50.0/s,201,20050; "), "{}", line);
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::BlobsWritten, "repo".to_string(), 1)));
+        assert!(captured.contains(&(ProgressStat::BytesWritten, "repo".to_string(), 50)));
+    }
+
+    #[fbinit::test]
+    fn test_retries(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let retries = |retries| StepStats {
+            retries,
+            ..Default::default()
+        };
+
+        for i in 0..4 {
+            state.record_step(&phase_node(i), Some(&children(0)));
+        }
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(!drain.take().remove(0).contains("Retries"));
+        assert_eq!(0.0, state.summary().retries_per_walked());
+
+        // One node needing five attempts, another two, then both complete
+        state.record_retries(&phase_node(4), &retries(4));
+        state.record_retries(&changeset_node(0), &retries(1));
+        state.record_step(&phase_node(4), Some(&children(0)));
+        state.record_step(&changeset_node(0), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let summary = state.summary();
+        assert_eq!(6, summary.walked());
+        assert_eq!(5, summary.retries());
+        assert!((summary.retries_per_walked() - 5.0 / 6.0).abs() < 1e-9);
+        let line = drain.take().remove(0);
+        assert!(
+            line.contains("Retries,Retries/Walked 5,0.833; "),
+            "{}",
+            line
+        );
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Retries, "repo".to_string(), 5)));
+
+        // Retries are kept by type, without counting extra steps
+        let report = state.final_report();
+        assert_eq!(4, report.types["PhaseMapping"].retries);
+        assert_eq!(5, report.types["PhaseMapping"].walked);
+        assert_eq!(1, report.types["Changeset"].retries);
+    }
+
+    #[fbinit::test]
+    fn test_hash_mismatch(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let mismatch = StepStats {
+            hash_validation_failure_count: 1,
+            ..Default::default()
+        };
+
+        state.record_step(&changeset_node(1), Some(&mismatch));
+        state.record_step(&changeset_node(2), Some(&children(0)));
+        state.record_steps(&[
+            (phase_node(3), Some(children(0))),
+            (phase_node(4), Some(mismatch)),
+        ]);
+
+        // Logged as found, not only in the next report
+        let warnings = drain.take();
+        assert_eq!(
+            vec![
+                format!("Hash mismatch, data is corrupt for {}", changeset_node(1)),
+                format!("Hash mismatch, data is corrupt for {}", phase_node(4)),
+            ],
+            warnings
+        );
+        assert_eq!(
+            vec![
+                (ErrorCategory::Corrupt, changeset_node(1).to_string()),
+                (ErrorCategory::Corrupt, phase_node(4).to_string()),
+            ],
+            state
+                .work_stats
+                .recent_errors
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(2, state.summary().hash_validation_failure());
+        assert_eq!(0, state.summary().errors());
+        assert!(stats.take().contains(&(
+            ProgressStat::HashValidationFailure,
+            "repo".to_string(),
+            2
+        )));
+    }
+
+    #[fbinit::test]
+    fn test_run_id_everywhere(fb: FacebookInit) -> Result<(), Error> {
+        let log_file = std::env::temp_dir().join(format!("walker_run_id_{}", std::process::id()));
+        let _ = std::fs::remove_file(&log_file);
+        let drain = KvDrain::default();
+        let (sender, mut receiver) = mpsc::channel(10);
+        let mut state = test_progress_state_logging_to(fb, Logger::root(drain.clone(), o!()))
+            .with_report_channel(sender);
+        state.set_sample_builder(
+            MononokeScubaSampleBuilder::with_discard().with_log_file(&log_file)?,
+        );
+        let run_id = state.params.run_id.clone();
+        let is_timestamp = |timestamp: &str| {
+            DateTime::parse_from_rfc3339(timestamp).is_ok() && timestamp.ends_with('Z')
+        };
+
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.report_progress();
+
+        let records = drain.take();
+        let (msg, kv) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert!(msg.contains(&format!("; RunId {}; Type:", &run_id[..SHORT_RUN_ID_LEN])));
+        // Every record, not just the progress line
+        assert!(records.len() > 1);
+        for (_, kv) in &records {
+            assert_eq!(Some(&run_id), kv.get(RUN_ID));
+            assert!(is_timestamp(&kv[TIMESTAMP]), "{:?}", kv);
+        }
+        assert_eq!(Some(&run_id), kv.get(RUN_ID));
+
+        let rows = std::fs::read_to_string(&log_file)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        std::fs::remove_file(&log_file)?;
+        assert!(!rows.is_empty());
+        for row in &rows {
+            assert_eq!(Some(run_id.as_str()), row[RUN_ID].as_str());
+            assert!(is_timestamp(row[TIMESTAMP].as_str().unwrap()), "{}", row);
+        }
+
+        let line = receiver.try_recv()?;
+        assert_eq!(run_id, line.run_id);
+        assert!(is_timestamp(&line.timestamp));
+
+        let report = state.final_report();
+        assert_eq!(run_id, report.run_id);
+        assert!(is_timestamp(&report.timestamp));
+        Ok(())
+    }
+}
@@ -0,0 +1,591 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::bail;
+use anyhow::Error;
+
+use crate::detail::graph::NodeType;
+use crate::detail::jsonl::JsonlOptions;
+use crate::detail::progress::budgets::SlowdownDetection;
+use crate::detail::progress::histograms::PayloadSizeOptions;
+use crate::detail::progress::histograms::MAX_PAYLOAD_BUCKETS;
+
+#[derive(Clone, Debug)]
+pub struct ProgressOptions {
+    pub sample_rate: u64,
+    pub interval: Duration,
+    pub display: ProgressDisplay,
+    pub quiet: QuietMode,
+    /// Include per type rates in the type detail, at the cost of a longer line
+    pub type_rates: bool,
+    /// Per type stats and Scuba rows for types unchanged since they were last emitted are
+    /// only emitted every Nth report. Changed types and totals are emitted on every report.
+    pub type_emit_every_nth: u64,
+    pub type_grouping: TypeGrouping,
+    /// Show the longest running step in the progress line once it has run this long
+    pub stuck_step_threshold: Duration,
+    pub unchanged: UnchangedProgress,
+    /// Errors allowed per type before the run is marked failed. The walk carries on
+    /// either way. Types not in the map have no budget.
+    pub error_budgets: HashMap<NodeType, u64>,
+    /// Log every Nth node recorded of each type at debug level, 0 for none. Counted per
+    /// type so nodes of rarer types are still seen.
+    pub sample_node_every_nth: u64,
+    pub number_format: NumberFormat,
+    /// Stretch the interval as needed to log at most this many progress lines an hour,
+    /// and shrink it back to `interval` when reports are sparse. None for a fixed interval.
+    pub max_lines_per_hour: Option<u64>,
+    /// Flag types not walked for this long in throttled reports. None to not flag any.
+    pub idle_type_threshold: Option<Duration>,
+    /// Call out the slowest worker when it has been inactive this much longer than the
+    /// median worker. None to not compare workers.
+    pub worker_skew_threshold: Option<Duration>,
+    /// Make throttled reports on wall clock multiples of the interval, e.g. on the minute
+    /// for a 60s interval, rather than an interval after the last report
+    pub wall_clock_aligned: bool,
+    /// Warn when the walk rate stays well below its recent rates. None to not check.
+    pub slowdown: Option<SlowdownDetection>,
+    pub payload_sizes: PayloadSizeOptions,
+    /// Also append each report to a size rotated JSON lines file. None for no file.
+    pub jsonl: Option<JsonlOptions>,
+    /// Types to also report on their own every interest_interval, e.g. while chasing
+    /// one type's coverage. Empty for none.
+    pub types_of_interest: HashSet<NodeType>,
+    pub interest_interval: Duration,
+}
+
+/// How counts, rates and durations are written in the progress line. Key values, stats
+/// and Scuba always have the raw numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// Plain integers and seconds, for parsing
+    Raw,
+    /// Three significant figures with SI suffixes and d/h/m/s durations, for reading
+    Human,
+}
+
+impl NumberFormat {
+    pub fn count(&self, n: u64) -> String {
+        match self {
+            NumberFormat::Raw => n.to_string(),
+            NumberFormat::Human => human_count(n as f64),
+        }
+    }
+
+    pub fn rate(&self, per_s: f64) -> String {
+        match self {
+            NumberFormat::Raw => format!("{:.1}", per_s),
+            NumberFormat::Human => human_count(per_s),
+        }
+    }
+
+    pub fn duration(&self, d: Duration) -> String {
+        match self {
+            NumberFormat::Raw => format!("{}s", d.as_secs()),
+            NumberFormat::Human => human_duration(d),
+        }
+    }
+}
+
+const SI_PREFIXES: [&str; 7] = ["", "k", "M", "G", "T", "P", "E"];
+
+/// Three significant figures with an SI suffix, e.g. 18234992 as 18.2M. Whole numbers
+/// under 1000 are exact, and fractions are shown to at most three decimal places.
+pub fn human_count(n: f64) -> String {
+    if n.abs() < 1000.0 && n.fract() == 0.0 {
+        return format!("{}", n);
+    }
+    let mut scaled = n;
+    let mut prefix = 0;
+    // Thresholds are where rounding carries over, so 999.6 is 1.00k not 1000
+    while scaled.abs() >= 999.5 && prefix + 1 < SI_PREFIXES.len() {
+        scaled /= 1000.0;
+        prefix += 1;
+    }
+    let decimals = match scaled.abs() {
+        a if a >= 99.95 => 0,
+        a if a >= 9.995 => 1,
+        a if a >= 0.9995 => 2,
+        _ => 3,
+    };
+    format!("{:.*}{}", decimals, scaled, SI_PREFIXES[prefix])
+}
+
+/// The two largest whole units of d/h/m/s, e.g. 93612s as 1d 2h. Zero units are left out.
+pub fn human_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let parts: Vec<_> = units
+        .iter()
+        .skip_while(|(n, _)| *n == 0)
+        .take(2)
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// How to log a periodic report where nothing changed since the last one. Stats are
+/// emitted either way, and the final report is always logged in full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnchangedProgress {
+    /// The full progress line, as for any other report
+    Log,
+    /// A short line saying how long there has been no progress
+    Compact,
+    /// No line at all
+    Skip,
+}
+
+/// Whether the detail line and per type stats are by NodeType, by NodeTypeGroup, or both.
+/// Group stats are always emitted as there are only a few of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeGrouping {
+    Types,
+    Groups,
+    Both,
+}
+
+impl TypeGrouping {
+    pub fn by_type(&self) -> bool {
+        *self != TypeGrouping::Groups
+    }
+
+    pub fn by_group(&self) -> bool {
+        *self != TypeGrouping::Types
+    }
+}
+
+/// Steps are always recorded so final numbers are right, this only controls reporting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuietMode {
+    /// Throttled reports, heartbeats and the final report
+    Full,
+    /// Only the final report
+    FinalOnly,
+    /// Every Nth throttled report and the final report
+    EveryNth(u64),
+    /// No reports at all
+    Silent,
+}
+
+impl QuietMode {
+    pub fn reports_throttled(&self) -> bool {
+        matches!(self, QuietMode::Full | QuietMode::EveryNth(_))
+    }
+
+    pub fn reports_final(&self) -> bool {
+        *self != QuietMode::Silent
+    }
+}
+
+impl ProgressOptions {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.sample_rate == 0 {
+            bail!(
+                "Progress sample rate must be at least 1, use time only mode to sample every step"
+            );
+        }
+        if self.interval.is_zero() {
+            bail!("Progress interval must be non-zero");
+        }
+        if self.quiet == QuietMode::EveryNth(0) {
+            bail!("Progress every Nth report must be at least 1");
+        }
+        if self.type_emit_every_nth == 0 {
+            bail!("Progress per type emission every Nth report must be at least 1");
+        }
+        if self.max_lines_per_hour == Some(0) {
+            bail!("Progress max lines per hour must be at least 1");
+        }
+        let bounds = &self.payload_sizes.bounds;
+        if bounds.is_empty() || bounds.len() >= MAX_PAYLOAD_BUCKETS {
+            bail!(
+                "Progress payload size buckets must have between 1 and {} bounds",
+                MAX_PAYLOAD_BUCKETS - 1
+            );
+        }
+        if bounds.windows(2).any(|w| w[0] >= w[1]) {
+            bail!(
+                "Progress payload size bounds must be increasing, got {:?}",
+                bounds
+            );
+        }
+        if self
+            .jsonl
+            .as_ref()
+            .is_some_and(|jsonl| jsonl.max_bytes == 0)
+        {
+            bail!("Progress JSON lines file max size must be non-zero");
+        }
+        if !self.types_of_interest.is_empty() && self.interest_interval.is_zero() {
+            bail!("Progress interval for types of interest must be non-zero");
+        }
+        if let Some(slowdown) = &self.slowdown {
+            if slowdown.window == 0 || slowdown.consecutive == 0 {
+                bail!("Progress slowdown window and consecutive reports must be at least 1");
+            }
+            if !(slowdown.drop_pct > 0.0 && slowdown.drop_pct < 100.0) {
+                bail!(
+                    "Progress slowdown percentage must be between 0 and 100, got {}",
+                    slowdown.drop_pct
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds ProgressOptions, starting from the command line defaults
+#[derive(Clone, Debug)]
+pub struct ProgressOptionsBuilder {
+    pub(super) options: ProgressOptions,
+    time_only: bool,
+}
+
+impl Default for ProgressOptionsBuilder {
+    fn default() -> Self {
+        Self {
+            options: ProgressOptions {
+                sample_rate: 100,
+                interval: Duration::from_secs(5),
+                display: ProgressDisplay::Log,
+                quiet: QuietMode::Full,
+                type_rates: true,
+                type_emit_every_nth: 10,
+                type_grouping: TypeGrouping::Types,
+                stuck_step_threshold: Duration::from_secs(60),
+                unchanged: UnchangedProgress::Compact,
+                error_budgets: HashMap::new(),
+                sample_node_every_nth: 0,
+                number_format: NumberFormat::Raw,
+                max_lines_per_hour: None,
+                idle_type_threshold: None,
+                worker_skew_threshold: None,
+                wall_clock_aligned: false,
+                slowdown: None,
+                payload_sizes: PayloadSizeOptions::default(),
+                jsonl: None,
+                types_of_interest: HashSet::new(),
+                interest_interval: Duration::from_secs(5),
+            },
+            time_only: false,
+        }
+    }
+}
+
+impl ProgressOptionsBuilder {
+    pub fn with_sample_rate(mut self, sample_rate: u64) -> Self {
+        self.options.sample_rate = sample_rate;
+        self
+    }
+
+    /// Throttle by interval alone, checking the time on every step whatever the sample rate
+    pub fn with_time_only(mut self) -> Self {
+        self.time_only = true;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.options.interval = interval;
+        self
+    }
+
+    pub fn with_display(mut self, display: ProgressDisplay) -> Self {
+        self.options.display = display;
+        self
+    }
+
+    pub fn with_quiet(mut self, quiet: QuietMode) -> Self {
+        self.options.quiet = quiet;
+        self
+    }
+
+    pub fn with_type_rates(mut self, type_rates: bool) -> Self {
+        self.options.type_rates = type_rates;
+        self
+    }
+
+    pub fn with_type_emit_every_nth(mut self, type_emit_every_nth: u64) -> Self {
+        self.options.type_emit_every_nth = type_emit_every_nth;
+        self
+    }
+
+    pub fn with_type_grouping(mut self, type_grouping: TypeGrouping) -> Self {
+        self.options.type_grouping = type_grouping;
+        self
+    }
+
+    pub fn with_stuck_step_threshold(mut self, stuck_step_threshold: Duration) -> Self {
+        self.options.stuck_step_threshold = stuck_step_threshold;
+        self
+    }
+
+    pub fn with_unchanged(mut self, unchanged: UnchangedProgress) -> Self {
+        self.options.unchanged = unchanged;
+        self
+    }
+
+    pub fn with_error_budgets(mut self, error_budgets: HashMap<NodeType, u64>) -> Self {
+        self.options.error_budgets = error_budgets;
+        self
+    }
+
+    pub fn with_sample_node_every_nth(mut self, sample_node_every_nth: u64) -> Self {
+        self.options.sample_node_every_nth = sample_node_every_nth;
+        self
+    }
+
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        self.options.number_format = number_format;
+        self
+    }
+
+    pub fn with_max_lines_per_hour(mut self, max_lines_per_hour: u64) -> Self {
+        self.options.max_lines_per_hour = Some(max_lines_per_hour);
+        self
+    }
+
+    pub fn with_idle_type_threshold(mut self, idle_type_threshold: Duration) -> Self {
+        self.options.idle_type_threshold = Some(idle_type_threshold);
+        self
+    }
+
+    pub fn with_worker_skew_threshold(mut self, worker_skew_threshold: Duration) -> Self {
+        self.options.worker_skew_threshold = Some(worker_skew_threshold);
+        self
+    }
+
+    pub fn with_wall_clock_aligned(mut self, wall_clock_aligned: bool) -> Self {
+        self.options.wall_clock_aligned = wall_clock_aligned;
+        self
+    }
+
+    pub fn with_slowdown(mut self, slowdown: SlowdownDetection) -> Self {
+        self.options.slowdown = Some(slowdown);
+        self
+    }
+
+    pub fn with_types_of_interest(
+        mut self,
+        types_of_interest: HashSet<NodeType>,
+        interest_interval: Duration,
+    ) -> Self {
+        self.options.types_of_interest = types_of_interest;
+        self.options.interest_interval = interest_interval;
+        self
+    }
+
+    pub fn with_payload_sizes(mut self, payload_sizes: PayloadSizeOptions) -> Self {
+        self.options.payload_sizes = payload_sizes;
+        self
+    }
+
+    pub fn with_jsonl(mut self, jsonl: JsonlOptions) -> Self {
+        self.options.jsonl = Some(jsonl);
+        self
+    }
+
+    pub fn build(self) -> Result<ProgressOptions, Error> {
+        let mut options = self.options;
+        if self.time_only {
+            options.sample_rate = 1;
+        }
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressDisplay {
+    /// Log lines, suitable for files and services
+    Log,
+    /// Single line updated in place, for interactive use
+    Bar,
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[test]
+    fn test_progress_options_validation() {
+        let err = |builder: ProgressOptionsBuilder| builder.build().unwrap_err().to_string();
+        assert_eq!(
+            "Progress sample rate must be at least 1, use time only mode to sample every step",
+            err(ProgressOptionsBuilder::default().with_sample_rate(0))
+        );
+        assert_eq!(
+            "Progress interval must be non-zero",
+            err(ProgressOptionsBuilder::default().with_interval(Duration::ZERO))
+        );
+        assert_eq!(
+            "Progress every Nth report must be at least 1",
+            err(ProgressOptionsBuilder::default().with_quiet(QuietMode::EveryNth(0)))
+        );
+        assert_eq!(
+            "Progress per type emission every Nth report must be at least 1",
+            err(ProgressOptionsBuilder::default().with_type_emit_every_nth(0))
+        );
+        let slowdown = |drop_pct| SlowdownDetection {
+            window: 10,
+            drop_pct,
+            consecutive: 3,
+            warm_up: Duration::ZERO,
+        };
+        assert_eq!(
+            "Progress slowdown percentage must be between 0 and 100, got 100",
+            err(ProgressOptionsBuilder::default().with_slowdown(slowdown(100.0)))
+        );
+        assert_eq!(
+            "Progress slowdown window and consecutive reports must be at least 1",
+            err(
+                ProgressOptionsBuilder::default().with_slowdown(SlowdownDetection {
+                    consecutive: 0,
+                    ..slowdown(20.0)
+                })
+            )
+        );
+        let payload_bounds = |bounds| {
+            ProgressOptionsBuilder::default().with_payload_sizes(PayloadSizeOptions {
+                bounds,
+                types: HashSet::new(),
+            })
+        };
+        assert_eq!(
+            "Progress payload size bounds must be increasing, got [10, 10]",
+            err(payload_bounds(vec![10, 10]))
+        );
+        assert_eq!(
+            "Progress payload size buckets must have between 1 and 63 bounds",
+            err(payload_bounds(vec![]))
+        );
+
+        // Time only mode checks on every step, so a zero sample rate is fine
+        let options = ProgressOptionsBuilder::default()
+            .with_sample_rate(0)
+            .with_time_only()
+            .build()
+            .unwrap();
+        assert_eq!(1, options.sample_rate);
+    }
+
+    #[test]
+    fn test_human_count() {
+        for (n, expected) in [
+            (0.0, "0"),
+            (7.0, "7"),
+            (999.0, "999"),
+            (0.5, "0.500"),
+            (0.01234, "0.012"),
+            (1.5, "1.50"),
+            (12.34, "12.3"),
+            (99.96, "100"),
+            (999.4, "999"),
+            (999.6, "1.00k"),
+            (1000.0, "1.00k"),
+            (1234.0, "1.23k"),
+            (12345.0, "12.3k"),
+            (123456.0, "123k"),
+            (999_500.0, "1.00M"),
+            (18_234_992.0, "18.2M"),
+            (4_560_000_000.0, "4.56G"),
+            (7.89e12, "7.89T"),
+            (1.0e15, "1.00P"),
+            (2.5e18, "2.50E"),
+            // Past the largest prefix the figures grow instead
+            (1.234e21, "1234E"),
+        ] {
+            assert_eq!(expected, human_count(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_human_duration() {
+        for (secs, expected) in [
+            (0, "0s"),
+            (59, "59s"),
+            (60, "1m"),
+            (61, "1m 1s"),
+            (3599, "59m 59s"),
+            (3600, "1h"),
+            (3725, "1h 2m"),
+            (86399, "23h 59m"),
+            (86400, "1d"),
+            (86460, "1d"),
+            (93612, "1d 2h"),
+            (10 * 86400 + 5, "10d"),
+        ] {
+            assert_eq!(
+                expected,
+                human_duration(Duration::from_secs(secs)),
+                "{}",
+                secs
+            );
+        }
+    }
+
+    #[fbinit::test]
+    fn test_number_format(fb: FacebookInit) {
+        let drain = KvDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.number_format = NumberFormat::Human;
+        for _ in 0..1234 {
+            state.record_step(&phase_node(0), Some(&children(1000)));
+        }
+        state.report_progress_log(Some(Duration::from_secs(93612)));
+
+        let records = drain.take();
+        let (msg, kv) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert!(
+            msg.contains("Delta 0.013/s,13.2/s,1.23k,0,0,1.23M,1d 2h; "),
+            "{}",
+            msg
+        );
+        assert!(msg.contains(",1.23k,0,0,1.23M,0s; "), "{}", msg);
+        // Structured fields stay raw
+        for (key, value) in [
+            ("walked", "1234"),
+            ("queued", "1234000"),
+            ("delta_walked", "1234"),
+            ("delta_s", "93612"),
+        ] {
+            assert_eq!(Some(value), kv.get(key).map(String::as_str), "{}", key);
+        }
+
+        state.params.options.number_format = NumberFormat::Raw;
+        state.record_step(&phase_node(0), Some(&children(1000)));
+        state.report_progress_log(Some(Duration::from_secs(93612)));
+        let records = drain.take();
+        let (msg, _) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert!(msg.contains(",1235,0,0,1235000,"), "{}", msg);
+    }
+}
@@ -0,0 +1,1801 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Add;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use slog::info;
+use slog::warn;
+
+use crate::detail::graph::NodeType;
+use crate::detail::graph::NodeTypeGroup;
+use crate::detail::jsonl::JsonlSink;
+use crate::detail::log;
+use crate::detail::progress::gauges::error_rate;
+use crate::detail::progress::gauges::visit_hits_detail;
+use crate::detail::progress::gauges::FEEDBACK_LATENCY_PERCENTILE;
+use crate::detail::progress::options::NumberFormat;
+use crate::detail::progress::options::ProgressDisplay;
+use crate::detail::progress::options::UnchangedProgress;
+use crate::detail::progress::sinks::iso_timestamp;
+use crate::detail::progress::sinks::ProgressBar;
+use crate::detail::progress::sinks::ReportLine;
+use crate::detail::progress::sort_by_string;
+use crate::detail::progress::ProgressGauge;
+use crate::detail::progress::ProgressRates;
+use crate::detail::progress::ProgressStat;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::progress::OTHER_REPO_KEY;
+use crate::detail::progress::SHORT_RUN_ID_LEN;
+use crate::detail::progress::TOTAL;
+use crate::detail::report::FinalReport;
+use crate::detail::report::TypeReport;
+use crate::detail::report::WorkerReport;
+use crate::detail::state::BlobstoreReads;
+use crate::detail::state::StepStats;
+
+pub(super) fn summarize_by_type(
+    stats_by_type: &HashMap<NodeType, (u64, StepStats)>,
+) -> HashMap<NodeType, ProgressSummary> {
+    stats_by_type
+        .iter()
+        .map(|(k, (ps, ss))| {
+            let s = ProgressSummary {
+                walked: *ps,
+                checked: ss.visited_of_type as u64,
+                // num_expanded_new is per type children which when summed == a top level queued stat
+                queued: ss.num_expanded_new as u64,
+                errors: ss.error_count as u64,
+                missing: ss.missing_count as u64,
+                hash_validation_failure: ss.hash_validation_failure_count as u64,
+                corrupt: ss.corrupt_error_count as u64,
+                transient: ss.transient_error_count as u64,
+                other_errors: ss.other_error_count as u64,
+                repaired: ss.repaired_count as u64,
+                unrepairable: ss.unrepairable_count as u64,
+                blobs_written: ss.blobs_written as u64,
+                bytes_written: ss.bytes_written,
+                retries: ss.retries as u64,
+            };
+            (*k, s)
+        })
+        .collect()
+}
+
+pub(super) fn summarize_by_group<T>(
+    summary_by_type: &HashMap<NodeType, T>,
+) -> HashMap<NodeTypeGroup, T>
+where
+    T: Add<T, Output = T> + Copy + Default,
+{
+    let mut summary_by_group = HashMap::new();
+    for (t, s) in summary_by_type {
+        let group_summary = summary_by_group.entry(t.group()).or_insert_with(T::default);
+        *group_summary = *group_summary + *s;
+    }
+    summary_by_group
+}
+
+// Derived data types make up several node types each, e.g. unodes. Node types that are
+// not derived data are left out.
+pub(super) fn summarize_by_derived<T>(
+    summary_by_type: &HashMap<NodeType, T>,
+) -> BTreeMap<&'static str, T>
+where
+    T: Add<T, Output = T> + Copy + Default,
+{
+    let mut summary_by_derived = BTreeMap::new();
+    for (t, s) in summary_by_type {
+        if let Some(derived) = t.derived_data_name() {
+            let derived_summary = summary_by_derived.entry(derived).or_insert_with(T::default);
+            *derived_summary = *derived_summary + *s;
+        }
+    }
+    summary_by_derived
+}
+
+// Share of the total walked, as a percentage
+fn walked_pct(walked: u64, total_walked: u64) -> f64 {
+    if total_walked == 0 {
+        0.0
+    } else {
+        walked as f64 * 100.0 / total_walked as f64
+    }
+}
+
+// Per type Walked,Checks,Children,Walked% in the order of types_sorted_by_name, so the
+// output does not depend on hash map iteration order. Walked% is of the types shown, so
+// they add up to 100% give or take rounding. If given the last summary and the time
+// since, also Walked/s,Children/s so the slowest type is visible without subtracting.
+// Also used for groups, in the order given.
+fn type_detail<K>(
+    types_sorted_by_name: &[K],
+    summary_by_type: &HashMap<K, ProgressSummary>,
+    rates_since: Option<(&HashMap<K, ProgressSummary>, Duration)>,
+) -> String
+where
+    K: Eq + Hash + fmt::Display,
+{
+    let total_walked = types_sorted_by_name
+        .iter()
+        .filter_map(|t| summary_by_type.get(t))
+        .map(|s| s.walked)
+        .sum();
+    types_sorted_by_name
+        .iter()
+        .map(|t| {
+            let s = summary_by_type.get(t).cloned().unwrap_or_default();
+            let pct = walked_pct(s.walked, total_walked);
+            match rates_since {
+                Some((last_summary_by_type, delta_time)) => {
+                    let last = last_summary_by_type.get(t).cloned().unwrap_or_default();
+                    let rates = ProgressRates::new(&(s - last), delta_time);
+                    format!(
+                        "{}:{},{},{},{:.1}%,{:.1}/s,{:.1}/s",
+                        t, s.walked, s.checked, s.queued, pct, rates.walked, rates.queued
+                    )
+                }
+                None => format!("{}:{},{},{},{:.1}%", t, s.walked, s.checked, s.queued, pct),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Walked, errors and children by type name
+fn per_type_counts(
+    types_sorted_by_name: &[NodeType],
+    summary_by_type: &HashMap<NodeType, ProgressSummary>,
+) -> BTreeMap<String, [u64; 3]> {
+    types_sorted_by_name
+        .iter()
+        .filter_map(|t| {
+            let s = summary_by_type.get(t)?;
+            Some((t.to_string(), [s.walked, s.errors, s.queued]))
+        })
+        .collect()
+}
+
+// Per type Walked,Errors,Children as compact JSON keyed by type name, for the per_type
+// key value of the progress record
+fn per_type_kv(
+    types_sorted_by_name: &[NodeType],
+    summary_by_type: &HashMap<NodeType, ProgressSummary>,
+) -> String {
+    serde_json::to_string(&per_type_counts(types_sorted_by_name, summary_by_type))
+        .unwrap_or_default()
+}
+
+// Only scrub repairs anything, so keep the line short for other walks until the final report
+fn repair_detail(summary: &ProgressSummary, is_final: bool) -> Option<String> {
+    (is_final || summary.repaired > 0 || summary.unrepairable > 0).then(|| {
+        format!(
+            "Repaired,Unrepairable {},{}",
+            summary.repaired, summary.unrepairable
+        )
+    })
+}
+
+fn retry_detail(summary: &ProgressSummary, numbers: NumberFormat) -> Option<String> {
+    if summary.retries == 0 {
+        return None;
+    }
+    Some(format!(
+        "Retries,Retries/Walked {},{:.3}",
+        numbers.count(summary.retries),
+        summary.retries_per_walked(),
+    ))
+}
+
+// Delta write rates and run totals, omitted until the walk writes anything
+fn write_detail(
+    summary: &ProgressSummary,
+    delta_per_s: &ProgressRates,
+    numbers: NumberFormat,
+) -> Option<String> {
+    if summary.blobs_written == 0 {
+        return None;
+    }
+    Some(format!(
+        "Written Blobs/s,Bytes/s,Blobs,Bytes {}/s,{}/s,{},{}",
+        numbers.rate(delta_per_s.blobs_written),
+        numbers.rate(delta_per_s.bytes_written),
+        numbers.count(summary.blobs_written),
+        numbers.count(summary.bytes_written),
+    ))
+}
+
+// Reads per blobstore id, omitted with a single store as there is nothing to compare
+fn blobstore_detail(reads: &BlobstoreReads) -> Option<String> {
+    if reads.len() <= 1 {
+        return None;
+    }
+    let mut detail = reads
+        .iter()
+        .map(|(id, count)| format!("{}:{}", id, count))
+        .collect::<Vec<_>>();
+    if reads.other > 0 {
+        detail.push(format!("other:{}", reads.other));
+    }
+    Some(format!("Blobstore reads {}", detail.join(",")))
+}
+
+// The types of interest report keeps its own baselines, apart from the main report's
+pub(super) struct InterestBaseline<T> {
+    pub(super) last_update: Instant,
+    pub(super) last_by_type: HashMap<NodeType, T>,
+}
+
+impl<T> InterestBaseline<T> {
+    pub(super) fn new(now: Instant) -> Self {
+        Self {
+            last_update: now,
+            last_by_type: HashMap::new(),
+        }
+    }
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    // An included type that is never walked usually means a typo'd or unreachable
+    // --include-node-type, which otherwise just shows as zero counts
+    pub(super) fn report_unwalked_types(&self) {
+        let (unexpected, expected) = self.unwalked_types();
+        if !unexpected.is_empty() {
+            warn!(
+                self.params.logger,
+                "Included node types never walked for {}: {:?}",
+                self.params.repo_stats_key,
+                unexpected
+            );
+        }
+        if !expected.is_empty() {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Included node types not walked, as allowed, for {}: {:?}",
+                self.params.repo_stats_key,
+                expected
+            );
+        }
+    }
+
+    /// How long since each walked type was last walked, in type name order
+    pub fn idle_by_type(&self, now: Instant) -> Vec<(NodeType, Duration)> {
+        self.params
+            .types_sorted_by_name
+            .iter()
+            .filter_map(|t| {
+                let (_first, last) = self.work_stats.seen_by_type.get(t)?;
+                Some((*t, now.saturating_duration_since(*last)))
+            })
+            .collect()
+    }
+
+    // A type that stopped growing long before the end either had its frontier passed
+    // or was starved, which the last walked times help tell apart
+    pub(super) fn report_idle_types(&self) {
+        let idle = self.idle_by_type(self.params.clock.now());
+        if idle.is_empty() {
+            return;
+        }
+        let numbers = self.params.options.number_format;
+        let detail = idle
+            .iter()
+            .map(|(t, idle)| format!("{} idle for {}", t, numbers.duration(*idle)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(self.params.logger, #log::GRAPH, "Last walked: {}", detail);
+    }
+
+    // Excluded types are left out of everything else, so say how much was left out
+    pub(super) fn report_uncounted(&self) {
+        if self.work_stats.uncounted > 0 {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Steps not counted in progress, of excluded types {:?}: {}",
+                sort_by_string(&self.params.excluded_from_progress),
+                self.work_stats.uncounted;
+                "uncounted" => self.work_stats.uncounted,
+            );
+        }
+    }
+
+    /// Every included type's counters as a table, in type name order and then the total,
+    /// for looking into a running walk. Types not walked yet are shown with zeros, and
+    /// seen times are seconds since the start.
+    pub fn dump(&self) -> String {
+        let start = self.reporting_stats.start_time;
+        let now = self.params.clock.now();
+        let elapsed = now.saturating_duration_since(start);
+        let paused = self.reporting_stats.paused.total(now);
+        let active = elapsed.saturating_sub(paused);
+        let since_start =
+            |t: &Instant| format!("{:.1}", t.saturating_duration_since(start).as_secs_f64());
+        let row = |name: &str, s: &ProgressSummary, seen: Option<&(Instant, Instant)>| {
+            let mut row = vec![name.to_string()];
+            row.extend(
+                [
+                    s.walked,
+                    s.checked,
+                    s.queued,
+                    s.errors,
+                    s.corrupt,
+                    s.transient,
+                    s.other_errors,
+                    s.missing,
+                    s.hash_validation_failure,
+                    s.repaired,
+                    s.unrepairable,
+                ]
+                .iter()
+                .map(|v| v.to_string()),
+            );
+            row.push(format!("{:.1}", ProgressRates::new(s, active).walked));
+            row.push(seen.map_or_else(|| "-".to_string(), |(first, _)| since_start(first)));
+            row.push(seen.map_or_else(|| "-".to_string(), |(_, last)| since_start(last)));
+            row
+        };
+
+        let summary_by_type = self.summary_by_type();
+        let mut rows = vec![[
+            "Type",
+            "Walked",
+            "Checked",
+            "Children",
+            "Errors",
+            "Corrupt",
+            "Transient",
+            "Other",
+            "Missing",
+            "HashFail",
+            "Repaired",
+            "Unrepairable",
+            "Walked/s",
+            "FirstSeen",
+            "LastSeen",
+        ]
+        .map(String::from)
+        .to_vec()];
+        for t in &self.params.types_sorted_by_name {
+            rows.push(row(
+                t.into(),
+                &summary_by_type.get(t).copied().unwrap_or_default(),
+                self.work_stats.seen_by_type.get(t),
+            ));
+        }
+        let total = summary_by_type
+            .values()
+            .fold(ProgressSummary::default(), |acc, v| acc + *v);
+        rows.push(row(TOTAL, &total, None));
+
+        let mut widths = vec![0; rows[0].len()];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = cmp::max(*width, cell.len());
+            }
+        }
+        let paused_note = if paused > Duration::ZERO {
+            format!(" ({:.1}s paused)", paused.as_secs_f64())
+        } else {
+            String::new()
+        };
+        let mut out = format!(
+            "Progress of {} in {}, run {}, {:.1}s elapsed{}, {} uncounted steps\n",
+            self.params.subcommand_stats_key,
+            self.params.repo_stats_key,
+            self.params.run_id,
+            elapsed.as_secs_f64(),
+            paused_note,
+            self.work_stats.uncounted,
+        );
+        for row in rows {
+            // Names to the left, numbers to the right
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (cell, width))| {
+                    if i == 0 {
+                        format!("{:<width$}", cell, width = width)
+                    } else {
+                        format!("{:>width$}", cell, width = width)
+                    }
+                })
+                .collect();
+            out.push_str(&cells.join("  "));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Machine readable totals for the run so far
+    pub fn final_report(&self) -> FinalReport {
+        let now = self.params.clock.now();
+        let elapsed = now.saturating_duration_since(self.reporting_stats.start_time);
+        let paused = self.reporting_stats.paused.total(now);
+        let active = elapsed.saturating_sub(paused);
+        let payload_bounds = &self.params.options.payload_sizes.bounds;
+        let snapshot = self.summary_by_type();
+        let total_walked = snapshot.values().map(|s| s.walked).sum();
+        let type_report = |s: &ProgressSummary| TypeReport {
+            walked: s.walked,
+            walked_pct: walked_pct(s.walked, total_walked),
+            errors: s.errors,
+            missing: s.missing,
+            retries: s.retries,
+            rate: ProgressRates::new(s, active).walked,
+            ..Default::default()
+        };
+        let since_start = |t: Instant| {
+            t.saturating_duration_since(self.reporting_stats.start_time)
+                .as_secs_f64()
+        };
+        let types = snapshot
+            .iter()
+            .map(|(t, s)| {
+                let mut report = type_report(s);
+                if let Some((first, last)) = self.work_stats.seen_by_type.get(t) {
+                    report.first_seen_secs = Some(since_start(*first));
+                    report.last_seen_secs = Some(since_start(*last));
+                    report.idle_secs = Some(elapsed.as_secs_f64() - since_start(*last));
+                }
+                (t.to_string(), report)
+            })
+            .collect();
+        let derived = summarize_by_derived(&snapshot)
+            .iter()
+            .map(|(derived, s)| (derived.to_string(), type_report(s)))
+            .collect();
+        let (unwalked, unwalked_ok) = self.unwalked_types();
+        let workers = self
+            .reporting_stats
+            .gauges
+            .workers
+            .iter()
+            .enumerate()
+            .map(|(id, w)| WorkerReport {
+                id,
+                steps: w.steps,
+                idle_secs: now.saturating_duration_since(w.last_active).as_secs_f64(),
+                busy: w.busy,
+            })
+            .collect();
+        FinalReport {
+            repo: self.params.repo_stats_key.clone(),
+            subcommand: self.params.subcommand_stats_key.to_string(),
+            run_id: self.params.run_id.clone(),
+            timestamp: iso_timestamp(self.params.clock.wall_now()),
+            // Known only once the walk ends, see finish_walk
+            completed: false,
+            elapsed_secs: elapsed.as_secs_f64(),
+            types,
+            derived,
+            unwalked_types: unwalked.iter().map(|t| t.to_string()).collect(),
+            unwalked_ok_types: unwalked_ok.iter().map(|t| t.to_string()).collect(),
+            overhead_ms: self.overhead.total().as_secs_f64() * 1000.0,
+            overhead_pct: self.overhead.pct_of(elapsed),
+            budget_violations: self.budget_violations(),
+            uncounted_steps: self.work_stats.uncounted,
+            filtered: self
+                .reporting_stats
+                .gauges
+                .filtered
+                .iter()
+                .map(|(t, count)| (t.to_string(), *count))
+                .collect(),
+            visit_hits: (!self.reporting_stats.gauges.visit_hits.is_empty())
+                .then(|| self.total_visit_hits().report()),
+            visit_hits_by_type: self
+                .reporting_stats
+                .gauges
+                .visit_hits
+                .iter()
+                .map(|(t, hits)| (t.to_string(), hits.report()))
+                .collect(),
+            changeset_phases: self.work_stats.changeset_phases,
+            workers,
+            paused_secs: paused.as_secs_f64(),
+            folded_repo_keys: self.work_stats.folded_repo_keys.count(),
+            payload_sizes: (self.work_stats.payload_sizes.count() > 0)
+                .then(|| self.work_stats.payload_sizes.report(payload_bounds)),
+            payload_sizes_by_type: self
+                .work_stats
+                .payload_sizes_by_type
+                .iter()
+                .map(|(t, histogram)| (t.to_string(), histogram.report(payload_bounds)))
+                .collect(),
+        }
+    }
+
+    // Per repo log lines and stats, only used when more than one repo is being walked
+    fn report_progress_by_repo(&mut self, delta_time: Option<Duration>) {
+        let mut last_summary_by_repo = HashMap::new();
+        for repo in sort_by_string(self.work_stats.stats_by_repo.keys()) {
+            let summary_by_type = summarize_by_type(&self.work_stats.stats_by_repo[repo]);
+            let new_summary = summary_by_type
+                .values()
+                .fold(ProgressSummary::default(), |acc, v| acc + *v);
+            let last_summary = self
+                .reporting_stats
+                .last_summary_by_repo
+                .get(repo)
+                .cloned()
+                .unwrap_or_default();
+            let delta_summary = new_summary - last_summary;
+            let detail = &type_detail(&self.params.types_sorted_by_name, &summary_by_type, None);
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Repo {} Walked,Errors,Missing,Children; Delta {},{},{},{}; Run {},{},{},{}; Type:Walked,Checks,Children,Walked% {}",
+                repo,
+                delta_summary.walked,
+                delta_summary.errors,
+                delta_summary.missing,
+                delta_summary.queued,
+                new_summary.walked,
+                new_summary.errors,
+                new_summary.missing,
+                new_summary.queued,
+                detail,
+            );
+            let stats_key = self.params.instance_key(repo);
+            self.report_stats(&stats_key, &delta_summary);
+            if let Some(delta_time) = delta_time {
+                self.report_gauges(&stats_key, &ProgressRates::new(&delta_summary, delta_time));
+            }
+            last_summary_by_repo.insert(repo.clone(), new_summary);
+        }
+        let folded = &self.work_stats.folded_repo_keys;
+        if folded.count() > 0 {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Repo {} has {} repos over the cap of {}",
+                OTHER_REPO_KEY,
+                folded,
+                self.params.max_repo_keys.unwrap_or_default(),
+            );
+        }
+        self.reporting_stats.last_summary_by_repo = last_summary_by_repo;
+    }
+
+    // Final breakdown of errors by type, only logged if there were any
+    pub(super) fn report_errors_by_type(&self) {
+        let detail = self
+            .params
+            .types_sorted_by_name
+            .iter()
+            .filter_map(|t| {
+                let ss = self.work_stats.stats_by_type.get(t)?.1;
+                let distinct = self.work_stats.distinct_errors_by_type.get(t)?;
+                Some(format!(
+                    "{}:{},{},{},{},{}",
+                    t,
+                    ss.error_count,
+                    distinct,
+                    ss.corrupt_error_count,
+                    ss.transient_error_count,
+                    ss.other_error_count
+                ))
+            })
+            .collect::<Vec<_>>();
+        if !detail.is_empty() {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Type:Errors,Errors(distinct),Corrupt,Transient,Other {}",
+                detail.join(" "),
+            );
+        }
+    }
+
+    // Final breakdown of how many children each type expands to
+    pub(super) fn report_children_stats(&self) {
+        let detail = self
+            .params
+            .types_sorted_by_name
+            .iter()
+            .filter_map(|t| {
+                let s = self.work_stats.children_by_type.get(t)?;
+                Some(format!("{}:{:.1},{}", t, s.mean(), s.max))
+            })
+            .collect::<Vec<_>>();
+        if !detail.is_empty() {
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "Type:ChildrenMean,ChildrenMax {}",
+                detail.join(" "),
+            );
+        }
+    }
+
+    // Totals per derived data type, as that is how derivation is run
+    pub(super) fn report_derived_breakdown(&self) {
+        let summary_by_derived = summarize_by_derived(&self.summary_by_type());
+        if summary_by_derived.is_empty() {
+            return;
+        }
+        let detail = summary_by_derived
+            .iter()
+            .map(|(derived, s)| format!("{}:{},{},{}", derived, s.walked, s.errors, s.queued))
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "Derived:Walked,Errors,Children {}",
+            detail
+        );
+    }
+
+    // Only logs in compact mode, so repeated reports of no progress stay short
+    pub(super) fn log_unchanged(&self) {
+        if self.params.options.unchanged != UnchangedProgress::Compact {
+            return;
+        }
+        let unchanged_time = self
+            .params
+            .clock
+            .now()
+            .saturating_duration_since(self.reporting_stats.throttle.last_changed);
+        let numbers = self.params.options.number_format;
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "No progress for {}; Walked {}",
+            numbers.duration(unchanged_time),
+            numbers.count(self.work_stats.total_progress);
+            "walked" => self.work_stats.total_progress,
+            "unchanged_s" => unchanged_time.as_secs(),
+        );
+    }
+
+    /// Time since the last types of interest report, if another is due
+    pub fn should_log_interest(&mut self) -> Option<Duration> {
+        if self.params.options.types_of_interest.is_empty()
+            || !self.params.quiet_mode().reports_throttled()
+        {
+            return None;
+        }
+        let now = self.params.clock.now();
+        let delta_time = now.saturating_duration_since(self.reporting_stats.interest.last_update);
+        if delta_time < self.params.options.interest_interval {
+            return None;
+        }
+        self.reporting_stats.interest.last_update = now;
+        Some(delta_time)
+    }
+
+    // A compact line for just the types of interest. Deltas are since the last of these
+    // lines, leaving the main report's baselines alone.
+    pub fn report_interest(&mut self, delta_time: Duration) {
+        let numbers = self.params.options.number_format;
+        let secs = delta_time.as_secs_f64();
+        let summary_by_type: HashMap<NodeType, ProgressSummary> = self
+            .summary_by_type()
+            .into_iter()
+            .filter(|(t, _)| self.params.options.types_of_interest.contains(t))
+            .collect();
+        let detail = sort_by_string(&self.params.options.types_of_interest)
+            .into_iter()
+            .map(|t| {
+                let s = summary_by_type.get(t).copied().unwrap_or_default();
+                let last = self
+                    .reporting_stats
+                    .interest
+                    .last_by_type
+                    .get(t)
+                    .copied()
+                    .unwrap_or_default();
+                let delta = s - last;
+                let errors_per_s = if secs > 0.0 {
+                    delta.errors as f64 / secs
+                } else {
+                    0.0
+                };
+                format!(
+                    "{}:{},{},{}/s,{}/s",
+                    t,
+                    numbers.count(s.walked),
+                    numbers.count(s.errors),
+                    numbers.rate(ProgressRates::new(&delta, delta_time).walked),
+                    numbers.rate(errors_per_s),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "Interest Type:Walked,Errors,Walked/s,Errors/s {}; Delta {}",
+            detail,
+            numbers.duration(delta_time),
+        );
+        self.reporting_stats.interest.last_by_type = summary_by_type;
+    }
+
+    pub fn report_progress_log(&mut self, delta_time: Option<Duration>) {
+        let started = Instant::now();
+        // No delta time means this is the last report of a run or chunk
+        let is_final = delta_time.is_none();
+        let times = self.report_times(delta_time);
+        let (now, delta_time, total_time) = (times.now, times.delta, times.total);
+        let summary_by_type = self.summary_by_type();
+        let new_summary = summary_by_type
+            .values()
+            .fold(ProgressSummary::default(), |acc, v| acc + *v);
+        let delta_summary = new_summary - self.reporting_stats.last_summary;
+        self.check_invariants(&summary_by_type);
+        self.check_error_budgets();
+
+        let (delta_s, delta_summary_per_s) =
+            delta_time.map_or((0, ProgressRates::default()), |delta_time| {
+                (
+                    delta_time.as_secs(),
+                    ProgressRates::new(&delta_summary, delta_time),
+                )
+            });
+
+        let total_summary_per_s = ProgressRates::new(&new_summary, times.active);
+        let numbers = self.params.options.number_format;
+        if !is_final {
+            self.check_slowdown(total_time, delta_summary_per_s.walked);
+        }
+        let step_latencies = self.reporting_stats.gauges.step_latencies.clone();
+        self.reporting_stats.gauges.feedback.publish(
+            error_rate(&delta_summary),
+            step_latencies
+                .since(&self.reporting_stats.gauges.last_step_latencies)
+                .percentile(FEEDBACK_LATENCY_PERCENTILE),
+        );
+        self.reporting_stats.gauges.last_step_latencies = step_latencies;
+
+        // Each segment is omitted when it has nothing to show, the rest are joined by "; "
+        let mut segments = vec![
+            Some("Walked/s,Children/s,Walked,Errors,Missing,Children,Time".to_string()),
+            Some(format!(
+                "Delta {}/s,{}/s,{},{},{},{},{}",
+                numbers.rate(delta_summary_per_s.walked),
+                numbers.rate(delta_summary_per_s.queued),
+                numbers.count(delta_summary.walked),
+                numbers.count(delta_summary.errors),
+                numbers.count(delta_summary.missing),
+                numbers.count(delta_summary.queued),
+                numbers.duration(Duration::from_secs(delta_s)),
+            )),
+        ];
+
+        segments.push(self.reporting_stats.iteration.as_ref().map(|iteration| {
+            let iteration_summary = new_summary - iteration.start_summary;
+            let iteration_time = now.saturating_duration_since(iteration.start_time);
+            let iteration_summary_per_s = ProgressRates::new(&iteration_summary, iteration_time);
+            format!(
+                "Iter {} {}/s,{}/s,{},{},{},{},{}",
+                iteration.iteration,
+                numbers.rate(iteration_summary_per_s.walked),
+                numbers.rate(iteration_summary_per_s.queued),
+                numbers.count(iteration_summary.walked),
+                numbers.count(iteration_summary.errors),
+                numbers.count(iteration_summary.missing),
+                numbers.count(iteration_summary.queued),
+                numbers.duration(iteration_time),
+            )
+        }));
+
+        segments.push(Some(format!(
+            "Run {}/s,{}/s,{},{},{},{},{}",
+            numbers.rate(total_summary_per_s.walked),
+            numbers.rate(total_summary_per_s.queued),
+            numbers.count(self.work_stats.total_progress),
+            numbers.count(new_summary.errors),
+            numbers.count(new_summary.missing),
+            numbers.count(new_summary.queued),
+            numbers.duration(total_time),
+        )));
+
+        segments.push((new_summary.errors > 0).then(|| {
+            let (distinct, truncated) = self.work_stats.distinct_errors();
+            format!(
+                "Errors(distinct),Corrupt,Transient,Other {}{},{},{},{}",
+                distinct,
+                if truncated { "+" } else { "" },
+                new_summary.corrupt,
+                new_summary.transient,
+                new_summary.other_errors,
+            )
+        }));
+
+        segments.push(repair_detail(&new_summary, is_final));
+        segments.push(write_detail(&new_summary, &delta_summary_per_s, numbers));
+        segments.push(retry_detail(&new_summary, numbers));
+        segments.push(blobstore_detail(&self.work_stats.blobstore_reads));
+
+        segments.push(
+            (times.active < total_time)
+                .then(|| format!("Paused {}", numbers.duration(total_time - times.active))),
+        );
+
+        // All from the walk driver, so shown together
+        let gauges = &self.reporting_stats.gauges;
+        segments.push((gauges.max_outstanding > 0).then(|| {
+            format!(
+                "Outstanding,Max {},{}",
+                gauges.outstanding, gauges.max_outstanding
+            )
+        }));
+        segments.push(gauges.in_flight_occupancy.map(|window| {
+            format!(
+                "InFlight Avg,Peak,Limit {:.1},{},{}",
+                window.avg,
+                window.peak,
+                window
+                    .limit
+                    .map_or_else(|| "-".to_string(), |limit| limit.to_string())
+            )
+        }));
+        segments.push(self.total_visit_hits().hit_pct().map(|pct| {
+            format!(
+                "Visited% {:.1} {}",
+                pct,
+                visit_hits_detail(&gauges.visit_hits)
+            )
+        }));
+
+        segments.push(match gauges.oldest_in_flight.as_ref() {
+            Some((node, start))
+                if now.saturating_duration_since(*start)
+                    >= self.params.options.stuck_step_threshold =>
+            {
+                Some(format!(
+                    "Oldest in flight {}s {:?}",
+                    now.saturating_duration_since(*start).as_secs(),
+                    node
+                ))
+            }
+            _ => None,
+        });
+
+        segments.push(
+            self.params
+                .options
+                .worker_skew_threshold
+                .and_then(|threshold| {
+                    self.slowest_worker(now)
+                        .filter(|(_id, age, median)| age.saturating_sub(*median) >= threshold)
+                        .map(|(id, age, median)| {
+                            format!(
+                                "Slowest worker {} {}, median {}",
+                                id,
+                                numbers.duration(age),
+                                numbers.duration(median)
+                            )
+                        })
+                }),
+        );
+
+        segments.push(match self.params.options.idle_type_threshold {
+            Some(threshold) if !is_final => {
+                let idle = self
+                    .idle_by_type(now)
+                    .into_iter()
+                    .filter(|(_t, idle)| *idle >= threshold)
+                    .map(|(t, idle)| format!("{}:{}", t, numbers.duration(idle)))
+                    .collect::<Vec<_>>();
+                (!idle.is_empty()).then(|| format!("Idle {}", idle.join(",")))
+            }
+            _ => None,
+        });
+
+        segments.push(self.reporting_stats.chunk.as_ref().map(|chunk| {
+            let chunk_summary = new_summary - chunk.start_summary;
+            let chunk_time = now.saturating_duration_since(chunk.start_time);
+            format!(
+                "Chunk {} {},{},{},{},{}",
+                chunk.chunk_index,
+                numbers.count(chunk_summary.walked),
+                numbers.count(chunk_summary.errors),
+                numbers.count(chunk_summary.missing),
+                numbers.count(chunk_summary.queued),
+                numbers.duration(chunk_time),
+            )
+        }));
+
+        let gauges = &self.reporting_stats.gauges;
+        segments
+            .push((!gauges.position.is_empty()).then(|| format!("Position {}", gauges.position)));
+
+        segments.push(
+            self.params
+                .options
+                .max_lines_per_hour
+                .map(|_| format!("Interval {}", numbers.duration(self.report_interval()))),
+        );
+
+        // Short enough not to lengthen the line much, the key values have it in full.
+        // Ahead of the per type detail, which scripts expect to end the line.
+        let short_run_id = &self.params.run_id[..SHORT_RUN_ID_LEN.min(self.params.run_id.len())];
+        segments.push(Some(format!("RunId {}", short_run_id)));
+
+        let columns = if self.params.options.type_rates {
+            "Walked,Checks,Children,Walked%,Walked/s,Children/s"
+        } else {
+            "Walked,Checks,Children,Walked%"
+        };
+        if self.params.options.type_grouping.by_type() {
+            let rates_since = self.params.options.type_rates.then(|| {
+                (
+                    &self.reporting_stats.last_summary_by_type,
+                    delta_time.unwrap_or_default(),
+                )
+            });
+            segments.push(Some(format!(
+                "Type:{} {}",
+                columns,
+                type_detail(
+                    &self.params.types_sorted_by_name,
+                    &summary_by_type,
+                    rates_since
+                )
+            )));
+        }
+        if self.params.options.type_grouping.by_group() {
+            let last_summary_by_group =
+                summarize_by_group(&self.reporting_stats.last_summary_by_type);
+            let rates_since = self
+                .params
+                .options
+                .type_rates
+                .then(|| (&last_summary_by_group, delta_time.unwrap_or_default()));
+            segments.push(Some(format!(
+                "Group:{} {}",
+                columns,
+                type_detail(
+                    &self.params.groups(),
+                    &summarize_by_group(&summary_by_type),
+                    rates_since
+                )
+            )));
+        }
+        let line = segments
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let unchanged = !is_final && delta_summary == ProgressSummary::default();
+        if !unchanged {
+            self.reporting_stats.throttle.last_changed = now;
+        }
+
+        if self.params.options.display == ProgressDisplay::Bar && !is_final {
+            self.reporting_stats
+                .sinks
+                .bar
+                .get_or_insert_with(ProgressBar::stderr)
+                .render(
+                    self.work_stats.total_progress,
+                    // Roots are walked without being queued
+                    new_summary.queued.max(new_summary.walked),
+                    total_summary_per_s.walked,
+                    total_time,
+                );
+        } else if unchanged && self.params.options.unchanged != UnchangedProgress::Log {
+            self.log_unchanged();
+        } else {
+            if let Some(bar) = self.reporting_stats.sinks.bar.as_mut() {
+                bar.finish();
+            }
+            // Also as key values, so structured drains need not parse the message
+            let per_type = per_type_kv(&self.params.types_sorted_by_name, &summary_by_type);
+            info!(
+                self.params.logger,
+                #log::GRAPH,
+                "{}",
+                line;
+                "final" => is_final,
+                "walked" => self.work_stats.total_progress,
+                "errors" => new_summary.errors,
+                "missing" => new_summary.missing,
+                "queued" => new_summary.queued,
+                "elapsed_s" => total_time.as_secs(),
+                "walked_per_s" => total_summary_per_s.walked,
+                "queued_per_s" => total_summary_per_s.queued,
+                "delta_walked" => delta_summary.walked,
+                "delta_errors" => delta_summary.errors,
+                "delta_missing" => delta_summary.missing,
+                "delta_queued" => delta_summary.queued,
+                "delta_s" => delta_s,
+                "delta_walked_per_s" => delta_summary_per_s.walked,
+                "delta_queued_per_s" => delta_summary_per_s.queued,
+                "blobs_written" => new_summary.blobs_written,
+                "bytes_written" => new_summary.bytes_written,
+                "retries" => new_summary.retries,
+                "per_type" => per_type,
+            );
+        }
+
+        if self.work_stats.stats_by_repo.len() > 1 {
+            self.report_progress_by_repo(delta_time);
+        } else {
+            self.report_stats(&self.params.stats_key, &delta_summary);
+            if delta_time.is_some() {
+                self.report_gauges(&self.params.stats_key, &delta_summary_per_s);
+            }
+        }
+        // Roots are walked without being queued, so this is approximate
+        self.params.stats_sink.set_gauge(
+            ProgressGauge::InFlight,
+            self.params.subcommand_stats_key,
+            &self.params.stats_key,
+            new_summary.queued.saturating_sub(new_summary.walked) as i64,
+        );
+        for (gauge, value) in [
+            (
+                ProgressGauge::Outstanding,
+                self.reporting_stats.gauges.outstanding,
+            ),
+            (
+                ProgressGauge::MaxOutstanding,
+                self.reporting_stats.gauges.max_outstanding,
+            ),
+        ] {
+            self.params.stats_sink.set_gauge(
+                gauge,
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                value as i64,
+            );
+        }
+        if let Some(window) = self.reporting_stats.gauges.in_flight_occupancy {
+            for (gauge, value) in [
+                (ProgressGauge::InFlightAvg, window.avg.round() as i64),
+                (ProgressGauge::InFlightPeak, window.peak as i64),
+            ] {
+                self.params.stats_sink.set_gauge(
+                    gauge,
+                    self.params.subcommand_stats_key,
+                    &self.params.stats_key,
+                    value,
+                );
+            }
+        }
+        self.report_by_type(&summary_by_type, &new_summary, &delta_summary, is_final);
+        self.report_blobstore_reads();
+        self.report_changeset_phases();
+        self.report_visit_stats();
+        if self.params.options.jsonl.is_some()
+            || self.reporting_stats.sinks.report_channel.is_some()
+        {
+            let wall_now = self.params.clock.wall_now();
+            let line = ReportLine {
+                time: wall_now
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                timestamp: iso_timestamp(wall_now),
+                repo: self.params.repo_stats_key.clone(),
+                subcommand: self.params.subcommand_stats_key.to_string(),
+                run_id: self.params.run_id.clone(),
+                walk_instance: self.params.walk_instance.clone(),
+                is_final,
+                walked: self.work_stats.total_progress,
+                errors: new_summary.errors,
+                missing: new_summary.missing,
+                queued: new_summary.queued,
+                elapsed_s: total_time.as_secs(),
+                walked_per_s: total_summary_per_s.walked,
+                delta_walked: delta_summary.walked,
+                delta_errors: delta_summary.errors,
+                delta_s,
+                delta_walked_per_s: delta_summary_per_s.walked,
+                per_type: per_type_counts(&self.params.types_sorted_by_name, &summary_by_type),
+            };
+            if let Some(options) = &self.params.options.jsonl {
+                self.reporting_stats
+                    .sinks
+                    .jsonl
+                    .get_or_insert_with(|| JsonlSink::new(options.clone()))
+                    .append(&self.params.logger, now, &line);
+            }
+            if let Some(channel) = self.reporting_stats.sinks.report_channel.as_mut() {
+                channel.send(&self.params.logger, line);
+            }
+        }
+
+        self.reporting_stats.last_summary_by_type = summary_by_type;
+        self.reporting_stats.last_summary = new_summary;
+        self.reporting_stats
+            .sinks
+            .scuba_rows
+            .log_all(&self.params.logger);
+        self.finish_report(&times);
+
+        self.overhead.add_report(started.elapsed());
+        let overhead_delta_us = self.overhead.take_delta_us();
+        self.params.stats_sink.add_value(
+            ProgressStat::OverheadMicros,
+            self.params.subcommand_stats_key,
+            &self.params.stats_key,
+            overhead_delta_us as i64,
+        );
+    }
+
+    // Time spent in progress bookkeeping compared to the whole run
+    pub(super) fn report_overhead(&self) {
+        let elapsed = self
+            .params
+            .clock
+            .now()
+            .saturating_duration_since(self.reporting_stats.start_time);
+        info!(
+            self.params.logger,
+            #log::GRAPH,
+            "Progress overhead: {} ms ({:.2}%) over {} steps and {} reports",
+            self.overhead.total().as_millis(),
+            self.overhead.pct_of(elapsed),
+            self.overhead.record_calls,
+            self.overhead.report_calls,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use mononoke_types::ContentId;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::graph::ChangesetKey;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[fbinit::test]
+    fn test_final_report(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        for i in 0..20 {
+            state.record_step(&phase_node(i), Some(&children(0)));
+        }
+        state.record_step(
+            &phase_node(20),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        clock.advance(Duration::from_secs(7));
+
+        let report = state.final_report();
+        assert_eq!("repo", report.repo);
+        assert_eq!(7.0, report.elapsed_secs);
+        assert_eq!(
+            vec!["PhaseMapping"],
+            report.types.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            TypeReport {
+                walked: 21,
+                walked_pct: 100.0,
+                errors: 1,
+                missing: 0,
+                retries: 0,
+                rate: 3.0,
+                first_seen_secs: Some(0.0),
+                last_seen_secs: Some(0.0),
+                idle_secs: Some(7.0),
+            },
+            report.types["PhaseMapping"]
+        );
+    }
+
+    #[fbinit::test]
+    fn test_dump(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.run_id = "run".to_string();
+        for i in 0..12 {
+            state.record_step(&phase_node(i), Some(&children(2)));
+        }
+        clock.advance(Duration::from_secs(2));
+        state.record_step(
+            &phase_node(20),
+            Some(&StepStats {
+                error_count: 2,
+                corrupt_error_count: 1,
+                transient_error_count: 1,
+                ..Default::default()
+            }),
+        );
+        state.record_step(
+            &phase_node(21),
+            Some(&StepStats {
+                missing_count: 1,
+                ..Default::default()
+            }),
+        );
+        clock.advance(Duration::from_secs(2));
+
+        let expected = [
+            "Progress of test in repo, run run, 4.0s elapsed, 0 uncounted steps",
+            "Type          Walked  Checked  Children  Errors  Corrupt  Transient  Other  Missing  HashFail  Repaired  Unrepairable  Walked/s  FirstSeen  LastSeen",
+            "Changeset          0        0         0       0        0          0      0        0         0         0             0       0.0          -         -",
+            "PhaseMapping      14        0        24       2        1          1      0        1         0         0             0       3.5        0.0       2.0",
+            "total             14        0        24       2        1          1      0        1         0         0             0       3.5          -         -",
+        ]
+        .map(|line| format!("{}\n", line))
+        .concat();
+        assert_eq!(expected, state.dump());
+    }
+
+    #[test]
+    fn test_type_detail_order() {
+        let types = sort_by_string(hashset! {
+            NodeType::PhaseMapping,
+            NodeType::Changeset,
+            NodeType::Bookmark,
+        });
+        let summary_by_type = hashmap! {
+            NodeType::Changeset => ProgressSummary {
+                walked: 2,
+                checked: 3,
+                queued: 4,
+                ..Default::default()
+            },
+            NodeType::PhaseMapping => ProgressSummary {
+                walked: 1,
+                ..Default::default()
+            },
+        };
+        // Percentages to one place, of the types shown so they add up to 100%
+        assert_eq!(
+            "Bookmark:0,0,0,0.0% Changeset:2,3,4,66.7% PhaseMapping:1,0,0,33.3%",
+            type_detail(&types, &summary_by_type, None)
+        );
+        assert_eq!(
+            "Bookmark:0,0,0,0.0% Changeset:0,0,0,0.0% PhaseMapping:0,0,0,0.0%",
+            type_detail(&types, &HashMap::new(), None)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_type_rates(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let run_id = format!("RunId {}; ", &state.params.run_id[..SHORT_RUN_ID_LEN]);
+        let reports = || {
+            drain
+                .take()
+                .into_iter()
+                .filter(|msg| msg.starts_with("Walked/s"))
+                .inspect(|msg| assert!(msg.contains(&run_id), "{}", msg))
+                .collect::<Vec<_>>()
+        };
+
+        for i in 0..4 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        clock.advance(Duration::from_secs(2));
+        state.report_progress_log(Some(Duration::from_secs(2)));
+        let logged = reports();
+        assert!(
+            logged[0].ends_with(
+                "Type:Walked,Checks,Children,Walked%,Walked/s,Children/s Changeset:0,0,0,0.0%,0.0/s,0.0/s PhaseMapping:4,0,4,100.0%,2.0/s,2.0/s"
+            ),
+            "{}",
+            logged[0]
+        );
+
+        // Rates are for the latest interval only, not the whole run
+        state.record_step(&changeset_node(0), Some(&children(3)));
+        state.record_step(&phase_node(4), Some(&children(0)));
+        clock.advance(Duration::from_secs(4));
+        state.report_progress_log(Some(Duration::from_secs(4)));
+        let logged = reports();
+        assert!(
+            logged[0].ends_with(
+                "Changeset:1,0,3,16.7%,0.2/s,0.8/s PhaseMapping:5,0,4,83.3%,0.2/s,0.0/s"
+            ),
+            "{}",
+            logged[0]
+        );
+
+        // Can be turned off to keep the old line
+        state.params.options.type_rates = false;
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let logged = reports();
+        assert!(
+            logged[0].ends_with("Type:Walked,Checks,Children,Walked% Changeset:1,0,3,16.7% PhaseMapping:5,0,4,83.3%"),
+            "{}",
+            logged[0]
+        );
+    }
+
+    #[fbinit::test]
+    fn test_progress_by_repo(fb: FacebookInit) {
+        let mut state = test_progress_state(fb).with_repo_key_fn(Arc::new(|n: &Node| match n {
+            Node::PhaseMapping(_) => "repo_a",
+            _ => "repo_b",
+        }));
+
+        for i in 0..3 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+        }
+        let changeset = Node::Changeset(ChangesetKey {
+            inner: ChangesetId::from_byte_array([9; 32]),
+            filenode_known_derived: false,
+        });
+        state.record_step(&changeset, Some(&children(4)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let by_repo = &state.reporting_stats.last_summary_by_repo;
+        assert_eq!(2, by_repo.len());
+        assert_eq!(3, by_repo["repo_a"].walked);
+        assert_eq!(3, by_repo["repo_a"].queued);
+        assert_eq!(1, by_repo["repo_b"].walked);
+        assert_eq!(4, by_repo["repo_b"].queued);
+        assert_eq!(
+            1,
+            state.work_stats.stats_by_repo["repo_b"][&NodeType::Changeset].0
+        );
+
+        // Overall numbers are unchanged by the per repo breakdown
+        assert_eq!(4, state.reporting_stats.last_summary.walked);
+        assert_eq!(7, state.reporting_stats.last_summary.queued);
+    }
+
+    #[fbinit::test]
+    fn test_progress_single_repo(fb: FacebookInit) {
+        let mut state = test_progress_state(fb);
+        state.record_step(&phase_node(0), Some(&children(1)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(state.work_stats.stats_by_repo.is_empty());
+        assert!(state.reporting_stats.last_summary_by_repo.is_empty());
+        assert_eq!(1, state.reporting_stats.last_summary.walked);
+    }
+
+    #[fbinit::test]
+    fn test_unwalked_types(fb: FacebookInit) -> Result<(), Error> {
+        let drain = CapturingDrain::default();
+        // FileContent looks like a typo'd type that is never reached
+        let mut state: ProgressStateCountByType<StepStats, ProgressSummary> =
+            ProgressStateBuilder::new(
+                fb,
+                Logger::root(drain.clone(), o!()),
+                "test",
+                "repo".to_string(),
+            )
+            .with_included_types(hashset! {
+                NodeType::Changeset,
+                NodeType::FileContent,
+                NodeType::HgChangeset,
+                NodeType::PhaseMapping,
+            })
+            .with_unwalked_ok_types(hashset! {NodeType::HgChangeset})
+            .build()?;
+        state.record_step(&phase_node(0), None);
+        state.record_step(&changeset_node(1), None);
+
+        state.report_progress();
+        let logged = drain.take();
+        assert!(
+            logged
+                .contains(&"Included node types never walked for repo: [FileContent]".to_string()),
+            "{:?}",
+            logged
+        );
+        assert!(
+            logged.contains(
+                &"Included node types not walked, as allowed, for repo: [HgChangeset]".to_string()
+            ),
+            "{:?}",
+            logged
+        );
+
+        let report = state.final_report();
+        assert_eq!(vec!["FileContent".to_string()], report.unwalked_types);
+        assert_eq!(vec!["HgChangeset".to_string()], report.unwalked_ok_types);
+
+        // No warning once the unexpected type has been walked
+        state.record_step(
+            &Node::FileContent(ContentId::from_byte_array([0; 32])),
+            None,
+        );
+        state.report_progress();
+        assert!(!drain.take().iter().any(|msg| msg.contains("never walked")),);
+        assert!(state.final_report().unwalked_types.is_empty());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_unchanged_progress(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let clock = FakeClock::new();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb)
+            .with_clock(clock.clone())
+            .with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.unchanged = UnchangedProgress::Compact;
+        let report = |state: &mut ProgressStateCountByType<StepStats, ProgressSummary>, secs| {
+            clock.advance(Duration::from_secs(secs));
+            state.report_progress_log(Some(Duration::from_secs(secs)));
+            drain.take()
+        };
+
+        state.record_step(&phase_node(0), Some(&children(2)));
+        let logged = report(&mut state, 1);
+        assert!(logged[0].starts_with("Walked/s"), "{:?}", logged);
+
+        // Repeated reports with nothing new collapse, counting from the last change
+        assert_eq!(
+            vec!["No progress for 5s; Walked 1".to_string()],
+            report(&mut state, 5)
+        );
+        assert_eq!(
+            vec!["No progress for 10s; Walked 1".to_string()],
+            report(&mut state, 5)
+        );
+        // Stats are still emitted
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Walked, "repo".to_string(), 0)));
+
+        // Any change is reported in full and resets the count
+        state.record_step(&phase_node(1), None);
+        let logged = report(&mut state, 5);
+        assert!(logged[0].starts_with("Walked/s"), "{:?}", logged);
+        assert_eq!(
+            vec!["No progress for 2s; Walked 2".to_string()],
+            report(&mut state, 2)
+        );
+
+        // A stalled heartbeat also collapses
+        clock.advance(Duration::from_secs(1));
+        state.report_heartbeat();
+        assert_eq!(
+            vec!["No progress for 3s; Walked 2".to_string()],
+            drain.take()
+        );
+
+        // Skipped entirely, but stats are still emitted
+        state.params.options.unchanged = UnchangedProgress::Skip;
+        stats.take();
+        assert!(report(&mut state, 5).is_empty());
+        assert!(stats
+            .take()
+            .contains(&(ProgressStat::Walked, "repo".to_string(), 0)));
+
+        // The final report is always in full
+        state.report_progress_log(None);
+        let logged = drain.take();
+        assert!(logged[0].starts_with("Walked/s"), "{:?}", logged);
+    }
+
+    #[fbinit::test]
+    fn test_derived_breakdown(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let cs_id = |i| ChangesetId::from_byte_array([i; 32]);
+        let changeset_info = NodeType::ChangesetInfo.derived_data_name().unwrap();
+        let fsnodes = NodeType::FsnodeMapping.derived_data_name().unwrap();
+
+        // Both changeset info node types count towards the one derived type, and
+        // phases are not derived data
+        state.record_step(&Node::ChangesetInfo(cs_id(0)), Some(&children(2)));
+        state.record_step(
+            &Node::ChangesetInfoMapping(cs_id(0)),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        state.record_step(&Node::FsnodeMapping(cs_id(0)), None);
+        state.record_step(&phase_node(0), Some(&children(5)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let mut expected = vec![
+            (ProgressTypeStat::Walked, changeset_info, 2),
+            (ProgressTypeStat::Queued, changeset_info, 2),
+            (ProgressTypeStat::Errors, changeset_info, 1),
+            (ProgressTypeStat::Walked, fsnodes, 1),
+            (ProgressTypeStat::Queued, fsnodes, 0),
+            (ProgressTypeStat::Errors, fsnodes, 0),
+        ];
+        let mut derived_values = stats.take_derived_values();
+        let by_key = |(stat, derived, _): &(ProgressTypeStat, &str, i64)| {
+            (derived.to_string(), format!("{:?}", stat))
+        };
+        expected.sort_by_key(by_key);
+        derived_values.sort_by_key(by_key);
+        assert_eq!(expected, derived_values);
+
+        // Deltas since the last report
+        state.record_step(&Node::FsnodeMapping(cs_id(1)), None);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let walked: Vec<_> = stats
+            .take_derived_values()
+            .into_iter()
+            .filter(|(stat, _, _)| *stat == ProgressTypeStat::Walked)
+            .collect();
+        assert_eq!(
+            vec![
+                (ProgressTypeStat::Walked, changeset_info, 0),
+                (ProgressTypeStat::Walked, fsnodes, 1),
+            ],
+            walked
+        );
+
+        drain.take();
+        state.report_progress();
+        let expected_line = format!(
+            "Derived:Walked,Errors,Children {}:2,1,2 {}:2,0,0",
+            changeset_info, fsnodes
+        );
+        assert!(drain.take().contains(&expected_line));
+        let report = state.final_report();
+        assert_eq!(2, report.derived.len());
+        assert_eq!(2, report.derived[changeset_info].walked);
+        assert_eq!(1, report.derived[changeset_info].errors);
+        assert_eq!(2, report.derived[fsnodes].walked);
+    }
+
+    #[fbinit::test]
+    fn test_idle_types(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.idle_type_threshold = Some(Duration::from_secs(10));
+
+        state.record_step(&phase_node(1), Some(&children(1)));
+        clock.advance(Duration::from_secs(5));
+        state.record_steps(&[
+            (phase_node(2), Some(children(1))),
+            (changeset_node(1), Some(children(1))),
+        ]);
+        clock.advance(Duration::from_secs(8));
+        state.record_step(&changeset_node(2), Some(&children(1)));
+        clock.advance(Duration::from_secs(4));
+
+        let now = clock.now();
+        assert_eq!(
+            vec![
+                (NodeType::Changeset, Duration::from_secs(4)),
+                (NodeType::PhaseMapping, Duration::from_secs(12)),
+            ],
+            state.idle_by_type(now)
+        );
+
+        // Only types past the threshold are flagged while walking
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let line = drain.take().remove(0);
+        assert!(line.contains("Idle PhaseMapping:12s; "), "{}", line);
+        assert!(!line.contains("Changeset:4s"), "{}", line);
+
+        state.report_progress();
+        assert!(drain.take().contains(
+            &"Last walked: Changeset idle for 4s, PhaseMapping idle for 12s".to_string()
+        ));
+
+        let report = state.final_report();
+        let phases = &report.types["PhaseMapping"];
+        assert_eq!(Some(0.0), phases.first_seen_secs);
+        assert_eq!(Some(5.0), phases.last_seen_secs);
+        assert_eq!(Some(12.0), phases.idle_secs);
+        assert_eq!(Some(4.0), report.types["Changeset"].idle_secs);
+
+        // No flagging without a threshold
+        state.params.options.idle_type_threshold = None;
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(!drain.take()[0].contains("Idle "));
+    }
+
+    #[fbinit::test]
+    fn test_types_of_interest(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.interval = Duration::from_secs(10);
+        state.params.options.types_of_interest = hashset! {NodeType::Changeset};
+        state.params.options.interest_interval = Duration::from_secs(2);
+
+        // Interest lines every 2s, the main report at 10s
+        let mut lines = vec![];
+        for i in 0..10 {
+            state.record_step(&changeset_node(i), Some(&children(0)));
+            state.record_step(&phase_node(i), Some(&children(0)));
+            clock.advance(Duration::from_secs(1));
+            state.report_throttled();
+            lines.extend(drain.take());
+        }
+        let interest: Vec<_> = lines.iter().filter(|l| l.starts_with("Interest")).collect();
+        assert_eq!(4, interest.len(), "{:?}", lines);
+        assert_eq!(
+            "Interest Type:Walked,Errors,Walked/s,Errors/s Changeset:2,0,1.0/s,0.0/s; Delta 2s",
+            interest[0]
+        );
+        assert!(interest[3].contains("Changeset:8,0,1.0/s,0.0/s"));
+        assert!(!interest[0].contains("PhaseMapping"));
+        let main: Vec<_> = lines.iter().filter(|l| l.starts_with("Walked/s")).collect();
+        assert_eq!(1, main.len(), "{:?}", lines);
+        // Covering all 10s, as the interest lines left its baselines alone
+        assert!(
+            main[0].contains("Delta 2.0/s,0.0/s,20,0,0,0,10s;"),
+            "{}",
+            main[0]
+        );
+
+        // Errors in the delta since the last interest line only, which was at 8s as the
+        // main report took the turn at 10s
+        let error = StepStats {
+            error_count: 1,
+            other_error_count: 1,
+            ..Default::default()
+        };
+        state.record_step(&changeset_node(10), Some(&error));
+        clock.advance(Duration::from_secs(2));
+        state.report_throttled();
+        let line = drain.take().remove(0);
+        assert!(
+            line.ends_with("Changeset:11,1,0.8/s,0.2/s; Delta 4s"),
+            "{}",
+            line
+        );
+    }
+
+    #[fbinit::test]
+    fn test_structured_progress(fb: FacebookInit) {
+        let drain = KvDrain::default();
+        let mut state = test_progress_state(fb);
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.record_step(&changeset_node(0), Some(&children(3)));
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.record_step(
+            &phase_node(1),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        state.report_progress_log(Some(Duration::from_secs(2)));
+
+        let records = drain.take();
+        let (msg, kv) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        // Human readable message is unchanged
+        assert!(msg.contains("Delta 1.5/s,1.5/s,3,1,0,3,2s"), "{}", msg);
+        for (key, value) in [
+            ("final", "false"),
+            ("walked", "3"),
+            ("errors", "1"),
+            ("missing", "0"),
+            ("queued", "3"),
+            ("delta_walked", "3"),
+            ("delta_errors", "1"),
+            ("delta_queued", "3"),
+            ("delta_s", "2"),
+            ("delta_walked_per_s", "1.5"),
+            (
+                "per_type",
+                r#"{"Changeset":[1,0,3],"PhaseMapping":[2,1,0]}"#,
+            ),
+        ] {
+            assert_eq!(Some(value), kv.get(key).map(String::as_str), "{}", key);
+        }
+        assert!(kv.contains_key("walked_per_s"));
+        assert!(kv.contains_key("elapsed_s"));
+
+        // The final report says so
+        state.report_progress_log(None);
+        let records = drain.take();
+        let (_, kv) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert_eq!(Some("true"), kv.get("final").map(String::as_str));
+        assert_eq!(Some("0"), kv.get("delta_walked").map(String::as_str));
+    }
+
+    #[fbinit::test]
+    fn test_final_report_time(fb: FacebookInit) {
+        let drain = KvDrain::default();
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let start = clock.now();
+
+        state.record_step(&phase_node(0), Some(&children(0)));
+        clock.advance(Duration::from_secs(10));
+        state.report_throttled();
+        state.start_chunk(1, "(0, 10)".to_string());
+        // Walking on since the last throttled report
+        clock.advance(Duration::from_secs(3));
+        state.record_step(&phase_node(1), Some(&children(0)));
+        clock.advance(Duration::from_secs(4));
+        drain.take();
+        state.report_progress();
+
+        let records = drain.take();
+        let (msg, kv) = records
+            .iter()
+            .find(|(msg, _)| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert_eq!(Some("true"), kv.get("final").map(String::as_str));
+        assert_eq!(Some("17"), kv.get("elapsed_s").map(String::as_str));
+        assert_eq!(Some("7"), kv.get("delta_s").map(String::as_str));
+        assert!(msg.contains("Run 0.1/s,0.0/s,2,0,0,0,17s; "), "{}", msg);
+        assert!(msg.contains("Chunk 1 1,0,0,0,7s; "), "{}", msg);
+        assert_eq!(
+            Duration::from_secs(17),
+            state.reporting_stats.last_update - start
+        );
+        assert_eq!(17.0, state.final_report().elapsed_secs);
+    }
+
+    #[fbinit::test]
+    fn test_scrub_repairs(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        let repairs = |repaired, unrepairable| StepStats {
+            repaired_count: repaired,
+            unrepairable_count: unrepairable,
+            ..Default::default()
+        };
+
+        // Nothing repaired yet, so only the final report shows the columns
+        state.record_step(&phase_node(0), Some(&repairs(0, 0)));
+        assert_eq!(None, repair_detail(&state.summary(), false));
+        assert_eq!(
+            Some("Repaired,Unrepairable 0,0".to_string()),
+            repair_detail(&state.summary(), true)
+        );
+
+        state.record_step(&phase_node(1), Some(&repairs(2, 0)));
+        state.record_step(&phase_node(2), Some(&repairs(1, 1)));
+        state.record_step(&phase_node(3), None);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let summary = state.summary();
+        assert_eq!(3, summary.repaired());
+        assert_eq!(1, summary.unrepairable());
+        assert_eq!(0, summary.errors());
+        assert_eq!(
+            Some("Repaired,Unrepairable 3,1".to_string()),
+            repair_detail(&summary, false)
+        );
+
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Repaired, "repo".to_string(), 3)));
+        assert!(captured.contains(&(ProgressStat::Unrepairable, "repo".to_string(), 1)));
+
+        // Deltas only cover the new repairs
+        state.record_step(&phase_node(4), Some(&repairs(0, 2)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let captured = stats.take();
+        assert!(captured.contains(&(ProgressStat::Repaired, "repo".to_string(), 0)));
+        assert!(captured.contains(&(ProgressStat::Unrepairable, "repo".to_string(), 2)));
+    }
+}
@@ -0,0 +1,1364 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use chrono::DateTime;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use fbinit::FacebookInit;
+use scuba_ext::MononokeScubaSampleBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::warn;
+use slog::Logger;
+use stats::prelude::*;
+use tokio::sync::mpsc;
+
+use crate::detail::graph::NodeType;
+use crate::detail::graph::NodeTypeGroup;
+use crate::detail::jsonl::JsonlSink;
+use crate::detail::progress::reports::summarize_by_derived;
+use crate::detail::progress::reports::summarize_by_group;
+use crate::detail::progress::PhaseCounts;
+use crate::detail::progress::ProgressGauge;
+use crate::detail::progress::ProgressRates;
+use crate::detail::progress::ProgressStat;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::progress::ProgressTypeStat;
+use crate::detail::progress::CHECKED;
+use crate::detail::progress::DELTA_ERRORS;
+use crate::detail::progress::DELTA_QUEUED;
+use crate::detail::progress::DELTA_WALKED;
+use crate::detail::progress::ELAPSED_SECS;
+use crate::detail::progress::ERRORS;
+use crate::detail::progress::FINAL;
+use crate::detail::progress::NODE_TYPE;
+use crate::detail::progress::QUEUED;
+use crate::detail::progress::TIMESTAMP;
+use crate::detail::progress::TOTAL;
+use crate::detail::progress::WALKED;
+use crate::detail::state::BlobstoreReads;
+use crate::detail::state::StepStats;
+
+define_stats! {
+    prefix = "mononoke.walker";
+    walk_progress_walked: dynamic_timeseries("{}.progress.{}.walked", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_queued: dynamic_timeseries("{}.progress.{}.queued", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_errors: dynamic_timeseries("{}.progress.{}.errors", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_missing: dynamic_timeseries("{}.progress.{}.missing", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_hash_validation_failure: dynamic_timeseries("{}.progress.{}.hash_validation_failure", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_errors_corrupt: dynamic_timeseries("{}.progress.{}.errors.corrupt", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_errors_transient: dynamic_timeseries("{}.progress.{}.errors.transient", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_errors_other: dynamic_timeseries("{}.progress.{}.errors.other", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_repaired: dynamic_timeseries("{}.progress.{}.repaired", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_unrepairable: dynamic_timeseries("{}.progress.{}.unrepairable", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_blobs_written: dynamic_timeseries("{}.progress.{}.blobs_written", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_retries: dynamic_timeseries("{}.progress.{}.retries", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_blob_bytes_written: dynamic_timeseries("{}.progress.{}.blob_bytes_written", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_inconsistent: dynamic_timeseries("{}.progress.{}.inconsistent", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_heartbeat: dynamic_timeseries("{}.progress.{}.heartbeat", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_slowdown: dynamic_timeseries("{}.progress.{}.slowdown", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_overhead_us: dynamic_timeseries("{}.progress.{}.overhead_us", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_visit_offered: dynamic_timeseries("{}.progress.{}.visit.offered", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_already_visited: dynamic_timeseries("{}.progress.{}.visit.already_visited", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_walked_per_s: dynamic_singleton_counter("{}.progress.{}.walked_per_s", (subcommand: &'static str, repo: String)),
+    walk_progress_queued_per_s: dynamic_singleton_counter("{}.progress.{}.queued_per_s", (subcommand: &'static str, repo: String)),
+    walk_progress_in_flight: dynamic_singleton_counter("{}.progress.{}.in_flight", (subcommand: &'static str, repo: String)),
+    walk_progress_outstanding: dynamic_singleton_counter("{}.progress.{}.outstanding", (subcommand: &'static str, repo: String)),
+    walk_progress_max_outstanding: dynamic_singleton_counter("{}.progress.{}.max_outstanding", (subcommand: &'static str, repo: String)),
+    walk_progress_in_flight_avg: dynamic_singleton_counter("{}.progress.{}.in_flight_avg", (subcommand: &'static str, repo: String)),
+    walk_progress_in_flight_peak: dynamic_singleton_counter("{}.progress.{}.in_flight_peak", (subcommand: &'static str, repo: String)),
+    walk_progress_walked_by_type: dynamic_timeseries("{}.progress.{}.{}.walked", (subcommand: &'static str, repo: String, node_type: &'static str); Rate, Sum),
+    walk_progress_queued_by_type: dynamic_timeseries("{}.progress.{}.{}.queued", (subcommand: &'static str, repo: String, node_type: &'static str); Rate, Sum),
+    walk_progress_errors_by_type: dynamic_timeseries("{}.progress.{}.{}.errors", (subcommand: &'static str, repo: String, node_type: &'static str); Rate, Sum),
+    walk_progress_walked_by_group: dynamic_timeseries("{}.progress.{}.group.{}.walked", (subcommand: &'static str, repo: String, group: &'static str); Rate, Sum),
+    walk_progress_queued_by_group: dynamic_timeseries("{}.progress.{}.group.{}.queued", (subcommand: &'static str, repo: String, group: &'static str); Rate, Sum),
+    walk_progress_errors_by_group: dynamic_timeseries("{}.progress.{}.group.{}.errors", (subcommand: &'static str, repo: String, group: &'static str); Rate, Sum),
+    walk_progress_walked_by_derived: dynamic_timeseries("{}.progress.{}.derived.{}.walked", (subcommand: &'static str, repo: String, derived: &'static str); Rate, Sum),
+    walk_progress_queued_by_derived: dynamic_timeseries("{}.progress.{}.derived.{}.queued", (subcommand: &'static str, repo: String, derived: &'static str); Rate, Sum),
+    walk_progress_errors_by_derived: dynamic_timeseries("{}.progress.{}.derived.{}.errors", (subcommand: &'static str, repo: String, derived: &'static str); Rate, Sum),
+    walk_progress_walked_by_phase: dynamic_timeseries("{}.progress.{}.phase.{}.walked", (subcommand: &'static str, repo: String, phase: &'static str); Rate, Sum),
+    walk_progress_payload_bytes: dynamic_histogram("{}.progress.{}.payload_bytes", (subcommand: &'static str, repo: String); 10_000_000, 0, 1_000_000_000, Average, Count; P 50; P 99),
+    walk_progress_blobstore_reads: dynamic_timeseries("{}.progress.{}.blobstore.{}.reads", (subcommand: &'static str, repo: String, blobstore: String); Rate, Sum),
+}
+
+/// Destination for the walk progress stats, so tests can capture what is emitted
+pub trait ProgressStatsSink: Send + Sync {
+    fn add_value(&self, stat: ProgressStat, subcommand: &'static str, repo: &str, value: i64);
+
+    fn set_gauge(&self, gauge: ProgressGauge, subcommand: &'static str, repo: &str, value: i64);
+
+    fn add_type_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        node_type: NodeType,
+        value: i64,
+    );
+
+    fn add_group_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        group: NodeTypeGroup,
+        value: i64,
+    );
+
+    /// Keyed by derived data type name, see NodeType::derived_data_name
+    fn add_derived_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        derived: &'static str,
+        value: i64,
+    );
+
+    /// Keyed by blobstore id, or "other" for stores beyond those tracked individually
+    fn add_blobstore_reads(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        blobstore: &str,
+        value: i64,
+    );
+
+    /// Payload size of one step, see StepProgress::payload_bytes
+    fn add_payload_bytes(&self, subcommand: &'static str, repo: &str, bytes: i64);
+
+    /// Changesets walked, keyed by phase name, see PhaseCounts
+    fn add_phase_value(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        phase: &'static str,
+        value: i64,
+    );
+
+    /// Called at the end of each report, for sinks that batch what they are given
+    fn flush(&self) {}
+}
+
+pub struct DefaultProgressStatsSink {
+    fb: FacebookInit,
+}
+
+impl DefaultProgressStatsSink {
+    pub fn new(fb: FacebookInit) -> Self {
+        Self { fb }
+    }
+}
+
+impl ProgressStatsSink for DefaultProgressStatsSink {
+    fn add_value(&self, stat: ProgressStat, subcommand: &'static str, repo: &str, value: i64) {
+        let key = (subcommand, repo.to_string());
+        match stat {
+            ProgressStat::Walked => STATS::walk_progress_walked.add_value(value, key),
+            ProgressStat::Queued => STATS::walk_progress_queued.add_value(value, key),
+            ProgressStat::Errors => STATS::walk_progress_errors.add_value(value, key),
+            ProgressStat::Missing => STATS::walk_progress_missing.add_value(value, key),
+            ProgressStat::HashValidationFailure => {
+                STATS::walk_progress_hash_validation_failure.add_value(value, key)
+            }
+            ProgressStat::CorruptErrors => {
+                STATS::walk_progress_errors_corrupt.add_value(value, key)
+            }
+            ProgressStat::TransientErrors => {
+                STATS::walk_progress_errors_transient.add_value(value, key)
+            }
+            ProgressStat::OtherErrors => STATS::walk_progress_errors_other.add_value(value, key),
+            ProgressStat::Repaired => STATS::walk_progress_repaired.add_value(value, key),
+            ProgressStat::Unrepairable => STATS::walk_progress_unrepairable.add_value(value, key),
+            ProgressStat::BlobsWritten => STATS::walk_progress_blobs_written.add_value(value, key),
+            ProgressStat::BytesWritten => {
+                STATS::walk_progress_blob_bytes_written.add_value(value, key)
+            }
+            ProgressStat::Retries => STATS::walk_progress_retries.add_value(value, key),
+            ProgressStat::Inconsistent => STATS::walk_progress_inconsistent.add_value(value, key),
+            ProgressStat::Heartbeat => STATS::walk_progress_heartbeat.add_value(value, key),
+            ProgressStat::Slowdown => STATS::walk_progress_slowdown.add_value(value, key),
+            ProgressStat::OverheadMicros => STATS::walk_progress_overhead_us.add_value(value, key),
+            ProgressStat::VisitOffered => STATS::walk_progress_visit_offered.add_value(value, key),
+            ProgressStat::AlreadyVisited => {
+                STATS::walk_progress_already_visited.add_value(value, key)
+            }
+        }
+    }
+
+    fn set_gauge(&self, gauge: ProgressGauge, subcommand: &'static str, repo: &str, value: i64) {
+        let key = (subcommand, repo.to_string());
+        match gauge {
+            ProgressGauge::WalkedPerSecond => {
+                STATS::walk_progress_walked_per_s.set_value(self.fb, value, key)
+            }
+            ProgressGauge::QueuedPerSecond => {
+                STATS::walk_progress_queued_per_s.set_value(self.fb, value, key)
+            }
+            ProgressGauge::InFlight => {
+                STATS::walk_progress_in_flight.set_value(self.fb, value, key)
+            }
+            ProgressGauge::Outstanding => {
+                STATS::walk_progress_outstanding.set_value(self.fb, value, key)
+            }
+            ProgressGauge::MaxOutstanding => {
+                STATS::walk_progress_max_outstanding.set_value(self.fb, value, key)
+            }
+            ProgressGauge::InFlightAvg => {
+                STATS::walk_progress_in_flight_avg.set_value(self.fb, value, key)
+            }
+            ProgressGauge::InFlightPeak => {
+                STATS::walk_progress_in_flight_peak.set_value(self.fb, value, key)
+            }
+        }
+    }
+
+    fn add_type_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        node_type: NodeType,
+        value: i64,
+    ) {
+        let node_type: &'static str = node_type.into();
+        let key = (subcommand, repo.to_string(), node_type);
+        match stat {
+            ProgressTypeStat::Walked => STATS::walk_progress_walked_by_type.add_value(value, key),
+            ProgressTypeStat::Queued => STATS::walk_progress_queued_by_type.add_value(value, key),
+            ProgressTypeStat::Errors => STATS::walk_progress_errors_by_type.add_value(value, key),
+        }
+    }
+
+    fn add_group_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        group: NodeTypeGroup,
+        value: i64,
+    ) {
+        let group: &'static str = group.into();
+        let key = (subcommand, repo.to_string(), group);
+        match stat {
+            ProgressTypeStat::Walked => STATS::walk_progress_walked_by_group.add_value(value, key),
+            ProgressTypeStat::Queued => STATS::walk_progress_queued_by_group.add_value(value, key),
+            ProgressTypeStat::Errors => STATS::walk_progress_errors_by_group.add_value(value, key),
+        }
+    }
+
+    fn add_derived_value(
+        &self,
+        stat: ProgressTypeStat,
+        subcommand: &'static str,
+        repo: &str,
+        derived: &'static str,
+        value: i64,
+    ) {
+        let key = (subcommand, repo.to_string(), derived);
+        match stat {
+            ProgressTypeStat::Walked => {
+                STATS::walk_progress_walked_by_derived.add_value(value, key)
+            }
+            ProgressTypeStat::Queued => {
+                STATS::walk_progress_queued_by_derived.add_value(value, key)
+            }
+            ProgressTypeStat::Errors => {
+                STATS::walk_progress_errors_by_derived.add_value(value, key)
+            }
+        }
+    }
+
+    fn add_blobstore_reads(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        blobstore: &str,
+        value: i64,
+    ) {
+        STATS::walk_progress_blobstore_reads
+            .add_value(value, (subcommand, repo.to_string(), blobstore.to_string()));
+    }
+
+    fn add_payload_bytes(&self, subcommand: &'static str, repo: &str, bytes: i64) {
+        STATS::walk_progress_payload_bytes.add_value(bytes, (subcommand, repo.to_string()));
+    }
+
+    fn add_phase_value(
+        &self,
+        subcommand: &'static str,
+        repo: &str,
+        phase: &'static str,
+        value: i64,
+    ) {
+        STATS::walk_progress_walked_by_phase
+            .add_value(value, (subcommand, repo.to_string(), phase));
+    }
+}
+
+/// UTC ISO-8601 to the millisecond, e.g. 2024-01-02T03:04:05.678Z
+pub fn iso_timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const MAX_BAR_WIDTH: usize = 40;
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Renders walk progress as a single line updated in place. Only writes ASCII so
+/// it works on any terminal, and re-reads the width on each render to handle resizes.
+pub struct ProgressBar {
+    out: Box<dyn Write + Send>,
+    // Fixed width, otherwise from the COLUMNS environment variable
+    width: Option<usize>,
+    last_len: usize,
+}
+
+impl ProgressBar {
+    pub fn new(out: Box<dyn Write + Send>, width: Option<usize>) -> Self {
+        Self {
+            out,
+            width,
+            last_len: 0,
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(std::io::stderr()), None)
+    }
+
+    fn width(&self) -> usize {
+        self.width
+            .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+            .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+    }
+
+    pub fn render(&mut self, done: u64, known: u64, rate: f64, elapsed: Duration) {
+        let width = self.width();
+        let counts = format!("{}/{} {:.1}/s {}s", done, known, rate, elapsed.as_secs());
+        let bar_width = width.saturating_sub(counts.len() + 4).min(MAX_BAR_WIDTH);
+        let mut line = if bar_width >= MIN_BAR_WIDTH {
+            let filled = if known > 0 {
+                (bar_width as u128 * done.min(known) as u128 / known as u128) as usize
+            } else {
+                0
+            };
+            format!(
+                "[{}{}] {}",
+                "#".repeat(filled),
+                "-".repeat(bar_width - filled),
+                counts
+            )
+        } else {
+            counts
+        };
+        // Leave the last column free so the terminal does not wrap
+        line.truncate(width.saturating_sub(1));
+        let pad = self
+            .last_len
+            .min(width.saturating_sub(1))
+            .saturating_sub(line.len());
+        // Progress display is best effort, so ignore write failures
+        let _ = write!(self.out, "\r{}{}", line, " ".repeat(pad));
+        let _ = self.out.flush();
+        self.last_len = line.len();
+    }
+
+    /// Move off the progress line so normal output can follow
+    pub fn finish(&mut self) {
+        if self.last_len > 0 {
+            let _ = writeln!(self.out);
+            let _ = self.out.flush();
+            self.last_len = 0;
+        }
+    }
+}
+
+/// A progress report as saved by the JSON lines sink and sent to the report channel,
+/// with the same names as the key values of the progress log line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReportLine {
+    /// Wall clock time of the report, as seconds since the epoch
+    pub time: u64,
+    /// The same time as UTC ISO-8601
+    #[serde(default)]
+    pub timestamp: String,
+    pub repo: String,
+    pub subcommand: String,
+    pub run_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub walk_instance: String,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+    pub walked: u64,
+    pub errors: u64,
+    pub missing: u64,
+    pub queued: u64,
+    pub elapsed_s: u64,
+    pub walked_per_s: f64,
+    pub delta_walked: u64,
+    pub delta_errors: u64,
+    pub delta_s: u64,
+    pub delta_walked_per_s: f64,
+    /// Walked, errors and children by type
+    pub per_type: BTreeMap<String, [u64; 3]>,
+}
+
+/// Where reports go for with_report_channel
+pub struct ReportChannel {
+    sender: mpsc::Sender<ReportLine>,
+    // Reports not sent as the channel was full
+    dropped: u64,
+    // Set once the receiver is gone, after which nothing more is sent
+    closed: bool,
+}
+
+impl ReportChannel {
+    pub(super) fn new(sender: mpsc::Sender<ReportLine>) -> Self {
+        Self {
+            sender,
+            dropped: 0,
+            closed: false,
+        }
+    }
+
+    pub(super) fn send(&mut self, logger: &Logger, line: ReportLine) {
+        if self.closed {
+            return;
+        }
+        match self.sender.try_send(line) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => self.dropped += 1,
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                warn!(
+                    logger,
+                    "Progress report receiver dropped, no longer sending reports"
+                );
+                self.closed = true;
+            }
+        }
+    }
+
+    /// Number of reports dropped as the receiver had not kept up
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Scuba rows waiting to be logged, so each report's rows go out together rather than
+/// one at a time as they are made
+#[derive(Default)]
+pub struct ScubaRows {
+    rows: Vec<MononokeScubaSampleBuilder>,
+    // Rows the builder failed to log over the run, only warned about the first time
+    failed: u64,
+}
+
+impl ScubaRows {
+    pub(super) fn push(&mut self, row: MononokeScubaSampleBuilder) {
+        self.rows.push(row);
+    }
+
+    pub(super) fn log_all(&mut self, logger: &Logger) {
+        let mut failed = 0;
+        for mut row in self.rows.drain(..) {
+            // A discarding builder never logs, which is not a failure
+            if !row.log() && !row.is_discard() {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            if self.failed == 0 {
+                warn!(
+                    logger,
+                    "Failed to log {} progress Scuba rows, further failures are only counted",
+                    failed
+                );
+            }
+            self.failed += failed;
+        }
+    }
+
+    /// Number of rows the builder failed to log
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+}
+
+// Where reports go besides the log and the stats sink
+#[derive(Default)]
+pub(super) struct ReportSinks {
+    // Only set once the first in place report is rendered
+    pub(super) bar: Option<ProgressBar>,
+    // Opened on the first report, if options.jsonl is set
+    pub(super) jsonl: Option<JsonlSink>,
+    pub(super) report_channel: Option<ReportChannel>,
+    // Scuba rows made by the current report, logged together at its end
+    pub(super) scuba_rows: ScubaRows,
+}
+
+// What the stats sink was last sent, so it is sent deltas
+#[derive(Default)]
+pub(super) struct EmittedStats<T> {
+    // Per type summary as of the last time each type's stats were emitted. Kept apart
+    // from last_summary_by_type so deltas still add up when a type is skipped.
+    pub(super) last_emitted_by_type: HashMap<NodeType, T>,
+    // Reports that included per type stats, used to pick every Nth
+    pub(super) type_reports: u64,
+    pub(super) last_blobstore_reads: BlobstoreReads,
+    pub(super) last_changeset_phases: PhaseCounts,
+}
+
+impl ProgressStateCountByType<StepStats, ProgressSummary> {
+    pub(super) fn report_stats(&self, repo_stats_key: &str, delta_summary: &ProgressSummary) {
+        for (stat, value) in [
+            (ProgressStat::Walked, delta_summary.walked),
+            (ProgressStat::Queued, delta_summary.queued),
+            (ProgressStat::Errors, delta_summary.errors),
+            (ProgressStat::Missing, delta_summary.missing),
+            (
+                ProgressStat::HashValidationFailure,
+                delta_summary.hash_validation_failure,
+            ),
+            (ProgressStat::CorruptErrors, delta_summary.corrupt),
+            (ProgressStat::TransientErrors, delta_summary.transient),
+            (ProgressStat::OtherErrors, delta_summary.other_errors),
+            (ProgressStat::Repaired, delta_summary.repaired),
+            (ProgressStat::Unrepairable, delta_summary.unrepairable),
+            (ProgressStat::BlobsWritten, delta_summary.blobs_written),
+            (ProgressStat::BytesWritten, delta_summary.bytes_written),
+            (ProgressStat::Retries, delta_summary.retries),
+        ] {
+            self.params.stats_sink.add_value(
+                stat,
+                self.params.subcommand_stats_key,
+                repo_stats_key,
+                value as i64,
+            );
+        }
+    }
+
+    pub(super) fn report_gauges(&self, repo_stats_key: &str, rates: &ProgressRates) {
+        for (gauge, value) in [
+            (ProgressGauge::WalkedPerSecond, rates.walked),
+            (ProgressGauge::QueuedPerSecond, rates.queued),
+        ] {
+            self.params.stats_sink.set_gauge(
+                gauge,
+                self.params.subcommand_stats_key,
+                repo_stats_key,
+                value.round() as i64,
+            );
+        }
+    }
+
+    // Emits the totals on every report, but each type only if it changed since it was
+    // last emitted, or on every Nth or the final report.
+    pub(super) fn report_by_type(
+        &mut self,
+        summary_by_type: &HashMap<NodeType, ProgressSummary>,
+        new_summary: &ProgressSummary,
+        delta_summary: &ProgressSummary,
+        is_final: bool,
+    ) {
+        self.report_by_group(summary_by_type);
+        self.report_by_derived(summary_by_type);
+        let row = self.progress_row(TOTAL, new_summary, delta_summary);
+        self.reporting_stats.sinks.scuba_rows.push(row);
+        if !self.params.options.type_grouping.by_type() {
+            return;
+        }
+        self.reporting_stats.emitted.type_reports += 1;
+        let emit_unchanged = is_final
+            || self.reporting_stats.emitted.type_reports
+                % self.params.options.type_emit_every_nth.max(1)
+                == 0;
+        for t in &self.params.types_sorted_by_name {
+            let summary = summary_by_type.get(t).cloned().unwrap_or_default();
+            let last_emitted = self
+                .reporting_stats
+                .emitted
+                .last_emitted_by_type
+                .get(t)
+                .cloned()
+                .unwrap_or_default();
+            let delta = summary - last_emitted;
+            if delta == ProgressSummary::default() && !emit_unchanged {
+                continue;
+            }
+            for (stat, value) in [
+                (ProgressTypeStat::Walked, delta.walked),
+                (ProgressTypeStat::Queued, delta.queued),
+                (ProgressTypeStat::Errors, delta.errors),
+            ] {
+                self.params.stats_sink.add_type_value(
+                    stat,
+                    self.params.subcommand_stats_key,
+                    &self.params.stats_key,
+                    *t,
+                    value as i64,
+                );
+            }
+            let row = self.progress_row(t.into(), &summary, &delta);
+            self.reporting_stats.sinks.scuba_rows.push(row);
+            self.reporting_stats
+                .emitted
+                .last_emitted_by_type
+                .insert(*t, summary);
+        }
+    }
+
+    // Group deltas since the last report, on every report as there are only a few groups
+    fn report_by_group(&self, summary_by_type: &HashMap<NodeType, ProgressSummary>) {
+        let summary_by_group = summarize_by_group(summary_by_type);
+        let last_summary_by_group = summarize_by_group(&self.reporting_stats.last_summary_by_type);
+        for g in self.params.groups() {
+            let summary = summary_by_group.get(&g).cloned().unwrap_or_default();
+            let last = last_summary_by_group.get(&g).cloned().unwrap_or_default();
+            let delta = summary - last;
+            for (stat, value) in [
+                (ProgressTypeStat::Walked, delta.walked),
+                (ProgressTypeStat::Queued, delta.queued),
+                (ProgressTypeStat::Errors, delta.errors),
+            ] {
+                self.params.stats_sink.add_group_value(
+                    stat,
+                    self.params.subcommand_stats_key,
+                    &self.params.stats_key,
+                    g,
+                    value as i64,
+                );
+            }
+        }
+    }
+
+    fn report_by_derived(&self, summary_by_type: &HashMap<NodeType, ProgressSummary>) {
+        let last_summary_by_derived =
+            summarize_by_derived(&self.reporting_stats.last_summary_by_type);
+        for (derived, summary) in summarize_by_derived(summary_by_type) {
+            let last = last_summary_by_derived
+                .get(derived)
+                .cloned()
+                .unwrap_or_default();
+            let delta = summary - last;
+            for (stat, value) in [
+                (ProgressTypeStat::Walked, delta.walked),
+                (ProgressTypeStat::Queued, delta.queued),
+                (ProgressTypeStat::Errors, delta.errors),
+            ] {
+                self.params.stats_sink.add_derived_value(
+                    stat,
+                    self.params.subcommand_stats_key,
+                    &self.params.stats_key,
+                    derived,
+                    value as i64,
+                );
+            }
+        }
+    }
+
+    fn progress_row(
+        &self,
+        node_type: &str,
+        summary: &ProgressSummary,
+        delta: &ProgressSummary,
+    ) -> MononokeScubaSampleBuilder {
+        let mut row = self.params.scuba_builder.clone();
+        row.add(TIMESTAMP, iso_timestamp(self.params.clock.wall_now()))
+            .add(NODE_TYPE, node_type)
+            .add(WALKED, summary.walked)
+            .add(QUEUED, summary.queued)
+            .add(ERRORS, summary.errors)
+            .add(DELTA_WALKED, delta.walked)
+            .add(DELTA_QUEUED, delta.queued)
+            .add(DELTA_ERRORS, delta.errors);
+        row
+    }
+
+    // One row per type with its final counts, and one for the total, so a run's results
+    // can be queried directly rather than summed from the delta rows
+    pub(super) fn log_final_rows(&mut self) {
+        let elapsed = self
+            .params
+            .clock
+            .now()
+            .saturating_duration_since(self.reporting_stats.start_time)
+            .as_secs_f64();
+        let timestamp = iso_timestamp(self.params.clock.wall_now());
+        let final_row = |node_type: &str, summary: &ProgressSummary| {
+            let mut row = self.params.scuba_builder.clone();
+            row.add(FINAL, true)
+                .add(TIMESTAMP, timestamp.clone())
+                .add(NODE_TYPE, node_type)
+                .add(WALKED, summary.walked)
+                .add(CHECKED, summary.checked)
+                .add(QUEUED, summary.queued)
+                .add(ERRORS, summary.errors)
+                .add(ELAPSED_SECS, elapsed);
+            row
+        };
+        let summary_by_type = self.summary_by_type();
+        let total = summary_by_type
+            .values()
+            .fold(ProgressSummary::default(), |acc, v| acc + *v);
+        let mut rows: Vec<_> = self
+            .params
+            .types_sorted_by_name
+            .iter()
+            .filter_map(|t| {
+                summary_by_type
+                    .get(t)
+                    .map(|summary| final_row(t.into(), summary))
+            })
+            .collect();
+        rows.push(final_row(TOTAL, &total));
+        for row in rows {
+            self.reporting_stats.sinks.scuba_rows.push(row);
+        }
+    }
+
+    // Per store read deltas, only when there is more than one store to tell apart
+    pub(super) fn report_blobstore_reads(&mut self) {
+        let reads = self.work_stats.blobstore_reads;
+        if reads.len() <= 1 {
+            return;
+        }
+        let last = &self.reporting_stats.emitted.last_blobstore_reads;
+        for (id, count) in reads.iter() {
+            self.params.stats_sink.add_blobstore_reads(
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                &id.to_string(),
+                (count - last.get(id)) as i64,
+            );
+        }
+        if reads.other > 0 {
+            self.params.stats_sink.add_blobstore_reads(
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                "other",
+                (reads.other - last.other) as i64,
+            );
+        }
+        self.reporting_stats.emitted.last_blobstore_reads = reads;
+    }
+
+    // Deltas only once some changesets were walked, as most walks of other types have none
+    pub(super) fn report_changeset_phases(&mut self) {
+        let phases = self.work_stats.changeset_phases;
+        if phases == PhaseCounts::default() {
+            return;
+        }
+        let last = self.reporting_stats.emitted.last_changeset_phases.by_name();
+        for ((phase, count), (_, last)) in phases.by_name().into_iter().zip(last) {
+            self.params.stats_sink.add_phase_value(
+                self.params.subcommand_stats_key,
+                &self.params.stats_key,
+                phase,
+                (count - last) as i64,
+            );
+        }
+        self.reporting_stats.emitted.last_changeset_phases = phases;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+    use mononoke_types::ContentId;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::jsonl::FsyncPolicy;
+    use crate::detail::jsonl::JsonlOptions;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[test]
+    fn test_progress_bar_render() {
+        let terminal = FakeTerminal::default();
+        let mut bar = ProgressBar::new(Box::new(terminal.clone()), Some(60));
+
+        bar.render(50, 200, 2.5, Duration::from_secs(20));
+        assert_eq!(
+            "\r[##########------------------------------] 50/200 2.5/s 20s",
+            terminal.output()
+        );
+
+        // A shorter line clears the rest of the previous one
+        bar.render(2, 2, 1.0, Duration::from_secs(2));
+        let output = terminal.output();
+        let last = output.rsplit('\r').next().unwrap();
+        assert_eq!(
+            "[########################################] 2/2 1.0/s 2s    ",
+            last
+        );
+
+        bar.finish();
+        assert!(terminal.output().ends_with('\n'));
+        // Only finishes once
+        bar.finish();
+        assert!(!terminal.output().ends_with("\n\n"));
+    }
+
+    #[test]
+    fn test_progress_bar_narrow_terminal() {
+        let terminal = FakeTerminal::default();
+        let mut bar = ProgressBar::new(Box::new(terminal.clone()), Some(20));
+        // Too narrow for a bar, so just the counts, truncated to fit
+        bar.render(123456, 1234567, 1.0, Duration::from_secs(1000));
+        assert_eq!("\r123456/1234567 1.0/", terminal.output());
+
+        // Nothing known yet
+        let terminal = FakeTerminal::default();
+        let mut bar = ProgressBar::new(Box::new(terminal.clone()), Some(40));
+        bar.render(0, 0, 0.0, Duration::ZERO);
+        assert_eq!(
+            "\r[------------------------] 0/0 0.0/s 0s",
+            terminal.output()
+        );
+    }
+
+    #[fbinit::test]
+    fn test_progress_bar_display(fb: FacebookInit) {
+        let terminal = FakeTerminal::default();
+        let mut state = test_progress_state(fb);
+        state.params.options.display = ProgressDisplay::Bar;
+        state.reporting_stats.sinks.bar =
+            Some(ProgressBar::new(Box::new(terminal.clone()), Some(60)));
+
+        state.record_step(&phase_node(0), Some(&children(3)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(terminal.output().starts_with("\r["));
+        assert!(terminal.output().contains(" 1/3 "));
+
+        // Final report goes back to a normal line
+        state.report_progress();
+        assert!(terminal.output().ends_with('\n'));
+    }
+
+    #[fbinit::test]
+    fn test_rate_gauges(fb: FacebookInit) {
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+
+        for i in 0..10 {
+            state.record_step(&phase_node(i), Some(&children(3)));
+        }
+        state.report_progress_log(Some(Duration::from_secs(2)));
+        assert_eq!(Some(5), stats.gauge(ProgressGauge::WalkedPerSecond, "repo"));
+        assert_eq!(
+            Some(15),
+            stats.gauge(ProgressGauge::QueuedPerSecond, "repo")
+        );
+        assert_eq!(Some(20), stats.gauge(ProgressGauge::InFlight, "repo"));
+
+        // Gauges are instantaneous, so the next report replaces them
+        for i in 10..13 {
+            state.record_step(&phase_node(i), None);
+        }
+        state.report_progress_log(Some(Duration::from_secs(4)));
+        assert_eq!(Some(1), stats.gauge(ProgressGauge::WalkedPerSecond, "repo"));
+        assert_eq!(Some(0), stats.gauge(ProgressGauge::QueuedPerSecond, "repo"));
+        assert_eq!(Some(17), stats.gauge(ProgressGauge::InFlight, "repo"));
+    }
+
+    #[fbinit::test]
+    fn test_type_emission_downsampled(fb: FacebookInit) -> Result<(), Error> {
+        let log_file =
+            std::env::temp_dir().join(format!("walker_progress_scuba_{}", std::process::id()));
+        let _ = std::fs::remove_file(&log_file);
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.options.type_emit_every_nth = 3;
+        state.set_sample_builder(
+            MononokeScubaSampleBuilder::with_discard().with_log_file(&log_file)?,
+        );
+        let walked_by_type = |values: Vec<(ProgressTypeStat, NodeType, i64)>| {
+            values
+                .into_iter()
+                .filter(|(stat, _, _)| *stat == ProgressTypeStat::Walked)
+                .map(|(_, t, v)| (t, v))
+                .collect::<Vec<_>>()
+        };
+
+        // Only the changed type
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.record_step(&phase_node(1), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![(NodeType::PhaseMapping, 2)],
+            walked_by_type(stats.take_type_values())
+        );
+
+        state.record_step(&changeset_node(0), Some(&children(0)));
+        state.record_step(&phase_node(2), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![(NodeType::Changeset, 1), (NodeType::PhaseMapping, 1)],
+            walked_by_type(stats.take_type_values())
+        );
+
+        // Every 3rd report includes unchanged types
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![(NodeType::Changeset, 0), (NodeType::PhaseMapping, 0)],
+            walked_by_type(stats.take_type_values())
+        );
+
+        // Unchanged and not the 3rd, nothing per type
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(stats.take_type_values().is_empty());
+
+        // Deltas are from when the type was last emitted, and the final report has everything
+        state.record_step(&phase_node(3), Some(&children(0)));
+        state.report_progress_log(None);
+        assert_eq!(
+            vec![(NodeType::Changeset, 0), (NodeType::PhaseMapping, 1)],
+            walked_by_type(stats.take_type_values())
+        );
+
+        let mut rows_by_type: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for line in std::fs::read_to_string(&log_file)?.lines() {
+            let row: serde_json::Value = serde_json::from_str(line)?;
+            rows_by_type
+                .entry(row[NODE_TYPE].as_str().unwrap().to_string())
+                .or_default()
+                .push((
+                    row[WALKED].as_u64().unwrap(),
+                    row[DELTA_WALKED].as_u64().unwrap(),
+                ));
+        }
+        std::fs::remove_file(&log_file)?;
+        assert_eq!(
+            hashmap! {
+                TOTAL.to_string() => vec![(2, 2), (4, 2), (4, 0), (4, 0), (5, 1)],
+                "Changeset".to_string() => vec![(1, 1), (1, 0), (1, 0)],
+                "PhaseMapping".to_string() => vec![(2, 2), (3, 1), (3, 0), (4, 1)],
+            },
+            rows_by_type
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_final_rows(fb: FacebookInit) -> Result<(), Error> {
+        let log_file =
+            std::env::temp_dir().join(format!("walker_final_rows_{}", std::process::id()));
+        let _ = std::fs::remove_file(&log_file);
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.set_sample_builder(
+            MononokeScubaSampleBuilder::with_discard().with_log_file(&log_file)?,
+        );
+        let other_run_id = test_progress_state(fb).params.run_id;
+        assert_ne!(other_run_id, state.params.run_id);
+
+        state.record_step(&phase_node(0), Some(&children(2)));
+        state.record_step(&phase_node(1), Some(&children(0)));
+        state.record_step(
+            &changeset_node(0),
+            Some(&StepStats {
+                error_count: 1,
+                ..Default::default()
+            }),
+        );
+        clock.advance(Duration::from_secs(4));
+        state.report_progress_log(Some(Duration::from_secs(4)));
+        state.report_progress();
+
+        let rows = std::fs::read_to_string(&log_file)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        std::fs::remove_file(&log_file)?;
+        // Throttled and final progress rows, then the final counts
+        assert_eq!(9, rows.len());
+        assert!(rows
+            .iter()
+            .all(|row| row[RUN_ID].as_str() == Some(&state.params.run_id)));
+        let final_rows = rows
+            .iter()
+            .filter(|row| row[FINAL].as_bool() == Some(true))
+            .map(|row| {
+                (
+                    row[NODE_TYPE].as_str().unwrap(),
+                    [WALKED, CHECKED, QUEUED, ERRORS].map(|c| row[c].as_u64().unwrap()),
+                    row[ELAPSED_SECS].as_f64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("Changeset", [1, 0, 0, 1], 4.0),
+                ("PhaseMapping", [2, 0, 2, 0], 4.0),
+                (TOTAL, [3, 0, 2, 1], 4.0),
+            ],
+            final_rows
+        );
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_group_rollup(fb: FacebookInit) {
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.types_sorted_by_name = vec![
+            NodeType::Changeset,
+            NodeType::FileContent,
+            NodeType::PhaseMapping,
+        ];
+        state.params.options.type_rates = false;
+        state.params.options.type_grouping = TypeGrouping::Groups;
+        let content_node = |i| Node::FileContent(ContentId::from_byte_array([i; 32]));
+
+        state.record_step(&changeset_node(0), Some(&children(3)));
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.record_step(&phase_node(1), Some(&children(0)));
+        state.record_step(&content_node(0), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        // Group sums are the per type sums
+        let snapshot = state.summary_by_type();
+        let by_group = summarize_by_group(&snapshot);
+        for g in state.params.groups() {
+            let walked: u64 = snapshot
+                .iter()
+                .filter(|(t, _)| t.group() == g)
+                .map(|(_, s)| s.walked)
+                .sum();
+            assert_eq!(walked, by_group[&g].walked);
+        }
+        assert_eq!(
+            vec![(NodeTypeGroup::Bonsai, 3), (NodeTypeGroup::Content, 1)],
+            stats
+                .take_group_values()
+                .into_iter()
+                .filter(|(stat, _, _)| *stat == ProgressTypeStat::Walked)
+                .map(|(_, g, v)| (g, v))
+                .collect::<Vec<_>>()
+        );
+        // Groups instead of types
+        assert!(stats.take_type_values().is_empty());
+        let logged = drain.take();
+        let report = logged
+            .iter()
+            .find(|msg| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert!(
+            report.ends_with(
+                "Group:Walked,Checks,Children,Walked% Bonsai:3,0,3,75.0% Content:1,0,0,25.0%"
+            ),
+            "{}",
+            report
+        );
+
+        // Group stats are deltas, and both can be shown
+        state.params.options.type_grouping = TypeGrouping::Both;
+        state.record_step(&content_node(1), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(stats.take_group_values().contains(&(
+            ProgressTypeStat::Walked,
+            NodeTypeGroup::Content,
+            1
+        )));
+        assert!(!stats.take_type_values().is_empty());
+        let logged = drain.take();
+        let report = logged
+            .iter()
+            .find(|msg| msg.starts_with("Walked/s"))
+            .unwrap();
+        assert!(
+            report.ends_with(
+                "Type:Walked,Checks,Children,Walked% Changeset:1,0,3,20.0% FileContent:2,0,0,40.0% PhaseMapping:2,0,0,40.0%; Group:Walked,Checks,Children,Walked% Bonsai:3,0,3,60.0% Content:2,0,0,40.0%"
+            ),
+            "{}",
+            report
+        );
+    }
+
+    #[fbinit::test]
+    fn test_blobstore_reads(fb: FacebookInit) {
+        let reads = |per_store: &[(u64, u64)]| {
+            let mut stats = children(1);
+            for (id, n) in per_store {
+                stats.blobstore_reads.record(*id, *n);
+            }
+            stats
+        };
+        let drain = CapturingDrain::default();
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+
+        // A single store adds nothing to the output
+        state.record_step(&phase_node(0), Some(&reads(&[(1, 2)])));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(!drain.take().iter().any(|l| l.contains("Blobstore reads")));
+        assert!(stats.take_blobstore_reads().is_empty());
+
+        state.record_step(&phase_node(1), Some(&reads(&[(2, 1), (1, 1)])));
+        state.record_steps(&[
+            (phase_node(2), Some(reads(&[(2, 3)]))),
+            (changeset_node(0), None),
+            (changeset_node(1), Some(reads(&[(3, 5), (1, 1)]))),
+        ]);
+        assert_eq!(
+            vec![(1, 4), (2, 4), (3, 5)],
+            state
+                .work_stats
+                .blobstore_reads()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        let logged = drain.take();
+        assert!(
+            logged[0].contains("Blobstore reads 1:4,2:4,3:5; "),
+            "{:?}",
+            logged
+        );
+        // All reads so far, as the single store ones were not emitted
+        assert_eq!(
+            vec![
+                ("1".to_string(), 4),
+                ("2".to_string(), 4),
+                ("3".to_string(), 5)
+            ],
+            stats.take_blobstore_reads()
+        );
+
+        // Then only what changed since
+        state.record_step(&phase_node(3), Some(&reads(&[(3, 2)])));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![
+                ("1".to_string(), 0),
+                ("2".to_string(), 0),
+                ("3".to_string(), 2)
+            ],
+            stats.take_blobstore_reads()
+        );
+
+        // Beyond the tracked ids reads are lumped together
+        let mut many = BlobstoreReads::default();
+        for id in 0..10 {
+            many.record(id, id + 1);
+        }
+        assert_eq!(8, many.len());
+        assert_eq!(9 + 10, many.other);
+        assert_eq!(3, many.get(2));
+        assert_eq!(0, many.get(9));
+        let sum = many + many;
+        assert_eq!(6, sum.get(2));
+        assert_eq!(2 * (9 + 10), sum.other);
+    }
+
+    #[fbinit::test]
+    fn test_changeset_phases(fb: FacebookInit) -> Result<(), Error> {
+        let phase = |phase| StepStats {
+            phase,
+            ..Default::default()
+        };
+        let stats = Arc::new(CapturingStatsSink::default());
+        let mut state = test_progress_state(fb).with_stats_sink(stats.clone());
+
+        // Nothing to emit before any changesets
+        state.record_step(&phase_node(0), Some(&phase(Some(StepPhase::Public))));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(stats.take_phase_values().is_empty());
+
+        state.record_step(&changeset_node(0), Some(&phase(Some(StepPhase::Public))));
+        state.record_step(&changeset_node(1), Some(&phase(Some(StepPhase::Draft))));
+        state.record_steps(&[
+            (changeset_node(2), Some(phase(Some(StepPhase::Public)))),
+            (changeset_node(3), Some(phase(None))),
+            (changeset_node(4), None),
+            (phase_node(1), Some(phase(Some(StepPhase::Draft)))),
+        ]);
+        let expected = PhaseCounts {
+            public: 2,
+            draft: 1,
+            unknown: 2,
+        };
+        assert_eq!(expected, state.work_stats.changeset_phases);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![("public", 2), ("draft", 1), ("unknown", 2)],
+            stats.take_phase_values()
+        );
+
+        state.record_step(&changeset_node(5), Some(&phase(Some(StepPhase::Draft))));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(
+            vec![("public", 0), ("draft", 1), ("unknown", 0)],
+            stats.take_phase_values()
+        );
+
+        let report = state.final_report();
+        assert_eq!(
+            PhaseCounts {
+                draft: 2,
+                ..expected
+            },
+            report.changeset_phases
+        );
+        // And survives saving
+        let saved: FinalReport = serde_json::from_str(&serde_json::to_string(&report)?)?;
+        assert_eq!(report.changeset_phases, saved.changeset_phases);
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_jsonl_reports(fb: FacebookInit) -> Result<(), Error> {
+        let path =
+            std::env::temp_dir().join(format!("walker_progress_{}.jsonl", std::process::id()));
+        let rotated = |n| std::path::PathBuf::from(format!("{}.{}", path.display(), n));
+        for p in [path.clone(), rotated(1), rotated(2)] {
+            let _ = std::fs::remove_file(p);
+        }
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.options.jsonl = Some(JsonlOptions {
+            path: path.clone(),
+            max_bytes: 1000,
+            keep: 1,
+            fsync: FsyncPolicy::Never,
+        });
+
+        for i in 0..20 {
+            state.record_step(&phase_node(i), Some(&children(1)));
+            clock.advance(Duration::from_secs(1));
+            state.report_progress_log(Some(Duration::from_secs(1)));
+        }
+        state.report_progress();
+
+        assert!(!rotated(2).exists());
+        let mut lines = vec![];
+        for p in [rotated(1), path.clone()] {
+            for line in std::fs::read_to_string(&p)?.lines() {
+                lines.push(serde_json::from_str::<ReportLine>(line)?);
+            }
+            std::fs::remove_file(p)?;
+        }
+        // Rotated, so the oldest reports are gone
+        assert!(lines.len() < 21);
+        let last = lines.last().unwrap();
+        assert!(last.is_final);
+        assert_eq!(20, last.walked);
+        assert_eq!(20, last.elapsed_s);
+        assert_eq!([20, 0, 20], last.per_type["PhaseMapping"]);
+        assert_eq!(state.params.run_id, last.run_id);
+        assert!(lines[..lines.len() - 1]
+            .iter()
+            .all(|line| !line.is_final && line.delta_walked == 1 && line.delta_s == 1));
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_report_channel(fb: FacebookInit) {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_report_channel(sender);
+        state.params.logger = Logger::root(drain.clone(), o!());
+
+        // The receiver doesn't keep up, so all but the first two are dropped
+        for i in 0..5 {
+            state.record_step(&phase_node(i), Some(&children(0)));
+            state.report_progress_log(Some(Duration::from_secs(1)));
+        }
+        assert_eq!(3, state.dropped_reports());
+        assert_eq!(1, receiver.try_recv().unwrap().walked);
+        assert_eq!(2, receiver.try_recv().unwrap().walked);
+        assert!(receiver.try_recv().is_err());
+
+        // Sent again once there is room
+        state.report_progress();
+        let last = receiver.try_recv().unwrap();
+        assert!(last.is_final);
+        assert_eq!(5, last.walked);
+        assert_eq!(3, state.dropped_reports());
+
+        // A dropped receiver stops the sending, without counting drops
+        drop(receiver);
+        drain.take();
+        for _ in 0..2 {
+            state.report_progress_log(Some(Duration::from_secs(1)));
+        }
+        assert_eq!(3, state.dropped_reports());
+        let warnings: Vec<_> = drain
+            .take()
+            .into_iter()
+            .filter(|line| line.contains("receiver dropped"))
+            .collect();
+        assert_eq!(1, warnings.len());
+    }
+
+    #[fbinit::test]
+    fn test_scuba_batching(fb: FacebookInit) -> Result<(), Error> {
+        let log_file =
+            std::env::temp_dir().join(format!("walker_scuba_batching_{}", std::process::id()));
+        let _ = std::fs::remove_file(&log_file);
+        let row_count =
+            || -> Result<usize, Error> { Ok(std::fs::read_to_string(&log_file)?.lines().count()) };
+        let mut state = test_progress_state(fb);
+        state.set_sample_builder(
+            MononokeScubaSampleBuilder::with_discard().with_log_file(&log_file)?,
+        );
+
+        // The total and each type, all logged by the end of the report
+        state.record_step(&phase_node(0), Some(&children(0)));
+        state.record_step(&changeset_node(0), Some(&children(0)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(3, row_count()?);
+        assert!(state.reporting_stats.sinks.scuba_rows.rows.is_empty());
+
+        // Rows made outside a report wait for the flush
+        state.log_final_rows();
+        assert_eq!(3, row_count()?);
+        state.flush();
+        assert_eq!(6, row_count()?);
+        std::fs::remove_file(&log_file)?;
+
+        // Failures are counted, and warned about once
+        let drain = CapturingDrain::default();
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.set_sample_builder(
+            MononokeScubaSampleBuilder::with_discard().with_log_file("/dev/full")?,
+        );
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        state.report_progress();
+        assert_eq!(9, state.reporting_stats.sinks.scuba_rows.failed());
+        let warnings = drain
+            .take()
+            .into_iter()
+            .filter(|line| line.contains("progress Scuba rows"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["Failed to log 3 progress Scuba rows, further failures are only counted"],
+            warnings
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_iso_timestamp() {
+        assert_eq!(
+            "2024-01-02T03:04:05.678Z",
+            iso_timestamp(SystemTime::UNIX_EPOCH + Duration::from_millis(1704164645678))
+        );
+    }
+}
@@ -0,0 +1,521 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::io::Write;
+
+use maplit::hashset;
+use mononoke_types::ChangesetId;
+use slog::o;
+
+use super::*;
+use crate::detail::graph::ChangesetKey;
+
+/// A report asked of MockProgress
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MockReport {
+    Progress,
+    Throttled,
+    ThrottledNonblocking,
+    Heartbeat,
+    StartChunk(u64, String),
+    StartIteration(u64),
+    Flush,
+}
+
+struct Recorded<SS> {
+    steps: Vec<(NodeType, Option<SS>)>,
+    // Steps per record_step or record_steps call
+    calls: Vec<usize>,
+    reports: Vec<MockReport>,
+    position: String,
+    enqueued: u64,
+    dequeued: u64,
+}
+
+/// Records everything the walk tells progress and reports nothing. Clones share what
+/// is recorded, so a test can keep one to inspect.
+pub struct MockProgress<SS> {
+    recorded: Arc<Mutex<Recorded<SS>>>,
+    quiet: QuietMode,
+}
+
+impl<SS> Clone for MockProgress<SS> {
+    fn clone(&self) -> Self {
+        Self {
+            recorded: self.recorded.clone(),
+            quiet: self.quiet,
+        }
+    }
+}
+
+impl<SS> Default for MockProgress<SS> {
+    fn default() -> Self {
+        Self {
+            recorded: Arc::new(Mutex::new(Recorded {
+                steps: Vec::new(),
+                calls: Vec::new(),
+                reports: Vec::new(),
+                position: String::new(),
+                enqueued: 0,
+                dequeued: 0,
+            })),
+            quiet: QuietMode::Full,
+        }
+    }
+}
+
+impl<SS> MockProgress<SS> {
+    pub fn with_quiet(mut self, quiet: QuietMode) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    fn recorded(&self) -> MutexGuard<'_, Recorded<SS>> {
+        self.recorded.lock().unwrap()
+    }
+
+    /// Type and stats of each step, in the order recorded
+    pub fn steps(&self) -> Vec<(NodeType, Option<SS>)>
+    where
+        SS: Clone,
+    {
+        self.recorded().steps.clone()
+    }
+
+    /// Number of steps in each record_step or record_steps call
+    pub fn calls(&self) -> Vec<usize> {
+        self.recorded().calls.clone()
+    }
+
+    pub fn reports(&self) -> Vec<MockReport> {
+        self.recorded().reports.clone()
+    }
+
+    pub fn position(&self) -> String {
+        self.recorded().position.clone()
+    }
+
+    /// Steps (enqueued, dequeued) by the walk driver
+    pub fn queued(&self) -> (u64, u64) {
+        let recorded = self.recorded();
+        (recorded.enqueued, recorded.dequeued)
+    }
+
+    pub fn walked_by_type(&self) -> HashMap<NodeType, u64> {
+        let mut walked = HashMap::new();
+        for (t, _) in &self.recorded().steps {
+            *walked.entry(*t).or_default() += 1;
+        }
+        walked
+    }
+
+    /// Sum of the stats recorded for a type
+    pub fn stats_of_type(&self, t: NodeType) -> SS
+    where
+        SS: Add<SS, Output = SS> + Clone + Default,
+    {
+        self.recorded()
+            .steps
+            .iter()
+            .filter(|(step_type, _)| *step_type == t)
+            .filter_map(|(_, ss)| ss.clone())
+            .fold(SS::default(), |acc, ss| acc + ss)
+    }
+
+    /// Panics unless exactly these types were walked, this many times each
+    pub fn assert_walked(&self, expected: &[(NodeType, u64)]) {
+        let expected: HashMap<NodeType, u64> = expected.iter().cloned().collect();
+        assert_eq!(expected, self.walked_by_type());
+    }
+}
+
+impl<SS: Clone> ProgressRecorder<SS> for MockProgress<SS> {
+    fn record_step(&self, n: &Node, ss: Option<&SS>) {
+        let mut recorded = self.recorded();
+        recorded.steps.push((n.get_type(), ss.cloned()));
+        recorded.calls.push(1);
+    }
+
+    fn record_steps(&self, batch: &[(Node, Option<SS>)]) {
+        let mut recorded = self.recorded();
+        recorded
+            .steps
+            .extend(batch.iter().map(|(n, ss)| (n.get_type(), ss.clone())));
+        recorded.calls.push(batch.len());
+    }
+
+    fn set_sample_builder(&self, _s: MononokeScubaSampleBuilder) {}
+
+    fn set_position(&self, position: String) {
+        self.recorded().position = position;
+    }
+
+    fn record_enqueued(&self, count: u64) {
+        self.recorded().enqueued += count;
+    }
+
+    fn record_dequeued(&self, count: u64) {
+        self.recorded().dequeued += count;
+    }
+
+    fn record_in_flight(&self, _n: &Node) -> InFlightStep {
+        InFlightStep::untracked()
+    }
+}
+
+impl<SS> ProgressReporter for MockProgress<SS> {
+    fn report_progress(&self) {
+        self.recorded().reports.push(MockReport::Progress);
+    }
+
+    fn report_throttled(&self) {
+        self.recorded().reports.push(MockReport::Throttled);
+    }
+
+    fn report_throttled_nonblocking(&self) {
+        self.recorded()
+            .reports
+            .push(MockReport::ThrottledNonblocking);
+    }
+
+    fn start_chunk(&self, chunk_index: u64, bounds_description: String) {
+        self.recorded()
+            .reports
+            .push(MockReport::StartChunk(chunk_index, bounds_description));
+    }
+
+    fn start_iteration(&self, iteration: u64) {
+        self.recorded()
+            .reports
+            .push(MockReport::StartIteration(iteration));
+    }
+
+    // No background heartbeat, the test drives any it wants
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn report_heartbeat(&self) {
+        self.recorded().reports.push(MockReport::Heartbeat);
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.quiet
+    }
+
+    fn flush(&self) {
+        self.recorded().reports.push(MockReport::Flush);
+    }
+}
+
+pub(super) fn test_progress_state(
+    fb: FacebookInit,
+) -> ProgressStateCountByType<StepStats, ProgressSummary> {
+    test_progress_state_logging_to(fb, Logger::root(slog::Discard, o!()))
+}
+
+// For tests of what the state adds to its logger, which others replace
+pub(super) fn test_progress_state_logging_to(
+    fb: FacebookInit,
+    logger: Logger,
+) -> ProgressStateCountByType<StepStats, ProgressSummary> {
+    ProgressStateBuilder::new(fb, logger, "test", "repo".to_string())
+        .with_included_types(hashset! {NodeType::Changeset, NodeType::PhaseMapping})
+        .with_options(ProgressOptions {
+            sample_rate: 1,
+            interval: Duration::from_secs(1),
+            display: ProgressDisplay::Log,
+            quiet: QuietMode::Full,
+            type_rates: true,
+            type_emit_every_nth: 1,
+            type_grouping: TypeGrouping::Types,
+            stuck_step_threshold: Duration::from_secs(60),
+            unchanged: UnchangedProgress::Log,
+            error_budgets: HashMap::new(),
+            sample_node_every_nth: 0,
+            number_format: NumberFormat::Raw,
+            max_lines_per_hour: None,
+            idle_type_threshold: None,
+            worker_skew_threshold: None,
+            wall_clock_aligned: false,
+            slowdown: None,
+            payload_sizes: PayloadSizeOptions::default(),
+            jsonl: None,
+            types_of_interest: HashSet::new(),
+            interest_interval: Duration::from_secs(5),
+        })
+        .build()
+        .unwrap()
+}
+
+pub(super) struct FakeClock {
+    now: Mutex<Instant>,
+    wall: Mutex<SystemTime>,
+}
+
+impl FakeClock {
+    pub(super) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            now: Mutex::new(Instant::now()),
+            wall: Mutex::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        })
+    }
+
+    pub(super) fn advance(&self, d: Duration) {
+        *self.now.lock().unwrap() += d;
+        *self.wall.lock().unwrap() += d;
+    }
+
+    // As if the wall clock were corrected, which leaves the monotonic clock alone
+    pub(super) fn set_wall(&self, wall: SystemTime) {
+        *self.wall.lock().unwrap() = wall;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn wall_now(&self) -> SystemTime {
+        *self.wall.lock().unwrap()
+    }
+}
+
+#[derive(Default)]
+pub(super) struct CapturingStatsSink {
+    pub(super) values: Mutex<Vec<(ProgressStat, String, i64)>>,
+    gauges: Mutex<HashMap<(ProgressGauge, String), i64>>,
+    type_values: Mutex<Vec<(ProgressTypeStat, NodeType, i64)>>,
+    group_values: Mutex<Vec<(ProgressTypeStat, NodeTypeGroup, i64)>>,
+    derived_values: Mutex<Vec<(ProgressTypeStat, &'static str, i64)>>,
+    blobstore_reads: Mutex<Vec<(String, i64)>>,
+    phase_values: Mutex<Vec<(&'static str, i64)>>,
+    payload_bytes: Mutex<Vec<i64>>,
+}
+
+impl CapturingStatsSink {
+    pub(super) fn take(&self) -> Vec<(ProgressStat, String, i64)> {
+        std::mem::take(&mut *self.values.lock().unwrap())
+    }
+
+    pub(super) fn gauge(&self, gauge: ProgressGauge, repo: &str) -> Option<i64> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .get(&(gauge, repo.to_string()))
+            .cloned()
+    }
+
+    pub(super) fn take_type_values(&self) -> Vec<(ProgressTypeStat, NodeType, i64)> {
+        std::mem::take(&mut *self.type_values.lock().unwrap())
+    }
+
+    pub(super) fn take_group_values(&self) -> Vec<(ProgressTypeStat, NodeTypeGroup, i64)> {
+        std::mem::take(&mut *self.group_values.lock().unwrap())
+    }
+
+    pub(super) fn take_derived_values(&self) -> Vec<(ProgressTypeStat, &'static str, i64)> {
+        std::mem::take(&mut *self.derived_values.lock().unwrap())
+    }
+
+    pub(super) fn take_blobstore_reads(&self) -> Vec<(String, i64)> {
+        std::mem::take(&mut *self.blobstore_reads.lock().unwrap())
+    }
+
+    pub(super) fn take_phase_values(&self) -> Vec<(&'static str, i64)> {
+        std::mem::take(&mut *self.phase_values.lock().unwrap())
+    }
+
+    pub(super) fn take_payload_bytes(&self) -> Vec<i64> {
+        std::mem::take(&mut *self.payload_bytes.lock().unwrap())
+    }
+}
+
+impl ProgressStatsSink for CapturingStatsSink {
+    fn add_value(&self, stat: ProgressStat, _subcommand: &'static str, repo: &str, value: i64) {
+        self.values
+            .lock()
+            .unwrap()
+            .push((stat, repo.to_string(), value));
+    }
+
+    fn set_gauge(&self, gauge: ProgressGauge, _subcommand: &'static str, repo: &str, value: i64) {
+        self.gauges
+            .lock()
+            .unwrap()
+            .insert((gauge, repo.to_string()), value);
+    }
+
+    fn add_type_value(
+        &self,
+        stat: ProgressTypeStat,
+        _subcommand: &'static str,
+        _repo: &str,
+        node_type: NodeType,
+        value: i64,
+    ) {
+        self.type_values
+            .lock()
+            .unwrap()
+            .push((stat, node_type, value));
+    }
+
+    fn add_group_value(
+        &self,
+        stat: ProgressTypeStat,
+        _subcommand: &'static str,
+        _repo: &str,
+        group: NodeTypeGroup,
+        value: i64,
+    ) {
+        self.group_values.lock().unwrap().push((stat, group, value));
+    }
+
+    fn add_derived_value(
+        &self,
+        stat: ProgressTypeStat,
+        _subcommand: &'static str,
+        _repo: &str,
+        derived: &'static str,
+        value: i64,
+    ) {
+        self.derived_values
+            .lock()
+            .unwrap()
+            .push((stat, derived, value));
+    }
+
+    fn add_blobstore_reads(
+        &self,
+        _subcommand: &'static str,
+        _repo: &str,
+        blobstore: &str,
+        value: i64,
+    ) {
+        self.blobstore_reads
+            .lock()
+            .unwrap()
+            .push((blobstore.to_string(), value));
+    }
+
+    fn add_payload_bytes(&self, _subcommand: &'static str, _repo: &str, bytes: i64) {
+        self.payload_bytes.lock().unwrap().push(bytes);
+    }
+
+    fn add_phase_value(
+        &self,
+        _subcommand: &'static str,
+        _repo: &str,
+        phase: &'static str,
+        value: i64,
+    ) {
+        self.phase_values.lock().unwrap().push((phase, value));
+    }
+}
+
+#[derive(Clone, Default)]
+pub(super) struct FakeTerminal(Arc<Mutex<Vec<u8>>>);
+
+impl FakeTerminal {
+    pub(super) fn output(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for FakeTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub(super) struct CapturingDrain(Arc<Mutex<Vec<String>>>);
+
+impl CapturingDrain {
+    pub(super) fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl slog::Drain for CapturingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record<'_>,
+        _values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        self.0.lock().unwrap().push(record.msg().to_string());
+        Ok(())
+    }
+}
+
+pub(super) fn phase_node(i: u8) -> Node {
+    Node::PhaseMapping(ChangesetId::from_byte_array([i; 32]))
+}
+
+pub(super) fn changeset_node(i: u8) -> Node {
+    Node::Changeset(ChangesetKey {
+        inner: ChangesetId::from_byte_array([i; 32]),
+        filenode_known_derived: false,
+    })
+}
+
+pub(super) fn children(num_expanded_new: usize) -> StepStats {
+    StepStats {
+        num_expanded_new,
+        ..Default::default()
+    }
+}
+
+// Message and key values of a record, rendered as strings
+pub(super) type KvRecord = (String, HashMap<String, String>);
+
+#[derive(Clone, Default)]
+pub(super) struct KvDrain(Arc<Mutex<Vec<KvRecord>>>);
+
+impl KvDrain {
+    pub(super) fn take(&self) -> Vec<KvRecord> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+struct KvCollector(HashMap<String, String>);
+
+impl slog::Serializer for KvCollector {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.insert(key.to_string(), val.to_string());
+        Ok(())
+    }
+}
+
+impl slog::Drain for KvDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record<'_>,
+        values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let mut collector = KvCollector(HashMap::new());
+        let _ = slog::KV::serialize(values, record, &mut collector);
+        let _ = slog::KV::serialize(&record.kv(), record, &mut collector);
+        self.0
+            .lock()
+            .unwrap()
+            .push((record.msg().to_string(), collector.0));
+        Ok(())
+    }
+}
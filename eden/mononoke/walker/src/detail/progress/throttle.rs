@@ -0,0 +1,478 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::mem;
+use std::ops::Add;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::detail::progress::options::QuietMode;
+use crate::detail::progress::ProgressStateCountByType;
+
+/// Clock reading for a report, taken once so its delta, run and chunk times agree
+pub struct ReportTimes {
+    pub now: Instant,
+    /// Time the report covers. None for a final report made straight after another.
+    pub delta: Option<Duration>,
+    /// Time since the run started
+    pub total: Duration,
+    /// Time since the run started less the time paused, for run rates
+    pub active: Duration,
+}
+
+/// Time the walk driver has said it was waiting rather than walking
+#[derive(Debug, Default)]
+pub struct PausedTime {
+    // Pauses not yet resumed, as waits may nest
+    depth: u64,
+    // Start of the outermost pause, while paused
+    since: Option<Instant>,
+    // Finished pauses
+    finished: Duration,
+    // Resumes without a pause, which are ignored
+    pub unbalanced: u64,
+}
+
+impl PausedTime {
+    pub(super) fn pause(&mut self, now: Instant) {
+        if self.depth == 0 {
+            self.since = Some(now);
+        }
+        self.depth += 1;
+    }
+
+    // False if not paused
+    pub(super) fn resume(&mut self, now: Instant) -> bool {
+        if self.depth == 0 {
+            self.unbalanced += 1;
+            return false;
+        }
+        self.depth -= 1;
+        if self.depth == 0 {
+            if let Some(since) = self.since.take() {
+                self.finished += now.saturating_duration_since(since);
+            }
+        }
+        true
+    }
+
+    /// All time paused up to now, including an unfinished pause
+    pub fn total(&self, now: Instant) -> Duration {
+        self.finished
+            + self
+                .since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+}
+
+// Throttled report bookkeeping, see should_log_throttled
+pub(super) struct ThrottleState {
+    // Last report that had something change, for how long progress has been unchanged
+    pub(super) last_changed: Instant,
+    // total_progress / sample_rate as of the last throttle check
+    pub(super) last_sample: u64,
+    // Throttled reports due, including those skipped by QuietMode::EveryNth
+    pub(super) throttled_reports: u64,
+    // Time covered by skipped throttled reports, added to the next report's delta
+    pub(super) skipped_time: Duration,
+    // Only used with max_lines_per_hour, never less than the configured interval
+    pub(super) adaptive_interval: Duration,
+    // With wall_clock_aligned, the interval boundary of the last report, counted from
+    // the epoch. None until the first throttle check.
+    pub(super) last_boundary: Option<u128>,
+}
+
+impl ThrottleState {
+    pub(super) fn new(now: Instant) -> Self {
+        Self {
+            last_changed: now,
+            last_sample: 0,
+            throttled_reports: 0,
+            skipped_time: Duration::ZERO,
+            adaptive_interval: Duration::ZERO,
+            last_boundary: None,
+        }
+    }
+}
+
+impl<SS, T> ProgressStateCountByType<SS, T>
+where
+    SS: Add<SS, Output = SS> + Default,
+    T: Default,
+{
+    // Throttle by sample, then time. Checks once per sample_rate steps, even if
+    // steps were recorded in batches that skip over the exact multiple.
+    pub fn should_log_throttled(&mut self) -> Option<Duration> {
+        // Excluded steps still count here, so a walk of mostly excluded types still reports
+        let sample = (self.work_stats.total_progress + self.work_stats.uncounted)
+            / self.params.options.sample_rate;
+        if sample != self.reporting_stats.throttle.last_sample {
+            self.reporting_stats.throttle.last_sample = sample;
+            let new_update = self.params.clock.now();
+            let delta_time = new_update.duration_since(self.reporting_stats.last_update);
+            let due = if self.params.options.wall_clock_aligned {
+                self.crossed_boundary(delta_time)
+            } else {
+                delta_time >= self.report_interval()
+            };
+            if due {
+                self.reporting_stats.last_update = new_update;
+                self.adapt_interval(delta_time);
+                self.reporting_stats.throttle.throttled_reports += 1;
+                if let QuietMode::EveryNth(n) = self.params.options.quiet {
+                    if self.reporting_stats.throttle.throttled_reports % n.max(1) != 0 {
+                        self.reporting_stats.throttle.skipped_time += delta_time;
+                        return None;
+                    }
+                }
+                return Some(
+                    delta_time + mem::take(&mut self.reporting_stats.throttle.skipped_time),
+                );
+            }
+        }
+        None
+    }
+
+    // Whether the wall clock has reached a new multiple of the interval since the last
+    // report. A jump forward over many boundaries gives one report, and a jump back
+    // restarts counting from the new time without reporting. A boundary reached less than
+    // half an interval after the last report by the monotonic clock is skipped, so a wall
+    // clock stepped back and forth across a boundary can't cause a storm of reports.
+    fn crossed_boundary(&mut self, since_last: Duration) -> bool {
+        let interval = self.report_interval().as_millis().max(1);
+        let boundary = self
+            .params
+            .clock
+            .wall_now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            / interval;
+        let last = self
+            .reporting_stats
+            .throttle
+            .last_boundary
+            .get_or_insert(boundary);
+        if boundary < *last {
+            *last = boundary;
+            return false;
+        }
+        if boundary > *last {
+            *last = boundary;
+            return since_last.as_millis() * 2 >= interval;
+        }
+        false
+    }
+
+    /// Minimum time between throttled reports, the configured interval unless adapting
+    /// to max_lines_per_hour
+    pub fn report_interval(&self) -> Duration {
+        match self.params.options.max_lines_per_hour {
+            Some(_) => cmp::max(
+                self.params.options.interval,
+                self.reporting_stats.throttle.adaptive_interval,
+            ),
+            None => self.params.options.interval,
+        }
+    }
+
+    // Double the interval while reports come more often than the hourly cap allows, and
+    // halve it when they come at under half the rate it allows. Bounded by the configured
+    // interval and the spacing that meets the cap.
+    pub(super) fn adapt_interval(&mut self, since_last: Duration) {
+        let max_lines = match self.params.options.max_lines_per_hour {
+            Some(max_lines) => max_lines,
+            None => return,
+        };
+        let min = self.params.options.interval;
+        let max = cmp::max(min, Duration::from_secs(3600).div_f64(max_lines as f64));
+        let current = self.report_interval();
+        self.reporting_stats.throttle.adaptive_interval = if since_last < max {
+            cmp::min(current * 2, max)
+        } else if since_last >= current * 2 {
+            cmp::max(current / 2, min)
+        } else {
+            current
+        };
+    }
+
+    /// Times for a report made now, reading the clock once. Throttled reports pass the
+    /// delta_time from should_log_throttled, and final reports pass None to measure from
+    /// the last report. Call finish_report with the result once the report is made.
+    pub fn report_times(&self, delta_time: Option<Duration>) -> ReportTimes {
+        let now = self.params.clock.now();
+        let delta = delta_time.or_else(|| {
+            let t = now.saturating_duration_since(self.reporting_stats.last_update);
+            (t.as_millis() > 0).then_some(t)
+        });
+        let total = now.saturating_duration_since(self.reporting_stats.start_time);
+        ReportTimes {
+            now,
+            delta,
+            total,
+            active: total.saturating_sub(self.reporting_stats.paused.total(now)),
+        }
+    }
+
+    /// Marks a report as made, so the next delta is measured from it
+    pub fn finish_report(&mut self, times: &ReportTimes) {
+        self.reporting_stats.last_update = times.now;
+        self.params.stats_sink.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::testing::*;
+    use crate::detail::progress::*;
+
+    #[fbinit::test]
+    fn test_throttle_with_fake_clock(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+
+        state.record_step(&phase_node(0), None);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(None, state.should_log_throttled());
+
+        state.record_step(&phase_node(1), None);
+        clock.advance(Duration::from_millis(600));
+        assert_eq!(
+            Some(Duration::from_millis(1100)),
+            state.should_log_throttled()
+        );
+
+        // Interval restarts from the last report
+        state.record_step(&phase_node(2), None);
+        clock.advance(Duration::from_millis(900));
+        assert_eq!(None, state.should_log_throttled());
+    }
+
+    #[fbinit::test]
+    fn test_timings_with_fake_clock(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let start = clock.now();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        assert_eq!(start, state.reporting_stats.start_time);
+
+        clock.advance(Duration::from_secs(10));
+        state.start_chunk(1, "(0, 10)".to_string());
+        let chunk = state.reporting_stats.chunk.as_ref().unwrap();
+        assert_eq!(Duration::from_secs(10), chunk.start_time - start);
+
+        clock.advance(Duration::from_secs(5));
+        state.report_progress_log(None);
+        assert_eq!(
+            Duration::from_secs(15),
+            state.reporting_stats.last_update - start
+        );
+    }
+
+    #[fbinit::test]
+    fn test_every_nth_delta_time(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.options.quiet = QuietMode::EveryNth(3);
+
+        let mut reported = Vec::new();
+        for i in 0..6 {
+            state.record_step(&phase_node(i), Some(&children(0)));
+            clock.advance(Duration::from_secs(2));
+            reported.push(state.should_log_throttled());
+        }
+        // Skipped intervals are included in the next report's delta, so rates stay right
+        assert_eq!(
+            vec![
+                None,
+                None,
+                Some(Duration::from_secs(6)),
+                None,
+                None,
+                Some(Duration::from_secs(6)),
+            ],
+            reported
+        );
+    }
+
+    #[fbinit::test]
+    fn test_adaptive_interval(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        state.params.options.max_lines_per_hour = Some(60);
+        let mut i = 0;
+        let mut step = |state: &mut ProgressStateCountByType<StepStats, ProgressSummary>,
+                        every: Duration| {
+            i += 1;
+            state.record_step(&phase_node(i as u8), Some(&children(1)));
+            clock.advance(every);
+            state.should_log_throttled().is_some()
+        };
+
+        // A fast walk stretches the interval until it meets the cap
+        let mut intervals = vec![];
+        for _ in 0..200 {
+            if step(&mut state, Duration::from_secs(1)) {
+                intervals.push(state.report_interval().as_secs());
+            }
+        }
+        assert_eq!(vec![2, 4, 8, 16, 32, 60, 60, 60], intervals);
+        let reports = (0..3600)
+            .filter(|_| step(&mut state, Duration::from_secs(1)))
+            .count();
+        assert_eq!(60, reports);
+
+        state.report_progress_log(Some(Duration::from_secs(60)));
+        assert!(drain.take()[0].contains("Interval 60s; "));
+
+        // A slow one shrinks it back, but not below the configured interval
+        let mut intervals = vec![];
+        for _ in 0..10 {
+            if step(&mut state, Duration::from_secs(300)) {
+                intervals.push(state.report_interval().as_secs());
+            }
+        }
+        assert_eq!(vec![30, 15, 7, 3, 1, 1, 1, 1, 1, 1], intervals);
+        assert_eq!(Duration::from_secs(1), state.report_interval());
+
+        // The fixed interval is unaffected
+        state.params.options.max_lines_per_hour = None;
+        state.reporting_stats.throttle.adaptive_interval = Duration::from_secs(60);
+        assert_eq!(Duration::from_secs(1), state.report_interval());
+        assert!(step(&mut state, Duration::from_secs(1)));
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert!(!drain.take()[0].contains("Interval"));
+    }
+
+    #[fbinit::test]
+    fn test_wall_clock_aligned(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.options.interval = Duration::from_secs(60);
+        state.params.options.wall_clock_aligned = true;
+        let epoch_secs = |clock: &FakeClock| {
+            clock
+                .wall_now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        let mut i = 0;
+        // Wall clock seconds of each report while stepping once a second
+        let mut step_for = |state: &mut ProgressStateCountByType<StepStats, ProgressSummary>,
+                            secs: u64| {
+            let mut reported = vec![];
+            for _ in 0..secs {
+                i += 1;
+                clock.advance(Duration::from_secs(1));
+                state.record_step(&phase_node(i as u8), Some(&children(0)));
+                if state.should_log_throttled().is_some() {
+                    reported.push(epoch_secs(&clock));
+                }
+            }
+            reported
+        };
+
+        // Starts 20s into a minute, then reports on each minute
+        assert_eq!(20, epoch_secs(&clock) % 60);
+        let reported = step_for(&mut state, 200);
+        assert_eq!(3, reported.len());
+        assert!(reported.iter().all(|secs| secs % 60 == 0));
+
+        // The sample rate still gates reports
+        state.params.options.sample_rate = 1000;
+        assert!(step_for(&mut state, 120).is_empty());
+        state.params.options.sample_rate = 1;
+
+        // A jump forward over many boundaries is one report
+        let now = clock.wall_now();
+        clock.set_wall(now + Duration::from_secs(3600));
+        assert_eq!(1, step_for(&mut state, 1).len());
+        assert!(step_for(&mut state, 1).is_empty());
+
+        // A jump back does not repeat reports. They resume on a later boundary, skipping
+        // the first as it comes too soon after the last report.
+        let now = clock.wall_now();
+        clock.set_wall(now - Duration::from_secs(3600));
+        let reported = step_for(&mut state, 120);
+        assert_eq!(1, reported.len());
+        assert_eq!(0, reported[0] % 60);
+
+        // Stepping back and forth across a boundary for 40s crosses it 20 times, but
+        // reports at most once per half interval
+        clock.advance(Duration::from_secs(40));
+        let to_boundary = 60 - epoch_secs(&clock) % 60;
+        let before = clock.wall_now() + Duration::from_secs(to_boundary - 2);
+        let mut reports = 0;
+        for _ in 0..20 {
+            clock.set_wall(before);
+            reports += step_for(&mut state, 1).len();
+            clock.set_wall(before + Duration::from_secs(2));
+            reports += step_for(&mut state, 1).len();
+        }
+        assert_eq!(2, reports);
+    }
+
+    #[fbinit::test]
+    fn test_paused(fb: FacebookInit) {
+        let clock = FakeClock::new();
+        let drain = CapturingDrain::default();
+        let mut state = test_progress_state(fb).with_clock(clock.clone());
+        state.params.logger = Logger::root(drain.clone(), o!());
+        let mut step = 0;
+        let mut walk_for = |state: &mut ProgressStateCountByType<StepStats, ProgressSummary>,
+                            secs: u64| {
+            for _ in 0..secs {
+                step += 1;
+                clock.advance(Duration::from_secs(1));
+                state.record_step(&phase_node(step), Some(&children(0)));
+            }
+        };
+
+        // 20s walking around 40s paused, with a nested pause inside
+        walk_for(&mut state, 10);
+        state.pause();
+        clock.advance(Duration::from_secs(30));
+        state.pause();
+        clock.advance(Duration::from_secs(5));
+        state.resume();
+        clock.advance(Duration::from_secs(5));
+        state.resume();
+        walk_for(&mut state, 10);
+
+        state.report_progress_log(None);
+        let msg = drain.take().pop().unwrap();
+        assert!(msg.contains("Run 1.0/s,0.0/s,20,0,0,0,60s; "), "{}", msg);
+        assert!(msg.contains("Paused 40s; "), "{}", msg);
+        let report = state.final_report();
+        assert_eq!(60.0, report.elapsed_secs);
+        assert_eq!(40.0, report.paused_secs);
+        assert_eq!(1.0, report.types["PhaseMapping"].rate);
+        assert!(state.dump().contains("60.0s elapsed (40.0s paused)"));
+
+        // Unbalanced resumes are ignored, warning once
+        state.resume();
+        state.resume();
+        assert_eq!(1, drain.take().len());
+        assert_eq!(2, state.reporting_stats.paused.unbalanced);
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(0.5, state.final_report().types["PhaseMapping"].rate);
+
+        // An unfinished pause counts up to now
+        state.pause();
+        clock.advance(Duration::from_secs(10));
+        let report = state.final_report();
+        assert_eq!(50.0, report.paused_secs);
+        assert_eq!(0.5, report.types["PhaseMapping"].rate);
+    }
+}
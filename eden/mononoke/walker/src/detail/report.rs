@@ -0,0 +1,951 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use anyhow::Context;
+use anyhow::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+
+use crate::detail::log;
+use crate::detail::progress::PhaseCounts;
+use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::state::StepStats;
+
+/// Per NodeType totals at the end of a walk
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TypeReport {
+    pub walked: u64,
+    /// Share of everything walked, as a percentage
+    #[serde(default)]
+    pub walked_pct: f64,
+    pub errors: u64,
+    /// Steps whose target was definitively absent, which are not counted in errors
+    #[serde(default)]
+    pub missing: u64,
+    /// Failed attempts at steps that were retried
+    #[serde(default)]
+    pub retries: u64,
+    /// Walked per second over the whole run
+    pub rate: f64,
+    /// Seconds into the run the type was first and last walked, and seconds since then
+    /// at the end. Not set for derived data totals, or types only merged from elsewhere.
+    #[serde(default)]
+    pub first_seen_secs: Option<f64>,
+    #[serde(default)]
+    pub last_seen_secs: Option<f64>,
+    #[serde(default)]
+    pub idle_secs: Option<f64>,
+}
+
+/// How often the walk's visited check found a step's node already visited
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VisitHitReport {
+    pub offered: u64,
+    pub already_visited: u64,
+    /// Already visited as a percentage of offered
+    pub hit_pct: f64,
+}
+
+/// A NodeType with more errors than its budget allows
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BudgetViolation {
+    pub node_type: String,
+    pub errors: u64,
+    pub budget: u64,
+}
+
+/// Distribution of step payload sizes, see PayloadSizeOptions
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PayloadSizeReport {
+    /// Count per bucket, in order of size. Each bucket is up to but excluding its bound,
+    /// with no bound on the last.
+    pub buckets: Vec<PayloadBucketReport>,
+    pub count: u64,
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PayloadBucketReport {
+    pub below: Option<u64>,
+    pub count: u64,
+}
+
+/// Steps run by one of the walk's concurrent workers
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorkerReport {
+    pub id: usize,
+    pub steps: u64,
+    /// Seconds since the worker finished its last step, or started its current one
+    pub idle_secs: f64,
+    /// Still running a step at the end
+    pub busy: bool,
+}
+
+/// Machine readable summary of one repo's walk, saved as JSON so later runs can compare
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FinalReport {
+    pub repo: String,
+    pub subcommand: String,
+    /// As on every progress record of the run
+    #[serde(default)]
+    pub run_id: String,
+    /// When the report was made, as UTC ISO-8601
+    #[serde(default)]
+    pub timestamp: String,
+    /// Whether the walk finished, rather than stopping on an error. Reports saved
+    /// before this was recorded were only ever of finished walks.
+    #[serde(default = "completed_default")]
+    pub completed: bool,
+    pub elapsed_secs: f64,
+    /// Keyed by NodeType name so reports stay readable if types are added or removed
+    pub types: BTreeMap<String, TypeReport>,
+    /// Derived data types, each summed over the node types it is made of
+    #[serde(default)]
+    pub derived: BTreeMap<String, TypeReport>,
+    /// Included types that were never walked
+    #[serde(default)]
+    pub unwalked_types: Vec<String>,
+    /// Included types that were never walked, but were allowed to be
+    #[serde(default)]
+    pub unwalked_ok_types: Vec<String>,
+    /// Estimated time spent in progress bookkeeping, and as a percentage of elapsed
+    #[serde(default)]
+    pub overhead_ms: f64,
+    #[serde(default)]
+    pub overhead_pct: f64,
+    /// Types over their error budget, any of which fails the run
+    #[serde(default)]
+    pub budget_violations: Vec<BudgetViolation>,
+    /// Steps of types excluded from progress, so in none of the counts above
+    #[serde(default)]
+    pub uncounted_steps: u64,
+    /// Steps not taken as their node or edge type was filtered out, by node type
+    #[serde(default)]
+    pub filtered: BTreeMap<String, u64>,
+    /// Visited check outcomes over all types, if the walk driver recorded them
+    #[serde(default)]
+    pub visit_hits: Option<VisitHitReport>,
+    /// The same by the type of node checked
+    #[serde(default)]
+    pub visit_hits_by_type: BTreeMap<String, VisitHitReport>,
+    /// Changesets walked by phase
+    #[serde(default)]
+    pub changeset_phases: PhaseCounts,
+    /// Per worker activity, empty unless the walk's workers were tracked
+    #[serde(default)]
+    pub workers: Vec<WorkerReport>,
+    /// Time the walk was paused waiting, included in elapsed but not in rates
+    #[serde(default)]
+    pub paused_secs: f64,
+    /// Repo keys over the per repo cap, whose stats were kept together. A lower bound
+    /// if there were too many to count.
+    #[serde(default)]
+    pub folded_repo_keys: u64,
+    /// Payload sizes of the steps that had one, if any did
+    #[serde(default)]
+    pub payload_sizes: Option<PayloadSizeReport>,
+    /// Payload sizes of the types picked for their own histogram
+    #[serde(default)]
+    pub payload_sizes_by_type: BTreeMap<String, PayloadSizeReport>,
+}
+
+fn completed_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BaselineThresholds {
+    /// Fail if walked count for a type drops by more than this percentage
+    pub max_walked_drop_pct: Option<f64>,
+    /// Fail if rate for a type drops by more than this percentage
+    pub max_rate_drop_pct: Option<f64>,
+    /// Fail if errors for a type increase by more than this many
+    pub max_errors_increase: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FinalReportOptions {
+    /// Where to save this run's reports
+    pub summary_file: Option<PathBuf>,
+    /// Reports from a previous run to compare against
+    pub baseline_file: Option<PathBuf>,
+    pub thresholds: BaselineThresholds,
+}
+
+/// Change in one NodeType's totals from the baseline
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeDelta {
+    pub node_type: String,
+    pub walked: i64,
+    pub rate: f64,
+    pub errors: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BaselineComparison {
+    pub deltas: Vec<TypeDelta>,
+    /// Description of each threshold exceeded, empty if none were
+    pub exceeded: Vec<String>,
+}
+
+fn pct_drop(baseline: f64, current: f64) -> f64 {
+    if baseline > 0.0 {
+        (baseline - current) * 100.0 / baseline
+    } else {
+        0.0
+    }
+}
+
+impl BaselineComparison {
+    pub fn new(
+        baseline: &FinalReport,
+        current: &FinalReport,
+        thresholds: &BaselineThresholds,
+    ) -> Self {
+        let node_types: BTreeSet<&String> =
+            baseline.types.keys().chain(current.types.keys()).collect();
+        let mut comparison = Self::default();
+        for node_type in node_types {
+            let before = baseline.types.get(node_type).cloned().unwrap_or_default();
+            let after = current.types.get(node_type).cloned().unwrap_or_default();
+            let delta = TypeDelta {
+                node_type: node_type.clone(),
+                walked: after.walked as i64 - before.walked as i64,
+                rate: after.rate - before.rate,
+                errors: after.errors as i64 - before.errors as i64,
+            };
+
+            if let Some(max) = thresholds.max_walked_drop_pct {
+                let drop = pct_drop(before.walked as f64, after.walked as f64);
+                if drop > max {
+                    comparison.exceeded.push(format!(
+                        "{} walked dropped {:.1}% (max {}%)",
+                        node_type, drop, max
+                    ));
+                }
+            }
+            if let Some(max) = thresholds.max_rate_drop_pct {
+                let drop = pct_drop(before.rate, after.rate);
+                if drop > max {
+                    comparison.exceeded.push(format!(
+                        "{} rate dropped {:.1}% (max {}%)",
+                        node_type, drop, max
+                    ));
+                }
+            }
+            if let Some(max) = thresholds.max_errors_increase {
+                if delta.errors > max as i64 {
+                    comparison.exceeded.push(format!(
+                        "{} errors increased by {} (max {})",
+                        node_type, delta.errors, max
+                    ));
+                }
+            }
+            comparison.deltas.push(delta);
+        }
+        comparison
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        !self.exceeded.is_empty()
+    }
+
+    pub fn log(&self, logger: &Logger, repo: &str) {
+        let table = self
+            .deltas
+            .iter()
+            .map(|d| {
+                format!(
+                    "{}:{:+},{:+.1}/s,{:+}",
+                    d.node_type, d.walked, d.rate, d.errors
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        info!(
+            logger,
+            #log::GRAPH,
+            "Baseline delta for {} Type:Walked,Rate,Errors {}",
+            repo,
+            table
+        );
+        for exceeded in &self.exceeded {
+            warn!(
+                logger,
+                "Baseline threshold exceeded for {}: {}", repo, exceeded
+            );
+        }
+    }
+}
+
+/// Save reports as JSON, creating the directory if needed. Written to a temporary file
+/// that is renamed into place, so readers see either the old file or all of the new one,
+/// even if the walker is killed part way.
+pub fn save_reports(path: &Path, reports: &[FinalReport]) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(reports)?;
+    let context = || format!("While writing final report to {}", path.display());
+    let file_name = path.file_name().with_context(context)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir).with_context(context)?;
+    // In the same directory, as a rename is only atomic within a filesystem
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".tmp.{}", std::process::id()));
+    let tmp_path = dir.join(tmp_name);
+    let written = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(json.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written.with_context(context)
+}
+
+/// Load reports saved by save_reports
+pub fn load_reports(path: &Path) -> Result<Vec<FinalReport>, Error> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("While reading final report from {}", path.display()))?;
+    serde_json::from_str(&json)
+        .with_context(|| format!("While parsing final report from {}", path.display()))
+}
+
+// A bad baseline should not fail the walk it is only being compared against
+fn load_baseline(logger: &Logger, path: &Path) -> Option<Vec<FinalReport>> {
+    match load_reports(path) {
+        Ok(reports) => Some(reports),
+        Err(e) => {
+            warn!(
+                logger,
+                "Could not load baseline from {}, skipping comparison: {:?}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// A value in two reports and how it changed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ValueDiff {
+    pub before: f64,
+    pub after: f64,
+    pub delta: f64,
+    /// None if there was nothing before to compare to
+    pub delta_pct: Option<f64>,
+}
+
+impl ValueDiff {
+    fn new(before: f64, after: f64) -> Self {
+        Self {
+            before,
+            after,
+            delta: after - before,
+            delta_pct: (before != 0.0).then(|| (after - before) * 100.0 / before),
+        }
+    }
+
+    fn delta_pct_text(&self) -> String {
+        self.delta_pct
+            .map_or_else(|| "-".to_string(), |pct| format!("{:+.1}%", pct))
+    }
+}
+
+/// Which of the two reports had a repo or type
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Both,
+    BeforeOnly,
+    AfterOnly,
+}
+
+impl Presence {
+    fn new(before: bool, after: bool) -> Self {
+        match (before, after) {
+            (true, false) => Presence::BeforeOnly,
+            (false, true) => Presence::AfterOnly,
+            _ => Presence::Both,
+        }
+    }
+
+    fn note(&self) -> &'static str {
+        match self {
+            Presence::Both => "",
+            Presence::BeforeOnly => "only before",
+            Presence::AfterOnly => "only after",
+        }
+    }
+}
+
+/// Change in one NodeType between two saved reports
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TypeDiff {
+    pub node_type: String,
+    pub presence: Presence,
+    pub walked: ValueDiff,
+    pub errors: ValueDiff,
+    /// Payload bytes, only for types with their own payload size histogram in either
+    pub bytes: Option<ValueDiff>,
+}
+
+/// Change in one repo's report between two saved reports
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RepoDiff {
+    pub repo: String,
+    pub presence: Presence,
+    pub elapsed_secs: ValueDiff,
+    /// Payload bytes over all types
+    pub bytes: ValueDiff,
+    /// In name order
+    pub types: Vec<TypeDiff>,
+}
+
+fn total_bytes(sizes: Option<&PayloadSizeReport>) -> f64 {
+    sizes.map_or(0.0, |sizes| sizes.total_bytes as f64)
+}
+
+/// Line up two sets of saved reports by repo then type. A repo or type in only one of
+/// them is compared against zeros, and marked as such.
+pub fn diff_reports(before: &[FinalReport], after: &[FinalReport]) -> Vec<RepoDiff> {
+    let by_repo = |reports: &[FinalReport]| -> BTreeMap<String, FinalReport> {
+        reports
+            .iter()
+            .map(|report| (report.repo.clone(), report.clone()))
+            .collect()
+    };
+    let before = by_repo(before);
+    let after = by_repo(after);
+    let repos: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    repos
+        .into_iter()
+        .map(|repo| {
+            let empty = FinalReport::default();
+            let old = before.get(repo).unwrap_or(&empty);
+            let new = after.get(repo).unwrap_or(&empty);
+            let node_types: BTreeSet<&String> = old.types.keys().chain(new.types.keys()).collect();
+            let types = node_types
+                .into_iter()
+                .map(|node_type| {
+                    let old_type = old.types.get(node_type).cloned().unwrap_or_default();
+                    let new_type = new.types.get(node_type).cloned().unwrap_or_default();
+                    let old_sizes = old.payload_sizes_by_type.get(node_type);
+                    let new_sizes = new.payload_sizes_by_type.get(node_type);
+                    TypeDiff {
+                        node_type: node_type.clone(),
+                        presence: Presence::new(
+                            old.types.contains_key(node_type),
+                            new.types.contains_key(node_type),
+                        ),
+                        walked: ValueDiff::new(old_type.walked as f64, new_type.walked as f64),
+                        errors: ValueDiff::new(old_type.errors as f64, new_type.errors as f64),
+                        bytes: (old_sizes.is_some() || new_sizes.is_some()).then(|| {
+                            ValueDiff::new(total_bytes(old_sizes), total_bytes(new_sizes))
+                        }),
+                    }
+                })
+                .collect();
+            RepoDiff {
+                repo: repo.clone(),
+                presence: Presence::new(before.contains_key(repo), after.contains_key(repo)),
+                elapsed_secs: ValueDiff::new(old.elapsed_secs, new.elapsed_secs),
+                bytes: ValueDiff::new(
+                    total_bytes(old.payload_sizes.as_ref()),
+                    total_bytes(new.payload_sizes.as_ref()),
+                ),
+                types,
+            }
+        })
+        .collect()
+}
+
+/// The diffs as a table per repo, with the after value, delta and delta percentage for
+/// each type
+pub fn format_diffs(diffs: &[RepoDiff]) -> String {
+    let mut out = String::new();
+    for diff in diffs {
+        out.push_str(&format!(
+            "Repo {}{}, elapsed {:.1}s -> {:.1}s ({:+.1}s, {}), bytes {} -> {} ({:+}, {})\n",
+            diff.repo,
+            match diff.presence {
+                Presence::Both => String::new(),
+                presence => format!(" ({})", presence.note()),
+            },
+            diff.elapsed_secs.before,
+            diff.elapsed_secs.after,
+            diff.elapsed_secs.delta,
+            diff.elapsed_secs.delta_pct_text(),
+            diff.bytes.before,
+            diff.bytes.after,
+            diff.bytes.delta,
+            diff.bytes.delta_pct_text(),
+        ));
+        let mut rows = vec![[
+            "Type", "Walked", "Delta", "Delta%", "Errors", "Delta", "Delta%", "Bytes", "Delta",
+            "Delta%", "",
+        ]
+        .map(String::from)
+        .to_vec()];
+        for t in &diff.types {
+            let mut row = vec![t.node_type.clone()];
+            for value in [Some(&t.walked), Some(&t.errors), t.bytes.as_ref()] {
+                match value {
+                    Some(value) => row.extend([
+                        value.after.to_string(),
+                        format!("{:+}", value.delta),
+                        value.delta_pct_text(),
+                    ]),
+                    None => row.extend(["-", "-", "-"].map(String::from)),
+                }
+            }
+            row.push(t.presence.note().to_string());
+            rows.push(row);
+        }
+        let mut widths = vec![0; rows[0].len()];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = cmp::max(*width, cell.len());
+            }
+        }
+        for row in rows {
+            // Names to the left, numbers to the right
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (cell, width))| {
+                    if i == 0 || i == row.len() - 1 {
+                        format!("{:<width$}", cell, width = width)
+                    } else {
+                        format!("{:>width$}", cell, width = width)
+                    }
+                })
+                .collect();
+            out.push_str(cells.join("  ").trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Save the final reports and compare them to the baseline, if either was requested.
+/// Returns an error if a baseline threshold or error budget was exceeded so the run
+/// exits non-zero. If the walk itself failed, its reports are still saved, marked as
+/// not completed, and its error is returned without comparing.
+pub fn finish_walk(
+    logger: &Logger,
+    options: &FinalReportOptions,
+    progress_states: &[ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>>],
+    walked: Result<(), Error>,
+) -> Result<(), Error> {
+    let completed = walked.is_ok();
+    let reports: Vec<FinalReport> = progress_states
+        .iter()
+        .map(|s| FinalReport {
+            completed,
+            ..s.final_report()
+        })
+        .collect();
+
+    if let Err(e) = walked {
+        if let Some(summary_file) = &options.summary_file {
+            // The walk's error is the one to exit with
+            if let Err(save_error) = save_reports(summary_file, &reports) {
+                warn!(logger, "{:?}", save_error);
+            }
+        }
+        return Err(e);
+    }
+
+    if let Some(summary_file) = &options.summary_file {
+        save_reports(summary_file, &reports)?;
+    }
+
+    let baseline = options
+        .baseline_file
+        .as_ref()
+        .and_then(|path| load_baseline(logger, path));
+    let mut exceeded = false;
+    if let Some(baseline) = baseline {
+        for report in &reports {
+            match baseline.iter().find(|b| b.repo == report.repo) {
+                Some(baseline_report) => {
+                    let comparison =
+                        BaselineComparison::new(baseline_report, report, &options.thresholds);
+                    comparison.log(logger, &report.repo);
+                    exceeded |= comparison.is_exceeded();
+                }
+                None => warn!(logger, "No baseline for repo {}", report.repo),
+            }
+        }
+    }
+
+    let over_budget: Vec<String> = reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .budget_violations
+                .iter()
+                .map(move |v| format!("{} {}", report.repo, v.node_type))
+        })
+        .collect();
+
+    if exceeded {
+        Err(format_err!("Walk exceeded baseline thresholds"))
+    } else if !over_budget.is_empty() {
+        Err(format_err!(
+            "Walk exceeded error budgets for {}",
+            over_budget.join(", ")
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fbinit::FacebookInit;
+    use maplit::btreemap;
+    use maplit::hashmap;
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use slog::o;
+    use slog::Discard;
+
+    use super::*;
+    use crate::detail::graph::Node;
+    use crate::detail::graph::NodeType;
+    use crate::detail::progress::ProgressOptionsBuilder;
+    use crate::detail::progress::ProgressRecorder;
+    use crate::detail::progress::ProgressStateBuilder;
+
+    fn report(types: BTreeMap<String, TypeReport>) -> FinalReport {
+        FinalReport {
+            repo: "repo".to_string(),
+            subcommand: "scrub".to_string(),
+            elapsed_secs: 10.0,
+            types,
+            ..Default::default()
+        }
+    }
+
+    fn type_report(walked: u64, errors: u64) -> TypeReport {
+        TypeReport {
+            walked,
+            errors,
+            rate: walked as f64 / 10.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_baseline_deltas() {
+        let baseline = report(btreemap! {
+            "Changeset".to_string() => type_report(1000, 0),
+            "FileContent".to_string() => type_report(500, 2),
+        });
+        let current = report(btreemap! {
+            "Changeset".to_string() => type_report(1200, 1),
+            "HgChangeset".to_string() => type_report(100, 0),
+        });
+        let comparison = BaselineComparison::new(&baseline, &current, &Default::default());
+        assert_eq!(
+            vec![
+                TypeDelta {
+                    node_type: "Changeset".to_string(),
+                    walked: 200,
+                    rate: 20.0,
+                    errors: 1,
+                },
+                TypeDelta {
+                    node_type: "FileContent".to_string(),
+                    walked: -500,
+                    rate: -50.0,
+                    errors: -2,
+                },
+                TypeDelta {
+                    node_type: "HgChangeset".to_string(),
+                    walked: 100,
+                    rate: 10.0,
+                    errors: 0,
+                },
+            ],
+            comparison.deltas
+        );
+        // No thresholds configured, so never exceeded
+        assert!(!comparison.is_exceeded());
+    }
+
+    #[test]
+    fn test_baseline_thresholds() {
+        let baseline = report(btreemap! {
+            "Changeset".to_string() => type_report(1000, 0),
+        });
+        let thresholds = BaselineThresholds {
+            max_walked_drop_pct: Some(10.0),
+            max_rate_drop_pct: None,
+            max_errors_increase: Some(2),
+        };
+
+        // Within thresholds
+        let current = report(btreemap! {
+            "Changeset".to_string() => type_report(950, 2),
+        });
+        assert!(!BaselineComparison::new(&baseline, &current, &thresholds).is_exceeded());
+
+        // Walked dropped 20% and errors up by 3
+        let current = report(btreemap! {
+            "Changeset".to_string() => type_report(800, 3),
+        });
+        let comparison = BaselineComparison::new(&baseline, &current, &thresholds);
+        assert_eq!(2, comparison.exceeded.len());
+
+        // Rate threshold alone
+        let thresholds = BaselineThresholds {
+            max_rate_drop_pct: Some(50.0),
+            ..Default::default()
+        };
+        let mut current = report(btreemap! {
+            "Changeset".to_string() => type_report(1000, 0),
+        });
+        current.types.get_mut("Changeset").unwrap().rate = 40.0;
+        let comparison = BaselineComparison::new(&baseline, &current, &thresholds);
+        assert_eq!(
+            vec!["Changeset rate dropped 60.0% (max 50%)".to_string()],
+            comparison.exceeded
+        );
+    }
+
+    #[test]
+    fn test_bad_baseline_only_warns() {
+        let logger = Logger::root(Discard, o!());
+        let dir = std::env::temp_dir().join(format!("walker_baseline_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(load_baseline(&logger, &dir.join("missing.json")).is_none());
+
+        let bad = dir.join("bad.json");
+        fs::write(&bad, "not json").unwrap();
+        assert!(load_baseline(&logger, &bad).is_none());
+
+        let good = dir.join("good.json");
+        let reports = vec![report(btreemap! {
+            "Changeset".to_string() => type_report(1, 0),
+        })];
+        save_reports(&good, &reports).unwrap();
+        assert_eq!(Some(reports), load_baseline(&logger, &good));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[fbinit::test]
+    fn test_error_budgets_fail_walk(fb: FacebookInit) -> Result<(), Error> {
+        let logger = Logger::root(Discard, o!());
+        let state = |repo: &str| -> Result<_, Error> {
+            Ok(ProgressStateMutex::new(
+                ProgressStateBuilder::new(fb, logger.clone(), "scrub", repo.to_string())
+                    .with_included_types(hashset! {NodeType::PhaseMapping})
+                    .with_options(
+                        ProgressOptionsBuilder::default()
+                            .with_error_budgets(hashmap! {NodeType::PhaseMapping => 1})
+                            .build()?,
+                    )
+                    .build()?,
+            ))
+        };
+        let error = StepStats {
+            error_count: 1,
+            ..Default::default()
+        };
+        let within = state("within")?;
+        let over = state("over")?;
+        for i in 0..2 {
+            let node = Node::PhaseMapping(ChangesetId::from_byte_array([i; 32]));
+            over.record_step(&node, Some(&error));
+            if i == 0 {
+                within.record_step(&node, Some(&error));
+            }
+        }
+
+        // Only fails once a repo is over budget, with no summary or baseline needed
+        let options = FinalReportOptions::default();
+        finish_walk(&logger, &options, std::slice::from_ref(&within), Ok(()))?;
+        let err = finish_walk(&logger, &options, &[within, over], Ok(())).unwrap_err();
+        assert_eq!(
+            "Walk exceeded error budgets for over PhaseMapping",
+            err.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_reports() -> Result<(), Error> {
+        let sizes = |total_bytes| PayloadSizeReport {
+            total_bytes,
+            ..Default::default()
+        };
+        let mut before = report(btreemap! {
+            "Changeset".to_string() => type_report(1000, 0),
+            "FileContent".to_string() => type_report(500, 2),
+        });
+        before.payload_sizes = Some(sizes(1000));
+        before.payload_sizes_by_type = btreemap! {"FileContent".to_string() => sizes(800)};
+        let mut after = report(btreemap! {
+            "Changeset".to_string() => type_report(1200, 1),
+            "FileContent".to_string() => type_report(500, 0),
+            "HgChangeset".to_string() => type_report(100, 0),
+        });
+        after.elapsed_secs = 12.5;
+        after.payload_sizes = Some(sizes(1500));
+        after.payload_sizes_by_type = btreemap! {"FileContent".to_string() => sizes(1200)};
+
+        // Through saved files, as the subcommand reads them
+        let dir = std::env::temp_dir().join(format!("walker_diff_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        save_reports(&dir.join("before.json"), &[before])?;
+        save_reports(&dir.join("after.json"), &[after])?;
+        let diffs = diff_reports(
+            &load_reports(&dir.join("before.json"))?,
+            &load_reports(&dir.join("after.json"))?,
+        );
+        fs::remove_dir_all(&dir)?;
+
+        let expected = [
+            "Repo repo, elapsed 10.0s -> 12.5s (+2.5s, +25.0%), bytes 1000 -> 1500 (+500, +50.0%)",
+            "Type         Walked  Delta  Delta%  Errors  Delta   Delta%  Bytes  Delta  Delta%",
+            "Changeset      1200   +200  +20.0%       1     +1        -      -      -       -",
+            "FileContent     500     +0   +0.0%       0     -2  -100.0%   1200   +400  +50.0%",
+            "HgChangeset     100   +100       -       0     +0        -      -      -       -  only after",
+        ]
+        .map(|line| format!("{}\n", line))
+        .concat();
+        assert_eq!(expected, format_diffs(&diffs));
+
+        let json = serde_json::to_value(&diffs)?;
+        let added = &json[0]["types"][2];
+        assert_eq!("HgChangeset", added["node_type"]);
+        assert_eq!("after_only", added["presence"]);
+        assert_eq!(100.0, added["walked"]["delta"]);
+        assert!(added["walked"]["delta_pct"].is_null());
+        assert!(added["bytes"].is_null());
+        assert_eq!(-100.0, json[0]["types"][1]["errors"]["delta_pct"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_reports_atomic() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("walker_summary_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("summary.json");
+        let reports = |walked| {
+            vec![report(btreemap! {
+                "Changeset".to_string() => type_report(walked, 0),
+            })]
+        };
+
+        // Creates the missing directories, leaving nothing else behind
+        save_reports(&path, &reports(1))?;
+        assert_eq!(reports(1), load_reports(&path)?);
+        let names = |dir: &Path| -> Vec<_> {
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect()
+        };
+        assert_eq!(
+            vec![OsString::from("summary.json")],
+            names(path.parent().unwrap())
+        );
+
+        // A writer killed part way leaves only its temporary file, which neither the
+        // saved report nor the next save is affected by
+        let tmp_path = path.with_file_name(format!(".summary.json.tmp.{}", std::process::id()));
+        fs::write(&tmp_path, "[{\"repo\": ")?;
+        assert_eq!(reports(1), load_reports(&path)?);
+        save_reports(&path, &reports(2))?;
+        assert_eq!(reports(2), load_reports(&path)?);
+        assert!(!tmp_path.exists());
+
+        // Reports from before completed was recorded were of finished walks
+        let mut old: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        old[0].as_object_mut().unwrap().remove("completed");
+        fs::write(&path, old.to_string())?;
+        assert!(load_reports(&path)?[0].completed);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_failed_walk_saves_incomplete(fb: FacebookInit) -> Result<(), Error> {
+        let logger = Logger::root(Discard, o!());
+        let dir = std::env::temp_dir().join(format!("walker_failed_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let options = FinalReportOptions {
+            summary_file: Some(dir.join("summary.json")),
+            ..Default::default()
+        };
+        let state = ProgressStateMutex::new(
+            ProgressStateBuilder::new(fb, logger.clone(), "scrub", "repo".to_string())
+                .with_included_types(hashset! {NodeType::PhaseMapping})
+                .build()?,
+        );
+        let node = Node::PhaseMapping(ChangesetId::from_byte_array([1; 32]));
+        state.record_step(&node, Some(&StepStats::default()));
+        let states = std::slice::from_ref(&state);
+
+        // The walk's own error is returned, with what it got through saved
+        let err = finish_walk(
+            &logger,
+            &options,
+            states,
+            Err(format_err!("Blobstore unavailable")),
+        )
+        .unwrap_err();
+        assert_eq!("Blobstore unavailable", err.to_string());
+        let saved = load_reports(options.summary_file.as_ref().unwrap())?;
+        assert!(!saved[0].completed);
+        assert_eq!(1, saved[0].types["PhaseMapping"].walked);
+
+        // Even when the summary can't be saved
+        let blocked = FinalReportOptions {
+            summary_file: Some(dir.join("summary.json").join("summary.json")),
+            ..Default::default()
+        };
+        let err = finish_walk(&logger, &blocked, states, Err(format_err!("Aborted"))).unwrap_err();
+        assert_eq!("Aborted", err.to_string());
+
+        finish_walk(&logger, &options, states, Ok(()))?;
+        assert!(load_reports(options.summary_file.as_ref().unwrap())?[0].completed);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
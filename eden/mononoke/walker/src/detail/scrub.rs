@@ -41,6 +41,8 @@ use crate::commands::JobParams;
 use crate::commands::JobWalkParams;
 use crate::commands::RepoSubcommandParams;
 use crate::commands::SCRUB;
+use crate::detail::blobstore::read_counting_stream;
+use crate::detail::blobstore::ScrubRepairCounts;
 use crate::detail::graph::FileContentData;
 use crate::detail::graph::Node;
 use crate::detail::graph::NodeData;
@@ -54,10 +56,14 @@ use crate::detail::pack::PackInfoLogger;
 use crate::detail::progress::progress_stream;
 use crate::detail::progress::report_state;
 use crate::detail::progress::ProgressOptions;
+use crate::detail::progress::ProgressRecorder;
 use crate::detail::progress::ProgressReporter;
 use crate::detail::progress::ProgressReporterUnprotected;
+use crate::detail::progress::ProgressStateBuilder;
 use crate::detail::progress::ProgressStateCountByType;
 use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::QuietMode;
+use crate::detail::progress::StepProgress;
 use crate::detail::sampling::PathTrackingRoute;
 use crate::detail::sampling::SamplingOptions;
 use crate::detail::sampling::SamplingWalkVisitor;
@@ -65,6 +71,7 @@ use crate::detail::sampling::WalkKeyOptPath;
 use crate::detail::sampling::WalkPayloadMtime;
 use crate::detail::sampling::WalkSampleMapping;
 use crate::detail::sizing::SizingSample;
+use crate::detail::state::StepStats;
 use crate::detail::tail::walk_exact_tail;
 use crate::detail::validate::TOTAL;
 use crate::detail::walk::EmptyRoute;
@@ -84,6 +91,9 @@ pub struct ScrubStats {
     pub blobstore_keys: u64,
 }
 
+// Sizes only, errors and children are counted by the main walk progress
+impl StepProgress for ScrubStats {}
+
 impl From<Option<&ScrubSample>> for ScrubStats {
     fn from(sample: Option<&ScrubSample>) -> Self {
         sample
@@ -122,6 +132,29 @@ impl fmt::Display for ScrubStats {
     }
 }
 
+// Repairs are reported by blobstore key rather than by step, so they are attributed
+// to the next step to complete. Repairs while loading file contents land on a later step.
+fn repair_counting_stream<InStream, K, Payload>(
+    repair_counts: Arc<ScrubRepairCounts>,
+    s: InStream,
+) -> impl Stream<Item = Result<(K, Payload, Option<StepStats>), Error>>
+where
+    InStream: Stream<Item = Result<(K, Payload, Option<StepStats>), Error>> + 'static + Send,
+{
+    s.map_ok(move |(key, payload, stats_opt)| {
+        let stats_opt = stats_opt.map(|mut stats| {
+            let (repaired, unrepairable, bytes_written) = repair_counts.take();
+            stats.repaired_count += repaired;
+            stats.unrepairable_count += unrepairable;
+            // Each repair is a put of the blob to the store that needed it
+            stats.blobs_written += repaired;
+            stats.bytes_written += bytes_written;
+            stats
+        });
+        (key, payload, stats_opt)
+    })
+}
+
 // Force load of leaf data like file contents that graph traversal did not need
 fn loading_stream<InStream, SS, L>(
     limit_data_fetch: bool,
@@ -292,7 +325,7 @@ impl ProgressStateCountByType<ScrubStats, ScrubStats> {
                 *value as i64,
                 (
                     self.params.subcommand_stats_key,
-                    self.params.repo_stats_key.clone(),
+                    self.params.stats_key.clone(),
                     stat_key,
                     desc,
                 ),
@@ -308,6 +341,8 @@ impl ProgressStateCountByType<ScrubStats, ScrubStats> {
     }
 
     pub fn report_progress_log(&mut self, delta_time: Option<Duration>) {
+        let times = self.report_times(delta_time);
+        let total_time = times.total;
         let summary_by_type: HashMap<NodeType, ScrubStats> = self
             .work_stats
             .stats_by_type
@@ -332,20 +367,17 @@ impl ProgressStateCountByType<ScrubStats, ScrubStats> {
             .join(" ");
 
         let (delta_s, delta_summary_per_s) =
-            delta_time.map_or((0, ScrubStats::default()), |delta_time| {
-                (
-                    delta_time.as_secs(),
-                    delta_summary * 1000 / (delta_time.as_millis() as u64),
-                )
-            });
-
-        let total_time = self
-            .reporting_stats
-            .last_update
-            .duration_since(self.reporting_stats.start_time);
-
-        let total_summary_per_s = if total_time.as_millis() > 0 {
-            new_summary * 1000 / (total_time.as_millis() as u64)
+            times
+                .delta
+                .map_or((0, ScrubStats::default()), |delta_time| {
+                    (
+                        delta_time.as_secs(),
+                        delta_summary * 1000 / (delta_time.as_millis() as u64),
+                    )
+                });
+
+        let total_summary_per_s = if times.active.as_millis() > 0 {
+            new_summary * 1000 / (times.active.as_millis() as u64)
         } else {
             ScrubStats::default()
         };
@@ -369,19 +401,20 @@ impl ProgressStateCountByType<ScrubStats, ScrubStats> {
             delta_summary.blobstore_bytes as i64,
             (
                 self.params.subcommand_stats_key,
-                self.params.repo_stats_key.clone(),
+                self.params.stats_key.clone(),
             ),
         );
         STATS::walk_progress_keys.add_value(
             delta_summary.blobstore_keys as i64,
             (
                 self.params.subcommand_stats_key,
-                self.params.repo_stats_key.clone(),
+                self.params.stats_key.clone(),
             ),
         );
 
         self.reporting_stats.last_summary_by_type = summary_by_type;
         self.reporting_stats.last_summary = new_summary;
+        self.finish_report(&times);
 
         if delta_time.is_none() {
             self.report_completion_stats()
@@ -399,6 +432,10 @@ impl ProgressReporterUnprotected for ProgressStateCountByType<ScrubStats, ScrubS
             self.report_progress_log(Some(delta_time));
         }
     }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.params.quiet_mode()
+    }
 }
 
 #[derive(Clone)]
@@ -458,23 +495,45 @@ async fn run_one(
     command: ScrubCommand,
     cancellation_requested: Arc<AtomicBool>,
 ) -> Result<(), Error> {
-    let sizing_progress_state =
-        ProgressStateMutex::new(ProgressStateCountByType::<ScrubStats, ScrubStats>::new(
+    let sizing_progress_state = ProgressStateMutex::new(
+        ProgressStateBuilder::new(
             fb,
             repo_params.logger.clone(),
             SCRUB,
             repo_params.repo.repo_identity().name().to_string(),
-            command.sampling_options.node_types.clone(),
-            command.progress_options,
-        ));
+        )
+        .with_included_types(command.sampling_options.node_types.clone())
+        .with_options(command.progress_options.clone())
+        .with_walk_instance(repo_params.walk_instance.clone())
+        .build::<ScrubStats, ScrubStats>()?,
+    );
 
     let make_sink = {
-        cloned!(command, job_params.quiet, sub_params.progress_state,);
+        cloned!(
+            command,
+            job_params.scrub_repair_counts,
+            job_params.blobstore_read_counts,
+            sub_params.progress_state,
+        );
         move |ctx: &CoreContext, repo_params: &RepoWalkParams| {
             let repo_name = repo_params.repo.repo_identity().name().to_string();
             cloned!(ctx, repo_params.scheduled_max);
-            async move |walk_output, run_start, chunk_num, checkpoint_name| {
-                let walk_progress = progress_stream(quiet, &progress_state, walk_output);
+            async move |walk_output,
+                        run_start,
+                        chunk_num,
+                        checkpoint_name,
+                        chunk_bounds,
+                        iteration| {
+                if let Some(iteration) = iteration {
+                    progress_state.start_iteration(iteration);
+                }
+                if let Some(chunk_bounds) = chunk_bounds {
+                    progress_state.set_position(chunk_bounds.clone());
+                    progress_state.start_chunk(chunk_num, chunk_bounds);
+                }
+                let walk_output = read_counting_stream(blobstore_read_counts, walk_output);
+                let walk_output = repair_counting_stream(scrub_repair_counts, walk_output);
+                let walk_progress = progress_stream(&progress_state, walk_output);
                 let loading = loading_stream(
                     command.limit_data_fetch,
                     scheduled_max,
@@ -486,9 +545,12 @@ async fn run_one(
                         .pack_info_log_options
                         .map(|o| o.make_logger(repo_name, run_start, chunk_num, checkpoint_name)),
                 );
-                let report_sizing = progress_stream(quiet, &sizing_progress_state, loading);
+                let report_sizing = progress_stream(&sizing_progress_state, loading);
 
-                report_state(ctx, report_sizing).await?;
+                // Send the rows of the reports so far even if the walk fails
+                report_state(ctx, progress_state.quiet_mode(), report_sizing)
+                    .await
+                    .inspect_err(|_| progress_state.flush())?;
                 sizing_progress_state.report_progress();
                 progress_state.report_progress();
                 Ok(())
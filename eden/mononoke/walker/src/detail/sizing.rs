@@ -39,11 +39,13 @@ use mononoke_types::BlobstoreBytes;
 use repo_identity::RepoIdentityRef;
 use samplingblob::SamplingHandler;
 use slog::info;
+use stats::prelude::*;
 
 use crate::commands::JobParams;
 use crate::commands::JobWalkParams;
 use crate::commands::RepoSubcommandParams;
 use crate::commands::COMPRESSION_BENEFIT;
+use crate::detail::blobstore::read_counting_stream;
 use crate::detail::graph::FileContentData;
 use crate::detail::graph::Node;
 use crate::detail::graph::NodeData;
@@ -52,10 +54,14 @@ use crate::detail::graph::WrappedPath;
 use crate::detail::progress::progress_stream;
 use crate::detail::progress::report_state;
 use crate::detail::progress::ProgressOptions;
+use crate::detail::progress::ProgressRecorder;
 use crate::detail::progress::ProgressReporter;
 use crate::detail::progress::ProgressReporterUnprotected;
+use crate::detail::progress::ProgressStateBuilder;
 use crate::detail::progress::ProgressStateCountByType;
 use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::QuietMode;
+use crate::detail::progress::StepProgress;
 use crate::detail::sampling::PathTrackingRoute;
 use crate::detail::sampling::SamplingOptions;
 use crate::detail::sampling::SamplingWalkVisitor;
@@ -66,35 +72,56 @@ use crate::detail::tail::walk_exact_tail;
 use crate::detail::walk::RepoWalkParams;
 use crate::detail::walk::RepoWalkTypeParams;
 
-#[derive(Add, Div, Mul, Sub, Clone, Copy, Default, Debug)]
-struct SizingStats {
-    raw: u64,
-    compressed: u64,
+define_stats! {
+    prefix = "mononoke.walker";
+    walk_progress_raw_bytes: dynamic_timeseries("{}.progress.{}.raw_bytes", (subcommand: &'static str, repo: String); Rate, Sum),
+    walk_progress_compressed_bytes: dynamic_timeseries("{}.progress.{}.compressed_bytes", (subcommand: &'static str, repo: String); Rate, Sum),
 }
 
-impl SizingStats {
+/// Bytes before and after compression, per step and as summed for reporting
+#[derive(Add, Div, Mul, Sub, Clone, Copy, Default, Debug, PartialEq)]
+pub struct SizingProgressSummary {
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl SizingProgressSummary {
     fn compression_benefit_pct(&self) -> u64 {
-        if self.raw == 0 {
-            0
+        (100 * self.raw_bytes.saturating_sub(self.compressed_bytes))
+            .checked_div(self.raw_bytes)
+            .unwrap_or(0)
+    }
+
+    /// Raw bytes per compressed byte. 1.0 if nothing has been compressed yet.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
         } else {
-            100 * (self.raw - self.compressed) / self.raw
+            self.raw_bytes as f64 / self.compressed_bytes as f64
         }
     }
 }
 
-impl fmt::Display for SizingStats {
+// Sizes only, errors and children are counted by the main walk progress
+impl StepProgress for SizingProgressSummary {}
+
+impl fmt::Display for SizingProgressSummary {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         write!(
             fmt,
-            "{},{},{}%",
-            self.raw,
-            self.compressed,
-            self.compression_benefit_pct()
+            "{},{},{}%,{:.2}",
+            self.raw_bytes,
+            self.compressed_bytes,
+            self.compression_benefit_pct(),
+            self.compression_ratio(),
         )
     }
 }
 
-fn try_compress(raw_data: &Bytes, compressor_type: CompressorType) -> Result<SizingStats, Error> {
+fn try_compress(
+    raw_data: &Bytes,
+    compressor_type: CompressorType,
+) -> Result<SizingProgressSummary, Error> {
     let raw = raw_data.len() as u64;
     let compressed_buf = MeteredWrite::new(Cursor::new(Vec::with_capacity(4 * 1024)));
     let mut compressor = Compressor::new(compressed_buf, compressor_type);
@@ -102,7 +129,10 @@ fn try_compress(raw_data: &Bytes, compressor_type: CompressorType) -> Result<Siz
     let compressed_buf = compressor.try_finish().map_err(|(_encoder, e)| e)?;
     // Assume we wouldn't compress if its bigger
     let compressed = min(raw, compressed_buf.total_thru());
-    Ok(SizingStats { raw, compressed })
+    Ok(SizingProgressSummary {
+        raw_bytes: raw,
+        compressed_bytes: compressed,
+    })
 }
 
 // Force load of leaf data and check compression ratio
@@ -111,7 +141,7 @@ fn size_sampling_stream<InStream, InStats>(
     s: InStream,
     compressor_type: CompressorType,
     sampler: Arc<WalkSampleMapping<Node, SizingSample>>,
-) -> impl Stream<Item = Result<(Node, Option<NodeData>, Option<SizingStats>), Error>>
+) -> impl Stream<Item = Result<(Node, Option<NodeData>, Option<SizingProgressSummary>), Error>>
 where
     InStream: Stream<
             Item = Result<
@@ -149,7 +179,7 @@ where
                             .complete_step(&walk_key.node)
                             .map(|sizing_sample| {
                                 sizing_sample.data.values().try_fold(
-                                    SizingStats::default(),
+                                    SizingProgressSummary::default(),
                                     |acc, v| {
                                         try_compress(v.as_bytes(), compressor_type)
                                             .map(|sizes| acc + sizes)
@@ -177,12 +207,12 @@ where
                 let sizes = sampler
                     .complete_step(&walk_key.node)
                     .map(|sizing_sample| {
-                        sizing_sample
-                            .data
-                            .values()
-                            .try_fold(SizingStats::default(), |acc, v| {
+                        sizing_sample.data.values().try_fold(
+                            SizingProgressSummary::default(),
+                            |acc, v| {
                                 try_compress(v.as_bytes(), compressor_type).map(|sizes| acc + sizes)
-                            })
+                            },
+                        )
                     })
                     .transpose();
 
@@ -193,9 +223,29 @@ where
     .try_buffer_unordered(scheduled_max)
 }
 
-impl ProgressStateCountByType<SizingStats, SizingStats> {
+// Per type totals so far, followed by the change since the last report
+fn type_detail(
+    types_sorted_by_name: &[NodeType],
+    summary_by_type: &HashMap<NodeType, SizingProgressSummary>,
+    last_summary_by_type: &HashMap<NodeType, SizingProgressSummary>,
+) -> String {
+    let def = SizingProgressSummary::default();
+    types_sorted_by_name
+        .iter()
+        .map(|t| {
+            let s = summary_by_type.get(t).unwrap_or(&def);
+            let d = *s - *last_summary_by_type.get(t).unwrap_or(&def);
+            format!("{}:{},{},{}", t, s, d.raw_bytes, d.compressed_bytes)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ProgressStateCountByType<SizingProgressSummary, SizingProgressSummary> {
     pub fn report_progress_log(&mut self, delta_time: Option<Duration>) {
-        let summary_by_type: HashMap<NodeType, SizingStats> = self
+        let times = self.report_times(delta_time);
+        let total_time = times.total;
+        let summary_by_type: HashMap<NodeType, SizingProgressSummary> = self
             .work_stats
             .stats_by_type
             .iter()
@@ -203,60 +253,69 @@ impl ProgressStateCountByType<SizingStats, SizingStats> {
             .collect();
         let new_summary = summary_by_type
             .values()
-            .fold(SizingStats::default(), |acc, v| acc + *v);
+            .fold(SizingProgressSummary::default(), |acc, v| acc + *v);
         let delta_summary = new_summary - self.reporting_stats.last_summary;
 
-        let def = SizingStats::default();
-        let detail = &self
-            .params
-            .types_sorted_by_name
-            .iter()
-            .map(|t| {
-                let s = summary_by_type.get(t).unwrap_or(&def);
-                format!("{}:{}", t, s)
-            })
-            .collect::<Vec<_>>()
-            .join(" ");
+        let detail = type_detail(
+            &self.params.types_sorted_by_name,
+            &summary_by_type,
+            &self.reporting_stats.last_summary_by_type,
+        );
 
         let (delta_s, delta_summary_per_s) =
-            delta_time.map_or((0, SizingStats::default()), |delta_time| {
-                (
-                    delta_time.as_secs(),
-                    delta_summary * 1000 / (delta_time.as_millis() as u64),
-                )
-            });
-
-        let total_time = self
-            .reporting_stats
-            .last_update
-            .duration_since(self.reporting_stats.start_time);
-
-        let total_summary_per_s = if total_time.as_millis() > 0 {
-            new_summary * 1000 / (total_time.as_millis() as u64)
+            times
+                .delta
+                .map_or((0, SizingProgressSummary::default()), |delta_time| {
+                    (
+                        delta_time.as_secs(),
+                        delta_summary * 1000 / (delta_time.as_millis().max(1) as u64),
+                    )
+                });
+
+        let total_summary_per_s = if times.active.as_millis() > 0 {
+            new_summary * 1000 / (times.active.as_millis() as u64)
         } else {
-            SizingStats::default()
+            SizingProgressSummary::default()
         };
 
         info!(
             self.params.logger,
-            "Raw/s,Compressed/s,Raw,Compressed,%Saving; Delta {:06}/s,{:06}/s,{},{}s; Run {:06}/s,{:06}/s,{},{}s; Type:Raw,Compressed,%Saving {}",
-            delta_summary_per_s.raw,
-            delta_summary_per_s.compressed,
+            "Raw/s,Compressed/s,Raw,Compressed,%Saving,Ratio; Delta {:06}/s,{:06}/s,{},{}s; Run {:06}/s,{:06}/s,{},{}s; Type:Raw,Compressed,%Saving,Ratio,DeltaRaw,DeltaCompressed {}",
+            delta_summary_per_s.raw_bytes,
+            delta_summary_per_s.compressed_bytes,
             delta_summary,
             delta_s,
-            total_summary_per_s.raw,
-            total_summary_per_s.compressed,
+            total_summary_per_s.raw_bytes,
+            total_summary_per_s.compressed_bytes,
             new_summary,
             total_time.as_secs(),
             detail,
         );
 
+        STATS::walk_progress_raw_bytes.add_value(
+            delta_summary.raw_bytes as i64,
+            (
+                self.params.subcommand_stats_key,
+                self.params.stats_key.clone(),
+            ),
+        );
+        STATS::walk_progress_compressed_bytes.add_value(
+            delta_summary.compressed_bytes as i64,
+            (
+                self.params.subcommand_stats_key,
+                self.params.stats_key.clone(),
+            ),
+        );
+
         self.reporting_stats.last_summary_by_type = summary_by_type;
         self.reporting_stats.last_summary = new_summary;
+        self.finish_report(&times);
     }
 }
 
-impl ProgressReporterUnprotected for ProgressStateCountByType<SizingStats, SizingStats> {
+impl ProgressReporterUnprotected
+    for ProgressStateCountByType<SizingProgressSummary, SizingProgressSummary>
+{
     fn report_progress(&mut self) {
         self.report_progress_log(None);
     }
@@ -266,6 +325,10 @@ impl ProgressReporterUnprotected for ProgressStateCountByType<SizingStats, Sizin
             self.report_progress_log(Some(delta_time));
         }
     }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.params.quiet_mode()
+    }
 }
 
 #[derive(Debug)]
@@ -351,24 +414,44 @@ async fn run_one(
     command: SizingCommand,
     cancellation_requested: Arc<AtomicBool>,
 ) -> Result<(), Error> {
-    let sizing_progress_state =
-        ProgressStateMutex::new(ProgressStateCountByType::<SizingStats, SizingStats>::new(
+    let sizing_progress_state = ProgressStateMutex::new(
+        ProgressStateBuilder::new(
             fb,
             repo_params.logger.clone(),
             COMPRESSION_BENEFIT,
             repo_params.repo.repo_identity().name().to_string(),
-            command.sampling_options.node_types.clone(),
-            command.progress_options,
-        ));
+        )
+        .with_included_types(command.sampling_options.node_types.clone())
+        .with_options(command.progress_options.clone())
+        .with_walk_instance(repo_params.walk_instance.clone())
+        .build::<SizingProgressSummary, SizingProgressSummary>()?,
+    );
 
     let make_sink = {
-        cloned!(command, job_params.quiet, sub_params.progress_state,);
+        cloned!(
+            command,
+            job_params.blobstore_read_counts,
+            sub_params.progress_state,
+        );
         move |ctx: &CoreContext, repo_params: &RepoWalkParams| {
             cloned!(ctx, repo_params.scheduled_max);
-            async move |walk_output, _run_start, _chunk_num, _checkpoint_name| {
-                cloned!(ctx, sizing_progress_state);
+            async move |walk_output,
+                        _run_start,
+                        chunk_num,
+                        _checkpoint_name,
+                        chunk_bounds,
+                        iteration| {
+                cloned!(ctx, blobstore_read_counts, sizing_progress_state);
+                if let Some(iteration) = iteration {
+                    progress_state.start_iteration(iteration);
+                }
+                if let Some(chunk_bounds) = chunk_bounds {
+                    progress_state.set_position(chunk_bounds.clone());
+                    progress_state.start_chunk(chunk_num, chunk_bounds);
+                }
+                let walk_output = read_counting_stream(blobstore_read_counts, walk_output);
                 // Sizing doesn't use mtime, so remove it from payload
-                let walk_progress = progress_stream(quiet, &progress_state, walk_output).map_ok(
+                let walk_progress = progress_stream(&progress_state, walk_output).map_ok(
                     |(key, payload, stats): (_, WalkPayloadMtime, _)| (key, payload.data, stats),
                 );
 
@@ -380,9 +463,12 @@ async fn run_one(
                     },
                     command.sampler,
                 );
-                let report_sizing = progress_stream(quiet, &sizing_progress_state, compressor);
+                let report_sizing = progress_stream(&sizing_progress_state, compressor);
 
-                report_state(ctx, report_sizing).await?;
+                // Send the rows of the reports so far even if the walk fails
+                report_state(ctx, progress_state.quiet_mode(), report_sizing)
+                    .await
+                    .inspect_err(|_| progress_state.flush())?;
                 sizing_progress_state.report_progress();
                 progress_state.report_progress();
                 Ok(())
@@ -422,3 +508,154 @@ async fn run_one(
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use maplit::hashset;
+    use mononoke_types::ChangesetId;
+    use mononoke_types::ContentId;
+    use slog::o;
+    use slog::Logger;
+
+    use super::*;
+    use crate::detail::progress::NumberFormat;
+    use crate::detail::progress::PayloadSizeOptions;
+    use crate::detail::progress::ProgressDisplay;
+    use crate::detail::progress::ProgressRecorderUnprotected;
+    use crate::detail::progress::TypeGrouping;
+    use crate::detail::progress::UnchangedProgress;
+
+    fn sizes(raw_bytes: u64, compressed_bytes: u64) -> SizingProgressSummary {
+        SizingProgressSummary {
+            raw_bytes,
+            compressed_bytes,
+        }
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let s = sizes(1000, 250);
+        assert_eq!(75, s.compression_benefit_pct());
+        assert_eq!(4.0, s.compression_ratio());
+        assert_eq!("1000,250,75%,4.00", s.to_string());
+
+        // Incompressible
+        let s = sizes(1000, 1000);
+        assert_eq!(0, s.compression_benefit_pct());
+        assert_eq!(1.0, s.compression_ratio());
+    }
+
+    #[test]
+    fn test_compression_ratio_zero_bytes() {
+        // Nothing sized yet, e.g. only empty files or no steps
+        let s = SizingProgressSummary::default();
+        assert_eq!(0, s.compression_benefit_pct());
+        assert_eq!(1.0, s.compression_ratio());
+        assert_eq!("0,0,0%,1.00", s.to_string());
+
+        let s = sizes(1000, 0);
+        assert_eq!(100, s.compression_benefit_pct());
+        assert_eq!(1.0, s.compression_ratio());
+
+        // Never underflows, even if compressed is somehow bigger
+        let s = sizes(0, 10);
+        assert_eq!(0, s.compression_benefit_pct());
+        assert_eq!(0.0, s.compression_ratio());
+        let s = sizes(10, 20);
+        assert_eq!(0, s.compression_benefit_pct());
+        assert_eq!(0.5, s.compression_ratio());
+    }
+
+    #[fbinit::test]
+    fn test_sizing_progress_by_type(fb: FacebookInit) {
+        let mut state = ProgressStateBuilder::new(
+            fb,
+            Logger::root(slog::Discard, o!()),
+            COMPRESSION_BENEFIT,
+            "repo".to_string(),
+        )
+        .with_included_types(hashset! {NodeType::FileContent, NodeType::PhaseMapping})
+        .with_options(ProgressOptions {
+            sample_rate: 1,
+            interval: Duration::from_secs(1),
+            display: ProgressDisplay::Log,
+            quiet: QuietMode::Full,
+            type_rates: false,
+            type_emit_every_nth: 1,
+            type_grouping: TypeGrouping::Types,
+            stuck_step_threshold: Duration::from_secs(60),
+            unchanged: UnchangedProgress::Log,
+            error_budgets: HashMap::new(),
+            sample_node_every_nth: 0,
+            number_format: NumberFormat::Raw,
+            max_lines_per_hour: None,
+            idle_type_threshold: None,
+            worker_skew_threshold: None,
+            wall_clock_aligned: false,
+            slowdown: None,
+            payload_sizes: PayloadSizeOptions::default(),
+            jsonl: None,
+            types_of_interest: HashSet::new(),
+            interest_interval: Duration::from_secs(5),
+        })
+        .build::<SizingProgressSummary, SizingProgressSummary>()
+        .unwrap();
+        let content = |i| Node::FileContent(ContentId::from_byte_array([i; 32]));
+
+        state.record_step(&content(1), Some(&sizes(1000, 100)));
+        state.record_step(&content(2), Some(&sizes(0, 0)));
+        state.record_step(
+            &Node::PhaseMapping(ChangesetId::from_byte_array([1; 32])),
+            None,
+        );
+        state.report_progress_log(Some(Duration::from_secs(1)));
+        assert_eq!(sizes(1000, 100), state.reporting_stats.last_summary);
+
+        state.record_step(&content(3), Some(&sizes(1000, 900)));
+        let summary_by_type = HashMap::from([(NodeType::FileContent, sizes(2000, 1000))]);
+        assert_eq!(
+            "FileContent:2000,1000,50%,2.00,1000,900 PhaseMapping:0,0,0%,1.00,0,0",
+            type_detail(
+                &state.params.types_sorted_by_name,
+                &summary_by_type,
+                &state.reporting_stats.last_summary_by_type,
+            )
+        );
+        state.report_progress();
+        assert_eq!(sizes(2000, 1000), state.reporting_stats.last_summary);
+        assert_eq!(
+            Some(&sizes(2000, 1000)),
+            state
+                .reporting_stats
+                .last_summary_by_type
+                .get(&NodeType::FileContent)
+        );
+    }
+
+    #[fbinit::test]
+    fn test_sizing_final_report_time(fb: FacebookInit) {
+        let mut state = ProgressStateBuilder::new(
+            fb,
+            Logger::root(slog::Discard, o!()),
+            COMPRESSION_BENEFIT,
+            "repo".to_string(),
+        )
+        .with_included_types(hashset! {NodeType::FileContent})
+        .build::<SizingProgressSummary, SizingProgressSummary>()
+        .unwrap();
+        // As if the run started 20s ago, with the last throttled report 10s in
+        let start = Instant::now() - Duration::from_secs(20);
+        state.reporting_stats.start_time = start;
+        state.reporting_stats.last_update = start + Duration::from_secs(10);
+
+        let times = state.report_times(None);
+        assert!(times.total >= Duration::from_secs(20));
+        assert!(times.delta.unwrap() >= Duration::from_secs(10));
+
+        // The final report runs to now, not to the last throttled report
+        state.report_progress();
+        assert!(state.reporting_stats.last_update - start >= Duration::from_secs(20));
+    }
+}
@@ -43,6 +43,8 @@ use mononoke_types::ManifestUnodeId;
 use mononoke_types::SkeletonManifestId;
 use phases::Phase;
 use phases::Phases;
+use serde::Deserialize;
+use serde::Serialize;
 use slog::info;
 use slog::Logger;
 use strum::EnumCount;
@@ -51,6 +53,8 @@ use strum::EnumString;
 use strum::EnumVariantNames;
 
 use crate::detail::graph::EdgeType;
+use crate::detail::graph::ErrorCategory;
+use crate::detail::graph::FileContentData;
 use crate::detail::graph::Node;
 use crate::detail::graph::NodeData;
 use crate::detail::graph::NodeType;
@@ -66,13 +70,31 @@ use crate::detail::walk::TailingWalkVisitor;
 use crate::detail::walk::VisitOne;
 use crate::detail::walk::WalkVisitor;
 
-#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct StepStats {
     pub error_count: usize,
+    // Breakdown of error_count
+    pub corrupt_error_count: usize,
+    pub transient_error_count: usize,
+    pub other_error_count: usize,
+    // Blobs fixed or found unrecoverable by scrub while loading this step
+    pub repaired_count: usize,
+    pub unrepairable_count: usize,
+    // Blobs written back to the blobstore while loading this step, e.g. by scrub repairs
+    pub blobs_written: usize,
+    pub bytes_written: u64,
+    // Failed attempts at the step before it succeeded or gave up, see --step-retries
+    pub retries: usize,
     pub missing_count: usize,
     pub hash_validation_failure_count: usize,
     pub num_expanded_new: usize,
     pub visited_of_type: usize,
+    // Reads made while loading this step, by blobstore id
+    pub blobstore_reads: BlobstoreReads,
+    // Only set for changeset steps, and only where the walk knows the phase
+    pub phase: Option<StepPhase>,
+    // Size of the data the step is about, where the walk knows it
+    pub payload_bytes: Option<u64>,
 }
 
 impl Add<StepStats> for StepStats {
@@ -80,15 +102,90 @@ impl Add<StepStats> for StepStats {
     fn add(self, other: Self) -> Self {
         Self {
             error_count: self.error_count + other.error_count,
+            corrupt_error_count: self.corrupt_error_count + other.corrupt_error_count,
+            transient_error_count: self.transient_error_count + other.transient_error_count,
+            other_error_count: self.other_error_count + other.other_error_count,
+            repaired_count: self.repaired_count + other.repaired_count,
+            unrepairable_count: self.unrepairable_count + other.unrepairable_count,
+            blobs_written: self.blobs_written + other.blobs_written,
+            bytes_written: self.bytes_written + other.bytes_written,
+            retries: self.retries + other.retries,
             missing_count: self.missing_count + other.missing_count,
             hash_validation_failure_count: self.hash_validation_failure_count
                 + other.hash_validation_failure_count,
             num_expanded_new: self.num_expanded_new + other.num_expanded_new,
             visited_of_type: cmp::max(self.visited_of_type, other.visited_of_type),
+            blobstore_reads: self.blobstore_reads + other.blobstore_reads,
+            phase: self.phase.or(other.phase),
+            payload_bytes: match (self.payload_bytes, other.payload_bytes) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            },
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StepPhase {
+    Public,
+    Draft,
+}
+
+const MAX_BLOBSTORE_READ_IDS: usize = 8;
+
+/// Read counts by blobstore id. Fixed size so that StepStats stays Copy,
+/// reads from any further stores are lumped into `other`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlobstoreReads {
+    ids: [(u64, u64); MAX_BLOBSTORE_READ_IDS],
+    len: usize,
+    pub other: u64,
+}
+
+impl BlobstoreReads {
+    pub fn record(&mut self, id: u64, reads: u64) {
+        if let Some(entry) = self.ids[..self.len].iter_mut().find(|(i, _)| *i == id) {
+            entry.1 += reads;
+        } else if self.len < MAX_BLOBSTORE_READ_IDS {
+            self.ids[self.len] = (id, reads);
+            self.len += 1;
+        } else {
+            self.other += reads;
+        }
+    }
+
+    /// (blobstore id, reads) in the order ids were first seen
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.ids[..self.len].iter().copied()
+    }
+
+    pub fn get(&self, id: u64) -> u64 {
+        self.iter()
+            .find_map(|(i, reads)| (i == id).then_some(reads))
+            .unwrap_or(0)
+    }
+
+    /// Number of distinct blobstore ids seen
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.other == 0
+    }
+}
+
+impl Add<BlobstoreReads> for BlobstoreReads {
+    type Output = Self;
+    fn add(mut self, other: Self) -> Self {
+        for (id, reads) in other.iter() {
+            self.record(id, reads);
+        }
+        self.other += other.other;
+        self
+    }
+}
+
 // So we could change the type later without too much code churn
 type InternId = u32;
 
@@ -414,6 +511,17 @@ impl WalkState {
         self.visit_count[*t as usize].load(Ordering::Acquire)
     }
 
+    // Only public is ever known here, from an earlier phase lookup
+    fn known_phase(&self, bcs_id: &ChangesetId) -> Option<StepPhase> {
+        if let Some(id) = self.bcs_ids.get(bcs_id) {
+            if self.visited_bcs_phase.contains_key(&id) || self.public_not_visited.contains_key(&id)
+            {
+                return Some(StepPhase::Public);
+            }
+        }
+        None
+    }
+
     fn chunk_contains(&self, id: InternedId<ChangesetId>) -> bool {
         if self.chunk_bcs.is_empty() {
             true
@@ -995,15 +1103,27 @@ impl WalkVisitor<(Node, Option<NodeData>, Option<StepStats>), EmptyRoute> for Wa
         let node = resolved.target;
 
         let mut stats = StepStats {
-            error_count: 0,
-            missing_count: 0,
-            hash_validation_failure_count: 0,
             num_expanded_new,
             visited_of_type: self.get_visit_count(&node.get_type()),
+            ..Default::default()
+        };
+        if let Node::Changeset(k) = &node {
+            stats.phase = self.known_phase(&k.inner);
+        }
+        // File content is usually still a stream here, so its size comes from the metadata
+        stats.payload_bytes = match &node_data {
+            Some(NodeData::FileContentMetadataV2(Some(metadata))) => Some(metadata.total_size),
+            Some(NodeData::FileContent(FileContentData::Consumed(size))) => Some(*size as u64),
+            _ => None,
         };
         let node_data = match node_data {
-            Some(NodeData::ErrorAsData(_key)) => {
+            Some(NodeData::ErrorAsData(_key, category)) => {
                 stats.error_count += 1;
+                match category {
+                    ErrorCategory::Corrupt => stats.corrupt_error_count += 1,
+                    ErrorCategory::Transient => stats.transient_error_count += 1,
+                    ErrorCategory::Other => stats.other_error_count += 1,
+                }
                 None
             }
             Some(NodeData::MissingAsData(_key)) => {
@@ -213,7 +213,14 @@ pub async fn walk_exact_tail<RunFac, SinkFac, SinkOut, V, VOut, Route>(
 where
     RunFac: 'static + Clone + Send + Sync + FnOnce(&CoreContext, &RepoWalkParams) -> SinkFac,
     SinkFac: 'static
-        + FnOnce(BoxStream<'static, Result<VOut, Error>>, Timestamp, u64, Option<String>) -> SinkOut
+        + FnOnce(
+            BoxStream<'static, Result<VOut, Error>>,
+            Timestamp,
+            u64,
+            Option<String>,
+            Option<String>,
+            Option<u64>,
+        ) -> SinkOut
         + Clone
         + Send,
     SinkOut: Future<Output = Result<(), Error>> + 'static + Send,
@@ -230,10 +237,13 @@ where
         n == Some(MappedHgChangesetId::NAME) || n == Some(FilenodesOnlyPublic::NAME)
     });
 
+    let mut iteration: u64 = 0;
+
     // At every iteration, check if cancellation is requested by the caller.
     while !cancellation_requested.load(Ordering::Relaxed) {
         cloned!(job_params, tail_params, type_params, make_run);
         let tail_secs = tail_params.tail_secs;
+        iteration += 1;
         // Each loop get new ctx and thus session id so we can distinguish runs
         let ctx = CoreContext::new_with_logger(fb, repo_params.logger.clone());
         let session_text = ctx.session().metadata().session_id().to_string();
@@ -244,6 +254,9 @@ where
             )
         }
         repo_params.scuba_builder.add("session", session_text);
+        if tail_secs.is_some() {
+            repo_params.scuba_builder.add("iteration", iteration);
+        }
 
         let mut checkpoint = if let Some(checkpoints) = tail_params
             .chunking
@@ -453,7 +466,14 @@ where
             last_chunk_low.replace(chunk_low);
             last_chunk_upper.replace(chunk_upper);
 
+            let chunk_bounds = is_chunking.then(|| format!("({}, {})", chunk_low, chunk_upper));
+
             cloned!(mut repo_params);
+            if let Some(chunk_bounds) = chunk_bounds.as_ref() {
+                repo_params
+                    .scuba_builder
+                    .add("position", chunk_bounds.as_str());
+            }
             let hg_mapping_prepop = if with_hg && is_chunking {
                 // bulk prepopulate the hg/bonsai mappings
                 let ids =
@@ -484,7 +504,15 @@ where
                 .as_ref()
                 .and_then(|chunking| chunking.checkpoints.as_ref())
                 .map(|v| v.name().to_string());
-            make_sink(walk_output, run_start, chunk_num, cp_name).await?;
+            make_sink(
+                walk_output,
+                run_start,
+                chunk_num,
+                cp_name,
+                chunk_bounds,
+                tail_secs.map(|_| iteration),
+            )
+            .await?;
             visitor = Arc::try_unwrap(arc_v).map_err(|_| anyhow!("could not unwrap visitor"))?;
 
             if let Some(chunking) = tail_params.chunking.as_ref() {
@@ -556,7 +584,10 @@ where
             Some(interval) => {
                 let start = Instant::now();
                 let next_iter_deadline = start + Duration::from_secs(interval);
+                // Waiting for the next iteration isn't walking, so leave it out of rates
+                repo_params.progress_recorder.pause();
                 tokio::time::sleep_until(next_iter_deadline).await;
+                repo_params.progress_recorder.resume();
                 let age_secs = state_start.since_seconds();
                 if age_secs >= 0 && Duration::from_secs(age_secs as u64) > tail_params.state_max_age
                 {
@@ -16,9 +16,13 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
 use std::hash::Hash;
+use std::ops::Add;
+use std::ops::Div;
+use std::ops::Mul;
+use std::ops::Sub;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::Duration;
 
 use anyhow::Error;
 use async_trait::async_trait;
@@ -27,7 +31,11 @@ use bonsai_hg_mapping::BonsaiHgMappingEntry;
 use bulkops::Direction;
 use cloned::cloned;
 use context::CoreContext;
+use derive_more::Add;
 use derive_more::AddAssign;
+use derive_more::Div;
+use derive_more::Mul;
+use derive_more::Sub;
 use fbinit::FacebookInit;
 use futures::future::try_join_all;
 use futures::stream::TryStreamExt;
@@ -44,10 +52,14 @@ use slog::info;
 use slog::warn;
 use slog::Logger;
 use stats::prelude::*;
+use strum::EnumCount;
+use strum::IntoEnumIterator;
 
 use crate::commands::JobParams;
 use crate::commands::JobWalkParams;
 use crate::commands::RepoSubcommandParams;
+use crate::commands::VALIDATE;
+use crate::detail::blobstore::read_counting_stream;
 use crate::detail::graph::EdgeType;
 use crate::detail::graph::Node;
 use crate::detail::graph::NodeData;
@@ -59,10 +71,13 @@ use crate::detail::progress::report_state;
 use crate::detail::progress::sort_by_string;
 use crate::detail::progress::ProgressOptions;
 use crate::detail::progress::ProgressRecorder;
-use crate::detail::progress::ProgressRecorderUnprotected;
 use crate::detail::progress::ProgressReporter;
 use crate::detail::progress::ProgressReporterUnprotected;
+use crate::detail::progress::ProgressStateBuilder;
+use crate::detail::progress::ProgressStateCountByType;
 use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::QuietMode;
+use crate::detail::progress::StepProgress;
 use crate::detail::state::InternedType;
 use crate::detail::state::StepStats;
 use crate::detail::state::WalkState;
@@ -80,6 +95,7 @@ pub const NODES: &str = "nodes";
 pub const EDGES: &str = "edges";
 pub const PASS: &str = "pass";
 pub const FAIL: &str = "fail";
+pub const SKIPPED: &str = "skipped";
 pub const TOTAL: &str = "total";
 pub const NODE_KEY: &str = "node_key";
 pub const NODE_TYPE: &str = "node_type";
@@ -103,6 +119,9 @@ define_stats! {
     // e.g. mononoke.walker.validate.testrepo.hg_link_node_populated.pass
     walker_validate: dynamic_timeseries("{}.{}.{}", (repo: String, check: &'static str, status: &'static str); Rate, Sum),
     last_completed: dynamic_singleton_counter("{}.{}.last_completed.{}", (repo: String, check: &'static str, status: &'static str)),
+    // e.g. mononoke.walker.validate.testrepo.progress.hg_link_node_populated.skipped
+    walk_validate_checks: dynamic_timeseries("{}.progress.{}.{}", (repo: String, check: &'static str, status: &'static str); Rate, Sum),
+    walk_validate_nodes: dynamic_timeseries("{}.progress.nodes.{}", (repo: String, status: &'static str); Rate, Sum),
 }
 
 pub const DEFAULT_CHECK_TYPES: &[CheckType] = &[
@@ -142,6 +161,8 @@ impl ValidateInfo {
 enum CheckStatus {
     Fail(ValidateInfo),
     Pass(Option<ValidateInfo>),
+    // The check could not run, e.g. it needs an option that was not set
+    Skipped,
 }
 
 define_type_enum! {
@@ -529,13 +550,14 @@ impl WalkVisitor<(Node, Option<CheckData>, Option<StepStats>), ValidateRoute>
                                     lfs_threshold,
                                 )
                             } else {
-                                CheckStatus::Pass(None)
+                                CheckStatus::Skipped
                             }
                         }
                     };
                     match &status {
                         CheckStatus::Pass(_) => pass += 1,
                         CheckStatus::Fail(_) => fail += 1,
+                        CheckStatus::Skipped => {}
                     }
                     CheckOutput::new(*check, status)
                 })
@@ -594,98 +616,306 @@ impl WalkVisitor<(Node, Option<CheckData>, Option<StepStats>), ValidateRoute>
     }
 }
 
-struct ValidateProgressState {
-    logger: Logger,
-    fb: FacebookInit,
-    scuba_builder: MononokeScubaSampleBuilder,
-    repo_stats_key: String,
-    types_sorted_by_name: Vec<CheckType>,
-    stats_by_type: HashMap<CheckType, CheckStats>,
-    total_checks: CheckStats,
-    checked_nodes: u64,
-    passed_nodes: u64,
-    failed_nodes: u64,
-    throttle_options: ProgressOptions,
-    last_update: Instant,
+/// Outcomes of one check type
+#[derive(Add, Div, Mul, Sub, Clone, Copy, Default, Debug, PartialEq)]
+pub struct CheckCounts {
+    pub pass: u64,
+    pub fail: u64,
+    pub skipped: u64,
 }
 
-impl ValidateProgressState {
-    fn new(
-        logger: Logger,
-        fb: FacebookInit,
-        scuba_builder: MononokeScubaSampleBuilder,
-        repo_stats_key: String,
-        included_types: HashSet<CheckType>,
-        throttle_options: ProgressOptions,
+impl CheckCounts {
+    fn record(&mut self, status: &CheckStatus) {
+        match status {
+            CheckStatus::Pass(_) => self.pass += 1,
+            CheckStatus::Fail(_) => self.fail += 1,
+            CheckStatus::Skipped => self.skipped += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.pass + self.fail + self.skipped
+    }
+}
+
+impl fmt::Display for CheckCounts {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{},{},{}", self.pass, self.fail, self.skipped)
+    }
+}
+
+/// CheckCounts indexed by CheckType. An array rather than a map so the stats stay Copy.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct CountsByCheck([CheckCounts; CheckType::COUNT]);
+
+impl CountsByCheck {
+    pub fn get(&self, check: CheckType) -> &CheckCounts {
+        &self.0[check as usize]
+    }
+
+    fn get_mut(&mut self, check: CheckType) -> &mut CheckCounts {
+        &mut self.0[check as usize]
+    }
+
+    /// Over all check types
+    pub fn sum(&self) -> CheckCounts {
+        self.0
+            .iter()
+            .fold(CheckCounts::default(), |acc, c| acc + *c)
+    }
+
+    fn zip_with(
+        mut self,
+        other: Self,
+        f: impl Fn(CheckCounts, CheckCounts) -> CheckCounts,
     ) -> Self {
-        let types_sorted_by_name = sort_by_string(included_types);
-        let now = Instant::now();
+        for (c, o) in self.0.iter_mut().zip(other.0) {
+            *c = f(*c, o);
+        }
+        self
+    }
+}
+
+impl Add for CountsByCheck {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.zip_with(other, |a, b| a + b)
+    }
+}
+
+impl Sub for CountsByCheck {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.zip_with(other, |a, b| a - b)
+    }
+}
+
+impl Mul<u64> for CountsByCheck {
+    type Output = Self;
+
+    fn mul(self, rhs: u64) -> Self {
+        Self(self.0.map(|c| c * rhs))
+    }
+}
+
+impl Div<u64> for CountsByCheck {
+    type Output = Self;
+
+    fn div(self, rhs: u64) -> Self {
+        Self(self.0.map(|c| c / rhs))
+    }
+}
+
+/// Check outcomes for one step
+#[derive(Add, Clone, Copy, Default, Debug, PartialEq)]
+pub struct ValidateStats {
+    pub checks: CountsByCheck,
+    /// The node fails if any check failed, otherwise passes if any check passed
+    pub nodes: CheckCounts,
+    pub edges: u64,
+}
+
+// Check outcomes only, errors and children are counted by the main walk progress
+impl StepProgress for ValidateStats {}
+
+impl From<&CheckData> for ValidateStats {
+    fn from(checkdata: &CheckData) -> Self {
+        let mut checks = CountsByCheck::default();
+        for c in &checkdata.checked {
+            checks.get_mut(c.check).record(&c.status);
+        }
+        let all = checks.sum();
+        let nodes = if all.fail > 0 {
+            CheckCounts {
+                fail: 1,
+                ..Default::default()
+            }
+        } else if all.pass > 0 {
+            CheckCounts {
+                pass: 1,
+                ..Default::default()
+            }
+        } else {
+            CheckCounts {
+                skipped: 1,
+                ..Default::default()
+            }
+        };
         Self {
-            logger,
-            fb,
-            scuba_builder,
-            repo_stats_key,
-            types_sorted_by_name,
-            stats_by_type: HashMap::new(),
-            total_checks: CheckStats::default(),
-            checked_nodes: 0,
-            passed_nodes: 0,
-            failed_nodes: 0,
-            throttle_options,
-            last_update: now,
+            checks,
+            nodes,
+            edges: checkdata.stats.edges,
         }
     }
+}
 
-    fn report_progress_log(&self) {
-        let detail_by_type = &self
-            .types_sorted_by_name
-            .iter()
-            .map(|t| {
-                let d = CheckStats::default();
-                let stats = self.stats_by_type.get(t).unwrap_or(&d);
-                format!("{}:{},{}", t, stats.pass, stats.fail)
-            })
-            .collect::<Vec<_>>()
-            .join(" ");
-        info!(
-            self.logger,
-            #log::VALIDATE,
-            "Nodes,Pass,Fail:{},{},{}; EdgesChecked:{}; CheckType:Pass,Fail Total:{},{} {}",
-            self.checked_nodes,
-            self.passed_nodes,
-            self.failed_nodes,
-            self.total_checks.edges,
-            self.total_checks.pass,
-            self.total_checks.fail,
-            detail_by_type,
-        );
+/// Check outcomes over the walk so far
+#[derive(Add, Div, Mul, Sub, Clone, Copy, Default, Debug, PartialEq)]
+pub struct ValidateProgressSummary {
+    pub checks: CountsByCheck,
+    pub nodes: CheckCounts,
+    pub edges: u64,
+}
+
+impl From<ValidateStats> for ValidateProgressSummary {
+    fn from(stats: ValidateStats) -> Self {
+        Self {
+            checks: stats.checks,
+            nodes: stats.nodes,
+            edges: stats.edges,
+        }
     }
+}
 
-    fn report_progress_stats(&self) {
+// Per check type Pass,Fail,Skipped in the order given
+fn check_detail(checks: &[CheckType], counts: &CountsByCheck) -> String {
+    checks
+        .iter()
+        .map(|c| format!("{}:{}", c, counts.get(*c)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ProgressStateCountByType<ValidateStats, ValidateProgressSummary> {
+    // Checks are selected by node type, so only those for the walked types can have run
+    fn checks_sorted_by_name(&self) -> Vec<CheckType> {
+        sort_by_string(
+            CheckType::iter().filter(|c| self.params.types_sorted_by_name.contains(&c.node_type())),
+        )
+    }
+
+    fn report_completion_stats(&self) {
+        let summary = &self.reporting_stats.last_summary;
         // Per check type
-        for (k, v) in self.stats_by_type.iter() {
-            for (desc, value) in &[(PASS, v.pass), (FAIL, v.fail), (EDGES, v.edges)] {
+        for check in self.checks_sorted_by_name() {
+            let counts = summary.checks.get(check);
+            for (desc, value) in &[
+                (PASS, counts.pass),
+                (FAIL, counts.fail),
+                (SKIPPED, counts.skipped),
+            ] {
                 STATS::last_completed.set_value(
-                    self.fb,
+                    self.params.fb,
                     *value as i64,
-                    (self.repo_stats_key.clone(), k.stats_key(), desc),
+                    (self.params.stats_key.clone(), check.stats_key(), desc),
                 );
             }
         }
         // Overall by nodes and edges
         for (stat, desc, value) in &[
-            (NODES, PASS, self.passed_nodes),
-            (NODES, FAIL, self.failed_nodes),
-            (NODES, TOTAL, self.checked_nodes),
-            (EDGES, TOTAL, self.total_checks.edges),
+            (NODES, PASS, summary.nodes.pass),
+            (NODES, FAIL, summary.nodes.fail),
+            (NODES, TOTAL, self.work_stats.total_progress()),
+            (EDGES, TOTAL, summary.edges),
         ] {
             STATS::last_completed.set_value(
-                self.fb,
+                self.params.fb,
                 *value as i64,
-                (self.repo_stats_key.clone(), stat, desc),
+                (self.params.stats_key.clone(), stat, desc),
             );
         }
     }
+
+    fn report_progress_stats(&self, delta_summary: &ValidateProgressSummary) {
+        for check in CheckType::iter() {
+            let counts = delta_summary.checks.get(check);
+            for (status, value) in [
+                (PASS, counts.pass),
+                (FAIL, counts.fail),
+                (SKIPPED, counts.skipped),
+            ] {
+                STATS::walk_validate_checks.add_value(
+                    value as i64,
+                    (self.params.stats_key.clone(), check.stats_key(), status),
+                );
+            }
+        }
+        for (status, value) in [
+            (PASS, delta_summary.nodes.pass),
+            (FAIL, delta_summary.nodes.fail),
+            (SKIPPED, delta_summary.nodes.skipped),
+        ] {
+            STATS::walk_validate_nodes
+                .add_value(value as i64, (self.params.stats_key.clone(), status));
+        }
+    }
+
+    pub fn report_progress_log(&mut self, delta_time: Option<Duration>) {
+        let times = self.report_times(delta_time);
+        let total_time = times.total;
+        let summary_by_type: HashMap<NodeType, ValidateProgressSummary> = self
+            .work_stats
+            .stats_by_type
+            .iter()
+            .map(|(k, (_i, v))| (*k, ValidateProgressSummary::from(*v)))
+            .collect();
+        let new_summary = summary_by_type
+            .values()
+            .fold(ValidateProgressSummary::default(), |acc, v| acc + *v);
+        let delta_summary = new_summary - self.reporting_stats.last_summary;
+
+        let (delta_s, delta_summary_per_s) =
+            times
+                .delta
+                .map_or((0, ValidateProgressSummary::default()), |delta_time| {
+                    (
+                        delta_time.as_secs(),
+                        delta_summary * 1000 / (delta_time.as_millis().max(1) as u64),
+                    )
+                });
+
+        let total_summary_per_s = if times.active.as_millis() > 0 {
+            new_summary * 1000 / (times.active.as_millis() as u64)
+        } else {
+            ValidateProgressSummary::default()
+        };
+
+        info!(
+            self.params.logger,
+            #log::VALIDATE,
+            "Nodes,Pass,Fail:{},{},{}; EdgesChecked:{}; Checks/s Delta:{:06}/s,{}s Run:{:06}/s,{}s; CheckType:Pass,Fail,Skipped Total:{} {}",
+            self.work_stats.total_progress(),
+            new_summary.nodes.pass,
+            new_summary.nodes.fail,
+            new_summary.edges,
+            delta_summary_per_s.checks.sum().total(),
+            delta_s,
+            total_summary_per_s.checks.sum().total(),
+            total_time.as_secs(),
+            new_summary.checks.sum(),
+            check_detail(&self.checks_sorted_by_name(), &new_summary.checks),
+        );
+
+        self.report_progress_stats(&delta_summary);
+
+        self.reporting_stats.last_summary_by_type = summary_by_type;
+        self.reporting_stats.last_summary = new_summary;
+        self.finish_report(&times);
+
+        if delta_time.is_none() {
+            self.report_completion_stats()
+        }
+    }
+}
+
+impl ProgressReporterUnprotected
+    for ProgressStateCountByType<ValidateStats, ValidateProgressSummary>
+{
+    fn report_progress(&mut self) {
+        self.report_progress_log(None);
+    }
+
+    fn report_throttled(&mut self) {
+        if let Some(delta_time) = self.should_log_throttled() {
+            self.report_progress_log(Some(delta_time));
+        }
+    }
+
+    fn quiet_mode(&self) -> QuietMode {
+        self.params.quiet_mode()
+    }
 }
 
 fn scuba_log_node(
@@ -743,98 +973,43 @@ pub fn add_node_to_scuba(
     }
 }
 
-impl ProgressRecorderUnprotected<CheckData> for ValidateProgressState {
-    fn set_sample_builder(&mut self, s: MononokeScubaSampleBuilder) {
-        self.scuba_builder = s;
-    }
+// Scuba sample per interesting check outcome, and a warning for each failure
+fn log_check_outputs(
+    logger: &Logger,
+    scuba_builder: &MononokeScubaSampleBuilder,
+    resolved_node: &Node,
+    checkdata: &CheckData,
+) {
+    for c in &checkdata.checked {
+        let (validate_info, check_fail) = match &c.status {
+            CheckStatus::Pass(validate_info) => (validate_info.as_ref(), 0),
+            CheckStatus::Fail(validate_info) => (Some(validate_info), 1),
+            CheckStatus::Skipped => (None, 0),
+        };
+        if let Some(validate_info) = validate_info {
+            let mut scuba = scuba_builder.clone();
+            add_node_to_scuba(
+                validate_info.source_node.as_ref(),
+                validate_info.via_node.as_ref(),
+                resolved_node,
+                validate_info.resolved_path.as_ref(),
+                &mut scuba,
+            );
 
-    fn record_step(&mut self, resolved_node: &Node, checkdata: Option<&CheckData>) {
-        self.checked_nodes += 1;
-        let mut had_pass = false;
-        let mut had_fail = false;
-        if let Some(checkdata) = checkdata {
-            // By node. One fail is enough for a Node to be failed.
-            if checkdata.stats.fail > 0 {
-                had_fail = true;
-            } else if !had_fail && checkdata.stats.pass > 0 {
-                had_pass = true;
+            if let Some(check_size) = validate_info.check_size {
+                scuba.add(CHECK_SIZE, check_size);
             }
-            // total
-            self.total_checks += checkdata.stats;
-            // By type
-            for c in &checkdata.checked {
-                let k = c.check;
-                let stats = self
-                    .stats_by_type
-                    .entry(k)
-                    .or_insert_with(CheckStats::default);
-                let (validate_info, check_fail) = match &c.status {
-                    CheckStatus::Pass(validate_info) => {
-                        stats.pass += 1;
-                        STATS::walker_validate
-                            .add_value(1, (self.repo_stats_key.clone(), k.stats_key(), PASS));
-                        (validate_info.into(), 0)
-                    }
-                    CheckStatus::Fail(validate_info) => {
-                        STATS::walker_validate
-                            .add_value(1, (self.repo_stats_key.clone(), k.stats_key(), FAIL));
-                        stats.fail += 1;
-                        (Some(validate_info), 1)
-                    }
-                };
-                if let Some(validate_info) = validate_info {
-                    let mut scuba = self.scuba_builder.clone();
-                    add_node_to_scuba(
-                        validate_info.source_node.as_ref(),
-                        validate_info.via_node.as_ref(),
-                        resolved_node,
-                        validate_info.resolved_path.as_ref(),
-                        &mut scuba,
-                    );
-
-                    if let Some(check_size) = validate_info.check_size {
-                        scuba.add(CHECK_SIZE, check_size);
-                    }
 
-                    scuba
-                        .add(CHECK_TYPE, k.stats_key())
-                        .add(CHECK_FAIL, check_fail)
-                        .log();
-                    if check_fail > 0 {
-                        if let Ok(json) = scuba.get_sample().to_json() {
-                            warn!(self.logger, "Validation failed: {}", json)
-                        }
-                    }
+            scuba
+                .add(CHECK_TYPE, c.check.stats_key())
+                .add(CHECK_FAIL, check_fail)
+                .log();
+            if check_fail > 0 {
+                if let Ok(json) = scuba.get_sample().to_json() {
+                    warn!(logger, "Validation failed: {}", json)
                 }
             }
         }
-
-        if had_pass {
-            self.passed_nodes += 1;
-            STATS::walker_validate.add_value(1, (self.repo_stats_key.clone(), NODES, PASS));
-        } else if had_fail {
-            self.failed_nodes += 1;
-            STATS::walker_validate.add_value(1, (self.repo_stats_key.clone(), NODES, FAIL));
-        }
-        STATS::walker_validate.add_value(1, (self.repo_stats_key.clone(), NODES, TOTAL));
-    }
-}
-
-impl ProgressReporterUnprotected for ValidateProgressState {
-    fn report_progress(&mut self) {
-        self.report_progress_log();
-        self.report_progress_stats();
-    }
-
-    fn report_throttled(&mut self) {
-        if self.checked_nodes % self.throttle_options.sample_rate == 0 {
-            let new_update = Instant::now();
-            let delta_time = new_update.duration_since(self.last_update);
-            if delta_time >= self.throttle_options.interval {
-                self.report_progress_log();
-                self.last_update = new_update;
-            }
-        }
     }
 }
 
@@ -897,30 +1072,68 @@ async fn run_one(
         sort_by_string(&command.include_check_types)
     );
 
-    let validate_progress_state = ProgressStateMutex::new(ValidateProgressState::new(
-        repo_params.logger.clone(),
-        fb,
-        repo_params.scuba_builder.clone(),
-        repo_params.repo.repo_identity().name().to_string(),
-        command.include_check_types.clone(),
-        command.progress_options,
-    ));
+    let validate_progress_state = ProgressStateMutex::new(
+        ProgressStateBuilder::new(
+            fb,
+            repo_params.logger.clone(),
+            VALIDATE,
+            repo_params.repo.repo_identity().name().to_string(),
+        )
+        .with_included_types(
+            command
+                .include_check_types
+                .iter()
+                .map(|c| c.node_type())
+                .collect(),
+        )
+        .with_options(command.progress_options.clone())
+        .with_walk_instance(repo_params.walk_instance.clone())
+        .build::<ValidateStats, ValidateProgressSummary>()?,
+    );
 
-    cloned!(job_params.quiet, sub_params.progress_state);
+    cloned!(job_params.blobstore_read_counts, sub_params.progress_state);
     let make_sink = move |ctx: &CoreContext, repo_params: &RepoWalkParams| {
         cloned!(ctx);
-        validate_progress_state.set_sample_builder(repo_params.scuba_builder.clone());
-        async move |walk_output, _run_start, _chunk_num, _checkpoint_name| {
-            cloned!(ctx, progress_state, validate_progress_state);
+        let logger = repo_params.logger.clone();
+        let scuba_builder = repo_params.scuba_builder.clone();
+        async move |walk_output,
+                    _run_start,
+                    chunk_num,
+                    _checkpoint_name,
+                    chunk_bounds,
+                    iteration| {
+            cloned!(
+                ctx,
+                blobstore_read_counts,
+                progress_state,
+                validate_progress_state,
+                logger,
+                scuba_builder
+            );
+            if let Some(iteration) = iteration {
+                progress_state.start_iteration(iteration);
+            }
+            if let Some(chunk_bounds) = chunk_bounds {
+                progress_state.set_position(chunk_bounds.clone());
+                progress_state.start_chunk(chunk_num, chunk_bounds);
+            }
+            let walk_output = read_counting_stream(blobstore_read_counts, walk_output);
             let walk_progress =
-                progress_stream(quiet, &progress_state, walk_output).map_ok(|(n, d, s)| {
+                progress_stream(&progress_state, walk_output).map_ok(move |(n, d, s)| {
+                    let stats = d.as_ref().map(|checkdata| {
+                        log_check_outputs(&logger, &scuba_builder, &n, checkdata);
+                        ValidateStats::from(checkdata)
+                    });
                     // swap stats and data round
-                    (n, s, d)
+                    (n, s, stats)
                 });
 
-            let validate_progress = progress_stream(quiet, &validate_progress_state, walk_progress);
+            let validate_progress = progress_stream(&validate_progress_state, walk_progress);
 
-            report_state(ctx, validate_progress).await?;
+            // Send the rows of the reports so far even if the walk fails
+            report_state(ctx, progress_state.quiet_mode(), validate_progress)
+                .await
+                .inspect_err(|_| progress_state.flush())?;
             progress_state.report_progress();
             validate_progress_state.report_progress();
             Ok(())
@@ -973,3 +1186,215 @@ async fn run_one(
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashset;
+    use mononoke_types::ContentId;
+    use slog::o;
+
+    use super::*;
+    use crate::detail::progress::NumberFormat;
+    use crate::detail::progress::PayloadSizeOptions;
+    use crate::detail::progress::ProgressDisplay;
+    use crate::detail::progress::ProgressRecorderUnprotected;
+    use crate::detail::progress::TypeGrouping;
+    use crate::detail::progress::UnchangedProgress;
+
+    fn test_validate_state(
+        fb: FacebookInit,
+    ) -> ProgressStateCountByType<ValidateStats, ValidateProgressSummary> {
+        ProgressStateBuilder::new(
+            fb,
+            Logger::root(slog::Discard, o!()),
+            VALIDATE,
+            "repo".to_string(),
+        )
+        .with_included_types(hashset! {
+            CheckType::ChangesetPhaseIsPublic.node_type(),
+            CheckType::FileContentIsLfs.node_type(),
+        })
+        .with_options(ProgressOptions {
+            sample_rate: 1,
+            interval: Duration::from_secs(1),
+            display: ProgressDisplay::Log,
+            quiet: QuietMode::Full,
+            type_rates: false,
+            type_emit_every_nth: 1,
+            type_grouping: TypeGrouping::Types,
+            stuck_step_threshold: Duration::from_secs(60),
+            unchanged: UnchangedProgress::Log,
+            error_budgets: HashMap::new(),
+            sample_node_every_nth: 0,
+            number_format: NumberFormat::Raw,
+            max_lines_per_hour: None,
+            idle_type_threshold: None,
+            worker_skew_threshold: None,
+            wall_clock_aligned: false,
+            slowdown: None,
+            payload_sizes: PayloadSizeOptions::default(),
+            jsonl: None,
+            types_of_interest: HashSet::new(),
+            interest_interval: Duration::from_secs(5),
+        })
+        .build()
+        .unwrap()
+    }
+
+    fn check_data(checked: Vec<(CheckType, CheckStatus)>) -> CheckData {
+        let mut stats = CheckStats {
+            edges: 1,
+            ..Default::default()
+        };
+        for (_check, status) in &checked {
+            match status {
+                CheckStatus::Pass(_) => stats.pass += 1,
+                CheckStatus::Fail(_) => stats.fail += 1,
+                CheckStatus::Skipped => {}
+            }
+        }
+        CheckData {
+            checked: checked
+                .into_iter()
+                .map(|(check, status)| CheckOutput::new(check, status))
+                .collect(),
+            stats,
+        }
+    }
+
+    fn fail() -> CheckStatus {
+        CheckStatus::Fail(ValidateInfo::new(None, None, None, None))
+    }
+
+    fn phase_node(i: u8) -> Node {
+        Node::PhaseMapping(ChangesetId::from_byte_array([i; 32]))
+    }
+
+    fn content_node(i: u8) -> Node {
+        Node::FileContentMetadataV2(ContentId::from_byte_array([i; 32]))
+    }
+
+    #[test]
+    fn test_validate_stats_from_check_data() {
+        let stats = ValidateStats::from(&check_data(vec![
+            (CheckType::ChangesetPhaseIsPublic, CheckStatus::Pass(None)),
+            (CheckType::FileContentIsLfs, fail()),
+        ]));
+        assert_eq!(
+            CheckCounts {
+                pass: 1,
+                fail: 0,
+                skipped: 0
+            },
+            *stats.checks.get(CheckType::ChangesetPhaseIsPublic)
+        );
+        assert_eq!(1, stats.checks.get(CheckType::FileContentIsLfs).fail);
+        // One fail is enough for the node to fail
+        assert_eq!(
+            CheckCounts {
+                pass: 0,
+                fail: 1,
+                skipped: 0
+            },
+            stats.nodes
+        );
+
+        let stats = ValidateStats::from(&check_data(vec![(
+            CheckType::FileContentIsLfs,
+            CheckStatus::Skipped,
+        )]));
+        assert_eq!(1, stats.checks.get(CheckType::FileContentIsLfs).skipped);
+        assert_eq!(1, stats.nodes.skipped);
+    }
+
+    #[test]
+    fn test_counts_by_check_ops() {
+        let mut a = CountsByCheck::default();
+        a.get_mut(CheckType::HgLinkNodePopulated).pass = 10;
+        a.get_mut(CheckType::FileContentIsLfs).fail = 4;
+        let mut b = CountsByCheck::default();
+        b.get_mut(CheckType::HgLinkNodePopulated).pass = 2;
+
+        let sum = a + b;
+        assert_eq!(12, sum.get(CheckType::HgLinkNodePopulated).pass);
+        assert_eq!(a, sum - b);
+        let scaled = a * 3 / 2;
+        assert_eq!(15, scaled.get(CheckType::HgLinkNodePopulated).pass);
+        assert_eq!(6, scaled.get(CheckType::FileContentIsLfs).fail);
+        assert_eq!(
+            CheckCounts {
+                pass: 10,
+                fail: 4,
+                skipped: 0
+            },
+            a.sum()
+        );
+    }
+
+    #[fbinit::test]
+    fn test_validate_progress_summary(fb: FacebookInit) {
+        let mut state = test_validate_state(fb);
+        assert_eq!(
+            vec![
+                CheckType::ChangesetPhaseIsPublic,
+                CheckType::FileContentIsLfs
+            ],
+            state.checks_sorted_by_name()
+        );
+
+        for i in 0..3 {
+            let stats = ValidateStats::from(&check_data(vec![(
+                CheckType::ChangesetPhaseIsPublic,
+                CheckStatus::Pass(None),
+            )]));
+            state.record_step(&phase_node(i), Some(&stats));
+        }
+        let stats = ValidateStats::from(&check_data(vec![(
+            CheckType::ChangesetPhaseIsPublic,
+            fail(),
+        )]));
+        state.record_step(&phase_node(3), Some(&stats));
+        // Steps without checks still count as nodes walked
+        state.record_step(&phase_node(4), None);
+        state.report_progress_log(Some(Duration::from_secs(1)));
+
+        let summary = state.reporting_stats.last_summary;
+        assert_eq!(
+            CheckCounts {
+                pass: 3,
+                fail: 1,
+                skipped: 0
+            },
+            *summary.checks.get(CheckType::ChangesetPhaseIsPublic)
+        );
+        assert_eq!(3, summary.nodes.pass);
+        assert_eq!(1, summary.nodes.fail);
+        assert_eq!(4, summary.edges);
+
+        for i in 0..2 {
+            let stats = ValidateStats::from(&check_data(vec![(
+                CheckType::FileContentIsLfs,
+                CheckStatus::Skipped,
+            )]));
+            state.record_step(&content_node(i), Some(&stats));
+        }
+        state.report_progress();
+
+        let summary = state.reporting_stats.last_summary;
+        assert_eq!(
+            "ChangesetPhaseIsPublic:3,1,0 FileContentIsLfs:0,0,2",
+            check_detail(&state.checks_sorted_by_name(), &summary.checks)
+        );
+        assert_eq!(2, summary.nodes.skipped);
+        assert_eq!(
+            summary.checks.get(CheckType::FileContentIsLfs),
+            state
+                .reporting_stats
+                .last_summary_by_type
+                .get(&NodeType::FileContentMetadataV2)
+                .unwrap()
+                .checks
+                .get(CheckType::FileContentIsLfs)
+        );
+    }
+}
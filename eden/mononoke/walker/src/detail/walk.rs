@@ -8,7 +8,10 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::format_err;
 use anyhow::Context;
@@ -90,9 +93,12 @@ use unodes::RootUnodeManifestId;
 use yield_stream::YieldStreamExt;
 
 use crate::commands::JobWalkParams;
+use crate::detail::concurrency::AdaptiveConcurrencyOptions;
+use crate::detail::concurrency::AdaptiveGate;
 use crate::detail::graph::AliasKey;
 use crate::detail::graph::ChangesetKey;
 use crate::detail::graph::EdgeType;
+use crate::detail::graph::ErrorCategory;
 use crate::detail::graph::FastlogKey;
 use crate::detail::graph::FileContentData;
 use crate::detail::graph::HashValidationError;
@@ -106,7 +112,9 @@ use crate::detail::graph::UnodeKey;
 use crate::detail::graph::UnodeManifestEntry;
 use crate::detail::graph::WrappedPath;
 use crate::detail::log;
+use crate::detail::progress::ProgressRecorder;
 use crate::detail::state::InternedType;
+use crate::detail::state::StepStats;
 use crate::detail::validate::add_node_to_scuba;
 use crate::detail::validate::CHECK_FAIL;
 use crate::detail::validate::CHECK_TYPE;
@@ -116,6 +124,9 @@ use crate::detail::validate::ERROR_MSG;
 /// How frequently to yield the CPU when processing large manifests.
 const MANIFEST_YIELD_EVERY_ENTRY_COUNT: usize = 2_000;
 
+/// Back off before retrying a step, multiplied by the attempt number.
+const STEP_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 pub trait StepRoute: Debug {
     /// Where we stepped from, useful for immediate reproductions with --walk-root
     fn source_node(&self) -> Option<&Node>;
@@ -1820,6 +1831,7 @@ struct Checker<V: VisitOne> {
     required_node_data_types: HashSet<NodeType>,
     keep_edge_paths: bool,
     visitor: V,
+    progress_recorder: Arc<dyn ProgressRecorder<StepStats> + Send + Sync>,
     phases_store: Arc<dyn Phases>,
     bonsai_hg_mapping: Arc<dyn BonsaiHgMapping>,
     with_blame: bool,
@@ -1900,9 +1912,11 @@ impl<V: VisitOne> Checker<V> {
         let always_emit = self.always_emit_edge_types.contains(&edge_type);
         if always_emit || self.include_edge_types.contains(&edge_type) {
             let outgoing = OutgoingEdge::new(edge_type, node_fn());
-            if always_emit || self.visitor.needs_visit(&outgoing) {
+            if always_emit || self.needs_visit(&outgoing) {
                 return Some(outgoing);
             }
+        } else {
+            self.record_filtered(edge_type);
         }
         None
     }
@@ -1925,13 +1939,29 @@ impl<V: VisitOne> Checker<V> {
             } else {
                 OutgoingEdge::new(edge_type, node_fn())
             };
-            if always_emit || self.visitor.needs_visit(&outgoing) {
+            if always_emit || self.needs_visit(&outgoing) {
                 return Some(outgoing);
             }
+        } else {
+            self.record_filtered(edge_type);
         }
         None
     }
 
+    // Ask the visitor, recording whether the step was turned away as already visited
+    fn needs_visit(&self, outgoing: &OutgoingEdge) -> bool {
+        let needs_visit = self.visitor.needs_visit(outgoing);
+        self.progress_recorder
+            .record_visit_check(outgoing.target.get_type(), !needs_visit);
+        needs_visit
+    }
+
+    // Counted by the type the edge leads to, without making its node
+    fn record_filtered(&self, edge_type: EdgeType) {
+        self.progress_recorder
+            .record_filtered(edge_type.outgoing_type())
+    }
+
     // Only add the node data if requested
     fn step_data<D>(&self, t: NodeType, data_fn: D) -> NodeData
     where
@@ -1957,6 +1987,12 @@ pub struct RepoWalkParams {
     pub include_node_types: HashSet<NodeType>,
     pub include_edge_types: HashSet<EdgeType>,
     pub hash_validation_node_types: HashSet<NodeType>,
+    // Told as steps are queued and started, so the backlog can be reported
+    pub progress_recorder: Arc<dyn ProgressRecorder<StepStats> + Send + Sync>,
+    // Labels progress output apart from other walks in the process, empty for none
+    pub walk_instance: String,
+    // Adjusts the steps run at once below scheduled_max from progress feedback, if set
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyOptions>,
 }
 
 // Parameters that vary per repo but are set differently by scrub, validate etc.
@@ -2016,7 +2052,9 @@ where
             repo_params.hash_validation_node_types,
             repo_params.include_node_types,
             repo_params.sql_shard_info,
+            repo_params.progress_recorder,
         );
+        progress_recorder.record_enqueued(walk_roots.len() as u64);
 
         let mut required_node_data_types = type_params.required_node_data_types;
         required_node_data_types.extend(hash_validation_node_types.clone());
@@ -2034,15 +2072,31 @@ where
             always_emit_edge_types: type_params.always_emit_edge_types,
             keep_edge_paths: type_params.keep_edge_paths,
             visitor: visitor.clone(),
+            progress_recorder: progress_recorder.clone(),
             required_node_data_types,
             phases_store: repo.phases().with_frozen_public_heads(heads),
             bonsai_hg_mapping: repo.bonsai_hg_mapping_arc().clone(),
         });
+        // Steps running at once, for tuning scheduled_max
+        let in_flight = Arc::new(AtomicU64::new(0));
+        // Only if the progress recorder publishes feedback to adapt to
+        let adaptive_gate = match (
+            repo_params.adaptive_concurrency.clone(),
+            progress_recorder.feedback(),
+        ) {
+            (Some(options), Some(feedback)) => Some(Arc::new(AdaptiveGate::new(
+                repo_params.logger.clone(),
+                feedback,
+                options,
+            )?)),
+            _ => None,
+        };
 
         Ok(limited_by_key_shardable(
             repo_params.scheduled_max,
             walk_roots,
             move |(via, walk_item): (Option<Route>, OutgoingEdge)| {
+                progress_recorder.record_dequeued(1);
                 cloned!(repo_params.sql_shard_info);
                 let shard_key = walk_item.target.sql_shard(&sql_shard_info);
                 let ctx =
@@ -2057,16 +2111,27 @@ where
                     job_params.error_as_data_node_types,
                     job_params.error_as_data_edge_types,
                     job_params.enable_derive,
+                    job_params.step_retries,
                     published_bookmarks,
                     repo_params.repo,
                     repo_params.scuba_builder,
                     visitor,
                     checker,
                     walk_item.target,
+                    progress_recorder,
+                    in_flight,
+                    adaptive_gate,
                 );
 
                 // Each step returns the walk result, and next steps
                 async move {
+                    // Waits before the step counts as in flight, so its latency is its own
+                    let _pass = match &adaptive_gate {
+                        Some(gate) => Some(gate.enter().await),
+                        None => None,
+                    };
+                    let _in_flight = progress_recorder.record_in_flight(&target);
+                    progress_recorder.set_in_flight(in_flight.fetch_add(1, Ordering::Relaxed) + 1);
                     let next = walk_one(
                         ctx,
                         via,
@@ -2079,10 +2144,20 @@ where
                         scuba_builder,
                         published_bookmarks,
                         checker,
+                        step_retries,
+                        progress_recorder.clone(),
                     );
 
                     let handle = tokio::task::spawn(next);
-                    handle.await?
+                    let stepped = handle.await;
+                    progress_recorder.set_in_flight(in_flight.fetch_sub(1, Ordering::Relaxed) - 1);
+                    stepped?.map(|stepped| {
+                        stepped.map(|(vout, next)| {
+                            let next: Vec<_> = next.into_iter().collect();
+                            progress_recorder.record_enqueued(next.len() as u64);
+                            (vout, next)
+                        })
+                    })
                 }
                 .map(move |v| (target, shard_key, v))
                 .boxed()
@@ -2117,6 +2192,8 @@ async fn walk_one<V, VOut, Route>(
     mut scuba: MononokeScubaSampleBuilder,
     published_bookmarks: Arc<HashMap<BookmarkKey, ChangesetId>>,
     checker: Arc<Checker<V>>,
+    step_retries: u32,
+    progress_recorder: Arc<dyn ProgressRecorder<StepStats> + Send + Sync>,
 ) -> Result<
     Option<(
         VOut,
@@ -2136,127 +2213,153 @@ where
         visitor.visit(&ctx, walk_item.clone(), None, None, vec![walk_item.clone()]);
     }
 
-    let step_result = match walk_item.target.clone() {
-        Node::Root(_) => Err(StepError::Other(format_err!(
-            "Not expecting Roots to be generated"
-        ))),
-        // Bonsai
-        Node::Bookmark(bookmark_name) => {
-            bookmark_step(
-                ctx.clone(),
-                &repo,
-                &checker,
-                bookmark_name,
-                published_bookmarks.clone(),
-            )
-            .await
-        }
-        Node::Changeset(key) => bonsai_changeset_step(&ctx, &repo, &checker, &key).await,
-        Node::BonsaiHgMapping(bcs_id) => {
-            bonsai_to_hg_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::PhaseMapping(bcs_id) => bonsai_phase_step(&ctx, &checker, &bcs_id).await,
-        Node::PublishedBookmarks(_) => {
-            published_bookmarks_step(published_bookmarks.clone(), &checker).await
-        }
-        // Hg
-        Node::HgBonsaiMapping(key) => hg_to_bonsai_mapping_step(&ctx, &checker, key).await,
-        Node::HgChangeset(hg_csid) => hg_changeset_step(&ctx, &repo, &checker, hg_csid).await,
-        Node::HgChangesetViaBonsai(hg_csid) => {
-            hg_changeset_via_bonsai_step(&ctx, &repo, &checker, hg_csid, enable_derive).await
-        }
-        Node::HgFileEnvelope(hg_file_node_id) => {
-            hg_file_envelope_step(
-                &ctx,
-                &repo,
-                &checker,
-                hg_file_node_id,
-                walk_item.path.as_ref(),
-            )
-            .await
-        }
-        Node::HgFileNode(PathKey { id, path }) => {
-            hg_file_node_step(ctx.clone(), &repo, &checker, path, id).await
-        }
-        Node::HgManifestFileNode(PathKey { id, path }) => {
-            hg_manifest_file_node_step(ctx.clone(), &repo, &checker, path, id).await
-        }
-        Node::HgManifest(PathKey { id, path }) => {
-            hg_manifest_step(&ctx, &repo, &checker, path, id).await
-        }
-        // Content
-        Node::FileContent(content_id) => {
-            file_content_step(ctx.clone(), &repo, &checker, content_id).await
-        }
-        Node::FileContentMetadataV2(content_id) => {
-            file_content_metadata_v2_step(&ctx, &repo, &checker, content_id, enable_derive).await
-        }
-        Node::AliasContentMapping(AliasKey(alias)) => {
-            alias_content_mapping_step(&ctx, &repo, &checker, alias).await
-        }
-        // Derived
-        Node::Blame(blame_id) => blame_step(&ctx, &repo, &checker, blame_id).await,
-        Node::ChangesetInfo(bcs_id) => {
-            changeset_info_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::ChangesetInfoMapping(bcs_id) => {
-            bonsai_changeset_info_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::DeletedManifestV2(id) => {
-            deleted_manifest_v2_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::DeletedManifestV2Mapping(bcs_id) => {
-            deleted_manifest_v2_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::FastlogBatch(id) => {
-            fastlog_batch_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::FastlogDir(id) => {
-            fastlog_dir_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::FastlogFile(id) => {
-            fastlog_file_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::Fsnode(id) => fsnode_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await,
-        Node::FsnodeMapping(bcs_id) => {
-            bonsai_to_fsnode_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::SkeletonManifest(id) => {
-            skeleton_manifest_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::SkeletonManifestMapping(bcs_id) => {
-            skeleton_manifest_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
-        }
-        Node::BasenameSuffixSkeletonManifest(id) => {
-            basename_suffix_skeleton_manifest_step(
-                &ctx,
-                &repo,
-                &checker,
-                &id,
-                walk_item.path.as_ref(),
-            )
-            .await
-        }
-        Node::BasenameSuffixSkeletonManifestMapping(bcs_id) => {
-            basename_suffix_skeleton_manifest_mapping_step(
-                &ctx,
-                &repo,
-                &checker,
-                bcs_id,
-                enable_derive,
-            )
-            .await
-        }
-        Node::UnodeFile(id) => {
-            unode_file_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::UnodeManifest(id) => {
-            unode_manifest_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
-        }
-        Node::UnodeMapping(bcs_id) => {
-            bonsai_to_unode_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+    // Transient errors, e.g. blobstore timeouts, are retried in place. The failed
+    // attempts are recorded against the node's type, so they show in progress.
+    let mut retries = 0;
+    let step_result = loop {
+        let step_result = match walk_item.target.clone() {
+            Node::Root(_) => Err(StepError::Other(format_err!(
+                "Not expecting Roots to be generated"
+            ))),
+            // Bonsai
+            Node::Bookmark(bookmark_name) => {
+                bookmark_step(
+                    ctx.clone(),
+                    &repo,
+                    &checker,
+                    bookmark_name,
+                    published_bookmarks.clone(),
+                )
+                .await
+            }
+            Node::Changeset(key) => bonsai_changeset_step(&ctx, &repo, &checker, &key).await,
+            Node::BonsaiHgMapping(bcs_id) => {
+                bonsai_to_hg_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+            Node::PhaseMapping(bcs_id) => bonsai_phase_step(&ctx, &checker, &bcs_id).await,
+            Node::PublishedBookmarks(_) => {
+                published_bookmarks_step(published_bookmarks.clone(), &checker).await
+            }
+            // Hg
+            Node::HgBonsaiMapping(key) => hg_to_bonsai_mapping_step(&ctx, &checker, key).await,
+            Node::HgChangeset(hg_csid) => hg_changeset_step(&ctx, &repo, &checker, hg_csid).await,
+            Node::HgChangesetViaBonsai(hg_csid) => {
+                hg_changeset_via_bonsai_step(&ctx, &repo, &checker, hg_csid, enable_derive).await
+            }
+            Node::HgFileEnvelope(hg_file_node_id) => {
+                hg_file_envelope_step(
+                    &ctx,
+                    &repo,
+                    &checker,
+                    hg_file_node_id,
+                    walk_item.path.as_ref(),
+                )
+                .await
+            }
+            Node::HgFileNode(PathKey { id, path }) => {
+                hg_file_node_step(ctx.clone(), &repo, &checker, path, id).await
+            }
+            Node::HgManifestFileNode(PathKey { id, path }) => {
+                hg_manifest_file_node_step(ctx.clone(), &repo, &checker, path, id).await
+            }
+            Node::HgManifest(PathKey { id, path }) => {
+                hg_manifest_step(&ctx, &repo, &checker, path, id).await
+            }
+            // Content
+            Node::FileContent(content_id) => {
+                file_content_step(ctx.clone(), &repo, &checker, content_id).await
+            }
+            Node::FileContentMetadataV2(content_id) => {
+                file_content_metadata_v2_step(&ctx, &repo, &checker, content_id, enable_derive)
+                    .await
+            }
+            Node::AliasContentMapping(AliasKey(alias)) => {
+                alias_content_mapping_step(&ctx, &repo, &checker, alias).await
+            }
+            // Derived
+            Node::Blame(blame_id) => blame_step(&ctx, &repo, &checker, blame_id).await,
+            Node::ChangesetInfo(bcs_id) => {
+                changeset_info_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+            Node::ChangesetInfoMapping(bcs_id) => {
+                bonsai_changeset_info_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive)
+                    .await
+            }
+            Node::DeletedManifestV2(id) => {
+                deleted_manifest_v2_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::DeletedManifestV2Mapping(bcs_id) => {
+                deleted_manifest_v2_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+            Node::FastlogBatch(id) => {
+                fastlog_batch_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::FastlogDir(id) => {
+                fastlog_dir_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::FastlogFile(id) => {
+                fastlog_file_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::Fsnode(id) => {
+                fsnode_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::FsnodeMapping(bcs_id) => {
+                bonsai_to_fsnode_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+            Node::SkeletonManifest(id) => {
+                skeleton_manifest_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::SkeletonManifestMapping(bcs_id) => {
+                skeleton_manifest_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+            Node::BasenameSuffixSkeletonManifest(id) => {
+                basename_suffix_skeleton_manifest_step(
+                    &ctx,
+                    &repo,
+                    &checker,
+                    &id,
+                    walk_item.path.as_ref(),
+                )
+                .await
+            }
+            Node::BasenameSuffixSkeletonManifestMapping(bcs_id) => {
+                basename_suffix_skeleton_manifest_mapping_step(
+                    &ctx,
+                    &repo,
+                    &checker,
+                    bcs_id,
+                    enable_derive,
+                )
+                .await
+            }
+            Node::UnodeFile(id) => {
+                unode_file_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::UnodeManifest(id) => {
+                unode_manifest_step(&ctx, &repo, &checker, &id, walk_item.path.as_ref()).await
+            }
+            Node::UnodeMapping(bcs_id) => {
+                bonsai_to_unode_mapping_step(&ctx, &repo, &checker, bcs_id, enable_derive).await
+            }
+        };
+        match &step_result {
+            Err(StepError::Other(e))
+                if retries < step_retries
+                    && ErrorCategory::classify(e) == ErrorCategory::Transient =>
+            {
+                retries += 1;
+                tokio::time::sleep(STEP_RETRY_DELAY * retries).await;
+            }
+            _ => break step_result,
         }
     };
+    if retries > 0 {
+        let stats = StepStats {
+            retries: retries as usize,
+            ..Default::default()
+        };
+        progress_recorder.record_retries(&walk_item.target, &stats);
+    }
 
     let edge_label = walk_item.label;
     let node_type = walk_item.target.get_type();
@@ -2330,8 +2433,11 @@ where
                             NodeData::HashValidationFailureAsData(walk_item.target.clone()),
                             vec![],
                         )),
-                        StepError::Other(_e) => Ok(StepOutput::Done(
-                            NodeData::ErrorAsData(walk_item.target.clone()),
+                        StepError::Other(e) => Ok(StepOutput::Done(
+                            NodeData::ErrorAsData(
+                                walk_item.target.clone(),
+                                ErrorCategory::classify(&e),
+                            ),
                             vec![],
                         )),
                     }
@@ -6,12 +6,16 @@
  */
 
 #![feature(async_closure)]
+#![cfg_attr(test, feature(test))]
 
 mod args;
 mod commands;
 mod detail;
 mod setup;
 
+#[cfg(test)]
+extern crate test;
+
 use std::num::NonZeroU32;
 
 use anyhow::Error;
@@ -48,15 +48,26 @@ use crate::commands::JobParams;
 use crate::commands::JobWalkParams;
 use crate::commands::RepoSubcommandParams;
 use crate::detail::blobstore::replace_blobconfig;
+use crate::detail::blobstore::BlobstoreReadCounts;
+use crate::detail::blobstore::ReadCountingSampler;
+use crate::detail::blobstore::ScrubRepairCounts;
 use crate::detail::blobstore::StatsScrubHandler;
+use crate::detail::concurrency::AdaptiveConcurrencyOptions;
 use crate::detail::graph::EdgeType;
 use crate::detail::graph::NodeType;
 use crate::detail::graph::SqlShardInfo;
 use crate::detail::log;
+#[cfg(feature = "otel")]
+use crate::detail::otel::otlp_stats_sink_from_env;
 use crate::detail::progress::sort_by_string;
+#[cfg(feature = "otel")]
+use crate::detail::progress::DefaultProgressStatsSink;
 use crate::detail::progress::ProgressOptions;
-use crate::detail::progress::ProgressStateCountByType;
+use crate::detail::progress::ProgressRecorder;
+use crate::detail::progress::ProgressStateBuilder;
 use crate::detail::progress::ProgressStateMutex;
+use crate::detail::progress::ProgressSummary;
+use crate::detail::state::StepStats;
 use crate::detail::tail::TailParams;
 use crate::detail::validate::REPO;
 use crate::detail::validate::WALK_TYPE;
@@ -118,10 +129,14 @@ pub async fn setup_common<'a>(
         .iter()
         .map(|(name, conf)| (conf.repoid, name.clone()))
         .collect();
+    let scrub_repair_counts = Arc::new(ScrubRepairCounts::default());
+    let blobstore_read_counts = Arc::new(BlobstoreReadCounts::default());
     let repo_factory = setup_repo_factory(
         walk_stats_key,
         app,
         repo_id_to_name,
+        scrub_repair_counts.clone(),
+        blobstore_read_counts.clone(),
         blobstore_sampler,
         blobstore_component_sampler,
         scuba_builder.clone(),
@@ -129,7 +144,10 @@ pub async fn setup_common<'a>(
         common_args.quiet,
     );
 
-    let progress_options = common_args.progress.parse_args();
+    let progress_options = common_args.progress.parse_args(common_args.quiet)?;
+    let unwalked_ok_types = common_args.progress.parse_unwalked_ok_types();
+    let excluded_progress_types = common_args.progress.parse_excluded_types();
+    let walk_instance = common_args.progress.parse_walk_instance();
     let hash_validation_node_types = common_args.hash_validation.parse_args();
 
     let mysql_options = app.mysql_options();
@@ -166,6 +184,9 @@ pub async fn setup_common<'a>(
         let scheduled_max_concurrency = walker_config_params
             .and_then(|p| p.scheduled_max_concurrency.map(|i| i as usize))
             .unwrap_or(common_args.scheduled_max);
+        // setup_repo shares the concurrency between the repos, so adapt within each share
+        let adaptive_concurrency =
+            common_args.adaptive_concurrency(scheduled_max_concurrency / repo_count)?;
         // Exclude nodes that might be provided as part of walker config.
         let included_nodes = walker_config_params
             .and_then(|p| p.exclude_node_type.as_ref())
@@ -227,7 +248,11 @@ pub async fn setup_common<'a>(
             include_edge_types.clone(),
             included_nodes,
             hash_validation_node_types.clone(),
-            progress_options,
+            progress_options.clone(),
+            unwalked_ok_types.clone(),
+            excluded_progress_types.clone(),
+            walk_instance.clone(),
+            adaptive_concurrency,
             common_config,
         )
         .await?;
@@ -241,6 +266,9 @@ pub async fn setup_common<'a>(
             error_as_data_node_types: error_as_data_node_types_for_all_repos,
             error_as_data_edge_types,
             repo_count,
+            step_retries: common_args.step_retries,
+            scrub_repair_counts,
+            blobstore_read_counts,
         },
         per_repo,
     })
@@ -318,6 +346,8 @@ fn setup_repo_factory<'a>(
     walk_stats_key: &'static str,
     app: &MononokeApp,
     repo_id_to_name: HashMap<RepositoryId, String>,
+    scrub_repair_counts: Arc<ScrubRepairCounts>,
+    blobstore_read_counts: Arc<BlobstoreReadCounts>,
     blobstore_sampler: Option<Arc<dyn SamplingHandler>>,
     blobstore_component_sampler: Option<Arc<dyn ComponentSamplingHandler>>,
     scuba_builder: MononokeScubaSampleBuilder,
@@ -339,15 +369,18 @@ fn setup_repo_factory<'a>(
         });
     }
 
-    if let Some(sampler) = blobstore_component_sampler {
-        repo_factory.with_blobstore_component_sampler(sampler);
-    }
+    // Always installed, as it also counts reads by component blobstore for progress
+    repo_factory.with_blobstore_component_sampler(Arc::new(ReadCountingSampler::new(
+        blobstore_read_counts,
+        blobstore_component_sampler,
+    )));
 
     repo_factory.with_scrub_handler(Arc::new(StatsScrubHandler::new(
         false,
         scuba_builder,
         walk_stats_key,
         repo_id_to_name,
+        scrub_repair_counts,
     )) as Arc<dyn ScrubHandler>);
 
     repo_factory
@@ -400,6 +433,10 @@ async fn setup_repo<'a>(
     mut include_node_types: HashSet<NodeType>,
     hash_validation_node_types: HashSet<NodeType>,
     progress_options: ProgressOptions,
+    unwalked_ok_types: HashSet<NodeType>,
+    excluded_progress_types: HashSet<NodeType>,
+    walk_instance: String,
+    adaptive_concurrency: Option<AdaptiveConcurrencyOptions>,
     common_config: CommonConfig,
 ) -> Result<(RepoSubcommandParams, RepoWalkParams), Error> {
     let logger = logger.new(o!("repo" => repo_name.clone()));
@@ -453,14 +490,26 @@ async fn setup_repo<'a>(
         progress_node_types.insert(e.target.get_type());
     }
 
-    let progress_state = ProgressStateMutex::new(ProgressStateCountByType::new(
-        fb,
-        logger.clone(),
-        walk_stats_key,
-        repo_name.clone(),
-        progress_node_types,
-        progress_options,
-    ));
+    let progress_builder =
+        ProgressStateBuilder::new(fb, logger.clone(), walk_stats_key, repo_name.clone())
+            .with_included_types(progress_node_types)
+            .with_unwalked_ok_types(unwalked_ok_types)
+            .with_excluded_from_progress(excluded_progress_types)
+            .with_options(progress_options)
+            .with_walk_instance(walk_instance.clone());
+    // Also export progress over OTLP if the environment names an endpoint
+    #[cfg(feature = "otel")]
+    let progress_builder =
+        match otlp_stats_sink_from_env(logger, Arc::new(DefaultProgressStatsSink::new(fb)))? {
+            Some(stats_sink) => progress_builder.with_stats_sink(stats_sink),
+            None => progress_builder,
+        };
+    let progress_state =
+        ProgressStateMutex::new(progress_builder.build::<StepStats, ProgressSummary>()?)
+            .with_worker_slots(scheduled_max)
+            .with_in_flight_limit(scheduled_max);
+    progress_state.set_sample_builder(scuba_builder.clone());
+    let progress_recorder = Arc::new(progress_state.clone());
 
     let repo: BlobRepo = repo_factory
         .build(repo_name.clone(), repo_config.clone(), common_config)
@@ -482,6 +531,9 @@ async fn setup_repo<'a>(
             include_edge_types,
             hash_validation_node_types,
             scuba_builder,
+            progress_recorder,
+            walk_instance,
+            adaptive_concurrency,
         },
     ))
 }
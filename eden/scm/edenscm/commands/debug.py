@@ -676,6 +676,60 @@ def debugcolor(ui, **opts) -> None:
         return _debugdisplaycolor(ui)
 
 
+@command(
+    "debugcommandserver",
+    [
+        ("", "list", None, _("list running command servers")),
+        ("", "metrics", None, _("show usage of exited command servers")),
+    ],
+    "",
+    norepo=True,
+)
+def debugcommandserver(ui, **opts) -> None:
+    """inspect command servers"""
+    if opts.get("metrics"):
+        summary = bindings.commandserver.metricssummary()
+        for name in [
+            "servers",
+            "unused_servers",
+            "uptime_ms",
+            "commands_served",
+            "failed_commands",
+            "saved_ms",
+        ]:
+            ui.write(_x("%s: %s\n") % (name, summary[name]))
+        return
+    if not opts.get("list"):
+        raise error.Abort(_("no action specified (try --list or --metrics)"))
+    servers = bindings.commandserver.listservers()
+    ui.write(
+        _x("%8s %-12s %-20s %10s %10s %7s %s\n")
+        % ("PID", "STATE", "VERSION", "UPTIME", "IDLE", "CLIENTS", "SOCKET")
+    )
+
+    def seconds(ms):
+        if ms is None:
+            return "-"
+        return "%ds" % (ms // 1000)
+
+    for server in servers:
+        ping = server["ping"] or {}
+        ui.write(
+            _x("%8s %-12s %-20s %10s %10s %7s %s\n")
+            % (
+                server["pid"] or "-",
+                server["state"],
+                ping.get("version") or "-",
+                seconds(ping.get("uptime_ms")),
+                seconds(server["idle_ms"]),
+                ping.get("active_clients", "-"),
+                server["socket_path"],
+            )
+        )
+        if ping.get("log_path"):
+            ui.write(_x("%8s log: %s\n") % ("", ping["log_path"]))
+
+
 @command("debugcompactmetalog", [], "")
 def debugcompactmetalog(ui, repo) -> None:
     """compact the metalog by dropping history"""
@@ -0,0 +1,32 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use commandserver::client::MetricsSummary;
+use commandserver::client::ServerInfo;
+use cpython::*;
+use cpython_ext::convert::Serde;
+use cpython_ext::ResultPyErrExt;
+
+pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
+    let name = [package, "commandserver"].join(".");
+    let m = PyModule::new(py, &name)?;
+    m.add(py, "listservers", py_fn!(py, list_servers()))?;
+    m.add(py, "metricssummary", py_fn!(py, metrics_summary()))?;
+    Ok(m)
+}
+
+/// List command servers in the runtime directory, as a list of dicts.
+fn list_servers(py: Python) -> PyResult<Serde<Vec<ServerInfo>>> {
+    let servers = commandserver::client::list_servers().map_pyerr(py)?;
+    Ok(Serde(servers))
+}
+
+/// Add up the usage records of exited command servers, as a dict.
+fn metrics_summary(py: Python) -> PyResult<Serde<MetricsSummary>> {
+    let summary = commandserver::client::metrics_summary().map_pyerr(py)?;
+    Ok(Serde(summary))
+}
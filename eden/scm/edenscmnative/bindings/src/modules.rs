@@ -45,6 +45,7 @@ pub(crate) fn populate_module(py: Python<'_>, module: &PyModule) -> PyResult<PyN
             checkout,
             clientinfo,
             cliparser,
+            commandserver,
             conchparser,
             configloader,
             copytrace,
@@ -5,86 +5,278 @@
  * GNU General Public License version 2.
  */
 
+use std::fmt;
+use std::io;
 use std::io::IsTerminal;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
 
+use anyhow::Context;
 use configmodel::Config;
 use configmodel::ConfigExt;
+use nodeipc::NodeIpc;
+use serde::Serialize;
 use udsipc::pool;
 
+use crate::idle;
 use crate::ipc::Client;
+use crate::ipc::ClientHello;
 use crate::ipc::CommandEnv;
+use crate::ipc::Handshake;
+use crate::ipc::HandshakeReply;
+use crate::ipc::Hello;
+use crate::ipc::PingInfo;
+use crate::ipc::ProbeIpc;
 use crate::ipc::ProcessProps;
 use crate::ipc::ServerIpc;
+use crate::metrics;
+pub use crate::metrics::MetricsSummary;
+use crate::serverlog;
 use crate::spawn;
 use crate::util;
+use crate::util::CompatFingerprint;
+
+/// Skip servers that do not answer `ping` for this long.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Treat servers that do not answer the handshake for this long as
+/// incompatible. Servers predating the handshake do not answer it.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connections to try, skipping unresponsive servers, before giving up,
+/// unless `commandserver.connect-attempts` is set.
+const CONNECT_ATTEMPTS: usize = 3;
+
+/// Delay after the first failed connection, in milliseconds, unless
+/// `commandserver.connect-backoff` is set. With the default attempts, the
+/// delays add up to at most 45ms.
+const CONNECT_BACKOFF_MS: u64 = 10;
+
+/// Estimated startup time of running a command without a server, in
+/// milliseconds, unless `commandserver.startup-cost` is set. Metrics only.
+const DEFAULT_STARTUP_COST_MS: u64 = 300;
+
+/// Why a command did not run via a server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FallbackReason {
+    /// The command should not run via a server, see `should_run_remotely`.
+    Skipped(&'static str),
+    /// No server is waiting for clients.
+    NoServer,
+    /// Connecting or talking to a server failed, with the `io::ErrorKind`
+    /// name, or "other".
+    ConnectError(String),
+    /// The server did not answer in time.
+    Timeout,
+    /// The server cannot serve this client. Names of the mismatched
+    /// attributes, see `CompatFingerprint`, "config" for `ConfigStamp`, and
+    /// `Handshake::mismatches`.
+    Incompatible(Vec<String>),
+}
+
+impl FallbackReason {
+    /// Classify an error of connecting or talking to a server.
+    fn from_error(error: &anyhow::Error) -> Self {
+        let kind = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<io::Error>())
+            .map(|e| e.kind());
+        match kind {
+            // `SO_RCVTIMEO` reports `EAGAIN`.
+            Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Self::Timeout,
+            Some(kind) => Self::ConnectError(format!("{:?}", kind)),
+            None => Self::ConnectError("other".to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for FallbackReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Skipped(why) => write!(f, "skipped: {}", why),
+            Self::NoServer => write!(f, "no-server"),
+            Self::ConnectError(kind) => write!(f, "connect-error: {}", kind),
+            Self::Timeout => write!(f, "timeout"),
+            Self::Incompatible(names) => write!(f, "incompatible: {}", names.join(", ")),
+        }
+    }
+}
+
+/// Error of `run_via_commandserver`, with the reason to fall back.
+#[derive(Debug)]
+pub struct FallbackError {
+    pub reason: FallbackReason,
+    pub error: anyhow::Error,
+}
+
+impl FallbackError {
+    fn new(reason: FallbackReason, error: anyhow::Error) -> Self {
+        Self { reason, error }
+    }
+}
+
+impl fmt::Display for FallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not using commandserver ({})", self.reason)
+    }
+}
+
+impl std::error::Error for FallbackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+impl From<anyhow::Error> for FallbackError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<FallbackError>() {
+            Ok(e) => e,
+            Err(error) => Self::new(FallbackReason::from_error(&error), error),
+        }
+    }
+}
+
+/// How `connect_responsive` retries transient failures, like a server that
+/// is exiting, or a socket removed by another client.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Connections to try, at least 1.
+    pub attempts: usize,
+    /// Delay after the first failure. Doubled after each failure, with
+    /// jitter.
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Try once.
+    pub const NONE: Self = Self {
+        attempts: 1,
+        backoff: Duration::ZERO,
+    };
+
+    /// Read `commandserver.connect-attempts` and
+    /// `commandserver.connect-backoff` (milliseconds).
+    pub fn from_config(config: &dyn Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            attempts: config
+                .get_or::<usize>("commandserver", "connect-attempts", || CONNECT_ATTEMPTS)?,
+            backoff: Duration::from_millis(config.get_or::<u64>(
+                "commandserver",
+                "connect-backoff",
+                || CONNECT_BACKOFF_MS,
+            )?),
+        })
+    }
+
+    /// Delay before the next attempt, after `failures` failed attempts.
+    fn delay(&self, failures: usize) -> Duration {
+        // Jitter 50% to 150%, so clients racing for servers spread out.
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let jitter = 0.5 + (nanos % 1000) as f64 / 1000.0;
+        let exp = failures.saturating_sub(1).min(16) as u32;
+        self.backoff.mul_f64(jitter) * 2u32.pow(exp)
+    }
+}
 
 /// Connect to a server to run a command. Returns exit code.
 ///
-/// Error when no compatible server can be connected.
+/// Error when no compatible server can be connected, with the reason.
 /// Spawn new servers on demand.
-pub fn run_via_commandserver(args: Vec<String>, config: &dyn Config) -> anyhow::Result<i32> {
+pub fn run_via_commandserver(args: Vec<String>, config: &dyn Config) -> Result<i32, FallbackError> {
+    run(args, config).map_err(|e| {
+        let e = FallbackError::from(e);
+        tracing::info!(reason = %e.reason, "not using commandserver");
+        tracing::debug!("not using commandserver:\n{:?}", &e.error);
+        e
+    })
+}
+
+fn run(args: Vec<String>, config: &dyn Config) -> anyhow::Result<i32> {
     let (should, reason) = should_run_remotely(&args);
     if !should {
-        tracing::debug!("skipped using commandserver: {}", reason);
-        anyhow::bail!("skipped using commandserver: {}", reason);
+        let error = anyhow::format_err!("skipped using commandserver: {}", reason);
+        return Err(FallbackError::new(FallbackReason::Skipped(reason), error).into());
     }
 
-    // For now, the server does not fork and can only be used with "exclusive".
-    let exclusive = true;
-    let dir = util::runtime_dir()?;
+    // Servers serving one client are consumed by it. Take them exclusively,
+    // so no other client waits for them. Servers serving clients
+    // concurrently are shared.
+    let exclusive = spawn::max_clients(config)? <= 1;
     let prefix = util::prefix();
-    let ipc = match pool::connect(&dir, prefix, exclusive) {
+    let retry = RetryPolicy::from_config(config)?;
+    // Named pipes do not need the runtime directory to connect.
+    let pipe_name = util::pipe_name();
+    let abstract_name = util::abstract_socket_name(Some(config));
+    let connected = match (&pipe_name, &abstract_name) {
+        (Some(name), _) => retry_connect(&retry, || connect_pipe_once(name)),
+        (None, Some(name)) => retry_connect(&retry, || connect_abstract_once(name)),
+        (None, None) => connect_responsive(&util::runtime_dir()?, prefix, exclusive, &retry),
+    };
+    let client = match connected {
         Err(e) => {
             tracing::debug!("no server to connect:\n{:?}", &e);
-            if pool::list_uds_paths(&dir, prefix).next().is_none() {
+            let no_server = if pipe_name.is_some() || abstract_name.is_some() {
+                e.downcast_ref::<FallbackError>()
+                    .is_some_and(|e| e.reason == FallbackReason::NoServer)
+            } else {
+                pool::list_uds_paths(&util::runtime_dir()?, prefix)
+                    .next()
+                    .is_none()
+            };
+            let incompatible = e
+                .downcast_ref::<FallbackError>()
+                .is_some_and(|e| matches!(e.reason, FallbackReason::Incompatible(_)));
+            if no_server {
                 // No servers are running. Spawn a pool of servers.
-                let pool_size = config.get_or::<usize>("commandserver", "pool-size", || 2)?;
-                let _ = spawn::spawn_pool(pool_size);
+                let _ = spawn::spawn_pool(config);
+            } else if incompatible {
+                // Servers of other versions cannot be used. Spawn one of
+                // this version for the next command.
+                let _ = spawn::spawn_one(config);
             }
-            return Err(e.into());
+            return Err(e);
         }
-        Ok(ipc) => {
-            // Going to consume one server, so spawn another one.
-            let _ = spawn::spawn_one();
-            ipc
+        Ok(client) => {
+            if exclusive {
+                // Going to consume one server, so spawn another one.
+                let _ = spawn::spawn_one(config);
+            }
+            // Also a good time to remove sockets and old logs of dead
+            // servers, and excess idle servers.
+            if let Ok(dir) = util::runtime_dir() {
+                let _ = util::cleanup_stale_sockets(&dir);
+                let _ = serverlog::cleanup_old_logs(&dir);
+                let _ = spawn::reap_idle(spawn::max_idle(config)?);
+            }
+            client
         }
     };
 
-    tracing::debug!("sending stdio to server");
-    ipc.send_stdio()?;
-
     // Check if the server is compatible.
-    let client = Client { ipc };
     let props: ProcessProps = ServerIpc::process_props(&client)?;
-    if let Some(ref server_groups) = props.groups {
-        if let Some(ref client_groups) = util::groups() {
-            if server_groups != client_groups {
-                tracing::debug!("server groups mismatch");
-                anyhow::bail!("Server groups do not match");
-            }
-        }
-    }
-    if let Some(server_nofile) = props.rlimit_nofile {
-        if let Some(client_nofile) = util::rlimit_nofile() {
-            if server_nofile < client_nofile {
-                tracing::debug!("server RLIMIT_NOFILE incompatible");
-                anyhow::bail!("Server RLIMIT_NOFILE is incompatible");
-            }
-        }
-    }
+    check_props(&props, &CompatFingerprint::current())?;
 
     // Replace the server's env vars and chdir.
     // Disable demandimport as modules are expected to be pre-imported.
     let mut env = CommandEnv::current()?;
     env.env
         .push(("HGDEMANDIMPORT".to_owned(), "disable".to_owned()));
-    let mask = util::get_umask();
-    let applied = ServerIpc::apply_env(&client, env, mask)?;
+    let applied = ServerIpc::apply_env(&client, env)?;
     if !applied {
         tracing::debug!("server apply_env failed");
         anyhow::bail!("Server cannot apply env");
     }
 
+    // Tell the server what it saves, for metrics.
+    let startup_cost =
+        config.get_or::<u64>("commandserver", "startup-cost", || DEFAULT_STARTUP_COST_MS)?;
+    let _ = ServerIpc::add_saved_time(&client, startup_cost);
+
     // We're likely going to use this command server.
     // Forward signals so terminal resize, etc can work.
     forward_signals(&props);
@@ -92,11 +284,359 @@ pub fn run_via_commandserver(args: Vec<String>, config: &dyn Config) -> anyhow::
     // Send the run_command request.
     // Note the server might ask the client for "ui.system" requests.
     tracing::debug!("sending command request");
-    let ret = ServerIpc::run_command(&client, args.clone())?;
+    let ret = ServerIpc::run_command(&client, args.clone(), util::get_umask())?;
     tracing::debug!("command {:?} returned: {}", &args, ret);
     Ok(ret)
 }
 
+/// Check the server reported by `props` can serve a client with `compat`,
+/// and loaded the current config.
+fn check_props(props: &ProcessProps, compat: &CompatFingerprint) -> anyhow::Result<()> {
+    let mismatches = compat.mismatches(&props.compat);
+    if !mismatches.is_empty() {
+        tracing::debug!(
+            "server incompatible: {:?}\nserver: {:?}\nclient: {:?}",
+            &mismatches,
+            &props.compat.attributes,
+            &compat.attributes,
+        );
+        let error = anyhow::format_err!("Server is incompatible: {}", mismatches.join(", "));
+        let names = mismatches.iter().map(|s| s.to_string()).collect();
+        return Err(FallbackError::new(FallbackReason::Incompatible(names), error).into());
+    }
+
+    // Check if the server loaded the current config. A stale server exits
+    // after this client disconnects, and the server spawned on connect
+    // replaces it.
+    let changed = props.config.changed_paths();
+    if !changed.is_empty() {
+        tracing::debug!("server config outdated: {:?}", &changed);
+        let error = anyhow::format_err!(
+            "Server config is outdated: {}",
+            changed
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let reason = FallbackReason::Incompatible(vec!["config".to_owned()]);
+        return Err(FallbackError::new(reason, error).into());
+    }
+
+    Ok(())
+}
+
+/// Connect to a server that answers `ping` within `PING_TIMEOUT`.
+///
+/// Failures are retried with backoff, following `retry`. Servers that do
+/// not answer are skipped. Connecting already removed their sockets, and
+/// disconnecting lets a server that is only slow exit.
+///
+/// Errors are `FallbackError`s. If the last attempt finds no server, the
+/// error of the previous attempt is returned, as it is more interesting.
+fn connect_responsive(
+    dir: &Path,
+    prefix: &str,
+    exclusive: bool,
+    retry: &RetryPolicy,
+) -> anyhow::Result<Client> {
+    retry_connect(retry, || connect_once(dir, prefix, exclusive))
+}
+
+/// Call `connect` until it succeeds, following `retry`.
+/// See `connect_responsive` for errors.
+fn retry_connect(
+    retry: &RetryPolicy,
+    connect: impl Fn() -> anyhow::Result<Client>,
+) -> anyhow::Result<Client> {
+    let mut last_error: Option<FallbackError> = None;
+    for failures in 0..retry.attempts.max(1) {
+        if failures > 0 {
+            std::thread::sleep(retry.delay(failures));
+        }
+        let error = match connect() {
+            Ok(client) => return Ok(client),
+            Err(e) => FallbackError::from(e),
+        };
+        tracing::debug!(
+            "connect attempt {} failed:\n{:?}",
+            failures + 1,
+            &error.error
+        );
+        if error.reason == FallbackReason::NoServer {
+            return Err(last_error.unwrap_or(error).into());
+        }
+        last_error = Some(error);
+    }
+    Err(last_error.expect("attempted at least once").into())
+}
+
+fn connect_once(dir: &Path, prefix: &str, exclusive: bool) -> anyhow::Result<Client> {
+    if pool::list_uds_paths(dir, prefix).next().is_none() {
+        let error = anyhow::format_err!("No server in {}", dir.display());
+        return Err(FallbackError::new(FallbackReason::NoServer, error).into());
+    }
+    let ipc = pool::connect(dir, prefix, exclusive)?;
+    handshake(ipc)
+}
+
+/// Connect to the server listening at the Linux abstract socket `name`.
+///
+/// Unlike socket files in the private runtime directory, abstract names can
+/// be bound by any user. The server must run as the same user.
+fn connect_abstract_once(name: &str) -> anyhow::Result<Client> {
+    #[cfg(target_os = "linux")]
+    {
+        let ipc = match udsipc::ipc::connect_abstract(name) {
+            Err(e)
+                if e.downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::ConnectionRefused) =>
+            {
+                let error = e.context(format!("No server at abstract socket {}", name));
+                return Err(FallbackError::new(FallbackReason::NoServer, error).into());
+            }
+            r => r?,
+        };
+        util::check_peer_credentials(&ipc)?;
+        return handshake(ipc);
+    }
+
+    #[allow(unreachable_code)]
+    {
+        anyhow::bail!("abstract socket {} is not supported", name);
+    }
+}
+
+/// Connect to a server waiting at the Windows named pipe `name`.
+///
+/// All servers of the user and version wait at instances of the same pipe.
+/// Fail with `FallbackReason::NoServer` if none is waiting, because all are
+/// busy or none is running.
+fn connect_pipe_once(name: &str) -> anyhow::Result<Client> {
+    #[cfg(windows)]
+    {
+        let ipc = match udsipc::ipc::connect_pipe(name) {
+            Err(e)
+                if e.chain()
+                    .filter_map(|e| e.downcast_ref::<io::Error>())
+                    .any(|e| e.kind() == io::ErrorKind::NotFound) =>
+            {
+                let error = e.context(format!("No server waiting at pipe {}", name));
+                return Err(FallbackError::new(FallbackReason::NoServer, error).into());
+            }
+            r => r?,
+        };
+        return handshake(ipc);
+    }
+
+    #[allow(unreachable_code)]
+    {
+        anyhow::bail!("named pipe {} is not supported", name);
+    }
+}
+
+/// Start serving as a client on a new connection, and check that the server
+/// is responsive.
+fn handshake(ipc: NodeIpc) -> anyhow::Result<Client> {
+    send_hello(&ipc, Hello::Client)?;
+    tracing::debug!("sending stdio to server");
+    ipc.send_stdio()?;
+    let client = Client { ipc };
+    let info = ping(&client, PING_TIMEOUT)?;
+    tracing::debug!("server answered ping: {:?}", &info);
+    Ok(client)
+}
+
+/// Send `hello` as the first message on a new connection, and check the
+/// server accepts it.
+///
+/// Servers that reject the handshake, close the connection, or do not
+/// answer within `HANDSHAKE_TIMEOUT` are `FallbackReason::Incompatible`.
+fn send_hello(ipc: &NodeIpc, hello: Hello) -> anyhow::Result<()> {
+    let current = Handshake::current();
+    ipc.send(ClientHello {
+        handshake: current.clone(),
+        hello,
+    })?;
+    util::set_read_timeout(ipc, Some(HANDSHAKE_TIMEOUT))?;
+    let (mismatches, error) = match ipc.recv::<HandshakeReply>() {
+        Ok(Some(HandshakeReply::Accepted(server))) => {
+            let mismatches = current.mismatches(&server, &hello);
+            if mismatches.is_empty() {
+                util::set_read_timeout(ipc, None)?;
+                return Ok(());
+            }
+            let error = anyhow::format_err!("Server is incompatible: {:?}", &server);
+            (mismatches, error)
+        }
+        Ok(Some(HandshakeReply::Incompatible { server, mismatches })) => {
+            let error = anyhow::format_err!("Server rejected the client: {:?}", &server);
+            (mismatches, error)
+        }
+        Ok(None) => {
+            let error = anyhow::format_err!(
+                "Server closed the connection without answering the handshake, possibly rejecting the client's credentials"
+            );
+            (vec!["protocol".to_owned()], error)
+        }
+        Err(e) => {
+            let error = e.context(
+                "Server did not answer the handshake in time, or answered in another protocol",
+            );
+            (vec!["protocol".to_owned()], error)
+        }
+    };
+    Err(FallbackError::new(FallbackReason::Incompatible(mismatches), error).into())
+}
+
+/// Ask a connected server about itself, waiting at most `timeout` for the
+/// answer on POSIX.
+pub fn ping(client: &Client, timeout: Duration) -> anyhow::Result<PingInfo> {
+    util::set_read_timeout(&client.ipc, Some(timeout))?;
+    // The server drops connections from other users without replying.
+    let info = ServerIpc::ping(client).context(
+        "Server did not answer ping in time, or closed the connection, possibly rejecting the client's credentials",
+    )?;
+    util::set_read_timeout(&client.ipc, None)?;
+    Ok(info)
+}
+
+/// Stop all idle servers in the runtime directory, including servers of
+/// other versions. Return the socket path and result of each server.
+///
+/// Busy servers are not listed. They exit after serving their client.
+/// Neither are servers using abstract sockets or named pipes, which have no
+/// socket files.
+pub fn stop_all() -> anyhow::Result<Vec<(PathBuf, anyhow::Result<()>)>> {
+    let dir = util::runtime_dir()?;
+    Ok(stop_all_in(&dir))
+}
+
+fn stop_all_in(dir: &Path) -> Vec<(PathBuf, anyhow::Result<()>)> {
+    list_all_server_paths(dir)
+        .map(|path| {
+            let socket_path = path.path().to_owned();
+            let result = stop_one(path);
+            tracing::debug!("stopping {}: {:?}", socket_path.display(), &result);
+            (socket_path, result)
+        })
+        .collect()
+}
+
+fn stop_one(path: pool::ConnectablePath) -> anyhow::Result<()> {
+    let socket_path = path.path().to_owned();
+    // Take the server, so no other client can connect to it.
+    path.exclusive()
+        .and_then(stop_taken)
+        .with_context(|| format!("Stopping server at {}", socket_path.display()))
+}
+
+/// Ask the server at `path`, already made exclusive, to stop.
+///
+/// Connecting removes the socket file, even if the server does not answer.
+/// Such servers exit once they notice.
+pub(crate) fn stop_taken(path: pool::ConnectablePath) -> anyhow::Result<()> {
+    let ipc = path.connect()?;
+    // The server stops after accepting the handshake.
+    send_hello(&ipc, Hello::Stop)?;
+    Ok(())
+}
+
+/// A server found in the runtime directory.
+#[derive(Debug, Serialize)]
+pub struct ServerInfo {
+    pub socket_path: PathBuf,
+    /// Parsed from the socket name.
+    pub pid: Option<u32>,
+    pub state: ServerState,
+    /// Answer to `ping`, if the server is alive.
+    pub ping: Option<PingInfo>,
+    /// How long the server has been waiting for a client, if known.
+    pub idle_ms: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerState {
+    /// Answered `ping`.
+    Alive,
+    /// The process is running, but did not answer `ping` in time.
+    Unresponsive,
+    /// The process is gone. Listing removes its socket.
+    Dead,
+}
+
+/// List servers waiting for clients in the runtime directory, including
+/// servers of other versions.
+///
+/// Servers are probed without being claimed, so they remain usable.
+/// Busy servers, and servers using abstract sockets or named pipes, are not
+/// listed.
+pub fn list_servers() -> anyhow::Result<Vec<ServerInfo>> {
+    let dir = util::runtime_dir()?;
+    list_servers_in(&dir)
+}
+
+fn list_servers_in(dir: &Path) -> anyhow::Result<Vec<ServerInfo>> {
+    let idle_since = idle::idle_since_by_socket(dir).unwrap_or_default();
+    let now = SystemTime::now();
+    let servers = list_all_server_paths(dir)
+        .map(|path| {
+            let socket_path = path.path().to_owned();
+            let pid = socket_path.file_name().and_then(util::socket_pid);
+            let ping = match probe(path) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    tracing::debug!("probing {}:\n{:?}", socket_path.display(), &e);
+                    None
+                }
+            };
+            let state = if ping.is_some() {
+                ServerState::Alive
+            } else if pid.is_some_and(util::is_process_alive) {
+                ServerState::Unresponsive
+            } else {
+                ServerState::Dead
+            };
+            let idle_ms = idle_since
+                .get(&socket_path)
+                .and_then(|t| now.duration_since(*t).ok())
+                .map(|d| d.as_millis() as u64);
+            ServerInfo {
+                socket_path,
+                pid,
+                state,
+                ping,
+                idle_ms,
+            }
+        })
+        .collect();
+    Ok(servers)
+}
+
+/// Sockets of servers of all versions in `dir`.
+fn list_all_server_paths(dir: &Path) -> impl Iterator<Item = pool::ConnectablePath> {
+    // An empty prefix matches all files, including other files of this crate.
+    pool::list_uds_paths(dir, "")
+        .filter(|path| !idle::is_meta_path(path.path()) && !metrics::is_metrics_path(path.path()))
+}
+
+/// Add up the usage records of servers that exited.
+pub fn metrics_summary() -> anyhow::Result<MetricsSummary> {
+    let dir = util::runtime_dir()?;
+    Ok(metrics::summarize(&dir))
+}
+
+/// Ping a server without claiming it.
+fn probe(path: pool::ConnectablePath) -> anyhow::Result<PingInfo> {
+    let ipc = path.connect()?;
+    send_hello(&ipc, Hello::Probe)?;
+    let client = Client { ipc };
+    util::set_read_timeout(&client.ipc, Some(PING_TIMEOUT))?;
+    let info = ProbeIpc::ping(&client)?;
+    Ok(info)
+}
+
 /// Check if a command should run remotely, with reasons.
 /// See also `hgmain::chg`.
 fn should_run_remotely(args: &[String]) -> (bool, &'static str) {
@@ -172,3 +712,700 @@ fn forward_signals(props: &ProcessProps) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::ipc::Server;
+    use crate::server::serve_one_client_at;
+
+    // Bind a socket that passes `udsipc::ipc::check_private_socket`, without
+    // serving it.
+    #[cfg(unix)]
+    fn bind_private(path: &Path) -> std::os::unix::net::UnixListener {
+        use std::os::unix::fs::PermissionsExt;
+        let listener = std::os::unix::net::UnixListener::bind(path).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        listener
+    }
+
+    #[test]
+    fn test_stop_all() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-stop-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let results = stop_all_in(&dir);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].0, socket_path);
+            results[0].1.as_ref().unwrap();
+
+            server.join().unwrap().unwrap();
+            assert!(!socket_path.exists());
+            assert!(!socket_path.with_extension("private").exists());
+        });
+
+        // Nothing left to stop.
+        assert!(stop_all_in(&dir).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_racing_servers() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-race-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+        let barrier = std::sync::Barrier::new(2);
+
+        std::thread::scope(|s| {
+            // Both bind the same socket path, since they share the pid.
+            let servers = [(); 2].map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    serve_one_client_at(&dir, "p", &run_func)
+                })
+            });
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+
+            // One gives up quietly, without removing the other's socket.
+            while !servers.iter().any(|s| s.is_finished()) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(socket_path.exists());
+            let results = stop_all_in(&dir);
+            assert_eq!(results.len(), 1);
+            results[0].1.as_ref().unwrap();
+
+            for server in servers {
+                server.join().unwrap().unwrap();
+            }
+            assert!(!socket_path.exists());
+            assert!(!socket_path.with_extension("lock").exists());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ping() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-ping-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            let info = ping(&client, Duration::from_secs(10)).unwrap();
+            assert_eq!(info.pid, std::process::id());
+            assert_eq!(info.version, version::VERSION);
+            assert_eq!(info.cli_name, identity::cli_name());
+            assert_eq!(info.commands_served, 0);
+
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ping_no_listener() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-noping-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Connections are queued, but never accepted.
+        let socket_path = dir.join("p-1");
+        let _listener = bind_private(&socket_path);
+
+        let ipc = pool::connect(&dir, "p", false).unwrap();
+        let client = Client { ipc };
+        let start = std::time::Instant::now();
+        assert!(ping(&client, Duration::from_millis(100)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // The unresponsive server is skipped, and its socket is removed.
+        assert!(connect_responsive(&dir, "p", true, &RetryPolicy::NONE).is_err());
+        assert!(!socket_path.exists());
+        assert!(!socket_path.with_extension("private").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_servers() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-list-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // A dead server. Its socket file remains, but nothing listens.
+        let dead_path = dir.join(format!("q-{}", i32::MAX));
+        drop(bind_private(&dead_path));
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let mut servers = list_servers_in(&dir).unwrap();
+            servers.sort_by_key(|s| s.socket_path.clone());
+            assert_eq!(servers.len(), 2);
+
+            let live = &servers[0];
+            assert_eq!(live.socket_path, socket_path);
+            assert_eq!(live.pid, Some(std::process::id()));
+            assert_eq!(live.state, ServerState::Alive);
+            let info = live.ping.as_ref().unwrap();
+            assert_eq!(info.pid, std::process::id());
+            assert_eq!(info.version, version::VERSION);
+
+            let dead = &servers[1];
+            assert_eq!(dead.socket_path, dead_path);
+            assert_eq!(dead.state, ServerState::Dead);
+            assert!(dead.ping.is_none());
+            assert!(!dead_path.exists());
+
+            // Listing does not claim the server.
+            assert!(socket_path.exists());
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_metrics() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-usage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // The first arg is the exit code.
+        let run_func = |_: &Server, args: Vec<String>| -> i32 { args[0].parse().unwrap() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            for code in ["0", "1"] {
+                ServerIpc::add_saved_time(&client, 100).unwrap();
+                let ret = ServerIpc::run_command(&client, vec![code.to_owned()], None).unwrap();
+                assert_eq!(ret.to_string(), code);
+            }
+            assert_eq!(
+                ping(&client, Duration::from_secs(10))
+                    .unwrap()
+                    .commands_served,
+                2
+            );
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+
+        let summary = metrics::summarize(&dir);
+        assert_eq!(summary.servers, 1);
+        assert_eq!(summary.unused_servers, 0);
+        assert_eq!(summary.commands_served, 2);
+        assert_eq!(summary.failed_commands, 1);
+        assert_eq!(summary.saved_ms, 200);
+
+        // The metrics are not mistaken for a server.
+        assert!(list_servers_in(&dir).unwrap().is_empty());
+        assert!(stop_all_in(&dir).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fallback_reasons() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-fallback-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let retry = RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let connect = || {
+            FallbackError::from(connect_responsive(&dir, "p", true, &retry).err().unwrap()).reason
+        };
+
+        assert_eq!(connect(), FallbackReason::NoServer);
+
+        // A dead server. Its socket is removed, then no server is found.
+        let socket_path = dir.join("p-1");
+        drop(bind_private(&socket_path));
+        assert_eq!(
+            connect(),
+            FallbackReason::ConnectError("ConnectionRefused".to_owned())
+        );
+        assert!(!socket_path.exists());
+
+        // A server that does not answer the handshake.
+        let _listener = bind_private(&dir.join("p-2"));
+        assert_eq!(
+            connect(),
+            FallbackReason::Incompatible(vec!["protocol".to_owned()])
+        );
+
+        // An incompatible server.
+        let compat = CompatFingerprint::current();
+        let mut props = ProcessProps {
+            pid: 1,
+            pgid: 1,
+            compat: compat.clone(),
+            config: Default::default(),
+        };
+        check_props(&props, &compat).unwrap();
+        props
+            .compat
+            .attributes
+            .insert("env_hash".to_owned(), "other".to_owned());
+        let error = FallbackError::from(check_props(&props, &compat).unwrap_err());
+        assert_eq!(
+            error.reason,
+            FallbackReason::Incompatible(vec!["env_hash".to_owned()])
+        );
+        assert_eq!(
+            error.to_string(),
+            "Not using commandserver (incompatible: env_hash)"
+        );
+
+        // Skipped before connecting.
+        let config: BTreeMap<&str, &str> = BTreeMap::new();
+        let args = vec!["sl".to_owned(), "/dev/fd/3".to_owned()];
+        assert_eq!(
+            run_via_commandserver(args, &config).unwrap_err().reason,
+            FallbackReason::Skipped("arg starts with /dev/fd or /proc/self/")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_handshake() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-handshake-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let send = |handshake: Handshake, hello: Hello| {
+                let ipc = pool::connect(&dir, "p", false).unwrap();
+                ipc.send(ClientHello { handshake, hello }).unwrap();
+                let reply = ipc.recv::<HandshakeReply>().unwrap().unwrap();
+                (ipc, reply)
+            };
+            let current = Handshake::current();
+            let other_protocol = Handshake {
+                protocol_version: crate::ipc::PROTOCOL_VERSION + 1,
+                ..current.clone()
+            };
+            let other_version = Handshake {
+                version: "other".to_owned(),
+                ..current.clone()
+            };
+
+            // Mismatched clients are rejected, then disconnected.
+            for (handshake, expected) in [
+                (other_protocol, "protocol"),
+                (other_version.clone(), "version"),
+            ] {
+                let (ipc, reply) = send(handshake, Hello::Client);
+                match reply {
+                    HandshakeReply::Incompatible { server, mismatches } => {
+                        assert_eq!(server, current);
+                        assert_eq!(mismatches, [expected]);
+                    }
+                    r => panic!("unexpected reply: {:?}", r),
+                }
+                // The server closed the connection.
+                assert!(!matches!(ipc.recv::<HandshakeReply>(), Ok(Some(_))));
+            }
+
+            // Probing a server of another version is fine.
+            let (_, reply) = send(other_version, Hello::Probe);
+            assert!(matches!(reply, HandshakeReply::Accepted(h) if h == current));
+
+            // The server still serves a matching client.
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+
+        // A peer that never answers, like a server predating the handshake.
+        std::fs::create_dir_all(&dir).unwrap();
+        let _listener = bind_private(&dir.join("p-1"));
+        let ipc = pool::connect(&dir, "p", false).unwrap();
+        let start = std::time::Instant::now();
+        let error = FallbackError::from(send_hello(&ipc, Hello::Client).unwrap_err());
+        assert_eq!(
+            error.reason,
+            FallbackReason::Incompatible(vec!["protocol".to_owned()])
+        );
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let config: BTreeMap<&str, &str> = BTreeMap::new();
+        let retry = RetryPolicy::from_config(&config).unwrap();
+        assert_eq!(retry.attempts, CONNECT_ATTEMPTS);
+        let total: Duration = (1..retry.attempts).map(|i| retry.delay(i)).sum();
+        assert!(total < Duration::from_millis(50), "{:?}", total);
+
+        let config: BTreeMap<&str, &str> = [
+            ("commandserver.connect-attempts", "1"),
+            ("commandserver.connect-backoff", "0"),
+        ]
+        .into_iter()
+        .collect();
+        let retry = RetryPolicy::from_config(&config).unwrap();
+        assert_eq!(retry.attempts, 1);
+        assert_eq!(retry.delay(1), Duration::ZERO);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket() {
+        use crate::server::serve_one_client_abstract;
+
+        let dir = std::env::temp_dir().join(format!("cmdserver-abstract-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let name = format!("cmdserver-test-{}", std::process::id());
+        let run_func = |_: &Server, args: Vec<String>| -> i32 { args.len() as i32 };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_abstract(&dir, &name, &run_func));
+            let client = (0..500)
+                .find_map(|_| match connect_abstract_once(&name) {
+                    Ok(client) => Some(client),
+                    Err(_) => {
+                        std::thread::sleep(Duration::from_millis(10));
+                        None
+                    }
+                })
+                .expect("server should listen");
+            let args = vec!["a".to_owned(), "b".to_owned()];
+            assert_eq!(ServerIpc::run_command(&client, args, None).unwrap(), 2);
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+
+        // No socket files. Only metrics and the log are written.
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let log_name = format!("{}-{}.log", util::prefix(), std::process::id());
+        assert_eq!(names, [log_name.as_str(), "metrics"]);
+
+        // The name is free once the server exits.
+        let error = FallbackError::from(connect_abstract_once(&name).err().unwrap());
+        assert_eq!(error.reason, FallbackReason::NoServer);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_named_pipe() {
+        use crate::server::serve_one_client_pipe;
+
+        let dir = std::env::temp_dir().join(format!("cmdserver-pipe-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let name = format!("cmdserver-test-{}", std::process::id());
+        let run_func = |_: &Server, args: Vec<String>| -> i32 { args.len() as i32 };
+
+        // Each user and elevation has its own pipe.
+        let pipe_name = util::pipe_name().unwrap();
+        assert!(pipe_name.ends_with(&udsipc::pipe::current_user_id().unwrap()));
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_pipe(&dir, &name, &run_func));
+            let client = (0..500)
+                .find_map(|_| match connect_pipe_once(&name) {
+                    Ok(client) => Some(client),
+                    Err(_) => {
+                        std::thread::sleep(Duration::from_millis(10));
+                        None
+                    }
+                })
+                .expect("server should listen");
+            let args = vec!["a".to_owned(), "b".to_owned()];
+            assert_eq!(ServerIpc::run_command(&client, args, None).unwrap(), 2);
+
+            // The server is busy. Other clients fall back.
+            let error = FallbackError::from(connect_pipe_once(&name).err().unwrap());
+            assert_eq!(error.reason, FallbackReason::NoServer);
+
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+
+        // No socket files. Only metrics and the log are written.
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let log_name = format!("{}-{}.log", util::prefix(), std::process::id());
+        assert_eq!(names, [log_name.as_str(), "metrics"]);
+
+        // No instance is left once the server exits.
+        let error = FallbackError::from(connect_pipe_once(&name).err().unwrap());
+        assert_eq!(error.reason, FallbackReason::NoServer);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_concurrent_clients() {
+        use crate::server::serve_incoming;
+
+        let dir = std::env::temp_dir().join(format!("cmdserver-concurrent-{}", std::process::id()));
+        let cwd_dir = dir.with_extension("cwd");
+        let _ = std::fs::remove_dir_all(&dir);
+        // Runs in forked processes. Return the value applied by `apply_env`,
+        // if it is still in place after the other command applied its own.
+        let run_func = |_: &Server, _: Vec<String>| -> i32 {
+            let read = || {
+                let value = std::env::var("CMDSERVER_TEST_VALUE").unwrap();
+                let cwd = std::env::current_dir().unwrap();
+                assert!(cwd.ends_with(&value));
+                value
+            };
+            let value = read();
+            std::thread::sleep(Duration::from_millis(500));
+            if read() == value {
+                value.parse().unwrap()
+            } else {
+                255
+            }
+        };
+        let run_client = |value: i32| -> i32 {
+            let client = connect_responsive(&dir, "p", false, &RetryPolicy::NONE).unwrap();
+            let cwd = cwd_dir.join(value.to_string());
+            std::fs::create_dir_all(&cwd).unwrap();
+            let env = CommandEnv {
+                env: vec![("CMDSERVER_TEST_VALUE".to_owned(), value.to_string())],
+                cwd: cwd.to_str().unwrap().to_owned(),
+            };
+            assert!(ServerIpc::apply_env(&client, env).unwrap());
+            ServerIpc::run_command(&client, Vec::new(), None).unwrap()
+        };
+        let active_clients = || {
+            let servers = list_servers_in(&dir).unwrap();
+            servers[0].ping.as_ref().unwrap().active_clients
+        };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| {
+                let incoming = pool::serve(&dir, "p").unwrap();
+                serve_incoming(&dir, incoming, None, 2, &run_func)
+            });
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let clients = [s.spawn(|| run_client(3)), s.spawn(|| run_client(5))];
+            // Both run at the same time.
+            let mut max_active = 0;
+            for _ in 0..100 {
+                max_active = max_active.max(active_clients());
+                if max_active == 2 {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(max_active, 2);
+            let [a, b] = clients.map(|c| c.join().unwrap());
+            assert_eq!((a, b), (3, 5));
+
+            while active_clients() > 0 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            // The server stays usable, until stopped.
+            assert!(socket_path.exists());
+            let results = stop_all_in(&dir);
+            results[0].1.as_ref().unwrap();
+            server.join().unwrap().unwrap();
+        });
+
+        // Counters of the forked processes reached the server.
+        assert_eq!(metrics::summarize(&dir).commands_served, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&cwd_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_concurrent_clients_reaped_while_forking() {
+        use crate::server::serve_incoming;
+        use crate::serverlog::ServerLog;
+
+        let dir = std::env::temp_dir().join(format!("cmdserver-reap-fork-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // Odd commands kill their forked process, so the server logs about
+        // reaping them while other clients are forked.
+        let run_func = |_: &Server, args: Vec<String>| -> i32 {
+            let n: i32 = args[0].parse().unwrap();
+            if n % 2 == 1 {
+                unsafe { libc::_exit(1) };
+            }
+            n
+        };
+        let run_client = |n: i32| -> Option<i32> {
+            let client = connect_responsive(&dir, "p", false, &RetryPolicy::NONE).unwrap();
+            ServerIpc::run_command(&client, vec![n.to_string()], None).ok()
+        };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| {
+                let incoming = pool::serve(&dir, "p").unwrap();
+                serve_incoming(&dir, incoming, None, 16, &run_func)
+            });
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            for batch in 0..3 {
+                let clients = (0..4)
+                    .map(|i| {
+                        let n = batch * 4 + i;
+                        (n, s.spawn(move || run_client(n)))
+                    })
+                    .collect::<Vec<_>>();
+                for (n, client) in clients {
+                    let result = client.join().unwrap();
+                    if n % 2 == 0 {
+                        assert_eq!(result, Some(n));
+                    } else {
+                        assert_eq!(result, None);
+                    }
+                }
+            }
+
+            let results = stop_all_in(&dir);
+            results[0].1.as_ref().unwrap();
+            server.join().unwrap().unwrap();
+        });
+
+        let log_socket_path = dir.join(format!("{}-{}", util::prefix(), std::process::id()));
+        let log = std::fs::read_to_string(ServerLog::for_socket(&log_socket_path).path()).unwrap();
+        assert_eq!(log.matches("exited with status").count(), 6, "{}", log);
+        // Only the commands that returned counted.
+        assert_eq!(metrics::summarize(&dir).commands_served, 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_umask() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("cmdserver-umask-{}", std::process::id()));
+        let files_dir = dir.with_extension("files");
+        let _ = std::fs::remove_dir_all(&dir);
+        let _ = std::fs::remove_dir_all(&files_dir);
+        std::fs::create_dir_all(&files_dir).unwrap();
+        // Create a file, like a hook writing to the working copy.
+        let run_func = |_: &Server, args: Vec<String>| -> i32 {
+            std::fs::write(files_dir.join(&args[0]), "").unwrap();
+            0
+        };
+        let server_umask = util::get_umask();
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            for (name, umask) in [("a", 0o022), ("b", 0o077)] {
+                let args = vec![name.to_owned()];
+                assert_eq!(
+                    ServerIpc::run_command(&client, args, Some(umask)).unwrap(),
+                    0
+                );
+                // The server umask is restored after each command.
+                assert_eq!(util::get_umask(), server_umask);
+            }
+            drop(client);
+            server.join().unwrap().unwrap();
+        });
+
+        let mode = |name: &str| {
+            let metadata = std::fs::metadata(files_dir.join(name)).unwrap();
+            metadata.permissions().mode() & 0o777
+        };
+        assert_eq!(mode("a"), 0o644);
+        assert_eq!(mode("b"), 0o600);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&files_dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_log() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-serverlog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { 3 };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            let socket_path = dir.join(format!("p-{}", std::process::id()));
+            while !socket_path.exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            // Logs are not servers.
+            assert_eq!(list_servers_in(&dir).unwrap().len(), 1);
+
+            let client = connect_responsive(&dir, "p", true, &RetryPolicy::NONE).unwrap();
+            let info = ping(&client, Duration::from_secs(10)).unwrap();
+            let log_path = info.log_path.unwrap();
+            assert_eq!(log_path, dir.join(format!("p-{}.log", std::process::id())));
+            let ret = ServerIpc::run_command(&client, vec!["x".to_owned()], None).unwrap();
+            assert_eq!(ret, 3);
+            drop(client);
+            server.join().unwrap().unwrap();
+
+            let log = std::fs::read_to_string(&log_path).unwrap();
+            assert!(log.contains(&format!("serving {}", identity::cli_name())));
+            assert!(log.contains(r#"command ["x"] failed with exit code 3"#));
+            assert!(log.contains("exiting after 1 commands"));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Stamps of the user and system config files, so clients can detect
+//! servers that still use config that was edited since.
+//!
+//! A server takes a stamp when it starts serving, and reports it in
+//! `process_props`. The client compares each file by size and mtime, and
+//! only reads files whose size or mtime changed.
+
+use std::fs;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The stamp of this process, taken on first use. Servers take it when they
+/// start serving, shortly after loading config.
+static LOADED: Lazy<ConfigStamp> = Lazy::new(|| ConfigStamp::from_paths(&config_paths()));
+
+/// A config file as seen by the server.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub path: PathBuf,
+    /// `None` if the file does not exist.
+    pub len: Option<u64>,
+    pub mtime_ns: Option<u64>,
+    pub content_hash: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigStamp {
+    pub files: Vec<FileStamp>,
+}
+
+impl ConfigStamp {
+    /// The stamp of config loaded by this process.
+    pub fn loaded() -> &'static Self {
+        &LOADED
+    }
+
+    fn from_paths(paths: &[PathBuf]) -> Self {
+        let files = paths
+            .iter()
+            .map(|path| {
+                let (len, mtime_ns) = stat(path);
+                let content_hash = len.and_then(|_| content_hash(path));
+                FileStamp {
+                    path: path.clone(),
+                    len,
+                    mtime_ns,
+                    content_hash,
+                }
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Compare the server stamp (`self`) with the config files of the client.
+    /// Return paths that changed since the server took the stamp.
+    ///
+    /// An empty stamp is from a server that does not report one, and is
+    /// considered up to date.
+    pub fn changed_paths(&self) -> Vec<PathBuf> {
+        if self.files.is_empty() {
+            return Vec::new();
+        }
+        self.changed_paths_of(&config_paths())
+    }
+
+    fn changed_paths_of(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let server_paths: Vec<&Path> = self.files.iter().map(|f| f.path.as_path()).collect();
+        if server_paths != paths.iter().map(|p| p.as_path()).collect::<Vec<_>>() {
+            // Different `CONFIG` env vars. Report all paths.
+            return paths.to_vec();
+        }
+        self.files
+            .iter()
+            .filter(|file| {
+                let (len, mtime_ns) = stat(&file.path);
+                if (len, mtime_ns) == (file.len, file.mtime_ns) {
+                    return false;
+                }
+                // Touched, but maybe not edited.
+                len != file.len || len.and_then(|_| content_hash(&file.path)) != file.content_hash
+            })
+            .map(|file| file.path.clone())
+            .collect()
+    }
+}
+
+/// User and system config paths, in loading order.
+fn config_paths() -> Vec<PathBuf> {
+    let ident = identity::default();
+    let mut paths = ident.system_config_paths();
+    paths.extend(ident.user_config_paths());
+    paths
+}
+
+/// Size and mtime of a file, or `None`s if it does not exist.
+fn stat(path: &Path) -> (Option<u64>, Option<u64>) {
+    match fs::metadata(path) {
+        Ok(meta) => {
+            let mtime_ns = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64);
+            (Some(meta.len()), mtime_ns)
+        }
+        Err(_) => (None, None),
+    }
+}
+
+fn content_hash(path: &Path) -> Option<String> {
+    let content = fs::read(path).ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    content.hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_changed_paths() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.conf");
+        let user = dir.join("user.conf");
+        let missing = dir.join("missing.conf");
+        fs::write(&system, "[ui]\nusername = a\n").unwrap();
+        fs::write(&user, "[ui]\npaginate = true\n").unwrap();
+        let paths = vec![system.clone(), user.clone(), missing.clone()];
+
+        let stamp = ConfigStamp::from_paths(&paths);
+        assert!(stamp.files[2].len.is_none());
+        assert!(stamp.changed_paths_of(&paths).is_empty());
+
+        // Rewriting the same content is not a change.
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&user, "[ui]\npaginate = true\n").unwrap();
+        assert!(stamp.changed_paths_of(&paths).is_empty());
+
+        // Editing, or creating a file, is a change. Same size here, so
+        // the content is compared.
+        fs::write(&user, "[ui]\npaginate = none\n").unwrap();
+        assert_eq!(stamp.changed_paths_of(&paths), [user.as_path()]);
+        fs::write(&missing, "").unwrap();
+        assert_eq!(stamp.changed_paths_of(&paths), [user.clone(), missing]);
+
+        // A new stamp, like a new server, is up to date.
+        let stamp = ConfigStamp::from_paths(&paths);
+        assert!(stamp.changed_paths_of(&paths).is_empty());
+
+        // Different paths.
+        assert_eq!(stamp.changed_paths_of(&paths[..1]), [system]);
+
+        // Servers without a stamp are not checked.
+        assert!(ConfigStamp::default().changed_paths().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Metadata of idle servers, so clients can limit how many stay running.
+//!
+//! Each waiting server writes `idle-{pid}` next to its socket, with the
+//! socket name and the time it started waiting. Excess idle servers are
+//! asked to stop like `stop_all` does. Servers that do not answer exit once
+//! their socket is removed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use fn_error_context::context;
+use udsipc::pool::ConnectablePath;
+
+use crate::client;
+
+const META_PREFIX: &str = "idle-";
+
+/// An idle server, according to its metadata file.
+#[derive(Debug)]
+struct IdleServer {
+    meta_path: PathBuf,
+    socket_path: PathBuf,
+    idle_since: SystemTime,
+}
+
+/// Write the metadata of this server, waiting at `socket_path`.
+/// Return the metadata path, to remove once the server gets a client.
+#[context("Writing idle server metadata")]
+pub(crate) fn write_meta(socket_path: &Path) -> anyhow::Result<PathBuf> {
+    let dir = socket_path.parent().unwrap_or(Path::new("."));
+    let name = socket_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::format_err!("Invalid socket path {}", socket_path.display()))?;
+    let idle_since = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let meta_path = dir.join(format!("{}{}", META_PREFIX, std::process::id()));
+    // Write then rename, so readers never see a partial file.
+    let tmp_path = meta_path.with_extension("tmp");
+    fs::write(
+        &tmp_path,
+        format!("socket={}\nidle_since={}\n", name, idle_since),
+    )?;
+    fs::rename(&tmp_path, &meta_path)?;
+    Ok(meta_path)
+}
+
+/// Check if a file in the runtime directory is idle server metadata.
+pub(crate) fn is_meta_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with(META_PREFIX))
+}
+
+fn read_meta(path: &Path) -> Option<IdleServer> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut socket = None;
+    let mut idle_since = None;
+    for line in content.lines() {
+        match line.split_once('=') {
+            Some(("socket", v)) => socket = Some(v),
+            Some(("idle_since", v)) => idle_since = v.parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+    Some(IdleServer {
+        meta_path: path.to_owned(),
+        socket_path: path.with_file_name(socket?),
+        idle_since: SystemTime::UNIX_EPOCH + Duration::from_millis(idle_since?),
+    })
+}
+
+/// List idle servers in `dir`, oldest idle first.
+///
+/// Metadata of servers that are no longer waiting (their socket was taken
+/// by a client, or removed) is removed.
+fn list_idle_servers(dir: &Path) -> anyhow::Result<Vec<IdleServer>> {
+    let mut servers = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue,
+        };
+        if !is_meta_path(&path) || path.extension().unwrap_or_default() == "tmp" {
+            continue;
+        }
+        match read_meta(&path) {
+            Some(server) if server.socket_path.exists() => servers.push(server),
+            _ => {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+    servers.sort_by_key(|s| s.idle_since);
+    Ok(servers)
+}
+
+/// Time each idle server in `dir` started waiting, by socket path.
+pub(crate) fn idle_since_by_socket(dir: &Path) -> anyhow::Result<HashMap<PathBuf, SystemTime>> {
+    let servers = list_idle_servers(dir)?;
+    Ok(servers
+        .into_iter()
+        .map(|s| (s.socket_path, s.idle_since))
+        .collect())
+}
+
+/// Ask the oldest idle servers in `dir` to exit, so at most `max_idle`
+/// remain. Return the number of servers asked to exit.
+///
+/// Servers that are still starting have no metadata yet, and are not counted.
+#[context("Limiting idle servers in {} to {}", dir.display(), max_idle)]
+pub(crate) fn reap_idle_servers(dir: &Path, max_idle: usize) -> anyhow::Result<usize> {
+    let servers = list_idle_servers(dir)?;
+    let excess = servers.len().saturating_sub(max_idle);
+    let mut reaped = 0;
+    for server in servers.into_iter().take(excess) {
+        let _ = fs::remove_file(&server.meta_path);
+        // Fails if a client took the socket meanwhile. Then the server is busy.
+        let path = match ConnectablePath::new(server.socket_path.clone()).exclusive() {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        match client::stop_taken(path) {
+            Ok(()) => tracing::debug!("stopped idle server {}", server.socket_path.display()),
+            Err(e) => tracing::debug!(
+                "idle server {} did not stop, its socket is removed: {:#}",
+                server.socket_path.display(),
+                e
+            ),
+        }
+        reaped += 1;
+    }
+    Ok(reaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::Server;
+    use crate::server::serve_one_client_at;
+
+    #[test]
+    fn test_reap_idle_servers() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-idle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Fake servers 1 to 5, idle since 5, 3, 1, 4, 2 seconds after the epoch.
+        for (i, idle_since) in [(1, 5000), (2, 3000), (3, 1000), (4, 4000), (5, 2000)] {
+            fs::write(dir.join(format!("p-{}", i)), "").unwrap();
+            fs::write(
+                dir.join(format!("{}{}", META_PREFIX, i)),
+                format!("socket=p-{}\nidle_since={}\n", i, idle_since),
+            )
+            .unwrap();
+        }
+        // Server 6 got a client. Its metadata is stale.
+        fs::write(dir.join("p-6.private"), "").unwrap();
+        fs::write(
+            dir.join(format!("{}6", META_PREFIX)),
+            "socket=p-6\nidle_since=0\n",
+        )
+        .unwrap();
+
+        let order: Vec<_> = list_idle_servers(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.socket_path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(order, ["p-3", "p-5", "p-2", "p-4", "p-1"]);
+        assert!(!dir.join(format!("{}6", META_PREFIX)).exists());
+
+        assert_eq!(reap_idle_servers(&dir, 2).unwrap(), 3);
+        let exists = |name: &str| dir.join(name).exists();
+        assert!(!exists("p-3") && !exists("p-5") && !exists("p-2"));
+        assert!(exists("p-4") && exists("p-1"));
+        assert!(exists("p-6.private"));
+
+        // Under the limit, nothing changes.
+        assert_eq!(reap_idle_servers(&dir, 2).unwrap(), 0);
+        assert_eq!(list_idle_servers(&dir).unwrap().len(), 2);
+
+        // Written metadata can be read back.
+        let socket_path = dir.join("p-7");
+        fs::write(&socket_path, "").unwrap();
+        let meta_path = write_meta(&socket_path).unwrap();
+        let server = read_meta(&meta_path).unwrap();
+        assert_eq!(server.socket_path, socket_path);
+        assert!(server.idle_since.elapsed().unwrap() < Duration::from_secs(60));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reap_running_server() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-reap-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let run_func = |_: &Server, _: Vec<String>| -> i32 { unreachable!() };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| serve_one_client_at(&dir, "p", &run_func));
+            while list_idle_servers(&dir).map_or(true, |servers| servers.is_empty()) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            assert_eq!(reap_idle_servers(&dir, 0).unwrap(), 1);
+            // Stopped as requested. Servers that only lose their socket exit
+            // the whole process instead.
+            server.join().unwrap().unwrap();
+        });
+
+        assert!(list_idle_servers(&dir).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
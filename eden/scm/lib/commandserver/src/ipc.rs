@@ -7,9 +7,11 @@
 
 use std::collections::HashSet;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Arc;
 use std::sync::Weak;
+use std::time::Instant;
 
 use nodeipc::derive::HasIpc;
 use nodeipc::ipc;
@@ -17,7 +19,11 @@ use nodeipc::NodeIpc;
 use serde::Deserialize;
 use serde::Serialize;
 
-use crate::util;
+use crate::configstamp::ConfigStamp;
+use crate::server::Stats;
+use crate::serverlog::ServerLog;
+use crate::util::CompatFingerprint;
+use crate::util::UmaskGuard;
 
 #[derive(Serialize, Deserialize)]
 pub struct CommandEnv {
@@ -29,17 +35,129 @@ pub struct CommandEnv {
 pub struct ProcessProps {
     pub pid: u32,
     pub pgid: u32,
-    pub groups: Option<Vec<u32>>,
-    pub rlimit_nofile: Option<u64>,
+    /// Attributes that must be compatible with the client.
+    #[serde(default)]
+    pub compat: CompatFingerprint,
+    /// Config files loaded by the server.
+    #[serde(default)]
+    pub config: ConfigStamp,
+}
+
+/// Version of the messages on a connection. Bump it on incompatible changes
+/// to `ClientHello`, `HandshakeReply`, or the IPC methods.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Versions of one side of a connection, exchanged before anything else.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    /// Full build version.
+    pub version: String,
+}
+
+impl Handshake {
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            version: version::VERSION.to_owned(),
+        }
+    }
+
+    /// Names of the attributes ("protocol", "version") that prevent `self`
+    /// and `other` from talking about `hello`.
+    pub fn mismatches(&self, other: &Self, hello: &Hello) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.protocol_version != other.protocol_version {
+            names.push("protocol".to_owned());
+        }
+        // Probing and stopping servers of other versions is fine.
+        if matches!(hello, Hello::Client) && self.version != other.version {
+            names.push("version".to_owned());
+        }
+        names
+    }
+}
+
+/// First message on a connection, from the client.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub handshake: Handshake,
+    pub hello: Hello,
+}
+
+/// Answer to `ClientHello`. The server closes the connection after
+/// `Incompatible`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum HandshakeReply {
+    Accepted(Handshake),
+    Incompatible {
+        server: Handshake,
+        /// See `Handshake::mismatches`.
+        mismatches: Vec<String>,
+    },
+}
+
+/// What the client wants from the server, sent in `ClientHello`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Hello {
+    /// Use the server to run commands. Stdio follows.
+    Client,
+    /// Only ask `ping`. The server waits for another client afterwards.
+    Probe,
+    /// Stop the server. Nothing follows.
+    Stop,
+}
+
+/// Answer to `ping`. Identifies the server and describes its state.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PingInfo {
+    pub pid: u32,
+    pub version: String,
+    pub cli_name: String,
+    pub uptime_ms: u64,
+    pub commands_served: u64,
+    /// Clients being served. Servers serving one client report 1 to that
+    /// client. Forked clients see the number when they connected.
+    #[serde(default)]
+    pub active_clients: u64,
+    /// Log file of the server, see `serverlog`.
+    #[serde(default)]
+    pub log_path: Option<PathBuf>,
+}
+
+impl PingInfo {
+    fn current(stats: &Stats, active_clients: u64, log: &ServerLog) -> Self {
+        Self {
+            pid: std::process::id(),
+            version: version::VERSION.to_owned(),
+            cli_name: identity::cli_name().to_owned(),
+            uptime_ms: crate::server::uptime().as_millis() as u64,
+            commands_served: stats.commands_served(),
+            active_clients,
+            log_path: Some(log.path().to_owned()),
+        }
+    }
 }
 
 pub struct Client {
     pub ipc: NodeIpc,
 }
 
+/// Server side of a `Hello::Probe` connection.
+pub struct Probe {
+    pub ipc: NodeIpc,
+    pub(crate) stats: Arc<Stats>,
+    pub(crate) active_clients: u64,
+    pub(crate) log: Arc<ServerLog>,
+}
+
 pub struct Server<'a> {
     pub ipc: Arc<NodeIpc>,
     pub run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+    pub(crate) stats: Arc<Stats>,
+    /// Reported by `ping`, see `PingInfo::active_clients`.
+    pub(crate) active_clients: u64,
+    pub(crate) log: Arc<ServerLog>,
 }
 
 #[ipc]
@@ -112,16 +230,24 @@ impl Server<'_> {
         ProcessProps {
             pid: std::process::id() as _,
             pgid,
-            groups: util::groups(),
-            rlimit_nofile: util::rlimit_nofile(),
+            compat: CompatFingerprint::current(),
+            config: ConfigStamp::loaded().clone(),
         }
     }
 
+    /// Report the server identity. Cheap, to check if the server responds.
+    fn ping(&self) -> PingInfo {
+        tracing::debug!("server::ping");
+        PingInfo::current(&self.stats, self.active_clients, &self.log)
+    }
+
     /// Apply the environment. Return `true` on success.
-    fn apply_env(&self, env: CommandEnv, umask: Option<u32>) -> bool {
+    fn apply_env(&self, env: CommandEnv) -> bool {
         tracing::debug!("server::apply_env");
         let CommandEnv { cwd, env } = env;
-        if std::env::set_current_dir(&cwd).is_err() {
+        if let Err(e) = std::env::set_current_dir(&cwd) {
+            self.log
+                .write(format_args!("cannot change directory to {}: {}", &cwd, e));
             return false;
         }
         let new_key_set: HashSet<_> = env.iter().map(|(k, _)| k).collect();
@@ -133,22 +259,50 @@ impl Server<'_> {
         for (k, v) in &env {
             std::env::set_var(k, v);
         }
-        if let Some(umask) = umask {
-            #[cfg(unix)]
-            unsafe {
-                libc::umask(umask as _);
-            }
-            let _ = umask;
-        }
         true
     }
 
-    /// Run the given main command. Return exit code.
-    fn run_command(&self, argv: Vec<String>) -> i32 {
+    /// Record the startup time the client estimates it saves by using the
+    /// server, for metrics. Return the total saved time.
+    fn add_saved_time(&self, ms: u64) -> u64 {
+        tracing::debug!("server::add_saved_time {}", ms);
+        self.stats.add_saved_time(ms)
+    }
+
+    /// Run the given main command with the client's umask. Return exit code.
+    fn run_command(&self, argv: Vec<String>, umask: Option<u32>) -> i32 {
         tracing::debug!("server::run_command {:?}", &argv);
+        // Files created by the command get the client's modes. The server's
+        // umask is restored afterwards, so it does not leak into later
+        // commands.
+        let _umask = umask.map(UmaskGuard::set);
         // To avoid circular dependency, we cannot call hgcommands here.
         // Instead, rely on hgcommands to provide Server::run_func.
-        (self.run_func)(self, argv)
+        let start = Instant::now();
+        let ret = (self.run_func)(self, argv.clone());
+        self.stats.count_command(ret);
+        let elapsed_ms = start.elapsed().as_millis();
+        if ret == 0 {
+            self.log.write(format_args!(
+                "command {:?} succeeded in {}ms",
+                &argv, elapsed_ms
+            ));
+        } else {
+            self.log.write(format_args!(
+                "command {:?} failed with exit code {} in {}ms",
+                &argv, ret, elapsed_ms
+            ));
+        }
+        ret
+    }
+}
+
+#[ipc]
+impl Probe {
+    /// Same as `Server::ping`.
+    fn ping(&self) -> PingInfo {
+        tracing::debug!("probe::ping");
+        PingInfo::current(&self.stats, self.active_clients, &self.log)
     }
 }
 
@@ -184,6 +338,12 @@ impl HasIpc for Client {
     }
 }
 
+impl HasIpc for Probe {
+    fn ipc(&self) -> &NodeIpc {
+        &self.ipc
+    }
+}
+
 impl HasIpc for Server<'_> {
     fn ipc(&self) -> &NodeIpc {
         &self.ipc
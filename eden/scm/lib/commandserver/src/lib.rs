@@ -7,9 +7,17 @@
 
 //! Client-server with the ability to preload content server-side to reduce
 //! startup overhead.
+//!
+//! On POSIX, the transport is a unix domain socket in a per-user runtime
+//! directory, or a Linux abstract socket if enabled. On Windows, it is a
+//! named pipe only accessible by the current user. See `udsipc`.
 
 pub mod client;
+mod configstamp;
+mod idle;
 pub mod ipc;
+mod metrics;
 pub mod server;
+mod serverlog;
 mod spawn;
 mod util;
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Usage records of exited servers, to tell if servers are worth running.
+//!
+//! Each server appends one JSON line to `metrics/servers.jsonl` in the
+//! runtime directory when it exits. Once the file reaches
+//! `MAX_METRICS_SIZE`, it is moved to `servers.jsonl.old`, replacing the
+//! oldest records.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use fn_error_context::context;
+use serde::Deserialize;
+use serde::Serialize;
+
+const METRICS_DIR: &str = "metrics";
+const METRICS_FILE: &str = "servers.jsonl";
+const MAX_METRICS_SIZE: u64 = 1 << 20;
+
+/// Usage of one server, written when it exits.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerRecord {
+    pub pid: u32,
+    pub version: String,
+    pub uptime_ms: u64,
+    pub commands_served: u64,
+    /// Commands that exited with a non-zero code.
+    pub failed_commands: u64,
+    /// Startup time saved for clients, as estimated by them.
+    pub saved_ms: u64,
+}
+
+/// Records of servers that exited, added up.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct MetricsSummary {
+    pub servers: u64,
+    /// Servers that exited without running a command.
+    pub unused_servers: u64,
+    pub uptime_ms: u64,
+    pub commands_served: u64,
+    pub failed_commands: u64,
+    pub saved_ms: u64,
+}
+
+impl MetricsSummary {
+    fn from_records(records: &[ServerRecord]) -> Self {
+        let mut summary = Self::default();
+        for record in records {
+            summary.servers += 1;
+            if record.commands_served == 0 {
+                summary.unused_servers += 1;
+            }
+            summary.uptime_ms += record.uptime_ms;
+            summary.commands_served += record.commands_served;
+            summary.failed_commands += record.failed_commands;
+            summary.saved_ms += record.saved_ms;
+        }
+        summary
+    }
+}
+
+/// Check if a file in the runtime directory holds metrics.
+pub(crate) fn is_metrics_path(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| n == METRICS_DIR)
+}
+
+fn metrics_path(dir: &Path) -> PathBuf {
+    dir.join(METRICS_DIR).join(METRICS_FILE)
+}
+
+/// Append `record` to the metrics in the runtime directory `dir`.
+#[context("Writing server metrics")]
+pub(crate) fn append_record(dir: &Path, record: &ServerRecord) -> anyhow::Result<()> {
+    let path = metrics_path(dir);
+    fs::create_dir_all(dir.join(METRICS_DIR))?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let len = fs::metadata(&path).map_or(0, |m| m.len());
+    if len + line.len() as u64 > MAX_METRICS_SIZE {
+        fs::rename(&path, path.with_extension("jsonl.old"))?;
+    }
+    // Appending a line in one write keeps records of servers exiting at the
+    // same time separated.
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Read the records in the runtime directory `dir`, oldest first.
+/// Lines that cannot be parsed, for example, from other versions, are skipped.
+fn read_records(dir: &Path) -> Vec<ServerRecord> {
+    let path = metrics_path(dir);
+    [path.with_extension("jsonl.old"), path]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Add up the records in the runtime directory `dir`.
+pub(crate) fn summarize(dir: &Path) -> MetricsSummary {
+    MetricsSummary::from_records(&read_records(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_record() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-metrics-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(summarize(&dir), MetricsSummary::default());
+
+        let record = |i: u64| ServerRecord {
+            pid: i as u32,
+            version: "v".to_owned(),
+            uptime_ms: 1000,
+            commands_served: i % 2,
+            failed_commands: 0,
+            saved_ms: 300 * (i % 2),
+        };
+        for i in 0..4 {
+            append_record(&dir, &record(i)).unwrap();
+        }
+        assert_eq!(read_records(&dir), (0..4).map(record).collect::<Vec<_>>());
+        assert_eq!(
+            summarize(&dir),
+            MetricsSummary {
+                servers: 4,
+                unused_servers: 2,
+                uptime_ms: 4000,
+                commands_served: 2,
+                failed_commands: 0,
+                saved_ms: 600,
+            }
+        );
+
+        // Newest records win once the file is full.
+        let line_len = fs::metadata(metrics_path(&dir)).unwrap().len() / 4;
+        let count = MAX_METRICS_SIZE / line_len * 2 + 10;
+        for i in 4..count {
+            append_record(&dir, &record(i)).unwrap();
+        }
+        let records = read_records(&dir);
+        assert!(records.len() < count as usize);
+        assert_eq!(records.last().unwrap().pid, count as u32 - 1);
+        let size = |p: PathBuf| fs::metadata(p).unwrap().len();
+        assert!(size(metrics_path(&dir)) <= MAX_METRICS_SIZE);
+        assert!(size(metrics_path(&dir).with_extension("jsonl.old")) <= MAX_METRICS_SIZE);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,72 +5,869 @@
  * GNU General Public License version 2.
  */
 
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
 use nodeipc::derive::Serve;
+use nodeipc::NodeIpc;
+use once_cell::sync::Lazy;
 
+use crate::configstamp::ConfigStamp;
+use crate::ipc::ClientHello;
+use crate::ipc::Handshake;
+use crate::ipc::HandshakeReply;
+use crate::ipc::Hello;
+use crate::ipc::Probe;
 use crate::ipc::Server;
+use crate::metrics::ServerRecord;
+use crate::serverlog::ServerLog;
+
+/// Env var (ex. `SL_CMDSERVER_IDLE_TIMEOUT`) that sets the idle timeout in
+/// seconds. Clients set it from config when spawning servers.
+pub(crate) const IDLE_TIMEOUT_ENV: &str = "CMDSERVER_IDLE_TIMEOUT";
+
+/// Exit the server if no client connects for this long.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(1800);
+
+/// Env var (ex. `SL_CMDSERVER_MAX_CLIENTS`) that sets how many clients a
+/// server serves at once. Clients set it from config when spawning servers.
+pub(crate) const MAX_CLIENTS_ENV: &str = "CMDSERVER_MAX_CLIENTS";
+
+/// How often a server serving clients concurrently checks if it should stop,
+/// and reaps forked clients.
+#[cfg(unix)]
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often a server waiting for an abstract socket name retries.
+#[cfg(target_os = "linux")]
+const ABSTRACT_BIND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Drop connections that send no handshake for this long.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Drop probe connections that send nothing for this long.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+static START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Time since the server started serving.
+pub(crate) fn uptime() -> Duration {
+    START_TIME.elapsed()
+}
+
+/// Usage counters of a server, reported by `ping` and in metrics.
+#[derive(Default)]
+pub(crate) struct Stats {
+    commands_served: AtomicU64,
+    failed_commands: AtomicU64,
+    saved_ms: AtomicU64,
+}
+
+impl Stats {
+    /// Number of commands run by the server.
+    pub(crate) fn commands_served(&self) -> u64 {
+        self.commands_served.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn count_command(&self, exit_code: i32) {
+        self.commands_served.fetch_add(1, Ordering::AcqRel);
+        if exit_code != 0 {
+            self.failed_commands.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Add to the saved time. Return the total.
+    pub(crate) fn add_saved_time(&self, ms: u64) -> u64 {
+        self.saved_ms.fetch_add(ms, Ordering::AcqRel) + ms
+    }
+
+    /// Add counters of a forked client, see `counted_since`.
+    #[cfg(unix)]
+    fn add(&self, counted: &ServerRecord) {
+        self.commands_served
+            .fetch_add(counted.commands_served, Ordering::AcqRel);
+        self.failed_commands
+            .fetch_add(counted.failed_commands, Ordering::AcqRel);
+        self.saved_ms.fetch_add(counted.saved_ms, Ordering::AcqRel);
+    }
+
+    /// Counters added since `base` was taken. Other fields are left empty.
+    #[cfg(unix)]
+    fn counted_since(&self, base: &ServerRecord) -> ServerRecord {
+        let now = self.to_record();
+        ServerRecord {
+            commands_served: now.commands_served - base.commands_served,
+            failed_commands: now.failed_commands - base.failed_commands,
+            saved_ms: now.saved_ms - base.saved_ms,
+            ..Default::default()
+        }
+    }
+
+    fn to_record(&self) -> ServerRecord {
+        ServerRecord {
+            pid: std::process::id(),
+            version: version::VERSION.to_owned(),
+            uptime_ms: uptime().as_millis() as u64,
+            commands_served: self.commands_served(),
+            failed_commands: self.failed_commands.load(Ordering::Acquire),
+            saved_ms: self.saved_ms.load(Ordering::Acquire),
+        }
+    }
+}
 
 /// Serve one client.
 ///
 /// Internally, creates and listens to a uds.
 ///
-/// Exits the process when the uds file is removed and no clients
-/// are connected.
+/// Exits the process, removing the uds file, when no client connects
+/// within the idle timeout (`IDLE_TIMEOUT_ENV`). Also exits when the uds
+/// file is removed and no clients are connected.
 ///
 /// Returns if completes serving a client.
+///
+/// If `MAX_CLIENTS_ENV` is above 1, serves up to that many clients at once
+/// instead, each in a forked process, and returns once the uds file is
+/// removed or no client connects within the idle timeout.
 pub fn serve_one_client<'a>(
     run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
 ) -> anyhow::Result<()> {
+    // Serve clients that raised their limit after this server was spawned.
+    match crate::util::raise_rlimit_nofile() {
+        Ok(nofile) => tracing::debug!("RLIMIT_NOFILE: {:?}", nofile),
+        Err(e) => tracing::warn!("cannot raise RLIMIT_NOFILE:\n{:?}", &e),
+    }
+
+    // Config was loaded when the server started. Stamp the files now, so
+    // clients can tell if they were edited since.
+    ConfigStamp::loaded();
+
     let dir = crate::util::runtime_dir()?;
+    if let Some(name) = crate::util::pipe_name() {
+        return serve_one_client_pipe(&dir, &name, run_func);
+    }
+    if let Some(name) = crate::util::abstract_socket_name(None) {
+        return serve_one_client_abstract(&dir, &name, run_func);
+    }
     let prefix = crate::util::prefix();
+    serve_one_client_at(&dir, prefix, run_func)
+}
+
+pub(crate) fn serve_one_client_at<'a>(
+    dir: &Path,
+    prefix: &str,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) -> anyhow::Result<()> {
+    Lazy::force(&START_TIME);
     tracing::debug!("serving at {}/{}", dir.display(), prefix);
-    let incoming = udsipc::pool::serve(&dir, prefix)?;
+    let incoming = match udsipc::pool::serve(dir, prefix) {
+        // Another server took the socket first. It serves the clients.
+        Err(e)
+            if e.chain()
+                .filter_map(|e| e.downcast_ref::<io::Error>())
+                .any(|e| e.kind() == io::ErrorKind::AddrInUse) =>
+        {
+            tracing::debug!("not serving:\n{:?}", &e);
+            return Ok(());
+        }
+        r => r?,
+    };
+    // See `udsipc::pool::serve` for the socket name.
+    let socket_path = dir.join(format!("{}-{}", prefix, std::process::id()));
+    serve_incoming(dir, incoming, Some(socket_path), max_clients(), run_func)
+}
+
+/// Serve one client at the Linux abstract socket address `name`, with
+/// metrics in `dir`.
+///
+/// Only one server can listen at a name. Wait for the name to become free,
+/// as servers stop listening once they get a client, or exit if that takes
+/// longer than the idle timeout.
+pub(crate) fn serve_one_client_abstract<'a>(
+    dir: &Path,
+    name: &str,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        Lazy::force(&START_TIME);
+        tracing::debug!("serving at abstract socket {}", name);
+        let timeout = idle_timeout();
+        let incoming = loop {
+            match udsipc::ipc::serve_abstract(name) {
+                Ok(incoming) => break incoming,
+                Err(e) => {
+                    let in_use = e
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|e| e.kind() == std::io::ErrorKind::AddrInUse);
+                    if !in_use || START_TIME.elapsed() >= timeout {
+                        return Err(e);
+                    }
+                    thread::sleep(ABSTRACT_BIND_INTERVAL);
+                }
+            }
+        };
+        return serve_incoming(dir, incoming, None, max_clients(), run_func);
+    }
+
+    #[allow(unreachable_code)]
+    {
+        let _ = (dir, run_func);
+        anyhow::bail!("abstract socket {} is not supported", name);
+    }
+}
+
+/// Serve one client at the Windows named pipe `name`, with metrics in `dir`.
+///
+/// Unlike abstract sockets, servers share the name. Each waits at its own
+/// instance of the pipe, until it gets a client.
+pub(crate) fn serve_one_client_pipe<'a>(
+    dir: &Path,
+    name: &str,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) -> anyhow::Result<()> {
+    #[cfg(windows)]
+    {
+        Lazy::force(&START_TIME);
+        tracing::debug!("serving at named pipe {}", name);
+        let incoming = udsipc::ipc::serve_pipe(name)?;
+        return serve_incoming(dir, incoming, None, max_clients(), run_func);
+    }
+
+    #[allow(unreachable_code)]
+    {
+        let _ = (dir, run_func);
+        anyhow::bail!("named pipe {} is not supported", name);
+    }
+}
+
+/// Serve one client from `incoming`, or up to `max_clients` at once.
+/// `socket_path` is the socket file, or `None` for abstract sockets and
+/// named pipes.
+pub(crate) fn serve_incoming<'a>(
+    dir: &Path,
+    incoming: udsipc::ipc::Incoming,
+    socket_path: Option<PathBuf>,
+    max_clients: usize,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) -> anyhow::Result<()> {
+    // Let clients find this server if there are too many idle servers.
+    let meta_path = match socket_path.as_deref().map(crate::idle::write_meta) {
+        Some(Ok(path)) => Some(path),
+        Some(Err(e)) => {
+            tracing::warn!("cannot write idle server metadata:\n{:?}", &e);
+            None
+        }
+        None => None,
+    };
+    let remove_meta = || {
+        if let Some(path) = &meta_path {
+            let _ = fs::remove_file(path);
+        }
+    };
 
     let is_uds_alive = incoming.get_is_alive_func();
-    let is_waiting = AtomicBool::new(true);
-    let start_time = Instant::now();
+    let idle_state = IdleState::new();
+    let idle_timeout = idle_timeout();
+    let stats = Arc::new(Stats::default());
+    // Abstract sockets and named pipes have no file. Name the log like a
+    // socket file.
+    let log_socket_path = socket_path
+        .clone()
+        .unwrap_or_else(|| dir.join(format!("{}-{}", crate::util::prefix(), std::process::id())));
+    let log = Arc::new(ServerLog::for_socket(&log_socket_path));
+    log.write(format_args!(
+        "serving {} {} at {} (max clients {}, idle timeout {}s)",
+        identity::cli_name(),
+        version::VERSION,
+        match &socket_path {
+            Some(path) => path.display().to_string(),
+            None if cfg!(windows) => "a named pipe".to_owned(),
+            None => "an abstract socket".to_owned(),
+        },
+        max_clients,
+        idle_timeout.as_secs()
+    ));
+    let write_metrics = || {
+        if let Err(e) = crate::metrics::append_record(dir, &stats.to_record()) {
+            tracing::warn!("cannot write metrics:\n{:?}", &e);
+            log.write(format_args!("cannot write metrics: {:#}", &e));
+        }
+    };
+    #[cfg(unix)]
+    if max_clients > 1 {
+        serve_concurrently(
+            incoming,
+            &idle_state,
+            idle_timeout,
+            &*is_uds_alive,
+            max_clients,
+            &stats,
+            &log,
+            run_func,
+        );
+        remove_meta();
+        write_metrics();
+        log.write(format_args!(
+            "exiting after {} commands",
+            stats.commands_served()
+        ));
+        return Ok(());
+    }
 
     thread::scope(|s| {
         // `for ipc in incoming` might block forever waiting for
         // a client. Detect that and exit early.
         s.spawn(|| {
-            let idle_timeout = Duration::from_secs(1800);
             let interval = Duration::from_secs(5);
-            while is_waiting.load(Ordering::Acquire)
-                && start_time.elapsed() < idle_timeout
-                && is_uds_alive()
-            {
-                thread::sleep(interval);
-            }
-            if is_waiting.load(Ordering::Acquire) {
+            if wait_until_idle(&idle_state, idle_timeout, interval, &*is_uds_alive) {
                 tracing::debug!("exiting server due to inactivity");
+                log.write("exiting due to inactivity, or a removed socket");
+                // `process::exit` skips `Drop`. Remove the files explicitly.
+                if let Some(path) = &socket_path {
+                    let _ = fs::remove_file(path);
+                }
+                remove_meta();
+                write_metrics();
                 std::process::exit(0);
             }
         });
 
         tracing::debug!("waiting for client connection");
-        #[allow(clippy::never_loop)]
-        for ipc in incoming {
+        let mut incoming = incoming;
+        while let Some(ipc) = incoming.next() {
             tracing::debug!("got client connection");
-            is_waiting.store(false, Ordering::Release);
-            if let Err(e) = ipc.recv_stdio() {
-                tracing::warn!("failed to get client stdio:\n{:?}", &e);
-            } else {
-                tracing::debug!("server got client stdio");
-                let server = Server {
-                    ipc: ipc.into(),
-                    run_func,
-                };
-                let _ = server.serve();
+            let ipc = match accept_connection(ipc, &stats, idle_state.active_clients(), &log) {
+                Some((ipc, Hello::Client)) => ipc,
+                Some((_ipc, Hello::Stop)) => {
+                    remove_meta();
+                    break;
+                }
+                _ => continue,
+            };
+            idle_state.touch();
+
+            if !idle_state.claim() {
+                // The other thread is about to exit the process.
+                tracing::debug!("dropping client connection due to exiting");
+                break;
             }
+            remove_meta();
+            // Stop listening. Abstract names stay taken until then, and
+            // waiting servers can take over.
+            drop(incoming);
+            serve_client(ipc, run_func, stats.clone(), 1, log.clone());
             break;
         }
+        // Let the idle watcher return, if it has not decided to exit.
+        idle_state.claim();
     });
 
+    write_metrics();
+    log.write(format_args!(
+        "exiting after {} commands",
+        stats.commands_served()
+    ));
     Ok(())
 }
+
+/// Receive the `ClientHello` of a new connection, and answer it.
+///
+/// Return `None` if the connection should be closed: the client is
+/// incompatible, disconnected, or did not send a handshake in time.
+fn accept_handshake(ipc: &NodeIpc, log: &ServerLog) -> Option<Hello> {
+    let current = Handshake::current();
+    // Do not let a silent client block other clients.
+    let _ = crate::util::set_read_timeout(ipc, Some(HANDSHAKE_TIMEOUT));
+    let (mismatches, client) = match ipc.recv::<ClientHello>() {
+        Ok(Some(ClientHello { handshake, hello })) => {
+            let mismatches = current.mismatches(&handshake, &hello);
+            if mismatches.is_empty() {
+                if let Err(e) = ipc.send(HandshakeReply::Accepted(current)) {
+                    tracing::warn!("failed to answer client handshake:\n{:?}", &e);
+                    log.write(format_args!("failed to answer client handshake: {:#}", &e));
+                    return None;
+                }
+                let _ = crate::util::set_read_timeout(ipc, None);
+                return Some(hello);
+            }
+            (mismatches, format!("{:?}", &handshake))
+        }
+        Ok(None) => return None,
+        // Clients of other protocol versions might send anything.
+        Err(e) => (vec!["protocol".to_owned()], format!("{:#}", &e)),
+    };
+    tracing::warn!(
+        "rejected incompatible client ({:?}): {}",
+        &mismatches,
+        &client
+    );
+    log.write(format_args!(
+        "rejected incompatible client ({}): {}",
+        mismatches.join(", "),
+        &client
+    ));
+    // The client spawns a server of its version instead.
+    let _ = ipc.send(HandshakeReply::Incompatible {
+        server: current,
+        mismatches,
+    });
+    None
+}
+
+/// Check the credentials and the handshake of a new connection, and serve
+/// it if it is a probe. Return the connection and what it asked for, or
+/// `None` if it was served or rejected.
+fn accept_connection(
+    ipc: NodeIpc,
+    stats: &Arc<Stats>,
+    active_clients: u64,
+    log: &Arc<ServerLog>,
+) -> Option<(NodeIpc, Hello)> {
+    // The directory permissions should keep other users out.
+    // Double check in case they are somehow bypassed.
+    if let Err(e) = crate::util::check_peer_credentials(&ipc) {
+        tracing::warn!("rejected client connection:\n{:?}", &e);
+        log.write(format_args!("rejected client connection: {:#}", &e));
+        return None;
+    }
+    match accept_handshake(&ipc, log)? {
+        Hello::Probe => {
+            // Probes do not reset the idle time. Listing servers
+            // should not keep them running.
+            tracing::debug!("serving probe");
+            // Do not let a stuck probe block clients.
+            let _ = crate::util::set_read_timeout(&ipc, Some(PROBE_TIMEOUT));
+            let _ = Probe {
+                ipc,
+                stats: stats.clone(),
+                active_clients,
+                log: log.clone(),
+            }
+            .serve();
+            None
+        }
+        Hello::Stop => {
+            tracing::debug!("stopping server as requested");
+            log.write("stopping as requested");
+            Some((ipc, Hello::Stop))
+        }
+        hello => Some((ipc, hello)),
+    }
+}
+
+/// Serve up to `max_clients` clients at once, each in a forked process,
+/// until stopped, idle for `idle_timeout`, or `is_alive` returns `false`.
+/// Then wait for the forked clients.
+///
+/// Everything happens on this thread: accepting, forking, watching for
+/// idleness, and reaping. Forking while another thread holds a lock, for
+/// example, the log's, would leave it locked forever in the child.
+#[cfg(unix)]
+#[allow(clippy::too_many_arguments)]
+fn serve_concurrently<'a>(
+    mut incoming: udsipc::ipc::Incoming,
+    idle_state: &IdleState,
+    idle_timeout: Duration,
+    is_alive: &dyn Fn() -> bool,
+    max_clients: usize,
+    stats: &Arc<Stats>,
+    log: &Arc<ServerLog>,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) {
+    let mut children: Vec<ForkedClient> = Vec::new();
+    tracing::debug!("waiting for client connections");
+    loop {
+        let polled = poll_client(&mut incoming, ACCEPT_POLL_INTERVAL);
+        reap_clients(&mut children, false, stats, idle_state, log);
+        let ipc = match polled {
+            Ok(Some(ipc)) => ipc,
+            Ok(None) if idle_state.idle_time() < idle_timeout && is_alive() => continue,
+            Ok(None) => {
+                tracing::debug!("stopping server due to inactivity");
+                log.write("stopping due to inactivity, or a removed socket");
+                break;
+            }
+            Err(_) => break,
+        };
+        tracing::debug!("got client connection");
+        let ipc = match accept_connection(ipc, stats, idle_state.active_clients(), log) {
+            Some((ipc, Hello::Client)) => ipc,
+            Some((_ipc, Hello::Stop)) => break,
+            _ => continue,
+        };
+        idle_state.touch();
+
+        if idle_state.active_clients() >= max_clients as u64 {
+            // The client retries, possibly with another server.
+            tracing::debug!("dropping client connection due to too many clients");
+            continue;
+        }
+        let active_clients = idle_state.enter();
+        match fork_client(ipc, stats, active_clients, log, run_func) {
+            Ok(child) => children.push(child),
+            Err(e) => {
+                tracing::warn!("cannot fork for client:\n{:?}", &e);
+                log.write(format_args!("cannot fork for client: {:#}", &e));
+                idle_state.leave();
+            }
+        }
+    }
+
+    // Stop listening, so new clients go to other servers.
+    drop(incoming);
+    reap_clients(&mut children, true, stats, idle_state, log);
+}
+
+/// Wait up to `timeout` for the next connection. Return `Ok(None)` if
+/// there is none yet.
+#[cfg(unix)]
+fn poll_client(
+    incoming: &mut udsipc::ipc::Incoming,
+    timeout: Duration,
+) -> io::Result<Option<NodeIpc>> {
+    let fd = std::os::unix::io::AsRawFd::as_raw_fd(incoming);
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = timeout.as_millis() as libc::c_int;
+    match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+        0 => Ok(None),
+        n if n > 0 => match incoming.next() {
+            Some(ipc) => Ok(Some(ipc)),
+            None => Err(io::Error::other("cannot accept client connection")),
+        },
+        _ => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(None),
+            e => Err(e),
+        },
+    }
+}
+
+/// Serve a client that sent `Hello::Client`, until it disconnects.
+fn serve_client<'a>(
+    ipc: NodeIpc,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+    stats: Arc<Stats>,
+    active_clients: u64,
+    log: Arc<ServerLog>,
+) {
+    if let Err(e) = ipc.recv_stdio() {
+        tracing::warn!("failed to get client stdio:\n{:?}", &e);
+        log.write(format_args!("failed to get client stdio: {:#}", &e));
+        return;
+    }
+    tracing::debug!("server got client stdio");
+    let server = Server {
+        ipc: ipc.into(),
+        run_func,
+        stats,
+        active_clients,
+        log: log.clone(),
+    };
+    if let Err(e) = server.serve() {
+        log.write(format_args!("client session failed: {:#}", &e));
+    }
+}
+
+/// A client served by a forked child process.
+#[cfg(unix)]
+struct ForkedClient {
+    pid: libc::pid_t,
+    /// The child writes what it counted here before it exits.
+    reader: fs::File,
+}
+
+/// Serve a client in a forked child process, so concurrent commands do not
+/// share the cwd, env vars, umask, or stdio. Commands that need exclusive
+/// repo locks still wait for each other at the lock.
+///
+/// Must be called without other threads running, see `serve_concurrently`.
+#[cfg(unix)]
+fn fork_client<'a>(
+    ipc: NodeIpc,
+    stats: &Arc<Stats>,
+    active_clients: u64,
+    log: &Arc<ServerLog>,
+    run_func: &'a (dyn (Fn(&'_ Server<'a>, Vec<String>) -> i32) + Send + Sync),
+) -> anyhow::Result<ForkedClient> {
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // Commands should not inherit the pipe.
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    // Forked grandchildren might keep the pipe open. Read what is there
+    // once the child exits, without waiting for them.
+    unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) };
+    let reader = unsafe { fs::File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { fs::File::from_raw_fd(fds[1]) };
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            // The child never returns, so the parent's cleanup, like
+            // removing the socket, does not run here.
+            close_other_sockets(ipc.as_raw_file_descriptor());
+            drop(reader);
+            let base = stats.to_record();
+            let served = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                serve_client(ipc, run_func, stats.clone(), active_clients, log.clone())
+            }));
+            let counted = stats.counted_since(&base);
+            let _ = serde_json::to_writer(&mut writer, &counted);
+            let _ = writer.flush();
+            unsafe { libc::_exit(served.is_err() as libc::c_int) }
+        }
+        pid => Ok(ForkedClient { pid, reader }),
+    }
+}
+
+/// Close sockets inherited from the parent, except stdio and `keep`. The
+/// listener should not outlive the parent, and other connections should
+/// end when their clients disconnect.
+#[cfg(unix)]
+fn close_other_sockets(keep: libc::c_int) {
+    let fds: Vec<libc::c_int> = match fs::read_dir("/dev/fd") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in fds {
+        if fd <= 2 || fd == keep {
+            continue;
+        }
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } == 0
+            && (stat.st_mode & libc::S_IFMT) == libc::S_IFSOCK
+        {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Add what exited forked clients counted to `stats`, and stop counting
+/// them as active. With `wait`, wait for all of them to exit.
+#[cfg(unix)]
+fn reap_clients(
+    children: &mut Vec<ForkedClient>,
+    wait: bool,
+    stats: &Stats,
+    idle_state: &IdleState,
+    log: &ServerLog,
+) {
+    use std::io::Read;
+
+    let flags = if wait { 0 } else { libc::WNOHANG };
+    let mut reap = |child: &mut ForkedClient| -> bool {
+        let pid = child.pid;
+        let mut status = 0;
+        match unsafe { libc::waitpid(pid, &mut status, flags) } {
+            0 => return true,
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => return true,
+            _ => {}
+        }
+        // Written before the child exited, so it is all in the pipe.
+        let mut counted = Vec::new();
+        let _ = child.reader.read_to_end(&mut counted);
+        match serde_json::from_slice::<ServerRecord>(&counted) {
+            Ok(counted) => stats.add(&counted),
+            Err(_) => {
+                tracing::warn!("forked client {} did not report", pid);
+                log.write(format_args!("forked client {} did not report", pid));
+            }
+        }
+        if status != 0 {
+            log.write(format_args!(
+                "forked client {} exited with status {}",
+                pid, status
+            ));
+        }
+        tracing::debug!("forked client {} exited with status {}", pid, status);
+        idle_state.leave();
+        false
+    };
+    children.retain_mut(&mut reap);
+    // Waiting can be interrupted by signals.
+    while wait && !children.is_empty() {
+        children.retain_mut(&mut reap);
+    }
+}
+
+/// Get the number of clients to serve at once from `MAX_CLIENTS_ENV`, or 1.
+/// More than 1 client is only supported on POSIX, where clients are served
+/// by forked processes.
+fn max_clients() -> usize {
+    if cfg!(not(unix)) {
+        return 1;
+    }
+    match identity::env_var(MAX_CLIENTS_ENV) {
+        Some(Ok(value)) => match value.parse::<usize>() {
+            Ok(n) => n.max(1),
+            Err(_) => {
+                tracing::warn!("ignored invalid max clients {:?}", value);
+                1
+            }
+        },
+        _ => 1,
+    }
+}
+
+/// Get the idle timeout from `IDLE_TIMEOUT_ENV`, or `DEFAULT_IDLE_TIMEOUT`.
+fn idle_timeout() -> Duration {
+    match identity::env_var(IDLE_TIMEOUT_ENV) {
+        Some(Ok(value)) => match value.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                tracing::warn!("ignored invalid idle timeout {:?}", value);
+                DEFAULT_IDLE_TIMEOUT
+            }
+        },
+        _ => DEFAULT_IDLE_TIMEOUT,
+    }
+}
+
+/// Whether the server is still waiting for a client, and for how long.
+struct IdleState {
+    start_time: Instant,
+    /// Milliseconds from `start_time` to the last connection.
+    last_connected_ms: AtomicU64,
+    /// Cleared by either a client claiming the server, or the decision to
+    /// exit, whichever comes first.
+    is_waiting: AtomicBool,
+    /// Clients being served by forked processes. The server is not idle
+    /// while there are any.
+    active_clients: AtomicU64,
+}
+
+impl IdleState {
+    fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            last_connected_ms: AtomicU64::new(0),
+            is_waiting: AtomicBool::new(true),
+            active_clients: AtomicU64::new(0),
+        }
+    }
+
+    /// Reset the idle time on a connection.
+    fn touch(&self) {
+        let ms = self.start_time.elapsed().as_millis() as u64;
+        self.last_connected_ms.store(ms, Ordering::Release);
+    }
+
+    /// Count a forked client. Return the number of active clients.
+    #[cfg(unix)]
+    fn enter(&self) -> u64 {
+        self.active_clients.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Count a forked client that exited. The idle time starts over.
+    #[cfg(unix)]
+    fn leave(&self) {
+        self.active_clients.fetch_sub(1, Ordering::AcqRel);
+        self.touch();
+    }
+
+    fn active_clients(&self) -> u64 {
+        self.active_clients.load(Ordering::Acquire)
+    }
+
+    fn idle_time(&self) -> Duration {
+        if self.active_clients() > 0 {
+            return Duration::ZERO;
+        }
+        let last_connected = Duration::from_millis(self.last_connected_ms.load(Ordering::Acquire));
+        self.start_time.elapsed().saturating_sub(last_connected)
+    }
+
+    /// Stop waiting. Return `false` if it has already stopped.
+    fn claim(&self) -> bool {
+        self.is_waiting.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// Wait until the server has been idle for `timeout`, or `is_alive` returns
+/// `false`. Then return `true` if the server should exit, or `false` if a
+/// client claimed it first. A claimed server never exits here, so commands
+/// are not interrupted.
+fn wait_until_idle(
+    state: &IdleState,
+    timeout: Duration,
+    interval: Duration,
+    is_alive: &dyn Fn() -> bool,
+) -> bool {
+    while state.is_waiting.load(Ordering::Acquire) && state.idle_time() < timeout && is_alive() {
+        thread::sleep(interval.min(timeout));
+    }
+    state.claim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_timeout() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-server-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let incoming = udsipc::pool::serve(&dir, "p").unwrap();
+        let is_alive = incoming.get_is_alive_func();
+        let timeout = Duration::from_millis(100);
+        let interval = Duration::from_millis(5);
+
+        // Exits after the timeout.
+        let state = IdleState::new();
+        assert!(wait_until_idle(&state, timeout, interval, &*is_alive));
+        assert!(state.start_time.elapsed() >= timeout);
+        // A client arriving after that is not served.
+        assert!(!state.claim());
+
+        // A connection resets the timer.
+        let state = IdleState::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(60));
+                state.touch();
+            });
+            assert!(wait_until_idle(&state, timeout, interval, &*is_alive));
+        });
+        assert!(state.start_time.elapsed() >= Duration::from_millis(160));
+
+        // A claimed server does not exit.
+        let state = IdleState::new();
+        assert!(state.claim());
+        assert!(!wait_until_idle(&state, timeout, interval, &*is_alive));
+
+        // Exits early if the socket is removed.
+        let state = IdleState::new();
+        let socket_path = dir.join(format!("p-{}", std::process::id()));
+        fs::remove_file(&socket_path).unwrap();
+        assert!(wait_until_idle(
+            &state,
+            Duration::from_secs(60),
+            interval,
+            &*is_alive
+        ));
+        assert!(state.start_time.elapsed() < Duration::from_secs(60));
+
+        drop(incoming);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
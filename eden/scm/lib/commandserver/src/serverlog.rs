@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Log files of servers, so server-side errors can be found without tracing
+//! configured.
+//!
+//! Each server appends to `{socket}.log` next to its socket: startup info,
+//! a summary of each command, and errors. Once the file reaches
+//! `MAX_LOG_SIZE`, it is moved to `{socket}.log.old`, replacing the previous
+//! one. Forked clients write to the same file.
+//!
+//! Logging is best-effort. Write errors are ignored.
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+const MAX_LOG_SIZE: u64 = 256 << 10;
+
+/// Logs of exited servers are removed after this long.
+const LOG_RETENTION: Duration = Duration::from_secs(86400);
+
+pub(crate) struct ServerLog {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl ServerLog {
+    /// Log of the server listening at `socket_path`.
+    pub(crate) fn for_socket(socket_path: &Path) -> Self {
+        Self::with_max_size(log_path(socket_path), MAX_LOG_SIZE)
+    }
+
+    fn with_max_size(path: PathBuf, max_size: u64) -> Self {
+        Self { path, max_size }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a line with the time and pid.
+    pub(crate) fn write(&self, message: impl fmt::Display) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:03} [{}] {}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            std::process::id(),
+            message
+        );
+        if let Err(e) = self.append(&line) {
+            tracing::debug!("cannot write to {}: {:?}", self.path.display(), e);
+        }
+    }
+
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        let len = fs::metadata(&self.path).map_or(0, |m| m.len());
+        if len > 0 && len + line.len() as u64 > self.max_size {
+            fs::rename(&self.path, old_log_path(&self.path))?;
+        }
+        // The file is opened for each line, so forked clients keep writing
+        // to the current file after another process rotated it.
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+}
+
+fn log_path(socket_path: &Path) -> PathBuf {
+    let mut path = socket_path.as_os_str().to_owned();
+    path.push(".log");
+    PathBuf::from(path)
+}
+
+fn old_log_path(log_path: &Path) -> PathBuf {
+    let mut path = log_path.as_os_str().to_owned();
+    path.push(".old");
+    PathBuf::from(path)
+}
+
+/// Parse the server pid from a log name: `{prefix}-{pid}.log`, or
+/// `{prefix}-{pid}.log.old`.
+fn log_pid(name: &str) -> Option<u32> {
+    let name = name.strip_suffix(".old").unwrap_or(name);
+    let socket_name = name.strip_suffix(".log")?;
+    crate::util::socket_pid(socket_name.as_ref())
+}
+
+/// Remove logs in `dir` of servers that are no longer running, once they
+/// are older than `LOG_RETENTION`. Return the number of removed logs.
+pub(crate) fn cleanup_old_logs(dir: &Path) -> usize {
+    cleanup_logs_older_than(dir, LOG_RETENTION)
+}
+
+fn cleanup_logs_older_than(dir: &Path, retention: Duration) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_str().and_then(log_pid) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .map(|t| t.elapsed().unwrap_or_default());
+        if matches!(age, Ok(age) if age >= retention)
+            && !crate::util::is_process_alive(pid)
+            && fs::remove_file(entry.path()).is_ok()
+        {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("p-1");
+        let log = ServerLog::with_max_size(log_path(&socket_path), 200);
+        assert_eq!(log.path(), dir.join("p-1.log"));
+
+        for i in 0..20 {
+            log.write(format_args!("line {}", i));
+        }
+        let current = fs::read_to_string(log.path()).unwrap();
+        let old = fs::read_to_string(old_log_path(log.path())).unwrap();
+        assert!(current.len() <= 200);
+        assert!(old.len() <= 200);
+        assert!(current.ends_with("line 19\n"));
+        assert!(!old.contains("line 19\n"));
+        assert!(!current.contains("line 0\n") && !old.contains("line 0\n"));
+
+        // Logs of exited servers are removed once old enough.
+        assert_eq!(log_pid("p-1.log.old"), Some(1));
+        assert_eq!(log_pid("p-1"), None);
+        let dead = dir.join(format!("p-{}.log", i32::MAX));
+        fs::write(&dead, "").unwrap();
+        assert_eq!(cleanup_logs_older_than(&dir, Duration::from_secs(60)), 0);
+        assert_eq!(cleanup_logs_older_than(&dir, Duration::ZERO), 1);
+        assert!(!dead.exists());
+
+        // Write errors are ignored.
+        fs::remove_dir_all(&dir).unwrap();
+        log.write("lost");
+    }
+}
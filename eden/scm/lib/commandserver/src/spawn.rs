@@ -6,18 +6,63 @@
  */
 
 use std::fs;
-use std::io;
 use std::process::Child;
 use std::process::Command;
 
+use anyhow::Context;
+use configmodel::Config;
+use configmodel::ConfigExt;
 use fs2::FileExt;
 use spawn_ext::CommandExt;
 
+use crate::idle;
+use crate::server::IDLE_TIMEOUT_ENV;
+use crate::server::MAX_CLIENTS_ENV;
 use crate::util;
 
+/// Env var (ex. `SL_CMDSERVER_MAX_IDLE`) that overrides the
+/// `commandserver.max-idle` config.
+const MAX_IDLE_ENV: &str = "CMDSERVER_MAX_IDLE";
+
+/// Get the pool size, the number of servers to spawn when none are running.
+pub fn pool_size(config: &dyn Config) -> anyhow::Result<usize> {
+    Ok(config.get_or::<usize>("commandserver", "pool-size", || 2)?)
+}
+
+/// Get the maximum number of idle servers in the runtime directory.
+/// It is at least the pool size.
+pub fn max_idle(config: &dyn Config) -> anyhow::Result<usize> {
+    let max_idle = match identity::env_var(MAX_IDLE_ENV) {
+        Some(value) => {
+            let name = identity::default().env_name(MAX_IDLE_ENV);
+            let value = value.with_context(|| format!("Reading {}", name))?;
+            value
+                .parse()
+                .with_context(|| format!("Parsing {}={:?}", name, value))?
+        }
+        None => config.get_or::<usize>("commandserver", "max-idle", || 4)?,
+    };
+    Ok(max_idle.max(pool_size(config)?))
+}
+
+/// Get the number of clients a server serves at once. Servers serving more
+/// than one client stay connectable while busy, and are not consumed.
+pub fn max_clients(config: &dyn Config) -> anyhow::Result<usize> {
+    Ok(config
+        .get_or::<usize>("commandserver", "max-clients", || 1)?
+        .max(1))
+}
+
+/// Ask the oldest idle servers to exit, so at most `max_idle` remain.
+pub fn reap_idle(max_idle: usize) -> anyhow::Result<usize> {
+    let dir = util::runtime_dir()?;
+    idle::reap_idle_servers(&dir, max_idle)
+}
+
 /// Attempt to spawn servers (from a client) so there will be `pool_size`
 /// servers running in background.
-pub fn spawn_pool(pool_size: usize) -> anyhow::Result<()> {
+pub fn spawn_pool(config: &dyn Config) -> anyhow::Result<()> {
+    let pool_size = pool_size(config)?;
     let dir = util::runtime_dir()?;
     let prefix = util::prefix();
     let spawn_lock = fs::OpenOptions::new()
@@ -26,6 +71,11 @@ pub fn spawn_pool(pool_size: usize) -> anyhow::Result<()> {
         .open(dir.join("spawn.lock"))?;
     spawn_lock.lock_exclusive()?;
 
+    // Sockets of dead servers should not count as running servers.
+    let _ = util::cleanup_stale_sockets(&dir);
+
+    // Servers using abstract sockets or named pipes have no files to count.
+    // Clients only spawn them when no server is listening.
     let existing = udsipc::pool::list_uds_paths(&dir, prefix)
         .take(pool_size)
         .count();
@@ -33,14 +83,14 @@ pub fn spawn_pool(pool_size: usize) -> anyhow::Result<()> {
 
     tracing::debug!("spawning {} command servers", needed);
     for _ in 0..needed {
-        spawn_one()?;
+        spawn_one(config)?;
     }
     Ok(())
 }
 
 /// Attempt to spawn one server (from a client).
 /// Assume `$0 --spawn-commandserver` is the way to run a command server.
-pub fn spawn_one() -> io::Result<Child> {
+pub fn spawn_one(config: &dyn Config) -> anyhow::Result<Child> {
     let arg0 = std::env::current_exe()?;
     let mut cmd = Command::new(arg0);
     cmd.arg("start-commandserver")
@@ -49,12 +99,45 @@ pub fn spawn_one() -> io::Result<Child> {
         // They should not have NODE_CHANNEL_FD via env vars.
         .env_remove("NODE_CHANNEL_FD");
 
+    // Pass the idle timeout config. An env var set by the user wins.
+    if identity::env_var(IDLE_TIMEOUT_ENV).is_none() {
+        if let Some(secs) = config.get_opt::<u64>("commandserver", "idle-timeout")? {
+            cmd.env(
+                identity::default().env_name(IDLE_TIMEOUT_ENV).as_ref(),
+                secs.to_string(),
+            );
+        }
+    }
+
+    // Pass the concurrency limit, like the idle timeout.
+    if identity::env_var(MAX_CLIENTS_ENV).is_none() {
+        if let Some(n) = config.get_opt::<usize>("commandserver", "max-clients")? {
+            cmd.env(
+                identity::default().env_name(MAX_CLIENTS_ENV).as_ref(),
+                n.to_string(),
+            );
+        }
+    }
+
+    // Pass the abstract socket config, like the idle timeout.
+    if identity::env_var(util::ABSTRACT_SOCKET_ENV).is_none()
+        && util::abstract_socket_name(Some(config)).is_some()
+    {
+        cmd.env(
+            identity::default()
+                .env_name(util::ABSTRACT_SOCKET_ENV)
+                .as_ref(),
+            "1",
+        );
+    }
+
     tracing::debug!("spawning a command server");
-    if tracing::enabled!(tracing::Level::DEBUG) {
+    let child = if tracing::enabled!(tracing::Level::DEBUG) {
         // Do not silent stderr for easier debugging.
         cmd.spawn()
     } else {
         // Silent stderr.
         cmd.spawn_detached()
-    }
+    };
+    Ok(child?)
 }
@@ -7,23 +7,53 @@
 
 //! Utilities shared for the crate.
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::io;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use anyhow::Context;
+use configmodel::Config;
+use configmodel::ConfigExt;
 use fn_error_context::context;
+use fs2::FileExt;
+use nodeipc::NodeIpc;
 use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
 
-// The socket directory and prefix contain identity and version
-// so we can have multiple servers running with different
-// identities. or versions, and we don't need to check versions
+// The socket directory contains identity, and the prefix contains
+// version, so we can have multiple servers running with different
+// identities or versions, and we don't need to check versions
 // and invalidate servers manually.
+//
+// All versions share one directory, so upgrades do not leave old
+// directories behind. Servers of old versions are limited by
+// `cleanup_stale_sockets`, see `sweep_other_versions`.
 static SOCKET_DIR_NAME: Lazy<String> = Lazy::new(|| {
     let cli_name = identity::default().cli_name();
     format!("{}-cmdserver", cli_name)
 });
 
+// Suffix of the env var that overrides `runtime_dir()`, after the identity's
+// env prefix.
+const RUNTIME_DIR_ENV: &str = "CMDSERVER_RUNTIME_DIR";
+
+// Suffix of the env var that makes servers and clients use Linux abstract
+// sockets when set to "1". Clients enabled by `commandserver.abstract-socket`
+// set it for servers they spawn.
+pub(crate) const ABSTRACT_SOCKET_ENV: &str = "CMDSERVER_ABSTRACT_SOCKET";
+
 static PREFIX: Lazy<String> = Lazy::new(|| {
     let short_version: &str =
         match version::VERSION.rsplit_once(|ch: char| !ch.is_ascii_alphanumeric()) {
@@ -32,7 +62,7 @@ static PREFIX: Lazy<String> = Lazy::new(|| {
         };
     // Include number of groups in prefix so long running processes
     // with different groups can co-exist with new processes.
-    let ngroups = groups_count();
+    let ngroups = groups_count().unwrap_or(0);
     if ngroups == 0 {
         short_version.to_string()
     } else {
@@ -45,88 +75,706 @@ pub(crate) fn prefix() -> &'static str {
     &PREFIX
 }
 
+/// Return the Linux abstract socket name if abstract sockets are enabled by
+/// `ABSTRACT_SOCKET_ENV`, or by `commandserver.abstract-socket` in `config`.
+///
+/// Abstract sockets leave no files behind, and do not depend on a writable
+/// runtime directory. Anyone can bind any abstract name, so peer credentials
+/// must be checked. The name includes the uid, `SOCKET_DIR_NAME`, and the
+/// prefix, so servers and clients of the same user and version agree on it.
+///
+/// Always `None` on other platforms.
+pub(crate) fn abstract_socket_name(config: Option<&dyn Config>) -> Option<String> {
+    let enabled = match identity::env_var(ABSTRACT_SOCKET_ENV) {
+        Some(value) => value.ok().as_deref() == Some("1"),
+        None => config.is_some_and(|config| {
+            config
+                .get_or_default::<bool>("commandserver", "abstract-socket")
+                .unwrap_or_default()
+        }),
+    };
+
+    #[cfg(target_os = "linux")]
+    if enabled {
+        let uid = unsafe { libc::getuid() };
+        return Some(format!("{}-{}-{}", &*SOCKET_DIR_NAME, uid, prefix()));
+    }
+
+    let _ = enabled;
+    None
+}
+
+/// Return the Windows named pipe name that servers and clients use instead
+/// of socket files in `runtime_dir()`. The runtime directory only keeps the
+/// logs and metrics of servers then.
+///
+/// Like `abstract_socket_name`, the name includes `SOCKET_DIR_NAME`, the
+/// prefix, and the user (SID), so servers and clients of the same user and
+/// version agree on it. Pipes are only accessible by their user, and peers
+/// of other users are refused, see `udsipc::pipe`.
+///
+/// Always `None` on other platforms. `None` if the user cannot be identified,
+/// so sockets in the runtime directory are used instead.
+pub(crate) fn pipe_name() -> Option<String> {
+    #[cfg(windows)]
+    {
+        static PIPE_NAME: Lazy<Option<String>> =
+            Lazy::new(|| match udsipc::pipe::current_user_id() {
+                Ok(user) => Some(format!("{}-{}-{}", &*SOCKET_DIR_NAME, prefix(), user)),
+                Err(e) => {
+                    tracing::warn!("not using named pipes:\n{:?}", &e);
+                    None
+                }
+            });
+        return PIPE_NAME.clone();
+    }
+
+    #[allow(unreachable_code)]
+    None
+}
+
+/// A directory `runtime_dir` can use, with a name for logging. The path
+/// is only computed if earlier candidates are unusable.
+type RuntimeDirCandidate = (String, Box<dyn Fn() -> anyhow::Result<PathBuf>>);
+
 /// Create and return a runtime directory intended for uds files.
-/// The directory contains `SOCKET_DIR_NAME` in its path.
-#[context("Creating a runtime directory")]
+///
+/// The first usable directory of these is used:
+/// - The XDG runtime directory.
+/// - The local data directory (`~/.local/share`, `AppData\Local`).
+/// - A directory for this user in the temporary directory.
+///
+/// Each contains `SOCKET_DIR_NAME` in its path. The `RUNTIME_DIR_ENV` env
+/// var (ex. `SL_CMDSERVER_RUNTIME_DIR`) replaces them with its list of
+/// directories, separated like `PATH`.
+///
+/// A directory is usable if a file can be created in it, so read-only or
+/// full file systems are skipped. Servers are spawned with the client's env
+/// vars, so both sides try the same directories, in the same order.
+///
+/// Refuses directories that others could use to redirect the sockets,
+/// see `check_private_dir`.
+///
+/// If socket paths in the directory would be too long, returns a shorter
+/// directory instead, see `fit_socket_paths`.
+///
+/// The directory is only looked for once per process, since the env vars
+/// it depends on do not change. Failures are not cached.
 pub(crate) fn runtime_dir() -> anyhow::Result<PathBuf> {
-    let parent = match dirs::runtime_dir().or_else(|| {
-        // ~/.local/share, AppData\Local
-        dirs::data_local_dir().map(|local| local.join("CommandServer"))
-    }) {
+    static RUNTIME_DIR: OnceCell<PathBuf> = OnceCell::new();
+    RUNTIME_DIR.get_or_try_init(find_runtime_dir).cloned()
+}
+
+#[context("Creating a runtime directory")]
+fn find_runtime_dir() -> anyhow::Result<PathBuf> {
+    let candidates = match identity::env_var(RUNTIME_DIR_ENV) {
+        Some(value) => {
+            let name = identity::default().env_name(RUNTIME_DIR_ENV);
+            let value = value.with_context(|| format!("Reading {}", name))?;
+            override_runtime_dir_candidates(&name, &value)
+        }
+        None => default_runtime_dir_candidates(),
+    };
+    runtime_dir_from(candidates)
+}
+
+/// Return the first usable directory of `candidates`, shortened by
+/// `fit_socket_paths` if needed.
+fn runtime_dir_from(candidates: Vec<RuntimeDirCandidate>) -> anyhow::Result<PathBuf> {
+    let dir = first_usable_dir(candidates)?;
+    fit_socket_paths(dir)
+}
+
+/// Candidates for the directories in `value`, a list separated like `PATH`
+/// from the env var `name`.
+fn override_runtime_dir_candidates(name: &str, value: &str) -> Vec<RuntimeDirCandidate> {
+    std::env::split_paths(value)
+        .map(|dir| -> RuntimeDirCandidate { (name.to_string(), Box::new(move || Ok(dir.clone()))) })
+        .collect()
+}
+
+fn default_runtime_dir_candidates() -> Vec<RuntimeDirCandidate> {
+    let mut candidates: Vec<RuntimeDirCandidate> = Vec::new();
+    if let Some(dir) = dirs::runtime_dir() {
+        let dir = dir.join(&*SOCKET_DIR_NAME);
+        candidates.push((
+            "runtime directory".to_owned(),
+            Box::new(move || Ok(dir.clone())),
+        ));
+    }
+    if let Some(dir) = dirs::data_local_dir() {
+        let dir = dir.join("CommandServer").join(&*SOCKET_DIR_NAME);
+        candidates.push((
+            "local data directory".to_owned(),
+            Box::new(move || Ok(dir.clone())),
+        ));
+    }
+    candidates.push((
+        "temporary directory".to_owned(),
+        Box::new(|| Ok(user_temp_dir()?.join(&*SOCKET_DIR_NAME))),
+    ));
+    candidates
+}
+
+/// Create the directories of `candidates` in order, and return the first
+/// one that passes `check_private_dir` and `check_writable`. Log why
+/// earlier ones were skipped.
+fn first_usable_dir(candidates: Vec<RuntimeDirCandidate>) -> anyhow::Result<PathBuf> {
+    let mut failures: Vec<String> = Vec::new();
+    for (name, get_dir) in candidates {
+        let result = get_dir().and_then(|dir| {
+            create_private_dir(&dir)?;
+            check_writable(&dir)?;
+            Ok(dir)
+        });
+        match result {
+            Ok(dir) if failures.is_empty() => return Ok(dir),
+            Ok(dir) => {
+                tracing::info!(
+                    "using {} {} as the runtime directory, since:\n{}",
+                    name,
+                    dir.display(),
+                    failures.join("\n")
+                );
+                return Ok(dir);
+            }
+            Err(e) => failures.push(format!("{} is unusable: {:#}", name, e)),
+        }
+    }
+    anyhow::bail!("No usable directory:\n{}", failures.join("\n"));
+}
+
+/// Check that a file can be created in `dir`. Fails on read-only or full
+/// file systems, even if `dir` exists.
+fn check_writable(dir: &Path) -> anyhow::Result<()> {
+    // The "tmp" extension keeps `udsipc::pool` from listing it as a socket.
+    let path = dir.join(format!("probe-{}.tmp", std::process::id()));
+    let result = fs::write(&path, b"probe");
+    let _ = fs::remove_file(&path);
+    result.with_context(|| format!("Creating a file in {}", dir.display()))
+}
+
+/// Return a directory for this user in `temp_dir()`.
+fn user_temp_dir() -> anyhow::Result<PathBuf> {
+    #[allow(unused_mut)]
+    let mut dir = std::env::temp_dir();
+    #[cfg(unix)]
+    {
+        // temp_dir() is usually insecure, globally writable on *nix.
+        // Try to create a directory with 0o700 permission in it.
+        use std::fs::DirBuilder;
+        use std::os::unix::fs::DirBuilderExt;
+
+        let mut builder = DirBuilder::new();
+        dir = dir.join(format!("uid-{}", unsafe { libc::getuid() }));
+        match builder.mode(0o700).create(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Creating a user exclusive tmpdir at {}", dir.display())
+                });
+            }
+            Ok(_) => {}
+        }
+        // Others can create it first, for example, as a symlink.
+        check_private_dir(&dir)?;
+    }
+    Ok(dir)
+}
+
+// File in a runtime directory with too long socket paths, naming the
+// directory used for the sockets instead.
+const SOCKET_DIR_FILE: &str = "socket-dir";
+
+/// The longest socket path in `dir`. Socket files are named
+/// `{prefix}-{pid}`, and renamed to `.private` by exclusive clients, see
+/// `udsipc::pool`.
+fn longest_socket_path(dir: &Path) -> PathBuf {
+    dir.join(format!("{}-{}.private", prefix(), u32::MAX))
+}
+
+/// Check if socket paths in `dir` fit in `sun_path`.
+fn socket_paths_fit(dir: &Path) -> bool {
+    longest_socket_path(dir).as_os_str().len() <= udsipc::uds::SUN_PATH_MAX
+}
+
+/// Return `dir`, or a shorter directory for sockets if socket paths in `dir`
+/// are too long, for example, with a long home directory on macOS.
+///
+/// The shorter directory is named after a hash of `dir`, in the per-user
+/// temporary directory. It is recorded in `SOCKET_DIR_FILE` in `dir`, so
+/// servers and clients agree on it even if their temporary directories
+/// differ.
+fn fit_socket_paths(dir: PathBuf) -> anyhow::Result<PathBuf> {
+    if socket_paths_fit(&dir) {
+        return Ok(dir);
+    }
+    let record_path = dir.join(SOCKET_DIR_FILE);
+    let short_dir = match fs::read_to_string(&record_path) {
+        Ok(recorded) => PathBuf::from(recorded),
+        Err(_) => {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            dir.hash(&mut hasher);
+            user_temp_dir()?.join(format!("{:08x}", hasher.finish() as u32))
+        }
+    };
+    anyhow::ensure!(
+        socket_paths_fit(&short_dir),
+        "Socket paths like {} are too long (at most {} bytes), and so are {}",
+        longest_socket_path(&dir).display(),
+        udsipc::uds::SUN_PATH_MAX,
+        longest_socket_path(&short_dir).display()
+    );
+    create_private_dir(&short_dir)?;
+    if !record_path.exists() {
+        fs::write(&record_path, short_dir.to_string_lossy().as_bytes())
+            .with_context(|| format!("Recording the socket directory in {}", dir.display()))?;
+    }
+    tracing::debug!(
+        "socket paths in {} are too long, using {}",
+        dir.display(),
+        short_dir.display()
+    );
+    Ok(short_dir)
+}
+
+/// Create the directory and its parents if needed, with 0o700 permission
+/// on *nix. Then check it with `check_private_dir`.
+fn create_private_dir(dir: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::fs::DirBuilder;
+        use std::os::unix::fs::DirBuilderExt;
+
+        match DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Creating a directory at {}", dir.display()));
+            }
+            Ok(_) => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Creating a directory at {}", dir.display()))?;
+
+    check_private_dir(dir)
+}
+
+/// Check that sockets in the directory cannot be redirected, replaced or
+/// found by other users. On *nix, it must not be a symlink, must be owned by
+/// the current user, and must not be accessible by group or others.
+fn check_private_dir(dir: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(dir)
+            .with_context(|| format!("Checking the directory at {}", dir.display()))?;
+        anyhow::ensure!(
+            !metadata.file_type().is_symlink(),
+            "{} is a symlink",
+            dir.display()
+        );
+        anyhow::ensure!(metadata.is_dir(), "{} is not a directory", dir.display());
+        let uid = unsafe { libc::getuid() };
+        anyhow::ensure!(
+            metadata.uid() == uid,
+            "{} is owned by uid {}, not the current user (uid {})",
+            dir.display(),
+            metadata.uid(),
+            uid
+        );
+        anyhow::ensure!(
+            metadata.mode() & 0o077 == 0,
+            "{} is accessible by group or other users (mode {:o})",
+            dir.display(),
+            metadata.mode() & 0o777
+        );
+    }
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+// Sockets younger than this are kept, since their server might be starting.
+const STALE_SOCKET_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Remove sockets in `dir` left by servers that are no longer running,
+/// for example, crashed or killed by SIGKILL. Return the number of removed
+/// sockets.
+///
+/// A socket is dead if the server pid in its name (`{prefix}-{pid}`, see
+/// `udsipc::pool::serve`) is not running. Sockets without a pid in the name
+/// and sockets younger than `STALE_SOCKET_GRACE_PERIOD` are kept.
+///
+/// Lock files of dead sockets (`{prefix}-{pid}.lock`, see
+/// `udsipc::ipc::serve`) are removed the same way. They no longer block
+/// servers, since the OS released their locks.
+///
+/// Sockets of versions other than the `KEEP_OTHER_VERSIONS` most recent
+/// ones are removed too, see `sweep_other_versions`.
+#[context("Cleaning up stale sockets in {}", dir.display())]
+pub(crate) fn cleanup_stale_sockets(dir: &Path) -> anyhow::Result<usize> {
+    let removed = cleanup_stale_sockets_older_than(dir, STALE_SOCKET_GRACE_PERIOD)?;
+    Ok(removed + sweep_other_versions(dir, prefix(), KEEP_OTHER_VERSIONS)?)
+}
+
+// Servers of this many other versions are kept, so switching between
+// versions, for example, during a rollout, does not respawn them every time.
+const KEEP_OTHER_VERSIONS: usize = 2;
+
+// Held while sweeping, so concurrent clients do not sweep at once.
+const SWEEP_LOCK: &str = "sweep.lock";
+
+/// Remove sockets of servers whose prefix differs from `prefix`, except for
+/// the `keep_versions` prefixes that most recently started a server. Return
+/// the number of removed sockets.
+///
+/// The prefix includes the version and the number of groups, see `prefix`.
+/// Servers exit once their socket is removed. Unlike `Hello::Stop`, this
+/// works with servers of any version. Sockets taken by clients are kept.
+///
+/// Does nothing if another process is sweeping.
+fn sweep_other_versions(dir: &Path, prefix: &str, keep_versions: usize) -> anyhow::Result<usize> {
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(SWEEP_LOCK))?;
+    if lock.try_lock_exclusive().is_err() {
+        return Ok(0);
+    }
+
+    // Socket paths, and when the newest of them was created, by prefix.
+    let mut by_prefix: HashMap<String, (SystemTime, Vec<PathBuf>)> = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        // Taken sockets, lock files, and logs have extensions. Sockets do not.
+        if path.extension().is_some()
+            || crate::idle::is_meta_path(&path)
+            || crate::metrics::is_metrics_path(&path)
+            || socket_pid(&entry.file_name()).is_none()
+        {
+            continue;
+        }
+        let name = entry.file_name();
+        let socket_prefix = match name.to_str().and_then(|n| n.rsplit_once('-')) {
+            Some((socket_prefix, _pid)) if socket_prefix != prefix => socket_prefix,
+            _ => continue,
+        };
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if !metadata.file_type().is_socket() {
+                continue;
+            }
+        }
+        let created = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let (newest, paths) = by_prefix
+            .entry(socket_prefix.to_owned())
+            .or_insert((SystemTime::UNIX_EPOCH, Vec::new()));
+        *newest = (*newest).max(created);
+        paths.push(path);
+    }
+
+    let mut versions: Vec<_> = by_prefix.into_values().collect();
+    versions.sort_by_key(|(newest, _paths)| std::cmp::Reverse(*newest));
+    let mut removed = 0;
+    for (_newest, paths) in versions.into_iter().skip(keep_versions) {
+        for path in paths {
+            if fs::remove_file(&path).is_ok() {
+                tracing::debug!("removed socket of another version {}", path.display());
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+fn cleanup_stale_sockets_older_than(dir: &Path, grace_period: Duration) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if is_stale_socket(&entry, grace_period).unwrap_or(false)
+            && fs::remove_file(entry.path()).is_ok()
+        {
+            tracing::debug!("removed stale socket {}", entry.path().display());
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn is_stale_socket(entry: &fs::DirEntry, grace_period: Duration) -> io::Result<bool> {
+    let metadata = entry.metadata()?;
+    let name = entry.file_name();
+    let socket_name = match name.to_str().and_then(|n| n.strip_suffix(".lock")) {
+        Some(socket_name) => OsStr::new(socket_name),
         None => {
-            #[allow(unused_mut)]
-            let mut dir = std::env::temp_dir();
             #[cfg(unix)]
             {
-                // temp_dir() is usually insecure, globally writable on *nix.
-                // Try to create a directory with 0o700 permission in it.
-                use std::fs::DirBuilder;
-                use std::os::unix::fs::DirBuilderExt;
-
-                let mut builder = DirBuilder::new();
-                dir = dir.join(format!("uid-{}", unsafe { libc::getuid() }));
-                match builder.mode(0o700).create(&dir) {
-                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
-                    Err(e) => {
-                        return Err(e).with_context(|| {
-                            format!("Creating a user exclusive tmpdir at {}", dir.display())
-                        });
-                    }
-                    Ok(_) => {}
+                use std::os::unix::fs::FileTypeExt;
+                if !metadata.file_type().is_socket() {
+                    return Ok(false);
                 }
             }
-            dir
+            &name
         }
-        Some(dir) => dir,
     };
+    if metadata.modified()?.elapsed().unwrap_or_default() < grace_period {
+        return Ok(false);
+    }
+    Ok(match socket_pid(socket_name) {
+        Some(pid) => !is_process_alive(pid),
+        None => false,
+    })
+}
+
+/// Parse the server pid from a socket name: `{prefix}-{pid}`, optionally
+/// renamed to `{prefix}-{pid}.private` by a connecting client.
+pub(crate) fn socket_pid(name: &OsStr) -> Option<u32> {
+    let name = name.to_str()?;
+    let name = name.strip_suffix(".private").unwrap_or(name);
+    let (_prefix, pid) = name.rsplit_once('-')?;
+    // Rule out pids that would be negative as `pid_t`.
+    pid.parse()
+        .ok()
+        .filter(|&pid| pid > 0 && pid <= i32::MAX as u32)
+}
 
-    let dir = parent.join(&*SOCKET_DIR_NAME);
-    match fs::create_dir_all(&dir) {
-        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
-        Err(e) => {
-            return Err(e).with_context(|| format!("Creating a directory at {}", dir.display()));
+/// Check if a process is running. Return `true` if unsure.
+pub(crate) fn is_process_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // Signal 0 only checks the process. EPERM means it exists but is
+        // owned by another user.
+        if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+            return true;
         }
-        Ok(_) => {}
+        return io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH);
     }
 
-    Ok(dir)
+    #[allow(unreachable_code)]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+/// Check that the peer of a connected unix domain socket runs as the same
+/// user as this process, using `SO_PEERCRED` on Linux, or `getpeereid` on
+/// macOS and BSDs.
+///
+/// Named pipes on Windows check their peers when connecting, see
+/// `udsipc::pipe::check_peer`. For sockets on other platforms, the per-user
+/// runtime directory is the only barrier.
+pub fn check_peer_credentials(ipc: &NodeIpc) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let peer_uid =
+            peer_uid(ipc.as_raw_file_descriptor()).context("Getting peer credentials")?;
+        let euid = unsafe { libc::geteuid() };
+        anyhow::ensure!(
+            peer_uid == euid,
+            "Peer uid {} does not match uid {}",
+            peer_uid,
+            euid
+        );
+    }
+
+    #[cfg(not(unix))]
+    let _ = ipc;
+
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(fd: libc::c_int) -> io::Result<u32> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(fd: libc::c_int) -> io::Result<u32> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Attempts to read the group list before giving up, if it keeps changing.
+#[cfg(unix)]
+const GROUPS_ATTEMPTS: usize = 5;
+
+/// Give up looking up the groups of the user if there are more than this.
+#[cfg(target_os = "macos")]
+const MAX_USER_GROUPS: usize = 1 << 16;
+
+/// Set the read timeout of the socket behind `ipc`, so reads fail instead
+/// of blocking forever. `None` removes the timeout. Not supported on
+/// non-POSIX platforms, where reads always block.
+pub(crate) fn set_read_timeout(ipc: &NodeIpc, timeout: Option<Duration>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // A zero timeval means no timeout.
+        let timeout = timeout.unwrap_or_default();
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as _,
+            tv_usec: timeout.subsec_micros() as _,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                ipc.as_raw_file_descriptor(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = (ipc, timeout);
+
+    Ok(())
 }
 
 /// Get the number of groups.
-fn groups_count() -> usize {
+fn groups_count() -> Option<usize> {
     #[cfg(unix)]
     {
-        let mut ngroups: libc::c_int = 0;
-        if unsafe { libc::getgroups(0, std::ptr::null_mut()) } == -1 {
-            ngroups = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
-            if ngroups <= 0 {
-                return 0;
-            }
+        let ngroups = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if ngroups < 0 {
+            return None;
         }
-        return ngroups as usize;
+        return Some(ngroups as usize);
     }
 
     #[allow(unreachable_code)]
-    0
+    None
 }
 
-/// Get a sorted list of group ids on POSIX.
+/// Get a sorted, deduplicated list of group ids on POSIX.
 ///
 /// If the client and the server have different lists of groups,
 /// then the server should not serve the client.
 pub fn groups() -> Option<Vec<u32>> {
+    #[cfg(target_os = "macos")]
+    if let Some(groups) = user_groups() {
+        return Some(groups);
+    }
+
+    process_groups()
+}
+
+/// Get the groups of the current user from the user database on macOS,
+/// including groups resolved via Open Directory.
+///
+/// `getgroups` returns at most `NGROUPS_MAX` (16) groups on macOS, so it
+/// can differ between processes of the same user.
+#[cfg(target_os = "macos")]
+fn user_groups() -> Option<Vec<u32>> {
+    use std::ffi::CStr;
+
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 16384];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let ret = unsafe {
+        libc::getpwuid_r(
+            libc::geteuid(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+
+    let mut capacity = 64;
+    while capacity <= MAX_USER_GROUPS {
+        let mut groups = vec![0; capacity];
+        let mut ngroups = capacity as libc::c_int;
+        let ret = unsafe {
+            libc::getgrouplist(
+                name.as_ptr(),
+                passwd.pw_gid as _,
+                groups.as_mut_ptr(),
+                &mut ngroups,
+            )
+        };
+        if ret >= 0 {
+            groups.truncate(ngroups as _);
+            let mut groups: Vec<u32> = groups.into_iter().map(|v| v as u32).collect();
+            groups.sort_unstable();
+            groups.dedup();
+            return Some(groups);
+        }
+        // The list was truncated. Retry with a larger buffer.
+        capacity *= 2;
+    }
+    None
+}
+
+/// Get the groups of the current process via `getgroups`.
+fn process_groups() -> Option<Vec<u32>> {
     #[cfg(unix)]
     {
-        let ngroups = groups_count();
-        let mut groups: Vec<libc::gid_t> = vec![0; ngroups];
-        let ngroups = unsafe { libc::getgroups(ngroups as _, groups.as_mut_ptr()) };
-        if ngroups < 0 {
-            return None;
-        }
+        // The group list can grow between the two getgroups calls.
+        // Retry with the new count if the buffer turns out too small.
+        for _ in 0..GROUPS_ATTEMPTS {
+            let ngroups = groups_count()?;
+            let mut groups: Vec<libc::gid_t> = vec![0; ngroups];
+            let ngroups = unsafe { libc::getgroups(ngroups as _, groups.as_mut_ptr()) };
+            if ngroups < 0 {
+                if io::Error::last_os_error().raw_os_error() == Some(libc::EINVAL) {
+                    continue;
+                }
+                return None;
+            }
 
-        groups.truncate(ngroups as _);
-        groups.sort_unstable();
-        return Some(groups.into_iter().map(|v| v as u32).collect());
+            groups.truncate(ngroups as _);
+            groups.sort_unstable();
+            groups.dedup();
+            return Some(groups);
+        }
+        return None;
     }
 
     #[allow(unreachable_code)]
@@ -138,17 +786,66 @@ pub fn groups() -> Option<Vec<u32>> {
 /// If the client has a higher limit than the server, then the server
 /// should not serve the client.
 pub fn rlimit_nofile() -> Option<u64> {
+    rlimit_nofile_pair().map(|(soft, _hard)| soft)
+}
+
+/// Get the soft and hard `RLIMIT_NOFILE` limits on POSIX.
+pub fn rlimit_nofile_pair() -> Option<(u64, u64)> {
     #[cfg(unix)]
     {
         let mut rlim: libc::rlimit = unsafe { std::mem::zeroed() };
         if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } == 0 {
-            return Some(rlim.rlim_cur as _);
+            return Some((rlim.rlim_cur as _, rlim.rlim_max as _));
         }
     }
 
     None
 }
 
+/// Raise the soft `RLIMIT_NOFILE` limit to the hard limit on POSIX.
+/// Return the soft limit afterwards.
+///
+/// This allows the server to serve clients that raised their limit
+/// after the server was spawned.
+#[context("Raising RLIMIT_NOFILE")]
+pub(crate) fn raise_rlimit_nofile() -> anyhow::Result<Option<u64>> {
+    #[cfg(unix)]
+    {
+        let mut rlim: libc::rlimit = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let target = rlim.rlim_max;
+        // macOS rejects soft limits above OPEN_MAX, even with an unlimited hard limit.
+        #[cfg(target_os = "macos")]
+        let target = target.min(libc::OPEN_MAX as libc::rlim_t);
+        if rlim.rlim_cur < target {
+            rlim.rlim_cur = target;
+            if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) } != 0 {
+                return Err(io::Error::last_os_error()).with_context(|| {
+                    format!("Setting the soft limit to the hard limit {}", target)
+                });
+            }
+        }
+        return Ok(rlimit_nofile());
+    }
+
+    #[allow(unreachable_code)]
+    Ok(None)
+}
+
+/// Check if a server with the given soft `RLIMIT_NOFILE` limit can serve
+/// a client with the given soft limit. Unknown limits are compatible.
+///
+/// The server can serve clients with the same or a lower limit, since
+/// the command cannot tell the difference.
+pub(crate) fn is_rlimit_nofile_compatible(server: Option<u64>, client: Option<u64>) -> bool {
+    match (server, client) {
+        (Some(server), Some(client)) => server >= client,
+        _ => true,
+    }
+}
+
 /// Get the umask on POSIX.
 pub fn get_umask() -> Option<u32> {
     #[cfg(unix)]
@@ -160,3 +857,540 @@ pub fn get_umask() -> Option<u32> {
     #[allow(unreachable_code)]
     None
 }
+
+/// Serializes umask changes, since the umask is shared by all threads.
+static UMASK_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets the umask until dropped, then restores the previous one. No-op on
+/// Windows.
+///
+/// Guards are held one at a time, so commands of different umasks do not
+/// overlap in one process. Concurrent clients are served by forked processes,
+/// which have their own umasks anyway.
+pub(crate) struct UmaskGuard {
+    #[cfg(unix)]
+    previous: libc::mode_t,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl UmaskGuard {
+    pub(crate) fn set(mask: u32) -> Self {
+        let lock = UMASK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        #[cfg(unix)]
+        let previous = unsafe { libc::umask(mask as _) };
+        #[cfg(not(unix))]
+        let _ = mask;
+        Self {
+            #[cfg(unix)]
+            previous,
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for UmaskGuard {
+    fn drop(&mut self) {
+        // Runs before `_lock` is released.
+        #[cfg(unix)]
+        unsafe {
+            libc::umask(self.previous);
+        }
+    }
+}
+
+/// How the client compares a [`CompatFingerprint`] attribute with the server's.
+#[derive(Clone, Copy, Debug)]
+enum CompatRule {
+    /// The values must be equal.
+    Equal,
+    /// The server value must be at least the client value, as numbers.
+    ServerAtLeast,
+    /// Not compared. The server adopts the client value, or it is only for logging.
+    Ignore,
+}
+
+/// Attributes in a [`CompatFingerprint`] and how they are compared.
+/// To add an attribute, add it here and in [`CompatFingerprint::current`].
+const COMPAT_ATTRIBUTES: &[(&str, CompatRule)] = &[
+    ("groups", CompatRule::Equal),
+    ("rlimit_nofile", CompatRule::ServerAtLeast),
+    ("rlimit_nofile_hard", CompatRule::Ignore),
+    // Applied by `run_command`.
+    ("umask", CompatRule::Ignore),
+    ("cwd_dev", CompatRule::Equal),
+    ("env_hash", CompatRule::Equal),
+    ("locale", CompatRule::Equal),
+];
+
+/// Environment variables that take effect at process startup, so changing
+/// them in `apply_env` does not affect an already running server.
+const COMPAT_ENV_VARS: &[&str] = &[
+    "PYTHONHOME",
+    "PYTHONPATH",
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "DYLD_LIBRARY_PATH",
+    "DYLD_INSERT_LIBRARIES",
+];
+
+/// Named process attributes that decide if a server can serve a client.
+///
+/// Attributes unknown to one side, or unavailable on the platform, are
+/// skipped, so peers can add attributes without breaking each other.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompatFingerprint {
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl CompatFingerprint {
+    /// Collect the attributes of the current process.
+    pub fn current() -> Self {
+        let mut attributes = BTreeMap::new();
+        let mut insert = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                attributes.insert(name.to_owned(), value);
+            }
+        };
+        insert(
+            "groups",
+            groups().map(|g| {
+                g.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        );
+        let nofile = rlimit_nofile_pair();
+        insert("rlimit_nofile", nofile.map(|(soft, _)| soft.to_string()));
+        insert(
+            "rlimit_nofile_hard",
+            nofile.map(|(_, hard)| hard.to_string()),
+        );
+        insert("umask", get_umask().map(|v| format!("{:o}", v)));
+        insert("cwd_dev", cwd_dev().map(|v| v.to_string()));
+        insert("env_hash", Some(env_hash()));
+        insert("locale", locale());
+        Self { attributes }
+    }
+
+    /// Compare the client fingerprint (`self`) with the server's.
+    /// Return names of the mismatched attributes.
+    pub fn mismatches(&self, server: &CompatFingerprint) -> Vec<&'static str> {
+        let mut mismatches = Vec::new();
+        for &(name, rule) in COMPAT_ATTRIBUTES {
+            let (client_value, server_value) =
+                match (self.attributes.get(name), server.attributes.get(name)) {
+                    (Some(c), Some(s)) => (c, s),
+                    _ => continue,
+                };
+            let compatible = match rule {
+                CompatRule::Equal => client_value == server_value,
+                CompatRule::ServerAtLeast => {
+                    match (client_value.parse::<u64>(), server_value.parse::<u64>()) {
+                        (Ok(c), Ok(s)) => is_rlimit_nofile_compatible(Some(s), Some(c)),
+                        _ => client_value == server_value,
+                    }
+                }
+                CompatRule::Ignore => true,
+            };
+            if !compatible {
+                mismatches.push(name);
+            }
+        }
+        mismatches
+    }
+}
+
+/// Device id of the current directory on POSIX.
+fn cwd_dev() -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let cwd = std::env::current_dir().ok()?;
+        return Some(fs::metadata(cwd).ok()?.dev());
+    }
+
+    #[allow(unreachable_code)]
+    None
+}
+
+/// Hash of the [`COMPAT_ENV_VARS`] values.
+fn env_hash() -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for name in COMPAT_ENV_VARS {
+        std::env::var_os(name).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// The locale from the environment, following the POSIX precedence for
+/// `LC_CTYPE`.
+fn locale() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|v| !v.is_empty())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::process::Command;
+
+    use super::*;
+
+    // A pid that is no longer running.
+    fn dead_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    // Create a socket file that outlives its listener, like a killed server.
+    fn bind(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        drop(UnixListener::bind(&path).unwrap());
+        path
+    }
+
+    #[test]
+    fn test_cleanup_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let dead = dead_pid();
+        let dead_socket = bind(&dir, &format!("v1-{}", dead));
+        let dead_private = bind(&dir, &format!("v2n3-{}.private", dead));
+        let live_socket = bind(&dir, &format!("v1-{}", std::process::id()));
+        let no_pid = bind(&dir, "v1-foo");
+        let not_socket = dir.join(format!("v2-{}", dead));
+        fs::write(&not_socket, b"").unwrap();
+        let dead_lock = dir.join(format!("v1-{}.lock", dead));
+        fs::write(&dead_lock, b"").unwrap();
+        let live_lock = dir.join(format!("v1-{}.lock", std::process::id()));
+        fs::write(&live_lock, b"").unwrap();
+        let spawn_lock = dir.join("spawn.lock");
+        fs::write(&spawn_lock, b"").unwrap();
+
+        // Just created, so they might belong to servers that are starting.
+        assert_eq!(cleanup_stale_sockets(&dir).unwrap(), 0);
+        assert!(dead_socket.exists());
+
+        assert_eq!(
+            cleanup_stale_sockets_older_than(&dir, Duration::ZERO).unwrap(),
+            3
+        );
+        assert!(!dead_socket.exists());
+        assert!(!dead_private.exists());
+        assert!(!dead_lock.exists());
+        assert!(live_socket.exists());
+        assert!(no_pid.exists());
+        assert!(not_socket.exists());
+        assert!(live_lock.exists());
+        assert!(spawn_lock.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sweep_other_versions() {
+        let dir = std::env::temp_dir().join(format!("cmdserver-sweep-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Servers that started a second apart, oldest first.
+        for (i, name) in ["v1-11", "v2-21", "v1-12", "v3-31", "v4n2-41"]
+            .into_iter()
+            .enumerate()
+        {
+            let path = bind(&dir, name);
+            let created = SystemTime::UNIX_EPOCH + Duration::from_secs(1000 + i as u64);
+            set_modified(&path, created);
+        }
+        let current = bind(&dir, "cur-51");
+        let taken = bind(&dir, "v2-22.private");
+        let lock = dir.join("v2-21.lock");
+        fs::write(&lock, b"").unwrap();
+        let exists = |name: &str| dir.join(name).exists();
+
+        // Another process is sweeping.
+        let sweep_lock = fs::File::create(dir.join(SWEEP_LOCK)).unwrap();
+        sweep_lock.lock_exclusive().unwrap();
+        assert_eq!(sweep_other_versions(&dir, "cur", 2).unwrap(), 0);
+        sweep_lock.unlock().unwrap();
+
+        // v4n2 and v3 started servers most recently. v1 and v2 are removed.
+        assert_eq!(sweep_other_versions(&dir, "cur", 2).unwrap(), 3);
+        assert!(!exists("v1-11") && !exists("v1-12") && !exists("v2-21"));
+        assert!(exists("v3-31") && exists("v4n2-41"));
+        assert!(current.exists() && taken.exists() && lock.exists());
+
+        assert_eq!(sweep_other_versions(&dir, "cur", 2).unwrap(), 0);
+        assert_eq!(sweep_other_versions(&dir, "cur", 0).unwrap(), 2);
+        assert!(current.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Set the modification time of a socket, which cannot be opened.
+    fn set_modified(path: &Path, time: SystemTime) {
+        use std::os::unix::ffi::OsStrExt;
+
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let times = [libc::timespec {
+            tv_sec: secs as libc::time_t,
+            tv_nsec: 0,
+        }; 2];
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        let ret = unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0) };
+        assert_eq!(ret, 0);
+    }
+
+    #[test]
+    fn test_socket_pid() {
+        assert_eq!(socket_pid(OsStr::new("abc-12")), Some(12));
+        assert_eq!(socket_pid(OsStr::new("abcn3-12.private")), Some(12));
+        assert_eq!(socket_pid(OsStr::new("abc-12.lock")), None);
+        assert_eq!(socket_pid(OsStr::new("spawn.lock")), None);
+        assert_eq!(socket_pid(OsStr::new("abc-0")), None);
+        assert_eq!(socket_pid(OsStr::new("abc-4294967295")), None);
+    }
+
+    #[test]
+    fn test_runtime_dir_override() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("cmdserver-env-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        create_private_dir(&base).unwrap();
+        let dir = base.join("sockets");
+        let name = "TEST_CMDSERVER_RUNTIME_DIR";
+        let candidates = |dirs: &[&Path]| {
+            let value = std::env::join_paths(dirs).unwrap();
+            override_runtime_dir_candidates(name, value.to_str().unwrap())
+        };
+
+        // Created as needed, and servers listen there.
+        assert_eq!(runtime_dir_from(candidates(&[&dir])).unwrap(), dir);
+        let incoming = udsipc::pool::serve(&dir, prefix()).unwrap();
+        assert_eq!(udsipc::pool::list_uds_paths(&dir, prefix()).count(), 1);
+        drop(incoming);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o750)).unwrap();
+        let err = runtime_dir_from(candidates(&[&dir])).unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("is accessible by group or other users (mode 750)"),
+            "{:?}",
+            err
+        );
+
+        // Unusable directories are skipped. A directory cannot be created
+        // in a file, like in a read-only file system.
+        let file = base.join("file");
+        fs::write(&file, "").unwrap();
+        let in_file = file.join("sockets");
+        let fallback = base.join("fallback");
+        let dirs: [&Path; 3] = [&in_file, &dir, &fallback];
+        assert_eq!(runtime_dir_from(candidates(&dirs)).unwrap(), fallback);
+        assert_eq!(runtime_dir_from(candidates(&dirs)).unwrap(), fallback);
+        // No probe files are left.
+        assert_eq!(fs::read_dir(&fallback).unwrap().count(), 0);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        assert_eq!(runtime_dir_from(candidates(&dirs)).unwrap(), dir);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_fit_socket_paths() {
+        let base = std::env::temp_dir().join(format!("cmdserver-deep-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let deep = base.join("d".repeat(60)).join("e".repeat(60));
+        create_private_dir(&deep).unwrap();
+        assert!(!socket_paths_fit(&deep));
+
+        // Sockets go to a short directory, recorded in the deep directory.
+        let dir = fit_socket_paths(deep.clone()).unwrap();
+        assert_ne!(dir, deep);
+        assert!(socket_paths_fit(&dir));
+        let recorded = fs::read_to_string(deep.join(SOCKET_DIR_FILE)).unwrap();
+        assert_eq!(Path::new(&recorded), dir);
+        assert_eq!(fit_socket_paths(deep.clone()).unwrap(), dir);
+
+        // Clients can connect.
+        let incoming = udsipc::pool::serve(&dir, prefix()).unwrap();
+        let ipc = udsipc::pool::connect(&dir, prefix(), true).unwrap();
+        drop(ipc);
+        drop(incoming);
+
+        // Short directories are used as is.
+        assert_eq!(fit_socket_paths(base.clone()).unwrap(), base);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_check_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = std::env::temp_dir().join(format!("cmdserver-check-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let dir = base.join("a").join("b");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+
+        let link = base.join("link");
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        let err = create_private_dir(&link).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} is a symlink", link.display()));
+
+        for mode in [0o770, 0o702, 0o750, 0o701] {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(mode)).unwrap();
+            let err = check_private_dir(&dir).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "{} is accessible by group or other users (mode {:o})",
+                    dir.display(),
+                    mode
+                )
+            );
+        }
+
+        // Owned by root, so not by us unless running as root.
+        if unsafe { libc::getuid() } != 0 {
+            let err = check_private_dir(Path::new("/")).unwrap_err();
+            assert!(
+                err.to_string().starts_with("/ is owned by uid 0"),
+                "{}",
+                err
+            );
+        }
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_check_peer_credentials() {
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = NodeIpc::from_socket(server).unwrap();
+        assert_eq!(peer_uid(server.as_raw_file_descriptor()).unwrap(), unsafe {
+            libc::geteuid()
+        });
+        check_peer_credentials(&server).unwrap();
+        check_peer_credentials(&NodeIpc::from_socket(client).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_groups() {
+        let groups = groups().unwrap();
+        // Can be empty, for example, in containers without supplementary groups,
+        // but not when the kernel reports some.
+        if groups_count().unwrap() > 0 {
+            assert!(!groups.is_empty());
+        }
+        #[cfg(not(target_os = "macos"))]
+        assert!(groups.len() <= groups_count().unwrap());
+        assert!(groups.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(groups, super::groups().unwrap());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_user_groups() {
+        let groups = user_groups().unwrap();
+        // Includes the primary group, and is not limited to `NGROUPS_MAX`.
+        assert!(groups.contains(&unsafe { libc::getgid() }));
+        assert!(groups.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(groups, user_groups().unwrap());
+        assert_eq!(groups, super::groups().unwrap());
+    }
+
+    #[test]
+    fn test_raise_rlimit_nofile() {
+        let (soft, hard) = rlimit_nofile_pair().unwrap();
+        let raised = raise_rlimit_nofile().unwrap().unwrap();
+        assert!(raised >= soft);
+        assert!(raised <= hard);
+        assert_eq!(rlimit_nofile_pair(), Some((raised, hard)));
+        // Raising again changes nothing.
+        assert_eq!(raise_rlimit_nofile().unwrap(), Some(raised));
+    }
+
+    #[test]
+    fn test_is_rlimit_nofile_compatible() {
+        for (server, client, compatible) in [
+            (Some(1024), Some(1024), true),
+            (Some(4096), Some(1024), true),
+            (Some(1024), Some(4096), false),
+            (None, Some(4096), true),
+            (Some(1024), None, true),
+            (None, None, true),
+        ] {
+            assert_eq!(
+                is_rlimit_nofile_compatible(server, client),
+                compatible,
+                "server {:?} client {:?}",
+                server,
+                client
+            );
+        }
+    }
+
+    #[test]
+    fn test_compat_fingerprint() {
+        let client = CompatFingerprint::current();
+        assert!(client.attributes.contains_key("rlimit_nofile"));
+        assert!(client.mismatches(&client).is_empty());
+
+        let mut server = client.clone();
+        server
+            .attributes
+            .insert("groups".to_owned(), "1,2,3".to_owned());
+        server
+            .attributes
+            .insert("env_hash".to_owned(), "x".to_owned());
+        server
+            .attributes
+            .insert("umask".to_owned(), "777".to_owned());
+        assert_eq!(client.mismatches(&server), ["groups", "env_hash"]);
+
+        // The server limit can be higher, not lower.
+        let nofile = |v: &str| CompatFingerprint {
+            attributes: [("rlimit_nofile".to_owned(), v.to_owned())].into(),
+        };
+        assert!(nofile("1024").mismatches(&nofile("4096")).is_empty());
+        assert_eq!(
+            nofile("4096").mismatches(&nofile("1024")),
+            ["rlimit_nofile"]
+        );
+    }
+
+    #[test]
+    fn test_compat_fingerprint_unknown_attributes() {
+        let client = CompatFingerprint::current();
+
+        // A newer server with an attribute this client does not know.
+        let mut server = client.clone();
+        server
+            .attributes
+            .insert("from_the_future".to_owned(), "1".to_owned());
+        assert!(client.mismatches(&server).is_empty());
+
+        // An older server without some attributes.
+        let mut server = client.clone();
+        server.attributes.remove("locale");
+        server.attributes.remove("env_hash");
+        assert!(client.mismatches(&server).is_empty());
+        assert!(server.mismatches(&client).is_empty());
+    }
+}
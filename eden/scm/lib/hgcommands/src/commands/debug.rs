@@ -30,6 +30,7 @@ commands! {
     mod segmentclone;
     mod segmentgraph;
     mod segmentpull;
+    mod stopcommandservers;
     mod store;
     mod top;
 }
@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use clidispatch::ReqCtx;
+
+use super::ConfigSet;
+use super::NoOpts;
+use super::Result;
+
+pub fn run(ctx: ReqCtx<NoOpts>, _config: &mut ConfigSet) -> Result<u8> {
+    let mut failed = false;
+    for (path, result) in commandserver::client::stop_all()? {
+        match result {
+            Ok(()) => ctx.io().write(format!("stopped {}\n", path.display()))?,
+            Err(e) => {
+                failed = true;
+                ctx.io()
+                    .write_err(format!("cannot stop {}: {:#}\n", path.display(), e))?
+            }
+        }
+    }
+    Ok(failed as u8)
+}
+
+pub fn aliases() -> &'static str {
+    "debugstopcommandservers"
+}
+
+pub fn doc() -> &'static str {
+    "stop idle command servers"
+}
+
+pub fn synopsis() -> Option<&'static str> {
+    None
+}
@@ -16,6 +16,7 @@ use std::mem::ManuallyDrop;
 use std::sync::Mutex;
 
 use anyhow::Context;
+use filedescriptor::AsRawFileDescriptor;
 use filedescriptor::FileDescriptor;
 use filedescriptor::FromRawFileDescriptor;
 use filedescriptor::IntoRawSocketDescriptor;
@@ -127,6 +128,12 @@ impl NodeIpc {
         self
     }
 
+    /// Get the OS raw file descriptor, for example, to inspect the socket.
+    /// The file descriptor is still owned by `NodeIpc`.
+    pub fn as_raw_file_descriptor(&self) -> RawFileDescriptor {
+        self.w.lock().unwrap().as_raw_file_descriptor()
+    }
+
     /// Send a message to the other side. Might block if the OS buffer is full
     /// and the other side is not receiving the message.
     pub fn send(&self, message: impl Serialize) -> anyhow::Result<()> {
@@ -216,7 +223,11 @@ impl NodeIpc {
         }
         let mut line = String::new();
         let n = r.read_line(&mut line).context("in NodeIpc::recv")?;
-        if n == 0 { Ok(None) } else { Ok(Some(line)) }
+        if n == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(line))
+        }
     }
 }
 
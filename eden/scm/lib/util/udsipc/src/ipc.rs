@@ -16,6 +16,8 @@ use std::path::PathBuf;
 
 use nodeipc::NodeIpc;
 
+#[cfg(windows)]
+use crate::pipe;
 use crate::uds;
 use crate::uds::UnixListener;
 
@@ -23,23 +25,229 @@ use crate::uds::UnixListener;
 ///
 /// Return a iterator that yields a new `NodeIpc` for each client.
 /// Dropping the iterator deletes the unix domain socket.
+///
+/// The ".lock" file next to the socket is locked before binding, and until
+/// the iterator is dropped. If another server holds it, fail with
+/// `io::ErrorKind::AddrInUse` without touching its socket. Locks of
+/// crashed servers are released by the OS, so their lock files are reused.
+///
+/// On POSIX, the socket is only accessible by the current user (0o600).
 pub fn serve(path: PathBuf) -> anyhow::Result<Incoming> {
+    let lock_path = path.with_extension("lock");
+    let lock = lock_exclusive(&lock_path)?;
+    // Left by a crashed server, since the lock is free.
     let _ = fs::remove_file(&path);
-    let listener = uds::bind(&path)?;
+    let listener = bind_private(&path)?;
     let private_path = path.with_extension("private");
     let incoming = Incoming {
-        listener,
-        path,
-        private_path,
+        listener: Listener::Uds(listener),
+        paths: Some((path, private_path)),
+        lock: Some((lock, lock_path)),
     };
 
     Ok(incoming)
 }
 
+/// Lock the file at `path`, creating it if needed, without waiting.
+/// The lock is held until the returned file is closed.
+fn lock_exclusive(path: &Path) -> io::Result<fs::File> {
+    let in_use = || {
+        io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is locked by another server", path.display()),
+        )
+    };
+
+    #[cfg(unix)]
+    loop {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::AsRawFd;
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.kind() {
+                io::ErrorKind::WouldBlock => in_use(),
+                _ => err,
+            });
+        }
+        // The previous holder removes the file when it stops. If that
+        // happened after `open`, the lock is on a removed file, which the
+        // next server would not see. Lock the current file instead.
+        let locked = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(file);
+            }
+            _ => continue,
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // Without sharing, the file cannot be opened again until it is
+        // closed.
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        return match fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .share_mode(0)
+            .open(path)
+        {
+            Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Err(in_use()),
+            result => result,
+        };
+    }
+
+    #[allow(unreachable_code)]
+    {
+        let _ = in_use;
+        fs::File::create(path)
+    }
+}
+
+/// Serve at the Linux abstract socket address `name`. No file is created.
+///
+/// Anyone can connect to or bind abstract addresses, regardless of the
+/// user. Check the peer credentials of connections.
+#[cfg(target_os = "linux")]
+pub fn serve_abstract(name: &str) -> anyhow::Result<Incoming> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = UnixListener::bind_addr(&addr)?;
+    Ok(Incoming {
+        listener: Listener::Uds(listener),
+        paths: None,
+        lock: None,
+    })
+}
+
+/// Connect to the Linux abstract socket address `name`.
+///
+/// Anyone can bind abstract addresses. Check the peer credentials.
+#[cfg(target_os = "linux")]
+pub fn connect_abstract(name: &str) -> anyhow::Result<NodeIpc> {
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let stream = uds::UnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    let ipc = NodeIpc::from_socket(stream)?;
+    Ok(ipc)
+}
+
+/// Serve at the Windows named pipe `name`. No file is created.
+///
+/// Servers can serve at the same name. Each waits at its own instance of
+/// the pipe, and clients connect to any waiting instance. Connections from
+/// other users, or from processes of another elevation, are skipped.
+#[cfg(windows)]
+pub fn serve_pipe(name: &str) -> anyhow::Result<Incoming> {
+    let listener = pipe::bind(name)?;
+    Ok(Incoming {
+        listener: Listener::Pipe(listener),
+        paths: None,
+        lock: None,
+    })
+}
+
+/// Connect to a server waiting at the Windows named pipe `name`.
+///
+/// Fail with `io::ErrorKind::NotFound` if no server is waiting. Servers of
+/// other users, or of another elevation, are refused.
+#[cfg(windows)]
+pub fn connect_pipe(name: &str) -> anyhow::Result<NodeIpc> {
+    use std::os::windows::io::AsRawHandle;
+    use std::os::windows::io::IntoRawHandle;
+
+    let handle = pipe::connect(name)?;
+    pipe::check_peer(handle.as_raw_handle())?;
+    let ipc = NodeIpc::from_raw_file_descriptor(handle.into_raw_handle())?;
+    Ok(ipc)
+}
+
+/// Bind at `path`. On POSIX, bind at a temporary path first, then restrict
+/// the permission and rename, so the socket is never accessible by others
+/// at `path`. `bind` follows the umask, which is process-wide.
+fn bind_private(path: &Path) -> anyhow::Result<UnixListener> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_path = path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp_path);
+        let listener = uds::bind(&tmp_path)?;
+        let result = fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))
+            .and_then(|_| fs::rename(&tmp_path, path));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        return Ok(listener);
+    }
+
+    #[allow(unreachable_code)]
+    uds::bind(path)
+}
+
+/// Check that the socket at `path` was created by the current user with
+/// `serve`. On POSIX, it must not be a symlink, must be owned by the current
+/// user, and must not be accessible by group or others.
+///
+/// This complements the directory permission, and the peer credential check
+/// of the server.
+pub fn check_private_socket(path: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(path)?;
+        anyhow::ensure!(
+            metadata.file_type().is_socket(),
+            "{} is not a socket",
+            path.display()
+        );
+        let uid = unsafe { libc::geteuid() };
+        anyhow::ensure!(
+            metadata.uid() == uid,
+            "{} is owned by uid {}, not the current user (uid {})",
+            path.display(),
+            metadata.uid(),
+            uid
+        );
+        anyhow::ensure!(
+            metadata.mode() & 0o077 == 0,
+            "{} is accessible by group or other users (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o777
+        );
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
 /// Connect to the given path.
 ///
-/// Delete dead (ECONNREFUSED) files automatically.
+/// Delete dead (ECONNREFUSED) files automatically. Refuse sockets that fail
+/// `check_private_socket`.
 pub fn connect(path: &Path) -> anyhow::Result<NodeIpc> {
+    check_private_socket(path)?;
     let stream = match uds::connect(path) {
         Ok(stream) => stream,
         Err(e) => {
@@ -67,18 +275,40 @@ pub fn connect(path: &Path) -> anyhow::Result<NodeIpc> {
 /// - Deletes the domain sockets on drop.
 /// - Provides `get_is_alive_func()` to check if the socket file is still on disk.
 pub struct Incoming {
-    listener: UnixListener,
-    path: PathBuf,
-    private_path: PathBuf,
+    listener: Listener,
+    /// The socket file, and its ".private" name. `None` for abstract sockets
+    /// and named pipes.
+    paths: Option<(PathBuf, PathBuf)>,
+    /// The locked ".lock" file, and its path. See `serve`.
+    lock: Option<(fs::File, PathBuf)>,
+}
+
+/// What `Incoming` accepts connections from.
+enum Listener {
+    Uds(UnixListener),
+    #[cfg(windows)]
+    Pipe(pipe::PipeListener),
 }
 
 impl Incoming {
     /// Get a function to check if the socket file is still on disk.
     /// This can be useful to decide whether to exit in a loop.
+    ///
+    /// Abstract sockets and named pipes have no file, and are always alive.
     pub fn get_is_alive_func(&self) -> Box<dyn (Fn() -> bool) + Send + Sync + 'static> {
-        let path = self.path.clone();
-        let private_path = self.private_path.clone();
-        Box::new(move || path.exists() || private_path.exists())
+        match self.paths.clone() {
+            Some((path, private_path)) => Box::new(move || path.exists() || private_path.exists()),
+            None => Box::new(|| true),
+        }
+    }
+}
+
+/// The listening socket, for example, to `poll` before accepting.
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for Incoming {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        let Listener::Uds(listener) = &self.listener;
+        listener.as_raw_fd()
     }
 }
 
@@ -86,19 +316,194 @@ impl Iterator for Incoming {
     type Item = NodeIpc;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let stream = self.listener.accept().ok()?.0;
-        stream.set_read_timeout(None).ok()?;
-        stream.set_write_timeout(None).ok()?;
-        stream.set_nonblocking(false).ok()?;
-        let ipc = NodeIpc::from_socket(stream).ok()?;
-        Some(ipc)
+        match &mut self.listener {
+            Listener::Uds(listener) => {
+                let stream = listener.accept().ok()?.0;
+                stream.set_read_timeout(None).ok()?;
+                stream.set_write_timeout(None).ok()?;
+                stream.set_nonblocking(false).ok()?;
+                let ipc = NodeIpc::from_socket(stream).ok()?;
+                Some(ipc)
+            }
+            #[cfg(windows)]
+            Listener::Pipe(listener) => loop {
+                use std::os::windows::io::AsRawHandle;
+                use std::os::windows::io::IntoRawHandle;
+
+                let handle = listener.accept().ok()?;
+                // Dropping the connection lets the client know. Keep
+                // serving the others.
+                if pipe::check_peer(handle.as_raw_handle()).is_err() {
+                    continue;
+                }
+                let ipc = NodeIpc::from_raw_file_descriptor(handle.into_raw_handle()).ok()?;
+                return Some(ipc);
+            },
+        }
     }
 }
 
 impl Drop for Incoming {
     fn drop(&mut self) {
-        if fs::remove_file(&self.path).is_err() {
-            let _ = fs::remove_file(&self.private_path);
+        if let Some((path, private_path)) = &self.paths {
+            if fs::remove_file(path).is_err() {
+                let _ = fs::remove_file(private_path);
+            }
+        }
+        if let Some((lock, lock_path)) = self.lock.take() {
+            if cfg!(windows) {
+                // Files open without sharing cannot be removed.
+                drop(lock);
+                let _ = fs::remove_file(lock_path);
+            } else {
+                // Remove while locked, so no other server locks the file in
+                // between. See `lock_exclusive`.
+                let _ = fs::remove_file(lock_path);
+                drop(lock);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn test_private_socket() {
+        let dir = std::env::temp_dir().join(format!("udsipc-private-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("s");
+
+        let incoming = serve(path.clone()).unwrap();
+        let mode = fs::symlink_metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!path.with_extension("tmp").exists());
+        check_private_socket(&path).unwrap();
+        connect(&path).unwrap();
+
+        // A loosened socket is refused, and kept.
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o660)).unwrap();
+        let err = connect(&path).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} is accessible by group or other users (mode 660)",
+                path.display()
+            )
+        );
+        assert!(path.exists());
+
+        // So is a symlink to a socket.
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        let err = connect(&link).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("{} is not a socket", link.display())
+        );
+
+        drop(incoming);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_serve_race() {
+        let dir = std::env::temp_dir().join(format!("udsipc-race-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("s");
+        let lock_path = path.with_extension("lock");
+
+        // A lock file left by a crashed server is not held.
+        fs::write(&lock_path, "").unwrap();
+
+        for _ in 0..20 {
+            let barrier = std::sync::Barrier::new(2);
+            let results: Vec<anyhow::Result<Incoming>> = std::thread::scope(|s| {
+                let binders: Vec<_> = (0..2)
+                    .map(|_| {
+                        s.spawn(|| {
+                            barrier.wait();
+                            serve(path.clone())
+                        })
+                    })
+                    .collect();
+                binders.into_iter().map(|b| b.join().unwrap()).collect()
+            });
+            let (winners, losers): (Vec<_>, Vec<_>) = results.into_iter().partition(|r| r.is_ok());
+            assert_eq!(winners.len(), 1);
+            for loser in losers {
+                let err = loser.err().unwrap();
+                let kind = err.downcast_ref::<io::Error>().unwrap().kind();
+                assert_eq!(kind, io::ErrorKind::AddrInUse);
+            }
+
+            // The loser did not remove the winner's socket.
+            connect(&path).unwrap();
+            drop(winners);
+            assert!(!path.exists());
+            assert!(!lock_path.exists());
         }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn test_serve_pipe() {
+        let name = format!("udsipc-test-pipe-{}", std::process::id());
+        let mut incoming = serve_pipe(&name).unwrap();
+        let not_found = |name: &str| {
+            let err = connect_pipe(name).err().unwrap();
+            err.chain()
+                .filter_map(|e| e.downcast_ref::<io::Error>())
+                .any(|e| e.kind() == io::ErrorKind::NotFound)
+        };
+
+        std::thread::scope(|s| {
+            let server = s.spawn(|| {
+                let ipc = incoming.next().unwrap();
+                let message: String = ipc.recv().unwrap().unwrap();
+                ipc.send(format!("{} world", message)).unwrap();
+                // Keep the instance busy until the client disconnects.
+                let _ = ipc.recv::<String>();
+            });
+            let ipc = connect_pipe(&name).unwrap();
+            ipc.send("hello").unwrap();
+            let reply: String = ipc.recv().unwrap().unwrap();
+            assert_eq!(reply, "hello world");
+
+            // The server has no other instance waiting.
+            assert!(not_found(&name));
+            drop(ipc);
+            server.join().unwrap();
+        });
+
+        // The next client waits at a new instance.
+        std::thread::scope(|s| {
+            let server = s.spawn(|| incoming.next().is_some());
+            let _ipc = (0..500)
+                .find_map(|_| match connect_pipe(&name) {
+                    Ok(ipc) => Some(ipc),
+                    Err(_) => {
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        None
+                    }
+                })
+                .expect("server should wait at a new instance");
+            assert!(server.join().unwrap());
+        });
+
+        drop(incoming);
+        assert!(not_found(&name));
     }
 }
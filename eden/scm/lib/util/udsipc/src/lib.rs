@@ -5,8 +5,11 @@
  * GNU General Public License version 2.
  */
 
-//! Unix-domain socket IPC, with `NodeIpc` integration.
+//! Unix-domain socket IPC, with `NodeIpc` integration. Windows named pipes
+//! are supported too, see `ipc::serve_pipe`.
 
 pub mod ipc;
+#[cfg(windows)]
+pub mod pipe;
 pub mod pool;
 pub mod uds;
@@ -0,0 +1,494 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Low-level Windows named pipe utilities.
+//!
+//! - Pipes are only accessible by the current user, and refuse remote clients.
+//! - The process at the other end of a connection can be checked to run as
+//!   the current user, see `check_peer`.
+
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::AsRawHandle;
+use std::os::windows::io::FromRawHandle;
+use std::os::windows::io::OwnedHandle;
+use std::os::windows::io::RawHandle;
+use std::ptr;
+
+use fn_error_context::context;
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::sddl::ConvertSidToStringSidW;
+use winapi::shared::sddl::ConvertStringSecurityDescriptorToSecurityDescriptorW;
+use winapi::shared::sddl::SDDL_REVISION_1;
+use winapi::shared::winerror::ERROR_NO_DATA;
+use winapi::shared::winerror::ERROR_PIPE_BUSY;
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::fileapi::CreateFileW;
+use winapi::um::fileapi::OPEN_EXISTING;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+use winapi::um::namedpipeapi::ConnectNamedPipe;
+use winapi::um::namedpipeapi::CreateNamedPipeW;
+use winapi::um::namedpipeapi::GetNamedPipeInfo;
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::processthreadsapi::OpenProcess;
+use winapi::um::processthreadsapi::OpenProcessToken;
+use winapi::um::securitybaseapi::EqualSid;
+use winapi::um::securitybaseapi::GetLengthSid;
+use winapi::um::securitybaseapi::GetTokenInformation;
+use winapi::um::winbase::GetNamedPipeClientProcessId;
+use winapi::um::winbase::GetNamedPipeServerProcessId;
+use winapi::um::winbase::LocalFree;
+use winapi::um::winbase::PIPE_ACCESS_DUPLEX;
+use winapi::um::winbase::PIPE_READMODE_BYTE;
+use winapi::um::winbase::PIPE_REJECT_REMOTE_CLIENTS;
+use winapi::um::winbase::PIPE_SERVER_END;
+use winapi::um::winbase::PIPE_TYPE_BYTE;
+use winapi::um::winbase::PIPE_UNLIMITED_INSTANCES;
+use winapi::um::winbase::PIPE_WAIT;
+use winapi::um::winbase::SECURITY_IDENTIFICATION;
+use winapi::um::winbase::SECURITY_SQOS_PRESENT;
+use winapi::um::winnt::TokenElevation;
+use winapi::um::winnt::TokenUser;
+use winapi::um::winnt::GENERIC_READ;
+use winapi::um::winnt::GENERIC_WRITE;
+use winapi::um::winnt::HANDLE;
+use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+use winapi::um::winnt::PSECURITY_DESCRIPTOR;
+use winapi::um::winnt::PSID;
+use winapi::um::winnt::TOKEN_ELEVATION;
+use winapi::um::winnt::TOKEN_QUERY;
+use winapi::um::winnt::TOKEN_USER;
+
+/// Suggested size of the pipe buffers, in bytes.
+const BUFFER_SIZE: DWORD = 64 << 10;
+
+/// Listens at a named pipe.
+///
+/// Each server process waits at its own instance of the pipe. Instances are
+/// only created when waiting for a client, so a server that took its client
+/// cannot be connected to.
+pub struct PipeListener {
+    /// `\\.\pipe\{name}`, as a null-terminated wide string.
+    path: Vec<u16>,
+    /// The instance created by `bind`, until a client connects to it.
+    pending: Option<OwnedHandle>,
+}
+
+/// Create the first instance of the pipe `name` of this server.
+///
+/// Other servers of the current user can create instances of the same name.
+#[context("Creating named pipe {}", name)]
+pub fn bind(name: &str) -> anyhow::Result<PipeListener> {
+    let path = pipe_path(name);
+    let pending = Some(create_instance(&path)?);
+    Ok(PipeListener { path, pending })
+}
+
+impl PipeListener {
+    /// Wait for a client at an instance of the pipe, and return the connected
+    /// instance.
+    pub fn accept(&mut self) -> io::Result<OwnedHandle> {
+        loop {
+            let handle = match self.pending.take() {
+                Some(handle) => handle,
+                None => create_instance(&self.path)?,
+            };
+            let ok = unsafe { ConnectNamedPipe(handle.as_raw_handle() as HANDLE, ptr::null_mut()) };
+            if ok != 0 {
+                return Ok(handle);
+            }
+            let err = io::Error::last_os_error();
+            match err.raw_os_error().map(|e| e as DWORD) {
+                // The client connected after the instance was created.
+                Some(ERROR_PIPE_CONNECTED) => return Ok(handle),
+                // The client disconnected already. Wait at a new instance.
+                Some(ERROR_NO_DATA) => continue,
+                _ => return Err(err),
+            }
+        }
+    }
+
+    /// The instance waiting for a client, if `accept` was not called yet.
+    #[cfg(test)]
+    fn pending(&self) -> Option<&OwnedHandle> {
+        self.pending.as_ref()
+    }
+}
+
+/// Connect to an instance of the pipe `name` that waits for a client.
+///
+/// Fail with `io::ErrorKind::NotFound` if no instance waits, including if
+/// all instances are busy.
+///
+/// The server cannot impersonate the client. It can only identify it.
+#[context("Connecting to named pipe {}", name)]
+pub fn connect(name: &str) -> anyhow::Result<OwnedHandle> {
+    let path = pipe_path(name);
+    let handle = unsafe {
+        CreateFileW(
+            path.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            0,
+            ptr::null_mut(),
+            OPEN_EXISTING,
+            SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION,
+            ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) {
+            return Err(
+                io::Error::new(io::ErrorKind::NotFound, "all pipe instances are busy").into(),
+            );
+        }
+        return Err(err.into());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+}
+
+/// Check that the process at the other end of the connected pipe `handle`
+/// runs as the current user, with the same elevation.
+///
+/// Anyone can create a pipe with a name that is not taken yet. Elevated
+/// processes should neither serve nor be served by processes that are not.
+#[context("Checking the peer of a named pipe")]
+pub fn check_peer(handle: RawHandle) -> anyhow::Result<()> {
+    let handle = handle as HANDLE;
+    let mut flags: DWORD = 0;
+    let ok = unsafe {
+        GetNamedPipeInfo(
+            handle,
+            &mut flags,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut pid: ULONG = 0;
+    let ok = unsafe {
+        if flags & PIPE_SERVER_END != 0 {
+            GetNamedPipeClientProcessId(handle, &mut pid)
+        } else {
+            GetNamedPipeServerProcessId(handle, &mut pid)
+        }
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let peer = ProcessUser::of_pid(pid)?;
+    check_same_user(&peer, &ProcessUser::current()?, pid)
+}
+
+/// Identify the current user for pipe names, like the uid on POSIX.
+///
+/// This is the user SID, ex. `S-1-5-21-...`, followed by `-elevated` in
+/// elevated processes, so they do not share pipes with the others.
+pub fn current_user_id() -> anyhow::Result<String> {
+    let user = ProcessUser::current()?;
+    let sid = user.sid_string()?;
+    Ok(if user.elevated {
+        format!("{}-elevated", sid)
+    } else {
+        sid
+    })
+}
+
+fn check_same_user(peer: &ProcessUser, current: &ProcessUser, pid: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        unsafe { EqualSid(peer.sid(), current.sid()) } != 0,
+        "Peer process {} runs as {}, not the current user {}",
+        pid,
+        peer.sid_string()?,
+        current.sid_string()?
+    );
+    anyhow::ensure!(
+        peer.elevated == current.elevated,
+        "Peer process {} is {}elevated, unlike this process",
+        pid,
+        if peer.elevated { "" } else { "not " }
+    );
+    Ok(())
+}
+
+fn pipe_path(name: &str) -> Vec<u16> {
+    OsStr::new(&format!(r"\\.\pipe\{}", name))
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+/// Create an instance of the pipe at `path`, only accessible by the current
+/// user.
+fn create_instance(path: &[u16]) -> io::Result<OwnedHandle> {
+    let descriptor = SecurityDescriptor::current_user_only()?;
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: descriptor.0,
+        bInheritHandle: FALSE,
+    };
+    let handle = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            &mut attributes,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+}
+
+/// A security descriptor allocated by Windows, freed on drop.
+struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+impl SecurityDescriptor {
+    /// Allow the current user full access, and nobody else any access.
+    /// The DACL is protected from inheriting other entries.
+    fn current_user_only() -> io::Result<Self> {
+        let sid = ProcessUser::current()?.sid_string()?;
+        let sddl: Vec<u16> = OsStr::new(&format!("D:P(A;;GA;;;{})", sid))
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        let ok = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1 as DWORD,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(descriptor))
+    }
+}
+
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
+        unsafe { LocalFree(self.0) };
+    }
+}
+
+/// The user and the elevation of a process, from its access token.
+struct ProcessUser {
+    /// The user SID. `u32`s keep it aligned.
+    sid: Vec<u32>,
+    elevated: bool,
+}
+
+impl ProcessUser {
+    fn current() -> io::Result<Self> {
+        Self::of_process(unsafe { GetCurrentProcess() })
+    }
+
+    fn of_pid(pid: u32) -> io::Result<Self> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) };
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let process = unsafe { OwnedHandle::from_raw_handle(process as RawHandle) };
+        Self::of_process(process.as_raw_handle() as HANDLE)
+    }
+
+    fn of_process(process: HANDLE) -> io::Result<Self> {
+        let mut token: HANDLE = ptr::null_mut();
+        if unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+        let token = token.as_raw_handle() as HANDLE;
+
+        // The first call fails, and tells the size. `u64`s keep the
+        // pointer in `TOKEN_USER` aligned.
+        let mut size: DWORD = 0;
+        unsafe { GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut size) };
+        let mut token_user = vec![0u64; (size as usize).div_ceil(8)];
+        let ok = unsafe {
+            GetTokenInformation(
+                token,
+                TokenUser,
+                token_user.as_mut_ptr() as *mut _,
+                size,
+                &mut size,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sid = unsafe { (*(token_user.as_ptr() as *const TOKEN_USER)).User.Sid };
+
+        let mut elevation: TOKEN_ELEVATION = unsafe { mem::zeroed() };
+        let mut size = mem::size_of::<TOKEN_ELEVATION>() as DWORD;
+        let ok = unsafe {
+            GetTokenInformation(
+                token,
+                TokenElevation,
+                &mut elevation as *mut TOKEN_ELEVATION as *mut _,
+                size,
+                &mut size,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self::from_sid(sid, elevation.TokenIsElevated != 0))
+    }
+
+    /// Copy `sid`, which is only valid as long as its buffer.
+    fn from_sid(sid: PSID, elevated: bool) -> Self {
+        let len = unsafe { GetLengthSid(sid) } as usize;
+        let mut copy = vec![0u32; len.div_ceil(4)];
+        unsafe { ptr::copy_nonoverlapping(sid as *const u8, copy.as_mut_ptr() as *mut u8, len) };
+        Self {
+            sid: copy,
+            elevated,
+        }
+    }
+
+    fn sid(&self) -> PSID {
+        self.sid.as_ptr() as PSID
+    }
+
+    /// The SID in its string form, ex. `S-1-5-18`.
+    fn sid_string(&self) -> io::Result<String> {
+        let mut wide = ptr::null_mut();
+        if unsafe { ConvertSidToStringSidW(self.sid(), &mut wide) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = (0..).take_while(|&i| unsafe { *wide.add(i) } != 0).count();
+        let sid = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(wide, len) });
+        unsafe { LocalFree(wide as *mut _) };
+        Ok(sid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winapi::shared::sddl::ConvertStringSidToSidW;
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::accctrl::SE_KERNEL_OBJECT;
+    use winapi::um::aclapi::GetSecurityInfo;
+    use winapi::um::securitybaseapi::GetAce;
+    use winapi::um::securitybaseapi::GetAclInformation;
+    use winapi::um::winnt::AclSizeInformation;
+    use winapi::um::winnt::ACCESS_ALLOWED_ACE;
+    use winapi::um::winnt::ACCESS_ALLOWED_ACE_TYPE;
+    use winapi::um::winnt::ACL_SIZE_INFORMATION;
+    use winapi::um::winnt::DACL_SECURITY_INFORMATION;
+    use winapi::um::winnt::PACL;
+
+    use super::*;
+
+    fn sid_from_string(sid: &str) -> PSID {
+        let wide: Vec<u16> = OsStr::new(sid).encode_wide().chain(Some(0)).collect();
+        let mut psid = ptr::null_mut();
+        assert_ne!(
+            unsafe { ConvertStringSidToSidW(wide.as_ptr(), &mut psid) },
+            0
+        );
+        psid
+    }
+
+    #[test]
+    fn test_current_user_only() {
+        let name = format!("udsipc-test-dacl-{}", std::process::id());
+        let listener = bind(&name).unwrap();
+        let handle = listener.pending().unwrap().as_raw_handle();
+
+        let mut dacl: PACL = ptr::null_mut();
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        let ret = unsafe {
+            GetSecurityInfo(
+                handle as HANDLE,
+                SE_KERNEL_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut dacl,
+                ptr::null_mut(),
+                &mut descriptor,
+            )
+        };
+        assert_eq!(ret, ERROR_SUCCESS);
+
+        // A single entry, allowing the current user.
+        let mut info: ACL_SIZE_INFORMATION = unsafe { mem::zeroed() };
+        let ok = unsafe {
+            GetAclInformation(
+                dacl,
+                &mut info as *mut ACL_SIZE_INFORMATION as *mut _,
+                mem::size_of::<ACL_SIZE_INFORMATION>() as DWORD,
+                AclSizeInformation,
+            )
+        };
+        assert_ne!(ok, 0);
+        assert_eq!(info.AceCount, 1);
+        let mut ace = ptr::null_mut();
+        assert_ne!(unsafe { GetAce(dacl, 0, &mut ace) }, 0);
+        let ace = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+        assert_eq!(ace.Header.AceType, ACCESS_ALLOWED_ACE_TYPE);
+        let current = ProcessUser::current().unwrap();
+        let ace_sid = &ace.SidStart as *const DWORD as PSID;
+        assert_ne!(unsafe { EqualSid(ace_sid, current.sid()) }, 0);
+
+        unsafe { LocalFree(descriptor) };
+    }
+
+    #[test]
+    fn test_check_same_user() {
+        let current = ProcessUser::current().unwrap();
+        check_same_user(&current, &current, 1).unwrap();
+
+        // Other users are refused.
+        let system_sid = sid_from_string("S-1-5-18");
+        let system = ProcessUser::from_sid(system_sid, current.elevated);
+        unsafe { LocalFree(system_sid) };
+        let err = check_same_user(&system, &current, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Peer process 1 runs as S-1-5-18, not the current user {}",
+                current.sid_string().unwrap()
+            )
+        );
+
+        // So is the current user with another elevation.
+        let other = ProcessUser {
+            sid: current.sid.clone(),
+            elevated: !current.elevated,
+        };
+        let err = check_same_user(&other, &current, 1).unwrap_err();
+        assert!(err.to_string().ends_with("elevated, unlike this process"));
+    }
+
+    #[test]
+    fn test_current_user_id() {
+        let current = ProcessUser::current().unwrap();
+        let id = current_user_id().unwrap();
+        assert!(id.starts_with(&current.sid_string().unwrap()));
+        assert!(id.starts_with("S-1-"));
+    }
+}
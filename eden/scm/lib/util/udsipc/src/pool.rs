@@ -58,11 +58,15 @@ pub fn connect(dir: &Path, prefix: &str, exclusive: bool) -> anyhow::Result<Node
         };
     }
 
-    anyhow::bail!(
-        "Failed to connect to any uds files in {}. Attempted: {:?}",
-        dir.display(),
-        attempts,
-    )
+    // Keep the last error as the source, so callers can inspect it.
+    match attempts.pop() {
+        None => anyhow::bail!("No uds files to connect in {}", dir.display()),
+        Some(last) => Err(last.context(format!(
+            "Failed to connect to any uds files in {}. Also attempted: {:?}",
+            dir.display(),
+            attempts,
+        ))),
+    }
 }
 
 /// Unix-domain-socket path that can potentially be connected.
@@ -71,6 +75,16 @@ pub struct ConnectablePath {
 }
 
 impl ConnectablePath {
+    /// A socket file at a known path, for example, one recorded by its server.
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The current path of the socket file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Connect to this path.
     pub fn connect(self) -> anyhow::Result<NodeIpc> {
         let result = ipc::connect(&self.path);
@@ -112,8 +126,13 @@ pub fn list_uds_paths<'a>(
 
         let path = entry.path();
 
-        // Skip ".lock" files.
-        if path.extension().unwrap_or_default() == "lock" {
+        // Skip ".lock" files, sockets being created (see `ipc::serve`), and
+        // logs of servers.
+        if path.extension().unwrap_or_default() == "lock"
+            || path.extension().unwrap_or_default() == "tmp"
+            || name.ends_with(".log")
+            || name.ends_with(".log.old")
+        {
             return None;
         }
 
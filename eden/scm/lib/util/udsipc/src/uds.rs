@@ -8,7 +8,7 @@
 //! Low-level unix-domain-socket utilities.
 //!
 //! - Re-exports `std` or `uds_windows` types.
-//! - Supports long (>107 bytes) paths by `chdir` temporarily.
+//! - Supports long (>`SUN_PATH_MAX` bytes) paths by `chdir` temporarily.
 
 #[cfg(unix)]
 pub use std::os::unix::net;
@@ -20,6 +20,13 @@ pub use net::UnixStream;
 #[cfg(windows)]
 pub use uds_windows as net;
 
+/// The longest socket path, in bytes. See `sun_path` in `struct sockaddr_un`
+/// in `sys/un.h`. C needs a trailing '\0', which is not counted.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+pub const SUN_PATH_MAX: usize = 103;
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+pub const SUN_PATH_MAX: usize = 107;
+
 /// Bind to the unix domain socket for serving.
 ///
 /// Side effect: changes the process's current directory temporarily.
@@ -39,7 +46,7 @@ pub fn connect(path: &Path) -> anyhow::Result<UnixStream> {
 }
 
 /// Chdir to the directory of `path`, if the `path` is too long for unix-domain-socket.
-/// See `sun_path` in `struct sockaddr_un` in `sys/un.h` for the size limit (107 bytes).
+/// See `SUN_PATH_MAX` for the size limit.
 ///
 /// If chdir is not needed, the full path is passed to `func`. Otherwise, the file name
 /// is passed to `func`.
@@ -47,9 +54,7 @@ fn maybe_with_chdir<T, E: Into<anyhow::Error>>(
     path: &Path,
     func: impl FnOnce(&Path) -> Result<T, E>,
 ) -> anyhow::Result<T> {
-    // Check sys/un.h for this number. Note C needs a tailing '\0' for end-of-string.
-    const SOCKADDR_UN_SUN_PATH_SIZE: usize = 108;
-    let dir = if path.as_os_str().len() >= SOCKADDR_UN_SUN_PATH_SIZE {
+    let dir = if path.as_os_str().len() > SUN_PATH_MAX {
         path.parent()
     } else {
         None
@@ -61,6 +66,13 @@ fn maybe_with_chdir<T, E: Into<anyhow::Error>>(
                 Some(name) => Path::new(name),
                 None => path,
             };
+            anyhow::ensure!(
+                file_name.as_os_str().len() <= SUN_PATH_MAX,
+                "Socket path {} is too long ({} bytes, at most {})",
+                path.display(),
+                path.as_os_str().len(),
+                SUN_PATH_MAX
+            );
             let restore_dir = std::env::current_dir()?;
             std::env::set_current_dir(dir)?;
             (Some(restore_dir), file_name)
@@ -106,6 +106,7 @@ Show debug commands if there are no other candidates
   debugcleanremotenames
   debugcolor
   debugcommands
+  debugcommandserver
   debugcommitmessage
   debugcompactmetalog
   debugcomplete
@@ -200,6 +201,7 @@ Show debug commands if there are no other candidates
   debugsmallcommitmetadata
   debugssl
   debugstatus
+  debugstopcommandservers
   debugstore
   debugstrip
   debugsuccessorssets
@@ -435,6 +437,7 @@ Show all commands + options
   debugcleanremotenames: 
   debugcolor: style
   debugcommands: 
+  debugcommandserver: list, metrics
   debugcommitmessage: 
   debugcompactmetalog: 
   debugcomplete: options
@@ -528,6 +531,7 @@ Show all commands + options
   debugsmallcommitmetadata: rev, category, delete, template
   debugssl: 
   debugstatus: nonnormal
+  debugstopcommandservers: 
   debugstore: content
   debugstrip: rev, force, no-backup, keep, bookmark
   debugsuccessorssets: closest
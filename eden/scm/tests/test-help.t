@@ -938,6 +938,8 @@ Test list of internal help commands
    debugcolor    show available color, effects or style
    debugcommands
                  list all available commands and options
+   debugcommandserver
+                 inspect command servers
    debugcommitmessage
                  show commit template
    debugcompactmetalog
@@ -1103,6 +1105,8 @@ Test list of internal help commands
                  store string metadata for a commit
    debugssl      test a secure connection to a server
    debugstatus   common performance issues for status
+   debugstopcommandservers
+                 stop idle command servers
    debugstore    print information about blobstore
    debugstrip    strip commits and all their descendants from the repository
    debugsuccessorssets